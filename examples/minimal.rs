@@ -240,6 +240,16 @@ async fn handle_event(index: usize, node: &mut Routing, event: Event) -> bool {
         Event::MemberLeft { name, age } => {
             info!("Node #{} member left - name: {}, age: {}", index, name, age);
         }
+        Event::MemberRoleChanged {
+            name,
+            previous_role,
+            role,
+        } => {
+            info!(
+                "Node #{} member role changed - name: {}, previous_role: {:?}, role: {:?}",
+                index, name, previous_role, role
+            );
+        }
         Event::EldersChanged {
             prefix,
             key,
@@ -252,13 +262,47 @@ async fn handle_event(index: usize, node: &mut Routing, event: Event) -> bool {
                 index, prefix, key, sibling_key, elders, self_status_change
             );
         }
-        Event::MessageReceived { content, src, dst } => info!(
+        Event::MessageReceived {
+            content, src, dst, ..
+        } => info!(
             "Node #{} received message - src: {:?}, dst: {:?}, content: {}",
             index,
             src,
             dst,
             HexFmt(&content)
         ),
+        Event::MulticastReceived {
+            content,
+            src,
+            prefix,
+        } => info!(
+            "Node #{} received multicast message - src: {:?}, prefix: {:?}, content: {}",
+            index,
+            src,
+            prefix,
+            HexFmt(&content)
+        ),
+        Event::AnycastDelivered { recipient, .. } => info!(
+            "Node #{} message delivered to closest node {}",
+            index, recipient
+        ),
+        Event::MessageAccumulationExpired { src, dst, .. } => info!(
+            "Node #{} message accumulation expired - src: {:?}, dst: {:?}",
+            index, src, dst
+        ),
+        Event::MessageDelivered {
+            dst,
+            correlation_id,
+        } => info!(
+            "Node #{} delivered message - dst: {:?}, correlation_id: {}",
+            index, dst, correlation_id
+        ),
+        Event::MessageDeliveryFailed { content, dst, .. } => info!(
+            "Node #{} failed to deliver message - dst: {:?}, content: {}",
+            index,
+            dst,
+            HexFmt(&content)
+        ),
         Event::RelocationStarted { previous_name } => info!(
             "Node #{} relocation started - previous_name: {}",
             index, previous_name
@@ -279,6 +323,9 @@ async fn handle_event(index: usize, node: &mut Routing, event: Event) -> bool {
             index, user, msg
         ),
         Event::ClientLost(addr) => info!("Node #{} received ClientLost({:?})", index, addr),
+        Event::ClientThrottled(addr) => {
+            info!("Node #{} received ClientThrottled({:?})", index, addr)
+        }
     }
 
     true
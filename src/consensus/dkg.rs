@@ -581,6 +581,7 @@ impl DkgCommand {
                     &recipients,
                     recipients.len(),
                     message.to_bytes(),
+                    message.priority(),
                 ))
             }
             Self::ScheduleTimeout { duration, token } => {
@@ -605,6 +606,7 @@ impl DkgCommand {
                     &recipients,
                     recipients.len(),
                     message.to_bytes(),
+                    message.priority(),
                 ))
             }
             Self::HandleFailureAgreement {
@@ -7,6 +7,7 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 mod dkg;
+mod pending;
 mod proven;
 #[cfg(test)]
 pub mod test_utils;
@@ -15,6 +16,7 @@ mod vote;
 pub use self::{dkg::DkgKey, proven::Proven};
 pub(crate) use self::{
     dkg::{DkgCommands, DkgFailureProof, DkgFailureProofSet, DkgVoter},
+    pending::PendingAccumulations,
     vote::{Vote, VoteAccumulationError, VoteAccumulator},
 };
 pub(crate) use bls_signature_aggregator::{Proof, ProofShare, SignatureAggregator};
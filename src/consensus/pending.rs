@@ -0,0 +1,114 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::crypto::{self, Digest256};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Payloads that don't gather enough signature shares within this time since their first share
+/// are considered failed.
+pub(crate) const ACCUMULATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Keeps track of the payloads whose signature shares are still being accumulated, so the ones
+/// that never reach quorum can be detected and reported.
+pub(crate) struct PendingAccumulations<T> {
+    entries: HashMap<Digest256, Entry<T>>,
+}
+
+impl<T> PendingAccumulations<T> {
+    /// Records that a share of `payload` was added but not enough shares are accumulated yet. The
+    /// deadline is set on the first share only.
+    pub fn insert_with<F>(&mut self, payload: &[u8], value: F)
+    where
+        F: FnOnce() -> T,
+    {
+        let _ = self.get_or_insert_with(payload, value);
+    }
+
+    /// Like `insert_with`, but returns the tracked value so it can be updated.
+    pub fn get_or_insert_with<F>(&mut self, payload: &[u8], value: F) -> &mut T
+    where
+        F: FnOnce() -> T,
+    {
+        &mut self
+            .entries
+            .entry(crypto::sha3_256(payload))
+            .or_insert_with(|| Entry {
+                deadline: Instant::now() + ACCUMULATION_TIMEOUT,
+                value: value(),
+            })
+            .value
+    }
+
+    /// Stops tracking `payload`, because its signature got accumulated.
+    pub fn remove(&mut self, payload: &[u8]) {
+        let _ = self.entries.remove(&crypto::sha3_256(payload));
+    }
+
+    /// The payloads whose signature shares are still being accumulated, with their deadlines.
+    pub fn iter(&self) -> impl Iterator<Item = (&T, Instant)> {
+        self.entries
+            .values()
+            .map(|entry| (&entry.value, entry.deadline))
+    }
+
+    /// Removes and returns the entries whose deadline passed before `now`.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<T> {
+        let expired: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.deadline <= now)
+            .map(|(digest, _)| *digest)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|digest| self.entries.remove(&digest))
+            .map(|entry| entry.value)
+            .collect()
+    }
+}
+
+impl<T> Default for PendingAccumulations<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+struct Entry<T> {
+    deadline: Instant,
+    value: T,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry() {
+        let mut pending = PendingAccumulations::default();
+
+        pending.insert_with(b"accumulated", || 0);
+        pending.insert_with(b"failed", || 1);
+        // Subsequent shares don't replace the entry.
+        pending.insert_with(b"failed", || 2);
+
+        pending.remove(b"accumulated");
+
+        assert!(pending.remove_expired(Instant::now()).is_empty());
+        assert_eq!(
+            pending.remove_expired(Instant::now() + ACCUMULATION_TIMEOUT),
+            vec![1]
+        );
+        assert!(pending.entries.is_empty());
+    }
+}
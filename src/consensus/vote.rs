@@ -6,13 +6,14 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{Proof, ProofShare, Proven, SignatureAggregator};
+use super::{pending::PendingAccumulations, Proof, ProofShare, Proven, SignatureAggregator};
 use crate::{
     error::Result,
     messages::PlainMessage,
     section::{EldersInfo, MemberInfo, SectionProofChain},
 };
 use serde::{Deserialize, Serialize, Serializer};
+use std::{collections::BTreeMap, time::Instant};
 use thiserror::Error;
 use xor_name::{Prefix, XorName};
 
@@ -110,7 +111,17 @@ impl<'a> Serialize for SignableView<'a> {
 
 // Accumulator of `Vote`s.
 #[derive(Default)]
-pub(crate) struct VoteAccumulator(SignatureAggregator);
+pub(crate) struct VoteAccumulator {
+    aggregator: SignatureAggregator,
+    pending: PendingAccumulations<PendingVote>,
+}
+
+// Vote that didn't reach quorum yet, together with the shares received for it so far, by the
+// index of their signer, so a resent share is kept only once.
+struct PendingVote {
+    vote: Vote,
+    shares: BTreeMap<usize, ProofShare>,
+}
 
 impl VoteAccumulator {
     pub fn add(
@@ -119,8 +130,54 @@ impl VoteAccumulator {
         proof_share: ProofShare,
     ) -> Result<(Vote, Proof), VoteAccumulationError> {
         let bytes = bincode::serialize(&SignableView(&vote))?;
-        let proof = self.0.add(&bytes, proof_share)?;
-        Ok((vote, proof))
+        match self.aggregator.add(&bytes, proof_share.clone()) {
+            Ok(proof) => {
+                self.pending.remove(&bytes);
+                Ok((vote, proof))
+            }
+            Err(bls_signature_aggregator::Error::NotEnoughShares) => {
+                let _ = self
+                    .pending
+                    .get_or_insert_with(&bytes, || PendingVote {
+                        vote,
+                        shares: BTreeMap::new(),
+                    })
+                    .shares
+                    .insert(proof_share.index, proof_share);
+                Err(bls_signature_aggregator::Error::NotEnoughShares.into())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    // The votes that didn't reach quorum yet, with the deadlines to reach it.
+    pub fn pending(&self) -> impl Iterator<Item = (&Vote, Instant)> {
+        self.pending
+            .iter()
+            .map(|(pending, deadline)| (&pending.vote, deadline))
+    }
+
+    // The shares received so far of the votes that didn't reach quorum yet, so they can be
+    // persisted and re-added after restart.
+    pub fn pending_shares(&self) -> Vec<(Vote, ProofShare)> {
+        self.pending
+            .iter()
+            .flat_map(|(pending, _)| {
+                pending
+                    .shares
+                    .values()
+                    .map(move |share| (pending.vote.clone(), share.clone()))
+            })
+            .collect()
+    }
+
+    // Returns the votes that didn't reach quorum in time.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<Vote> {
+        self.pending
+            .remove_expired(now)
+            .into_iter()
+            .map(|pending| pending.vote)
+            .collect()
     }
 }
 
@@ -144,10 +201,17 @@ impl Serialize for SignableWrapper {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{consensus, section};
+    use crate::{
+        consensus::{self, pending::ACCUMULATION_TIMEOUT},
+        section,
+    };
     use anyhow::Result;
-    use rand::Rng;
-    use std::fmt::Debug;
+    use proptest::{collection::vec, prelude::*};
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::{
+        collections::{HashMap, HashSet},
+        fmt::Debug,
+    };
 
     #[test]
     fn serialize_for_signing() -> Result<()> {
@@ -204,4 +268,168 @@ mod tests {
 
         prefix
     }
+
+    #[test]
+    fn restore_pending_shares() -> Result<()> {
+        let sk_set = bls::SecretKeySet::random(1, &mut rand::thread_rng());
+        let pk_set = sk_set.public_keys();
+        let vote = Vote::TheirKnowledge {
+            prefix: Prefix::default(),
+            key_index: 1,
+        };
+        let prove = |index| vote.prove(pk_set.clone(), index, &sk_set.secret_key_share(index));
+
+        let mut accumulator = VoteAccumulator::default();
+        assert!(accumulator.add(vote.clone(), prove(0)?).is_err());
+        // The same share received again is kept once.
+        assert!(accumulator.add(vote.clone(), prove(0)?).is_err());
+
+        let shares = accumulator.pending_shares();
+        assert_eq!(shares.len(), 1);
+
+        // A fresh accumulator picks up where the previous one left off.
+        let mut accumulator = VoteAccumulator::default();
+        for (vote, share) in shares {
+            assert!(accumulator.add(vote, share).is_err());
+        }
+
+        let (accumulated, _) = accumulator.add(vote.clone(), prove(1)?)?;
+        assert_eq!(accumulated, vote);
+        assert!(accumulator.pending_shares().is_empty());
+
+        Ok(())
+    }
+
+    // Operation on the accumulator, generated by proptest.
+    #[derive(Clone, Debug)]
+    enum Op {
+        // Add the share of the elder with `index` of the vote identified by `vote`. A `forged`
+        // share is signed with a key share not belonging to the section.
+        Add {
+            vote: u64,
+            index: usize,
+            forged: bool,
+        },
+        // Remove the votes pending for longer than the accumulation timeout.
+        RemoveExpired,
+    }
+
+    fn arbitrary_op(elder_count: usize) -> impl Strategy<Value = Op> {
+        prop_oneof![
+            8 => (0..3u64, 0..elder_count, prop::bool::weighted(0.1))
+                .prop_map(|(vote, index, forged)| Op::Add { vote, index, forged }),
+            1 => Just(Op::RemoveExpired),
+        ]
+    }
+
+    // Threshold of the section key and the operations on the accumulator.
+    fn arbitrary_ops() -> impl Strategy<Value = (usize, Vec<Op>)> {
+        (1..=7usize)
+            .prop_flat_map(|elder_count| (0..elder_count, vec(arbitrary_op(elder_count), 0..100)))
+    }
+
+    proptest! {
+        // Apply arbitrary sequences of operations to the accumulator and check it against a model
+        // tracking the distinct valid shares of every vote since its last accumulation.
+        // NOTE: `seed` is for seeding the rng that generates the keys.
+        #[test]
+        fn proptest_accumulation(
+            (threshold, ops) in arbitrary_ops(),
+            seed in any::<u64>(),
+        ) {
+            proptest_accumulation_impl(threshold, ops, seed)
+        }
+    }
+
+    fn proptest_accumulation_impl(threshold: usize, ops: Vec<Op>, seed: u64) {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let sk_set = bls::SecretKeySet::random(threshold, &mut rng);
+        let forged_sk_set = bls::SecretKeySet::random(threshold, &mut rng);
+        let pk_set = sk_set.public_keys();
+
+        let mut accumulator = VoteAccumulator::default();
+        // Indices of the valid shares added since the last accumulation of each vote.
+        let mut shares: HashMap<u64, HashSet<usize>> = HashMap::new();
+        // Votes with shares added since they were last accumulated or expired.
+        let mut pending = HashSet::new();
+
+        for op in ops {
+            match op {
+                Op::Add {
+                    vote: key_index,
+                    index,
+                    forged,
+                } => {
+                    let vote = Vote::TheirKnowledge {
+                        prefix: Prefix::default(),
+                        key_index,
+                    };
+                    let bytes = bincode::serialize(&vote.as_signable()).unwrap();
+                    let signer = if forged { &forged_sk_set } else { &sk_set };
+                    let proof_share = vote
+                        .prove(pk_set.clone(), index, &signer.secret_key_share(index))
+                        .unwrap();
+
+                    let result = accumulator.add(vote.clone(), proof_share);
+
+                    if forged {
+                        assert!(
+                            matches!(
+                                result,
+                                Err(VoteAccumulationError::Aggregation(
+                                    bls_signature_aggregator::Error::InvalidShare
+                                ))
+                            ),
+                            "forged share not rejected: {:?}",
+                            result
+                        );
+                        continue;
+                    }
+
+                    let indices = shares.entry(key_index).or_default();
+                    let _ = indices.insert(index);
+
+                    if indices.len() > threshold {
+                        let (accumulated, proof) = result.expect("quorum not reported");
+                        assert_eq!(accumulated, vote);
+                        assert!(proof.verify(&bytes));
+                        assert_eq!(proof.public_key, pk_set.public_key());
+
+                        indices.clear();
+                        let _ = pending.remove(&key_index);
+                    } else {
+                        assert!(
+                            matches!(
+                                result,
+                                Err(VoteAccumulationError::Aggregation(
+                                    bls_signature_aggregator::Error::NotEnoughShares
+                                ))
+                            ),
+                            "quorum reported with {} of {} shares: {:?}",
+                            indices.len(),
+                            threshold + 1,
+                            result
+                        );
+
+                        let _ = pending.insert(key_index);
+                    }
+                }
+                Op::RemoveExpired => {
+                    // Nothing expires before the timeout.
+                    assert!(accumulator.remove_expired(Instant::now()).is_empty());
+
+                    let expired: HashSet<_> = accumulator
+                        .remove_expired(Instant::now() + ACCUMULATION_TIMEOUT)
+                        .into_iter()
+                        .map(|vote| match vote {
+                            Vote::TheirKnowledge { key_index, .. } => key_index,
+                            vote => panic!("unexpected vote {:?}", vote),
+                        })
+                        .collect();
+                    assert_eq!(expired, pending);
+                    pending.clear();
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,64 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::crypto;
+use hex_fmt::HexFmt;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug, Display, Formatter};
+
+/// Identifier attached to a user message so the responses to it can be paired with it, even when
+/// they come from a different section than the request was sent to.
+///
+/// The sender of a request generates a new id with `CorrelationId::random` and sends it along
+/// with the request using `Routing::send_message_with_correlation_id`. The responder sends its
+/// response with the id from the received `Event::MessageReceived`, which the sender then matches
+/// against its pending requests.
+///
+/// The id also identifies the message for deduplication at the destination: when the sender
+/// resends a request (e.g. after a timeout) with the same id and content, the destination raises
+/// `Event::MessageReceived` only for the first copy.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct CorrelationId([u8; 16]);
+
+impl CorrelationId {
+    /// Generates a new random id.
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// Derives the id from the given bytes, e.g. the serialized request, so the same logical
+    /// request always gets the same id, even when created again.
+    pub fn from_content(content: &[u8]) -> Self {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&crypto::sha3_256(content)[..16]);
+        Self(bytes)
+    }
+
+    /// Returns the raw bytes of this id.
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl From<[u8; 16]> for CorrelationId {
+    fn from(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Debug for CorrelationId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "CorrelationId({})", self)
+    }
+}
+
+impl Display for CorrelationId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:x}", HexFmt(&self.0))
+    }
+}
@@ -65,6 +65,23 @@ pub(crate) mod test_utils {
     use super::*;
     use ed25519_dalek::SECRET_KEY_LENGTH;
     use proptest::prelude::*;
+    use rand::{CryptoRng, RngCore};
+    use xor_name::Prefix;
+
+    /// Generate a keypair whose name matches `prefix`, drawn from `rng` so it's reproducible from
+    /// the seed of `rng`. Names can't be chosen, so this retries the generation until one matches,
+    /// on average `2^prefix.bit_count()` times. Use short prefixes only.
+    pub(crate) fn gen_keypair_within_prefix<R>(prefix: &Prefix, rng: &mut R) -> Keypair
+    where
+        R: CryptoRng + RngCore,
+    {
+        loop {
+            let keypair = Keypair::generate(rng);
+            if prefix.matches(&name(&keypair.public)) {
+                return keypair;
+            }
+        }
+    }
 
     pub(crate) fn arbitrary_keypair() -> impl Strategy<Value = Keypair> {
         any::<[u8; SECRET_KEY_LENGTH]>().prop_map(|bytes| {
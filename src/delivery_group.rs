@@ -12,9 +12,9 @@ use crate::{
     error::{Error, Result},
     majority,
     network::Network,
+    network_params::NetworkParams,
     peer::Peer,
     section::Section,
-    ELDER_SIZE,
 };
 use itertools::Itertools;
 use sn_messaging::DstLocation;
@@ -36,6 +36,7 @@ use xor_name::XorName;
 ///     - if the destination name is an entry in the routing table, returns it; otherwise
 ///     - returns the `N/3` closest members of the RT to the target
 pub(crate) fn delivery_targets(
+    network_params: &NetworkParams,
     dst: &DstLocation,
     our_name: &XorName,
     section: &Section,
@@ -51,11 +52,11 @@ pub(crate) fn delivery_targets(
 
     let (best_section, dg_size) = match dst {
         DstLocation::Section(target_name) => {
-            section_candidates(target_name, our_name, section, network)?
+            section_candidates(network_params, target_name, our_name, section, network)?
         }
         DstLocation::EndUser(user) => {
             let target_name = user.name();
-            section_candidates(&target_name, our_name, section, network)?
+            section_candidates(network_params, &target_name, our_name, section, network)?
         }
         DstLocation::Node(target_name) | DstLocation::AccumulatingNode(target_name) => {
             if target_name == our_name {
@@ -65,7 +66,7 @@ pub(crate) fn delivery_targets(
                 return Ok((vec![*node], 1));
             }
 
-            candidates(target_name, our_name, section, network)?
+            candidates(network_params, target_name, our_name, section, network)?
         }
         DstLocation::Direct => return Err(Error::CannotRoute),
     };
@@ -74,6 +75,7 @@ pub(crate) fn delivery_targets(
 }
 
 fn section_candidates(
+    network_params: &NetworkParams,
     target_name: &XorName,
     our_name: &XorName,
     section: &Section,
@@ -99,11 +101,12 @@ fn section_candidates(
         return Ok((section, dg_size));
     }
 
-    candidates(target_name, our_name, section, network)
+    candidates(network_params, target_name, our_name, section, network)
 }
 
 // Obtain the delivery group candidates for this target
 fn candidates(
+    network_params: &NetworkParams,
     target_name: &XorName,
     our_name: &XorName,
     section: &Section,
@@ -115,18 +118,18 @@ fn candidates(
         .sorted_by(|lhs, rhs| lhs.prefix.cmp_distance(&rhs.prefix, target_name))
         .map(|info| (&info.prefix, info.elders.len(), info.elders.values()));
 
-    let mut dg_size = majority(ELDER_SIZE);
+    let mut dg_size = majority(network_params.elder_size);
     let mut nodes_to_send = Vec::new();
     for (idx, (prefix, len, connected)) in sections.enumerate() {
         nodes_to_send.extend(connected.cloned());
         // If we don't have enough contacts send to as many as possible
         // up to majority of Elders
         dg_size = cmp::min(len, dg_size);
-        if len < majority(ELDER_SIZE) {
+        if len < majority(network_params.elder_size) {
             warn!(
                 "Delivery group only {:?} when it should be {:?}",
                 len,
-                majority(ELDER_SIZE)
+                majority(network_params.elder_size)
             )
         }
 
@@ -150,6 +153,89 @@ fn candidates(
     }
 }
 
+/// Extends the delivery group for a message for the section of `target_name`, as returned by
+/// `delivery_targets`, with the elders of the other sections next closest to the target, for the
+/// message to be sent over `routes` routes in total. Each added route starts at a different
+/// section, so the routes don't share their first hop. Returns the new targets, all of which are
+/// in the delivery group, and the delivery group size.
+pub(crate) fn add_redundant_routes(
+    network_params: &NetworkParams,
+    (targets, dg_size): (Vec<Peer>, usize),
+    target_name: &XorName,
+    routes: usize,
+    section: &Section,
+    network: &Network,
+) -> (Vec<Peer>, usize) {
+    let mut group: Vec<_> = targets.into_iter().take(dg_size).collect();
+
+    let other_sections = network
+        .all()
+        .filter(|info| info.prefix != *section.prefix())
+        .sorted_by(|lhs, rhs| lhs.prefix.cmp_distance(&rhs.prefix, target_name));
+
+    let mut route_count = 1;
+    for info in other_sections {
+        if route_count >= routes {
+            break;
+        }
+
+        // Skip the sections already on a route, e.g. the target section itself.
+        if info
+            .peers()
+            .any(|peer| group.iter().any(|other| other.name() == peer.name()))
+        {
+            continue;
+        }
+
+        let hops: Vec<_> = info
+            .peers()
+            .sorted_by(|lhs, rhs| target_name.cmp_distance(lhs.name(), rhs.name()))
+            .take(majority(network_params.elder_size))
+            .copied()
+            .collect();
+        group.extend(hops);
+        route_count += 1;
+    }
+
+    let dg_size = group.len();
+    (group, dg_size)
+}
+
+/// Returns an iterator over all the nodes we know (members of our section and elders of the other
+/// known sections) in the order of their distance to `target` (closest first).
+///
+/// The sections are visited in the order of their prefix distance to `target` and only the nodes
+/// of the section being currently visited are sorted, so taking only the first few nodes is cheap.
+pub(crate) fn closest_nodes_to<'a>(
+    target: &'a XorName,
+    section: &'a Section,
+    network: &'a Network,
+) -> impl Iterator<Item = &'a Peer> + 'a {
+    iter::once(section.prefix())
+        .chain(network.prefixes())
+        .sorted_by(|lhs, rhs| lhs.cmp_distance(rhs, target))
+        .flat_map(move |prefix| {
+            let peers: Vec<_> = if prefix == section.prefix() {
+                // Non-elders don't know the other members, so include the elders too.
+                section
+                    .active_members()
+                    .chain(section.elders_info().peers())
+                    .collect()
+            } else {
+                network
+                    .get(prefix)
+                    .into_iter()
+                    .flat_map(|info| info.peers())
+                    .collect()
+            };
+
+            peers
+                .into_iter()
+                .sorted_by(move |lhs, rhs| target.cmp_distance(lhs.name(), rhs.name()))
+        })
+        .unique_by(|peer| *peer.name())
+}
+
 // Returns a `Peer` for a known node.
 fn get_peer<'a>(name: &XorName, section: &'a Section, network: &'a Network) -> Option<&'a Peer> {
     section
@@ -161,7 +247,11 @@ fn get_peer<'a>(name: &XorName, section: &'a Section, network: &'a Network) -> O
 
 // Returns the set of peers that are responsible for collecting signatures to verify a message;
 // this may contain us or only other nodes.
-pub fn signature_targets<I>(dst: &DstLocation, our_elders: I) -> Vec<Peer>
+pub fn signature_targets<I>(
+    network_params: &NetworkParams,
+    dst: &DstLocation,
+    our_elders: I,
+) -> Vec<Peer>
 where
     I: IntoIterator<Item = Peer>,
 {
@@ -179,6 +269,6 @@ where
         .into_iter()
         .sorted_by(|lhs, rhs| dst_name.cmp_distance(lhs.name(), rhs.name()))
         .collect();
-    list.truncate(cmp::min(list.len(), majority(ELDER_SIZE)));
+    list.truncate(cmp::min(list.len(), majority(network_params.elder_size)));
     list
 }
@@ -6,6 +6,7 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+use crate::routing::TransportError;
 use thiserror::Error;
 
 /// The type returned by the sn_routing message handling methods.
@@ -21,6 +22,8 @@ pub enum Error {
     CannotRoute,
     #[error("Network layer error: {0}")]
     Network(#[from] qp2p::Error),
+    #[error("{0}")]
+    Transport(#[from] TransportError),
     #[error("The node is not in a state to handle the action.")]
     InvalidState,
     #[error("Bincode error: {0}")]
@@ -41,4 +44,28 @@ pub enum Error {
     InvalidVote,
     #[error("Messaging protocol error: {0}")]
     Messaging(#[from] sn_messaging::Error),
+    #[error("Storage error: {0}")]
+    Storage(std::io::Error),
+    #[error("Stored state is corrupted or invalid.")]
+    InvalidStoredState,
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Message is too large.")]
+    MessageTooLarge,
+    #[error("Incompatible protocol version: ours is {ours}, the network's is {theirs}.")]
+    IncompatibleProtocolVersion { ours: u16, theirs: u16 },
+    #[error("Failed to connect to any of the bootstrap contacts.")]
+    BootstrapFailed,
+    #[error("Failed to read the bootstrap contacts file: {0}")]
+    ContactsFile(std::io::Error),
+    #[error("Message trace error: {0}")]
+    MessageTrace(std::io::Error),
+    #[error("Failed to dump the node state: {0}")]
+    DumpState(std::io::Error),
+    #[cfg(feature = "metrics")]
+    #[error("Failed to serve metrics: {0}")]
+    Metrics(std::io::Error),
+    #[cfg(feature = "introspection")]
+    #[error("Failed to serve introspection: {0}")]
+    Introspection(std::io::Error),
 }
@@ -6,6 +6,7 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+use crate::{correlation_id::CorrelationId, section::NodeRole};
 use bytes::Bytes;
 use ed25519_dalek::Keypair;
 use hex_fmt::HexFmt;
@@ -39,7 +40,8 @@ pub enum NodeElderChange {
 /// `Request` and `Response` events from section locations are only raised once the majority has
 /// been reached, i.e. enough members of the section have sent the same message.
 pub enum Event {
-    /// Received a message.
+    /// Received a message. Raised once per message, even if its source sends it again, e.g. when
+    /// retrying it for lack of acknowledgement.
     MessageReceived {
         /// The content of the message.
         content: Bytes,
@@ -47,6 +49,54 @@ pub enum Event {
         src: SrcLocation,
         /// The destination location that receives the message.
         dst: DstLocation,
+        /// Correlation id the sender attached to the message, if any. Pass it to
+        /// `Routing::send_message_with_correlation_id` to send a response the sender can match.
+        correlation_id: Option<CorrelationId>,
+    },
+    /// Received a message sent to all the members of a prefix we belong to.
+    MulticastReceived {
+        /// The content of the message.
+        content: Bytes,
+        /// The source location that sent the message.
+        src: SrcLocation,
+        /// The prefix the message was sent to.
+        prefix: Prefix,
+    },
+    /// A message we sent with `Routing::send_message_to_closest` was delivered. Raised once per
+    /// message, or replaced by `MessageDeliveryFailed` if the recipient never reported it.
+    AnycastDelivered {
+        /// Name of the node the message was delivered to.
+        recipient: XorName,
+        /// Correlation id the message was sent with, if any.
+        correlation_id: Option<CorrelationId>,
+    },
+    /// Not enough members of the source section signed a message sent to us for accumulation at
+    /// destination, so it was discarded. Raised for every such message, whatever its kind. Votes of
+    /// our own section that fail to reach quorum are internal, so they are only logged and counted
+    /// in the `Stats`.
+    MessageAccumulationExpired {
+        /// The source location that sent the message.
+        src: SrcLocation,
+        /// The destination location of the message.
+        dst: DstLocation,
+        /// Correlation id the message was sent with, if any.
+        correlation_id: Option<CorrelationId>,
+    },
+    /// A message we sent with a correlation id was acknowledged by its destination.
+    MessageDelivered {
+        /// The destination location the message was sent to.
+        dst: DstLocation,
+        /// Correlation id the message was sent with.
+        correlation_id: CorrelationId,
+    },
+    /// A message we sent was not acknowledged by its destination, even after being resent.
+    MessageDeliveryFailed {
+        /// The content of the message.
+        content: Bytes,
+        /// The destination location the message was sent to.
+        dst: DstLocation,
+        /// Correlation id the message was sent with, if any.
+        correlation_id: Option<CorrelationId>,
     },
     /// The node has been promoted to adult
     PromotedToAdult,
@@ -68,6 +118,15 @@ pub enum Event {
         /// Age of the node
         age: u8,
     },
+    /// The role of a member of our section changed, e.g. an adult was promoted to elder.
+    MemberRoleChanged {
+        /// Name of the node
+        name: XorName,
+        /// Role of the node before the change
+        previous_role: NodeRole,
+        /// Role of the node after the change
+        role: NodeRole,
+    },
     /// The set of elders in our section has changed.
     EldersChanged {
         /// The prefix of our section.
@@ -107,17 +166,75 @@ pub enum Event {
     },
     /// Failed in sending a message to client, or connection to client is lost
     ClientLost(SocketAddr),
+    /// Messages from the client connected from the given address are being dropped, because it
+    /// exceeded its quota or all the clients together exceeded theirs. Raised once, until a
+    /// message from the client gets through again.
+    ClientThrottled(SocketAddr),
 }
 
 impl Debug for Event {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
         match self {
-            Self::MessageReceived { content, src, dst } => write!(
+            Self::MessageReceived {
+                content,
+                src,
+                dst,
+                correlation_id,
+            } => write!(
                 formatter,
-                "MessageReceived {{ content: \"{:<8}\", src: {:?}, dst: {:?} }}",
+                "MessageReceived {{ content: \"{:<8}\", src: {:?}, dst: {:?}, correlation_id: {:?} }}",
                 HexFmt(content),
                 src,
-                dst
+                dst,
+                correlation_id
+            ),
+            Self::MulticastReceived {
+                content,
+                src,
+                prefix,
+            } => write!(
+                formatter,
+                "MulticastReceived {{ content: \"{:<8}\", src: {:?}, prefix: {:?} }}",
+                HexFmt(content),
+                src,
+                prefix
+            ),
+            Self::AnycastDelivered {
+                recipient,
+                correlation_id,
+            } => formatter
+                .debug_struct("AnycastDelivered")
+                .field("recipient", recipient)
+                .field("correlation_id", correlation_id)
+                .finish(),
+            Self::MessageAccumulationExpired {
+                src,
+                dst,
+                correlation_id,
+            } => formatter
+                .debug_struct("MessageAccumulationExpired")
+                .field("src", src)
+                .field("dst", dst)
+                .field("correlation_id", correlation_id)
+                .finish(),
+            Self::MessageDelivered {
+                dst,
+                correlation_id,
+            } => formatter
+                .debug_struct("MessageDelivered")
+                .field("dst", dst)
+                .field("correlation_id", correlation_id)
+                .finish(),
+            Self::MessageDeliveryFailed {
+                content,
+                dst,
+                correlation_id,
+            } => write!(
+                formatter,
+                "MessageDeliveryFailed {{ content: \"{:<8}\", dst: {:?}, correlation_id: {:?} }}",
+                HexFmt(content),
+                dst,
+                correlation_id
             ),
             Self::PromotedToAdult => write!(formatter, "PromotedToAdult"),
             Self::MemberJoined {
@@ -137,6 +254,16 @@ impl Debug for Event {
                 .field("name", name)
                 .field("age", age)
                 .finish(),
+            Self::MemberRoleChanged {
+                name,
+                previous_role,
+                role,
+            } => formatter
+                .debug_struct("MemberRoleChanged")
+                .field("name", name)
+                .field("previous_role", previous_role)
+                .field("role", role)
+                .finish(),
             Self::EldersChanged {
                 prefix,
                 key,
@@ -170,6 +297,7 @@ impl Debug for Event {
                 msg, user,
             ),
             Self::ClientLost(addr) => write!(formatter, "ClientLost({:?})", addr),
+            Self::ClientThrottled(addr) => write!(formatter, "ClientThrottled({:?})", addr),
         }
     }
 }
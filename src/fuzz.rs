@@ -0,0 +1,131 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Entry points for fuzzing the handling of untrusted input, used by the targets in `fuzz/`. Only
+//! built with the `fuzzing` feature and not part of the public API.
+//!
+//! Each function runs arbitrary bytes through the same deserialization and validation as the
+//! bytes received from a peer. Any input is allowed to be rejected, but none must panic.
+
+use crate::{
+    messages::{Fragment, FragmentAssembler, Message, Variant},
+    section::SectionProofChain,
+};
+use bytes::Bytes;
+use sn_messaging::{node::NodeMessage, MessageType, SrcLocation, WireMsg};
+use std::iter;
+use xor_name::{Prefix, XorName, XOR_NAME_LEN};
+
+/// Runs `bytes` through the handling of everything received over the wire: the envelope, then the
+/// node message inside it, if any.
+pub fn wire_message(bytes: &[u8]) {
+    if let Ok(MessageType::NodeMessage(NodeMessage(msg_bytes))) =
+        WireMsg::deserialize(Bytes::copy_from_slice(bytes))
+    {
+        node_message(&msg_bytes)
+    }
+}
+
+/// Runs `bytes` through the deserialization and verification of a node message, including the
+/// reassembly of its content if it's a user message fragment.
+pub fn node_message(bytes: &[u8]) {
+    let _ = Message::hash_of(bytes);
+    let _ = Message::hop_count_of(bytes);
+
+    let msg = if let Ok(msg) = Message::from_bytes(Bytes::copy_from_slice(bytes)) {
+        msg
+    } else {
+        return;
+    };
+
+    // Verify against no trusted key and against all the keys of the message's own proof chain,
+    // to reach the variant-specific checks.
+    let prefix = Prefix::default();
+    let _ = msg.verify(iter::empty());
+    if let Ok(proof_chain) = msg.proof_chain() {
+        let _ = proof_chain.self_verify();
+        let _ = msg.verify(proof_chain.keys().map(|key| (&prefix, key)));
+    }
+
+    let _ = msg.to_relayed_bytes();
+
+    if let Variant::UserMessageFragment(fragment) = msg.variant() {
+        let _ = FragmentAssembler::new().add(src(false), fragment.clone());
+    }
+}
+
+/// Deserializes `bytes` into a sequence of user message fragments, each from one of two sources,
+/// and reassembles them.
+pub fn fragments(bytes: &[u8]) {
+    let fragments: Vec<(bool, Fragment)> = if let Ok(fragments) = bincode::deserialize(bytes) {
+        fragments
+    } else {
+        return;
+    };
+
+    let mut assembler = FragmentAssembler::new();
+    for (other_src, fragment) in fragments {
+        let _ = assembler.add(src(other_src), fragment);
+    }
+}
+
+/// Deserializes `bytes` into a section proof chain and verifies it.
+pub fn section_proof_chain(bytes: &[u8]) {
+    let chain: SectionProofChain = if let Ok(chain) = bincode::deserialize(bytes) {
+        chain
+    } else {
+        return;
+    };
+
+    if chain.self_verify() {
+        let _ = chain.check_trust(Some(chain.last_key()));
+        let _ = chain.slice(chain.last_key_index() / 2..).self_verify();
+    }
+}
+
+// Source of the fragments. Fixed, to keep the fuzzing deterministic.
+fn src(other: bool) -> SrcLocation {
+    SrcLocation::Node(XorName([u8::from(other); XOR_NAME_LEN]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto, node::Node, section::test_utils::gen_addr};
+    use anyhow::Result;
+    use sn_messaging::DstLocation;
+
+    #[test]
+    fn malformed_input() -> Result<()> {
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let variant = Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        };
+        let bytes = Message::single_src(&node, DstLocation::Direct, variant, None, None)?
+            .to_bytes()
+            .to_vec();
+
+        let mut inputs = vec![vec![], bytes.clone(), bytes[..bytes.len() / 2].to_vec()];
+        for index in 0..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[index] ^= 0xff;
+            inputs.push(flipped);
+        }
+
+        for input in &inputs {
+            wire_message(input);
+            node_message(input);
+            fragments(input);
+            section_proof_chain(input);
+        }
+
+        Ok(())
+    }
+}
@@ -71,32 +71,62 @@ extern crate tracing;
 // Public API
 // ############################################################################
 pub use self::{
+    correlation_id::CorrelationId,
     error::{Error, Result},
     event::{Event, NodeElderChange, SendStream},
-    routing::{Config, EventStream, Routing},
-    section::{SectionProofChain, MIN_AGE},
+    message_size_limits::MessageSizeLimits,
+    network::NetworkHealth,
+    network_params::NetworkParams,
+    routing::{
+        BlacklistConfig, BlacklistEntry, ChurnCounter, ChurnStats, ClientRateLimits, ClientUsage,
+        Config, ConnectionLimits, ContactSource, EventBufferConfig, EventOverflow, EventStream,
+        JoinProof, LatencyHistogram, MessageTrace, NodeBuilder, NoiseTransport, PeerBandwidth,
+        Quota, ResourceProofConfig, Routing, Stats, TraceDirection, TraceRecord, Traffic,
+        TrafficCategory, Transport, TransportError, TransportEvent, Violation, LATENCY_BUCKETS,
+    },
+    section::{Checkpoint, NodeRole, SectionProofChain, CHECKPOINT_INTERVAL, MIN_AGE},
+    xor_name_ext::XorNameExt,
 };
 pub use qp2p::Config as TransportConfig;
 
+// In-memory network and transport to test nodes without real connections.
+#[cfg(feature = "test-utils")]
+pub use self::routing::{
+    FilterAction, FilterId, Latency, LinkConfig, MemoryNetwork, MemoryTransport,
+};
+
 pub use xor_name::{Prefix, XorName, XOR_NAME_LEN}; // TODO remove pub on API update
 
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzz;
+
 // ############################################################################
 // Private
 // ############################################################################
 
+// Comes first, so its macros are visible in all the other modules.
+#[macro_use]
+mod log_limit;
+
 mod consensus;
+mod correlation_id;
 mod crypto;
 mod delivery_group;
 mod error;
 mod event;
 mod message_filter;
+mod message_size_limits;
 mod messages;
 mod network;
+mod network_params;
 mod node;
 mod peer;
+mod prefix_ext;
 mod relocation;
 mod routing;
 mod section;
+mod xor_name_ext;
 
 /// Recommended section size. sn_routing will keep adding nodes until the section reaches this size.
 /// More nodes might be added if requested by the upper layers.
@@ -0,0 +1,132 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Rate limiting of the log messages emitted on hot paths, e.g. for every message received, which
+//! during churn storms or attacks could otherwise flood the log.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Length of the window the number of log messages is limited within, in milliseconds.
+const WINDOW_MILLIS: u64 = 1000;
+/// Number of log messages emitted from a single call site within one window. The rest are
+/// suppressed and only counted.
+const MAX_PER_WINDOW: u64 = 5;
+
+/// Logs at the warn level, like `warn!`, but at most `MAX_PER_WINDOW` times per second from the
+/// same call site. The first message after some were suppressed reports how many.
+macro_rules! warn_limited {
+    ($($arg:tt)+) => {
+        log_limited!(warn, $($arg)+)
+    };
+}
+
+/// Logs at the error level, like `error!`, but rate limited as `warn_limited!`.
+macro_rules! error_limited {
+    ($($arg:tt)+) => {
+        log_limited!(error, $($arg)+)
+    };
+}
+
+macro_rules! log_limited {
+    ($level:ident, $($arg:tt)+) => {{
+        static LIMITER: $crate::log_limit::LogLimiter = $crate::log_limit::LogLimiter::new();
+        match LIMITER.check() {
+            Some(0) => $level!($($arg)+),
+            Some(suppressed) => $level!(
+                "{} ({} similar messages suppressed)",
+                format_args!($($arg)+),
+                suppressed
+            ),
+            None => (),
+        }
+    }};
+}
+
+/// Decides which log messages of a single call site to emit.
+pub(crate) struct LogLimiter {
+    // Start of the current window, in milliseconds since the UNIX epoch.
+    window_start: AtomicU64,
+    // Log messages within the current window.
+    count: AtomicU64,
+    // Log messages suppressed since the last one emitted.
+    suppressed: AtomicU64,
+}
+
+impl LogLimiter {
+    pub const fn new() -> Self {
+        Self {
+            window_start: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of log messages suppressed since the last one emitted if this one is to
+    /// be emitted, or `None` if it's to be suppressed.
+    pub fn check(&self) -> Option<u64> {
+        self.check_at(now_millis())
+    }
+
+    fn check_at(&self, now: u64) -> Option<u64> {
+        let start = self.window_start.load(Ordering::Relaxed);
+        if now.saturating_sub(start) >= WINDOW_MILLIS
+            && self
+                .window_start
+                .compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.count.store(0, Ordering::Relaxed);
+        }
+
+        if self.count.fetch_add(1, Ordering::Relaxed) < MAX_PER_WINDOW {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            let _ = self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppress_within_window() {
+        let limiter = LogLimiter::new();
+        let start = 1_000_000;
+
+        for _ in 0..MAX_PER_WINDOW {
+            assert_eq!(limiter.check_at(start), Some(0));
+        }
+        for offset in 0..3 {
+            assert_eq!(limiter.check_at(start + offset), None);
+        }
+
+        assert_eq!(limiter.check_at(start + WINDOW_MILLIS), Some(3));
+        assert_eq!(limiter.check_at(start + WINDOW_MILLIS), Some(0));
+    }
+
+    #[test]
+    fn macros() {
+        for index in 0..2 * MAX_PER_WINDOW {
+            warn_limited!("warning {}", index);
+            error_limited!("error {}", index);
+        }
+    }
+}
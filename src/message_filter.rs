@@ -6,15 +6,29 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use crate::messages::{Message, MessageHash};
+use crate::{
+    correlation_id::CorrelationId,
+    crypto::{self, Digest256},
+    messages::{Message, MessageHash, SrcAuthority, MAX_CLOCK_SKEW, MAX_NONCE_AGE},
+};
 use lru_time_cache::LruCache;
-use sn_messaging::DstLocation;
-use std::time::Duration;
+use sn_messaging::{DstLocation, SrcLocation};
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
 use xor_name::XorName;
 
 const INCOMING_EXPIRY_DURATION: Duration = Duration::from_secs(20 * 60);
 const OUTGOING_EXPIRY_DURATION: Duration = Duration::from_secs(10 * 60);
 const MAX_ENTRIES: usize = 5_000;
+// Every message signed by a single node is remembered, so allow for more of them. Handling on
+// average more than this many such messages per `NONCED_EXPIRY_DURATION` saturates the filter.
+const MAX_NONCED_ENTRIES: usize = 50_000;
+// A message handled with a nonce up to `MAX_CLOCK_SKEW` in the future stays fresh until
+// `MAX_NONCE_AGE + MAX_CLOCK_SKEW` past that.
+const NONCED_EXPIRY_DURATION: Duration =
+    Duration::from_secs(MAX_NONCE_AGE.as_secs() + 2 * MAX_CLOCK_SKEW.as_secs());
 
 /// An enum representing a result of message filtering
 #[derive(Eq, PartialEq)]
@@ -38,6 +52,15 @@ impl FilteringResult {
 pub(crate) struct MessageFilter {
     incoming: LruCache<MessageHash, ()>,
     outgoing: LruCache<(MessageHash, XorName), ()>,
+    // Handled messages signed by a single node, including direct ones. Remembered for as long as
+    // their nonce stays fresh, so they can't be replayed. Never evicted before that: once full,
+    // new such messages are rejected instead.
+    nonced: HashSet<MessageHash>,
+    // The same messages in the order they were handled, with the time they go stale at.
+    nonced_order: VecDeque<(Instant, MessageHash)>,
+    // Delivered user messages, by the digest of their source and correlation id and content, or
+    // of their source and routing-assigned id if sent without a correlation id.
+    user_messages: LruCache<Digest256, ()>,
 }
 
 impl MessageFilter {
@@ -51,15 +74,54 @@ impl MessageFilter {
                 OUTGOING_EXPIRY_DURATION,
                 MAX_ENTRIES,
             ),
+            nonced: HashSet::new(),
+            nonced_order: VecDeque::new(),
+            user_messages: LruCache::with_expiry_duration_and_capacity(
+                INCOMING_EXPIRY_DURATION,
+                MAX_ENTRIES,
+            ),
         }
     }
 
     pub fn contains_incoming(&self, msg: &Message) -> bool {
-        let hash = msg.hash();
+        self.contains_incoming_hash(msg.hash()) || self.nonced.contains(msg.hash())
+    }
+
+    // Check whether the message is signed by a single node, but has no nonce or its nonce is not
+    // fresh. Such message could still have just been delayed, or signed by a node whose clock is
+    // off by more than the tolerated skew, so it's not a proof of a replay.
+    pub fn has_stale_nonce(&self, msg: &Message) -> bool {
+        if let SrcAuthority::Node { .. } = msg.src() {
+            !matches!(msg.nonce(), Some(nonce) if nonce.is_fresh())
+        } else {
+            false
+        }
+    }
+
+    // Check whether the message with the given hash was already handled or relayed. Can be used
+    // before the message is deserialized and verified.
+    pub fn contains_incoming_hash(&self, hash: &MessageHash) -> bool {
         self.incoming.contains_key(hash)
     }
 
+    // Check whether the message carries a nonce, but we can't remember any more such messages
+    // until some of the remembered ones go stale. It must be rejected then, as it couldn't be told
+    // apart from its replays.
+    pub fn is_nonced_full(&mut self, msg: &Message) -> bool {
+        if msg.nonce().is_none() {
+            return false;
+        }
+
+        self.remove_expired_nonced();
+        self.nonced.len() >= MAX_NONCED_ENTRIES
+    }
+
     pub fn insert_incoming(&mut self, msg: &Message) {
+        if msg.nonce().is_some() && !self.is_nonced_full(msg) && self.nonced.insert(*msg.hash()) {
+            self.nonced_order
+                .push_back((Instant::now() + NONCED_EXPIRY_DURATION, *msg.hash()));
+        }
+
         // Not filtering direct messages.
         if let DstLocation::Direct = msg.dst() {
             return;
@@ -67,6 +129,33 @@ impl MessageFilter {
         let _ = self.incoming.insert(*msg.hash(), ());
     }
 
+    // Remember the delivery of a user message. Returns whether the same message wasn't delivered
+    // before: same source, correlation id and content if it has a correlation id, otherwise same
+    // source and routing-assigned id. Unlike the other filters, this one catches the copies the
+    // source sent again as new messages.
+    pub fn insert_user_message(
+        &mut self,
+        src: &SrcLocation,
+        id: u64,
+        correlation_id: Option<&CorrelationId>,
+        content: &[u8],
+    ) -> bool {
+        let serialized = if let Some(correlation_id) = correlation_id {
+            bincode::serialize(&(src, correlation_id)).map(|mut bytes| {
+                bytes.extend_from_slice(content);
+                bytes
+            })
+        } else {
+            bincode::serialize(&(src, id))
+        };
+        let digest = match serialized {
+            Ok(bytes) => crypto::sha3_256(&bytes),
+            Err(_) => return true,
+        };
+
+        self.user_messages.insert(digest, ()).is_none()
+    }
+
     // Filter outgoing `SNRoutingMessage`. Return whether this specific message has been seen recently
     // (and thus should not be sent, due to deduplication).
     //
@@ -83,7 +172,20 @@ impl MessageFilter {
         }
     }
 
-    // Resets both incoming and outgoing filters.
+    fn remove_expired_nonced(&mut self) {
+        let now = Instant::now();
+        while let Some(&(expiry, hash)) = self.nonced_order.front() {
+            if expiry > now {
+                break;
+            }
+
+            let _ = self.nonced.remove(&hash);
+            let _ = self.nonced_order.pop_front();
+        }
+    }
+
+    // Resets both incoming and outgoing filters. The handled nonced messages are kept, so resetting
+    // doesn't allow replaying them.
     pub fn reset(&mut self) {
         self.incoming.clear();
         self.outgoing.clear();
@@ -95,3 +197,94 @@ impl Default for MessageFilter {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto, messages::Variant, node::Node, section::test_utils::gen_addr};
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    #[test]
+    fn direct_message_replay() -> Result<()> {
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let variant = Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        };
+        let message = Message::single_src(&node, DstLocation::Direct, variant, None, None)?;
+
+        let mut filter = MessageFilter::new();
+        assert!(!filter.contains_incoming(&message));
+
+        filter.insert_incoming(&message);
+        assert!(filter.contains_incoming(&message));
+
+        // Still rejected after the filter is reset on section key change.
+        filter.reset();
+        assert!(filter.contains_incoming(&message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_nonced_when_full() -> Result<()> {
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let message = |dst| {
+            let variant = Variant::UserMessage {
+                id: rand::random(),
+                content: Bytes::from_static(b"hello"),
+                correlation_id: None,
+            };
+            Message::single_src(&node, dst, variant, None, None)
+        };
+
+        let mut filter = MessageFilter::new();
+        for index in 0..MAX_NONCED_ENTRIES {
+            let hash = MessageHash::from_bytes(&index.to_be_bytes());
+            assert!(filter.nonced.insert(hash));
+            filter
+                .nonced_order
+                .push_back((Instant::now() + NONCED_EXPIRY_DURATION, hash));
+        }
+
+        // Not remembered, so it must not be handled either.
+        let nonced = message(DstLocation::Direct)?;
+        assert!(filter.is_nonced_full(&nonced));
+        filter.insert_incoming(&nonced);
+        assert!(!filter.contains_incoming(&nonced));
+
+        // Accepted again once the remembered messages go stale.
+        let now = Instant::now();
+        for (expiry, _) in filter.nonced_order.iter_mut().take(MAX_NONCED_ENTRIES / 2) {
+            *expiry = now;
+        }
+        assert!(!filter.is_nonced_full(&nonced));
+        assert_eq!(filter.nonced.len(), MAX_NONCED_ENTRIES / 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_message_dedup() {
+        let mut filter = MessageFilter::new();
+        let src = SrcLocation::Node(rand::random());
+        let id = rand::random();
+
+        // Without a correlation id, the routing-assigned id tells the copies apart.
+        assert!(filter.insert_user_message(&src, id, None, b"hello"));
+        assert!(!filter.insert_user_message(&src, id, None, b"hello"));
+        assert!(filter.insert_user_message(&src, id.wrapping_add(1), None, b"hello"));
+
+        // With one, the copies sent again by the user are caught too, whatever their id.
+        let correlation_id = CorrelationId::random();
+        assert!(filter.insert_user_message(&src, id, Some(&correlation_id), b"hello"));
+        assert!(!filter.insert_user_message(
+            &src,
+            id.wrapping_add(2),
+            Some(&correlation_id),
+            b"hello"
+        ));
+    }
+}
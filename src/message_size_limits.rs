@@ -0,0 +1,49 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use sn_messaging::MessageType;
+
+/// Maximum sizes, in bytes, of the incoming messages of each type. Messages exceeding them are
+/// dropped without being deserialized and their sender is blocked for a while. Can differ between
+/// nodes, but must not be lower than what the other nodes send.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageSizeLimits {
+    /// Messages from other nodes. Must accommodate a whole user message fragment and the largest
+    /// section info (e.g. the `Sync` message).
+    pub node: usize,
+    /// Messages from clients.
+    pub client: usize,
+    /// Section info requests and responses exchanged with bootstrapping clients, and pings.
+    pub section_info: usize,
+}
+
+impl MessageSizeLimits {
+    /// The largest of the limits. Messages larger than that are dropped before even their type is
+    /// known.
+    pub fn max(&self) -> usize {
+        self.node.max(self.client).max(self.section_info)
+    }
+
+    pub(crate) fn for_message(&self, message: &MessageType) -> usize {
+        match message {
+            MessageType::NodeMessage(_) => self.node,
+            MessageType::ClientMessage(_) => self.client,
+            MessageType::Ping | MessageType::SectionInfo(_) => self.section_info,
+        }
+    }
+}
+
+impl Default for MessageSizeLimits {
+    fn default() -> Self {
+        Self {
+            node: 8 * 1024 * 1024,
+            client: 4 * 1024 * 1024,
+            section_info: 256 * 1024,
+        }
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{correlation_id::CorrelationId, crypto};
+use bytes::{Bytes, BytesMut};
+use hex_fmt::HexFmt;
+use serde::{Deserialize, Serialize};
+use sn_messaging::{DstLocation, SrcLocation};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt::{self, Debug, Formatter},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// User message content larger than this is split into multiple fragments.
+pub(crate) const MAX_FRAGMENT_SIZE: usize = 512 * 1024;
+/// Maximum number of fragments a single user message can be split into.
+pub(crate) const MAX_FRAGMENTS: usize = 128;
+/// Maximum total size of the fragments of incomplete messages we keep at any time.
+const MAX_BUFFERED_SIZE: usize = 128 * 1024 * 1024;
+/// Maximum total size of the fragments of incomplete messages from a single source we keep at any
+/// time. Enough for one message of the maximum size.
+const MAX_BUFFERED_SIZE_PER_SRC: usize = MAX_FRAGMENTS * MAX_FRAGMENT_SIZE;
+/// Incomplete messages whose first fragment is older than this are discarded.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// Part of a user message whose content is too large to be sent in a single message.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Fragment {
+    /// Id shared by all the fragments of the same message.
+    pub id: u64,
+    /// Position of this fragment within the message.
+    pub index: u32,
+    /// Total number of fragments of the message.
+    pub count: u32,
+    pub content: Bytes,
+    pub correlation_id: Option<CorrelationId>,
+}
+
+impl Fragment {
+    /// Splits `content` of the user message with the given id into fragments of at most
+    /// `MAX_FRAGMENT_SIZE` bytes, which all carry that id.
+    pub fn split(
+        id: u64,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+    ) -> Result<Vec<Self>, FragmentError> {
+        let count = (content.len() + MAX_FRAGMENT_SIZE - 1) / MAX_FRAGMENT_SIZE;
+        if count > MAX_FRAGMENTS {
+            return Err(FragmentError::TooLarge);
+        }
+
+        let fragments = (0..count)
+            .map(|index| {
+                let start = index * MAX_FRAGMENT_SIZE;
+                let end = (start + MAX_FRAGMENT_SIZE).min(content.len());
+                Self {
+                    id,
+                    index: index as u32,
+                    count: count as u32,
+                    content: content.slice(start..end),
+                    correlation_id,
+                }
+            })
+            .collect();
+
+        Ok(fragments)
+    }
+}
+
+/// Id of a user message derived from the hash of its destination, content and correlation id.
+/// All the elders sending the same message on behalf of their section derive the same id, so
+/// their messages (or fragments) are the same and accumulate.
+pub(crate) fn derived_user_message_id(
+    dst: &DstLocation,
+    content: &[u8],
+    correlation_id: Option<&CorrelationId>,
+) -> Result<u64, bincode::Error> {
+    let mut bytes = bincode::serialize(&(dst, correlation_id))?;
+    bytes.extend_from_slice(content);
+    let hash = crypto::sha3_256(&bytes);
+
+    let mut id = [0; 8];
+    id.copy_from_slice(&hash[..8]);
+    Ok(u64::from_be_bytes(id))
+}
+
+impl Debug for Fragment {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Fragment")
+            .field("id", &self.id)
+            .field("index", &self.index)
+            .field("count", &self.count)
+            .field("content", &format_args!("{:10}", HexFmt(&self.content)))
+            .field("correlation_id", &self.correlation_id)
+            .finish()
+    }
+}
+
+/// Collects received fragments until all the fragments of a message arrive.
+pub(crate) struct FragmentAssembler {
+    partials: HashMap<(SrcLocation, u64), Partial>,
+    buffered_size: usize,
+    // Total size of the buffered fragments by their source.
+    buffered_size_by_src: HashMap<SrcLocation, usize>,
+}
+
+impl FragmentAssembler {
+    pub fn new() -> Self {
+        Self {
+            partials: HashMap::new(),
+            buffered_size: 0,
+            buffered_size_by_src: HashMap::new(),
+        }
+    }
+
+    /// Adds a fragment of a message from `src`. Returns the whole content and the correlation id
+    /// of the message once all its fragments have been added.
+    pub fn add(
+        &mut self,
+        src: SrcLocation,
+        fragment: Fragment,
+    ) -> Result<Option<(Bytes, Option<CorrelationId>)>, FragmentError> {
+        if fragment.count == 0
+            || fragment.count as usize > MAX_FRAGMENTS
+            || fragment.index >= fragment.count
+            || fragment.content.len() > MAX_FRAGMENT_SIZE
+        {
+            return Err(FragmentError::Invalid);
+        }
+
+        self.remove_expired();
+
+        let src_buffered_size = self.buffered_size_by_src.get(&src).copied().unwrap_or(0);
+        if self.buffered_size + fragment.content.len() > MAX_BUFFERED_SIZE
+            || src_buffered_size + fragment.content.len() > MAX_BUFFERED_SIZE_PER_SRC
+        {
+            return Err(FragmentError::BufferFull);
+        }
+
+        let partial = self
+            .partials
+            .entry((src, fragment.id))
+            .or_insert_with(|| Partial {
+                started: Instant::now(),
+                correlation_id: fragment.correlation_id,
+                parts: vec![None; fragment.count as usize],
+                size: 0,
+            });
+
+        if partial.parts.len() != fragment.count as usize {
+            return Err(FragmentError::Invalid);
+        }
+
+        let part = &mut partial.parts[fragment.index as usize];
+        if part.is_some() {
+            // Duplicate fragment.
+            return Ok(None);
+        }
+
+        partial.size += fragment.content.len();
+        self.buffered_size += fragment.content.len();
+        *self.buffered_size_by_src.entry(src).or_insert(0) += fragment.content.len();
+        *part = Some(fragment.content);
+
+        if partial.parts.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+
+        let partial = if let Some(partial) = self.partials.remove(&(src, fragment.id)) {
+            partial
+        } else {
+            return Ok(None);
+        };
+        self.release(src, partial.size);
+
+        let mut content = BytesMut::with_capacity(partial.size);
+        for part in partial.parts.into_iter().flatten() {
+            content.extend_from_slice(&part);
+        }
+
+        Ok(Some((content.freeze(), partial.correlation_id)))
+    }
+
+    fn remove_expired(&mut self) {
+        let expired: Vec<_> = self
+            .partials
+            .iter()
+            .filter(|(_, partial)| partial.started.elapsed() >= REASSEMBLY_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in expired {
+            if let Some(partial) = self.partials.remove(&key) {
+                self.release(key.0, partial.size);
+            }
+        }
+    }
+
+    // Account for `size` bytes of fragments from `src` no longer being buffered.
+    fn release(&mut self, src: SrcLocation, size: usize) {
+        self.buffered_size -= size;
+
+        if let Entry::Occupied(mut entry) = self.buffered_size_by_src.entry(src) {
+            *entry.get_mut() -= size;
+            if *entry.get() == 0 {
+                let _ = entry.remove();
+            }
+        }
+    }
+}
+
+impl Default for FragmentAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Partial {
+    started: Instant,
+    correlation_id: Option<CorrelationId>,
+    parts: Vec<Option<Bytes>>,
+    size: usize,
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub(crate) enum FragmentError {
+    #[error("message is too large")]
+    TooLarge,
+    #[error("fragment is invalid")]
+    Invalid,
+    #[error("too many incomplete messages buffered")]
+    BufferFull,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{seq::SliceRandom, Rng};
+
+    #[test]
+    fn split_and_reassemble() {
+        let mut rng = rand::thread_rng();
+        let content: Vec<u8> = (0..3 * MAX_FRAGMENT_SIZE + 17).map(|_| rng.gen()).collect();
+        let content = Bytes::from(content);
+        let correlation_id = CorrelationId::random();
+
+        let mut fragments =
+            Fragment::split(rand::random(), content.clone(), Some(correlation_id)).unwrap();
+        assert_eq!(fragments.len(), 4);
+        fragments.shuffle(&mut rng);
+
+        let src = SrcLocation::Node(rand::random());
+        let mut assembler = FragmentAssembler::new();
+        let (last, rest) = fragments.split_last().unwrap();
+
+        for fragment in rest {
+            assert_eq!(assembler.add(src, fragment.clone()), Ok(None));
+            // Duplicates are ignored.
+            assert_eq!(assembler.add(src, fragment.clone()), Ok(None));
+        }
+
+        assert_eq!(
+            assembler.add(src, last.clone()),
+            Ok(Some((content, Some(correlation_id))))
+        );
+        assert_eq!(assembler.buffered_size, 0);
+        assert!(assembler.buffered_size_by_src.is_empty());
+        assert!(assembler.partials.is_empty());
+    }
+
+    #[test]
+    fn split_with_derived_id_is_deterministic() {
+        let content = Bytes::from(vec![7; 2 * MAX_FRAGMENT_SIZE]);
+        let correlation_id = Some(CorrelationId::random());
+        let dst = DstLocation::Section(rand::random());
+
+        // Every elder sending the same message creates the same fragments.
+        let split = |dst| {
+            let id = derived_user_message_id(&dst, &content, correlation_id.as_ref()).unwrap();
+            Fragment::split(id, content.clone(), correlation_id).unwrap()
+        };
+        let fragments = split(dst);
+        assert_eq!(split(dst), fragments);
+
+        // A different message gets a different id.
+        let other = split(DstLocation::Section(rand::random()));
+        assert_ne!(other[0].id, fragments[0].id);
+    }
+
+    #[test]
+    fn split_too_large() {
+        let content = Bytes::from(vec![0; MAX_FRAGMENTS * MAX_FRAGMENT_SIZE + 1]);
+        assert_eq!(
+            Fragment::split(rand::random(), content, None),
+            Err(FragmentError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn buffer_limited_per_src() {
+        let mut assembler = FragmentAssembler::new();
+        let greedy = SrcLocation::Node(rand::random());
+        let fragment = |id| Fragment {
+            id,
+            index: 0,
+            count: 2,
+            content: Bytes::from(vec![0; MAX_FRAGMENT_SIZE]),
+            correlation_id: None,
+        };
+
+        // Incomplete messages of a single source fill only its share of the buffer.
+        let per_src = MAX_BUFFERED_SIZE_PER_SRC / MAX_FRAGMENT_SIZE;
+        for id in 0..per_src as u64 {
+            assert_eq!(assembler.add(greedy, fragment(id)), Ok(None));
+        }
+        assert_eq!(
+            assembler.add(greedy, fragment(per_src as u64)),
+            Err(FragmentError::BufferFull)
+        );
+
+        // Other sources are not affected.
+        let other = SrcLocation::Node(rand::random());
+        assert_eq!(assembler.add(other, fragment(0)), Ok(None));
+    }
+
+    #[test]
+    fn reject_invalid_fragment() {
+        let mut assembler = FragmentAssembler::new();
+        let src = SrcLocation::Node(rand::random());
+        let fragment = Fragment {
+            id: 0,
+            index: 2,
+            count: 2,
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        };
+
+        assert_eq!(assembler.add(src, fragment), Err(FragmentError::Invalid));
+    }
+}
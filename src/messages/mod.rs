@@ -6,17 +6,24 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+mod fragment;
 mod hash;
+mod nonce;
 mod plain_message;
+mod priority;
 mod src_authority;
 mod variant;
 
-pub use self::{hash::MessageHash, src_authority::SrcAuthority};
 pub(crate) use self::{
+    fragment::{derived_user_message_id, Fragment, FragmentAssembler, MAX_FRAGMENT_SIZE},
+    nonce::{Nonce, MAX_CLOCK_SKEW, MAX_NONCE_AGE},
     plain_message::PlainMessage,
-    variant::{JoinRequest, ResourceProofResponse, Variant},
+    priority::Priority,
+    variant::{JoinRequest, Variant, PROTOCOL_VERSION},
 };
+pub use self::{hash::MessageHash, src_authority::SrcAuthority};
 use crate::{
+    correlation_id::CorrelationId,
     crypto::{self, name, Verifier},
     error::{Error, Result},
     node::Node,
@@ -33,6 +40,10 @@ use xor_name::{Prefix, XorName};
 /// Message sent over the network.
 #[derive(Clone, Eq, Serialize, Deserialize)]
 pub(crate) struct Message {
+    /// Number of times this message has been relayed. Not signed, as it changes on every hop. Must
+    /// stay the first field so it's serialized as the first byte, which can then be updated without
+    /// re-serializing the message and is excluded from the message hash.
+    hop_count: u8,
     /// Source authority.
     /// Messages do not need to sign this field as it is all verifiable (i.e. if the sig validates
     /// agains the public key and we know the pub key then we are good. If the proof is not recognised we
@@ -48,6 +59,12 @@ pub(crate) struct Message {
     /// to determine the length of the proof of messages sent to the source so the source would
     /// trust it (the proof needs to start at this key).
     dst_key: Option<bls::PublicKey>,
+    /// Protection against replays. Present in (and required for) messages signed by a single node
+    /// only, as the elders signing a section message must all sign the same bytes.
+    nonce: Option<Nonce>,
+    /// Whether every node relaying the message logs it, so its path can be reconstructed from the
+    /// logs of the network. Set by the source and signed, so it can't be changed on the way.
+    traced: bool,
     /// Serialised message, this is a signed and fully serialised message ready to send.
     #[serde(skip)]
     serialized: Bytes,
@@ -60,11 +77,7 @@ impl Message {
     pub(crate) fn from_bytes(msg_bytes: Bytes) -> Result<Self, CreateError> {
         let mut msg: Message = bincode::deserialize(&msg_bytes)?;
 
-        let signed_bytes = bincode::serialize(&SignableView {
-            dst: &msg.dst,
-            dst_key: msg.dst_key.as_ref(),
-            variant: &msg.variant,
-        })?;
+        let signed_bytes = bincode::serialize(&msg.signable_view())?;
 
         match &msg.src {
             SrcAuthority::Node {
@@ -73,13 +86,13 @@ impl Message {
                 ..
             } => {
                 if public_key.verify(&signed_bytes, signature).is_err() {
-                    error!("Failed signature: {:?}", msg);
+                    error_limited!("Failed signature: {:?}", msg);
                     return Err(CreateError::FailedSignature);
                 }
             }
             SrcAuthority::BlsShare { proof_share, .. } => {
                 if !proof_share.verify(&signed_bytes) {
-                    error!("Failed signature: {:?}", msg);
+                    error_limited!("Failed signature: {:?}", msg);
                     return Err(CreateError::FailedSignature);
                 }
             }
@@ -87,7 +100,7 @@ impl Message {
                 if let Some(proof_chain) = msg.proof_chain.as_ref() {
                     // FIXME Assumes the nodes proof last key is the one signing this message
                     if !proof_chain.last_key().verify(signature, &signed_bytes) {
-                        error!("Failed signature: {:?}", msg);
+                        error_limited!("Failed signature: {:?}", msg);
                         return Err(CreateError::FailedSignature);
                     }
                 }
@@ -95,16 +108,45 @@ impl Message {
         }
 
         msg.serialized = msg_bytes.clone();
-        msg.hash = MessageHash::from_bytes(&msg_bytes);
+        msg.hash = Self::hash_of(&msg_bytes);
 
         Ok(msg)
     }
 
+    /// Compute the hash of a serialized message. The hop count is excluded so the hash doesn't
+    /// change as the message is relayed.
+    pub(crate) fn hash_of(msg_bytes: &[u8]) -> MessageHash {
+        MessageHash::from_bytes(msg_bytes.get(1..).unwrap_or_default())
+    }
+
+    /// Hop count of a serialized message, without deserializing it.
+    pub(crate) fn hop_count_of(msg_bytes: &[u8]) -> u8 {
+        msg_bytes.first().copied().unwrap_or(0)
+    }
+
+    /// Name of the variant of a serialized message, without verifying the message. `None` if it
+    /// fails to deserialize.
+    pub(crate) fn variant_name_of(msg_bytes: &[u8]) -> Option<&'static str> {
+        bincode::deserialize::<Self>(msg_bytes)
+            .ok()
+            .map(|msg| msg.variant.name())
+    }
+
     /// send across wire
     pub(crate) fn to_bytes(&self) -> Bytes {
         self.serialized.clone()
     }
 
+    /// Serialized message with the hop count incremented, to relay the message to the next hop.
+    pub(crate) fn to_relayed_bytes(&self) -> Bytes {
+        let mut bytes = self.serialized.to_vec();
+        if let Some(hop_count) = bytes.first_mut() {
+            *hop_count = self.hop_count.saturating_add(1);
+        }
+
+        bytes.into()
+    }
+
     /// Creates a signed message where signature is assumed valid.
     fn new_signed(
         src: SrcAuthority,
@@ -112,19 +154,24 @@ impl Message {
         variant: Variant,
         proof_chain: Option<SectionProofChain>,
         dst_key: Option<bls::PublicKey>,
+        nonce: Option<Nonce>,
+        traced: bool,
     ) -> Result<Message, CreateError> {
         let mut msg = Message {
+            hop_count: 0,
             dst,
             src,
             proof_chain,
             variant,
             dst_key,
+            nonce,
+            traced,
             serialized: Default::default(),
             hash: Default::default(),
         };
 
         msg.serialized = bincode::serialize(&msg)?.into();
-        msg.hash = MessageHash::from_bytes(&msg.serialized);
+        msg.hash = Self::hash_of(&msg.serialized);
 
         Ok(msg)
     }
@@ -134,16 +181,24 @@ impl Message {
         node: &Node,
         key_share: &SectionKeyShare,
         dst_node_name: XorName,
+        id: u64,
         user_msg: Bytes,
+        correlation_id: Option<CorrelationId>,
         proof_chain: SectionProofChain,
         dst_key: Option<bls::PublicKey>,
     ) -> Result<Self, CreateError> {
         let dst = DstLocation::AccumulatingNode(dst_node_name);
-        let variant = Variant::UserMessage(user_msg);
+        let variant = Variant::UserMessage {
+            id,
+            content: user_msg,
+            correlation_id,
+        };
         let serialized = bincode::serialize(&SignableView {
             dst: &dst,
             dst_key: dst_key.as_ref(),
             variant: &variant,
+            nonce: None,
+            traced: false,
         })?;
         let signature_share = key_share.secret_key_share.sign(&serialized);
         let proof_share = ProofShare {
@@ -157,7 +212,7 @@ impl Message {
             age: node.age,
         };
 
-        Self::new_signed(src, dst, variant, Some(proof_chain), dst_key)
+        Self::new_signed(src, dst, variant, Some(proof_chain), dst_key, None, false)
     }
 
     pub(crate) fn signable_view(&self) -> SignableView {
@@ -165,6 +220,8 @@ impl Message {
             dst: &self.dst,
             dst_key: self.dst_key.as_ref(),
             variant: &self.variant,
+            nonce: self.nonce.as_ref(),
+            traced: self.traced,
         }
     }
 
@@ -175,11 +232,53 @@ impl Message {
         variant: Variant,
         proof_chain: Option<SectionProofChain>,
         dst_key: Option<bls::PublicKey>,
+    ) -> Result<Self, CreateError> {
+        Self::single_src_impl(
+            node,
+            dst,
+            variant,
+            proof_chain,
+            dst_key,
+            Nonce::new(),
+            false,
+        )
+    }
+
+    /// Creates a signed message from single node, traced across the hops it takes.
+    pub(crate) fn single_src_traced(
+        node: &Node,
+        dst: DstLocation,
+        variant: Variant,
+    ) -> Result<Self, CreateError> {
+        Self::single_src_impl(node, dst, variant, None, None, Nonce::new(), true)
+    }
+
+    /// Creates a signed message from single node with the given nonce, e.g. a stale one.
+    #[cfg(test)]
+    pub(crate) fn single_src_with_nonce(
+        node: &Node,
+        dst: DstLocation,
+        variant: Variant,
+        nonce: Nonce,
+    ) -> Result<Self, CreateError> {
+        Self::single_src_impl(node, dst, variant, None, None, nonce, false)
+    }
+
+    fn single_src_impl(
+        node: &Node,
+        dst: DstLocation,
+        variant: Variant,
+        proof_chain: Option<SectionProofChain>,
+        dst_key: Option<bls::PublicKey>,
+        nonce: Nonce,
+        traced: bool,
     ) -> Result<Self, CreateError> {
         let serialized = bincode::serialize(&SignableView {
             dst: &dst,
             dst_key: dst_key.as_ref(),
             variant: &variant,
+            nonce: Some(&nonce),
+            traced,
         })?;
         let signature = crypto::sign(&serialized, &node.keypair);
         let src = SrcAuthority::Node {
@@ -188,7 +287,7 @@ impl Message {
             signature,
         };
 
-        Self::new_signed(src, dst, variant, proof_chain, dst_key)
+        Self::new_signed(src, dst, variant, proof_chain, dst_key, Some(nonce), traced)
     }
 
     /// Creates a signed message from a section.
@@ -207,6 +306,8 @@ impl Message {
             plain.variant,
             Some(proof_chain),
             Some(plain.dst_key),
+            None,
+            false,
         )
     }
 
@@ -215,11 +316,7 @@ impl Message {
     where
         I: IntoIterator<Item = (&'a Prefix, &'a bls::PublicKey)>,
     {
-        let bytes = bincode::serialize(&SignableView {
-            dst: &self.dst,
-            dst_key: self.dst_key.as_ref(),
-            variant: &self.variant,
-        })?;
+        let bytes = bincode::serialize(&self.signable_view())?;
 
         match &self.src {
             SrcAuthority::Node {
@@ -292,6 +389,16 @@ impl Message {
         &self.variant
     }
 
+    /// Getter
+    pub fn hop_count(&self) -> u8 {
+        self.hop_count
+    }
+
+    /// Priority to send this message with.
+    pub fn priority(&self) -> Priority {
+        self.variant.priority()
+    }
+
     /// Getter
     pub fn src(&self) -> &SrcAuthority {
         &self.src
@@ -306,6 +413,16 @@ impl Message {
         &self.hash
     }
 
+    /// Getter
+    pub fn nonce(&self) -> Option<&Nonce> {
+        self.nonce.as_ref()
+    }
+
+    /// Getter
+    pub fn is_traced(&self) -> bool {
+        self.traced
+    }
+
     /// Returns the attached proof chain, if any.
     pub(crate) fn proof_chain(&self) -> Result<&SectionProofChain> {
         self.proof_chain.as_ref().ok_or(Error::InvalidMessage)
@@ -337,12 +454,14 @@ impl Message {
             self.variant,
             self.proof_chain,
             self.dst_key,
+            self.nonce,
+            self.traced,
         )?)
     }
 }
 
-// Ignore `serialized` and `hash` fields because they are only computed from the other fields and
-// in some cases might be even absent.
+// Ignore `hop_count` because it changes as the message is relayed, and `serialized` and `hash`
+// because they are only computed from the other fields and in some cases might be even absent.
 impl PartialEq for Message {
     fn eq(&self, other: &Self) -> bool {
         self.src == other.src
@@ -350,6 +469,8 @@ impl PartialEq for Message {
             && self.variant == other.variant
             && self.proof_chain == other.proof_chain
             && self.dst_key == other.dst_key
+            && self.nonce == other.nonce
+            && self.traced == other.traced
     }
 }
 
@@ -433,6 +554,8 @@ pub(crate) struct SignableView<'a> {
     pub dst: &'a DstLocation,
     pub dst_key: Option<&'a bls::PublicKey>,
     pub variant: &'a Variant,
+    pub nonce: Option<&'a Nonce>,
+    pub traced: bool,
 }
 
 #[cfg(test)]
@@ -445,6 +568,7 @@ mod tests {
         MIN_AGE,
     };
     use anyhow::Result;
+    use assert_matches::assert_matches;
     use std::iter;
 
     #[test]
@@ -498,4 +622,103 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn relayed_bytes() -> Result<()> {
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let variant = Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        };
+        let message = Message::single_src(&node, DstLocation::Direct, variant, None, None)?;
+        assert_eq!(message.hop_count(), 0);
+
+        let relayed = Message::from_bytes(message.to_relayed_bytes())?;
+        assert_eq!(relayed.hop_count(), 1);
+        assert_eq!(relayed, message);
+        assert_eq!(relayed.hash(), message.hash());
+
+        let relayed = Message::from_bytes(relayed.to_relayed_bytes())?;
+        assert_eq!(relayed.hop_count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn traced() -> Result<()> {
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let variant = Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        };
+
+        let message = Message::single_src(&node, DstLocation::Direct, variant.clone(), None, None)?;
+        assert!(!message.is_traced());
+
+        let message = Message::single_src_traced(&node, DstLocation::Direct, variant)?;
+        let relayed = Message::from_bytes(message.to_relayed_bytes())?;
+        assert!(relayed.is_traced());
+        assert_eq!(relayed.hash(), message.hash());
+
+        // The flag is signed, so it can't be cleared on the way. It's serialized last.
+        let mut bytes = message.to_bytes().to_vec();
+        if let Some(traced) = bytes.last_mut() {
+            *traced = 0;
+        }
+        assert_matches!(
+            Message::from_bytes(bytes.into()),
+            Err(CreateError::FailedSignature)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_variant() -> Result<()> {
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let variant = Variant::Unknown {
+            kind: 1000,
+            bytes: b"from the future".to_vec(),
+        };
+        let message = Message::single_src(&node, DstLocation::Direct, variant.clone(), None, None)?;
+
+        let received = Message::from_bytes(message.to_bytes())?;
+        assert_eq!(*received.variant(), variant);
+        assert_eq!(received.hash(), message.hash());
+
+        // The signature still verifies, so the message can be relayed untouched.
+        let relayed = Message::from_bytes(received.to_relayed_bytes())?;
+        assert_eq!(relayed, message);
+
+        Ok(())
+    }
+
+    #[test]
+    fn join_request_version_of_unknown_variant() -> Result<()> {
+        let variant = Variant::JoinRequest(Box::new(JoinRequest {
+            protocol_version: PROTOCOL_VERSION,
+            network_id: 0,
+            section_key: bls::SecretKey::random().public_key(),
+            relocate_payload: None,
+            join_proof_response: None,
+        }));
+        assert_eq!(variant.join_request_version(), Some(PROTOCOL_VERSION));
+
+        // Join request of another version, as received by this one. Skip the length prefix.
+        let bytes = bincode::serialize(&variant)?;
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&bytes[8..12]);
+        let mut bytes = bytes[12..].to_vec();
+        bytes[..2].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+
+        let variant = Variant::Unknown {
+            kind: u32::from_le_bytes(kind),
+            bytes,
+        };
+        assert_eq!(variant.join_request_version(), Some(PROTOCOL_VERSION + 1));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,93 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Messages whose nonce is older than this are rejected as possible replays.
+pub(crate) const MAX_NONCE_AGE: Duration = Duration::from_secs(5 * 60);
+/// Difference between the clocks of the signer and the receiver tolerated when checking the
+/// freshness of a nonce, the same in both directions: a nonce is still fresh when it's up to this
+/// much older than `MAX_NONCE_AGE`, or this much in the future.
+pub(crate) const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Signed creation time and random number of a message signed by a single node. Makes every such
+/// message unique and bounds the time it can be replayed for, so a receiver only needs to remember
+/// the messages it handled within the last `MAX_NONCE_AGE` (plus the clock skew).
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Debug)]
+pub(crate) struct Nonce {
+    /// Milliseconds since the UNIX epoch.
+    timestamp: u64,
+    random: u64,
+}
+
+impl Nonce {
+    pub fn new() -> Self {
+        Self {
+            timestamp: now_millis(),
+            random: rand::random(),
+        }
+    }
+
+    /// Nonce created the given time ago.
+    #[cfg(test)]
+    pub fn created_ago(age: Duration) -> Self {
+        Self {
+            timestamp: now_millis() - age.as_millis() as u64,
+            random: rand::random(),
+        }
+    }
+
+    /// Whether the nonce was created within `MAX_NONCE_AGE` before now, allowing for
+    /// `MAX_CLOCK_SKEW` either way.
+    pub fn is_fresh(&self) -> bool {
+        let now = now_millis();
+        let skew = MAX_CLOCK_SKEW.as_millis() as u64;
+        let max_age = MAX_NONCE_AGE.as_millis() as u64;
+
+        self.timestamp <= now.saturating_add(skew)
+            && self.timestamp >= now.saturating_sub(max_age + skew)
+    }
+}
+
+impl Default for Nonce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freshness() {
+        assert!(Nonce::new().is_fresh());
+
+        let max_age = MAX_NONCE_AGE.as_millis() as u64;
+        let skew = MAX_CLOCK_SKEW.as_millis() as u64;
+        let nonce = |timestamp| Nonce {
+            timestamp,
+            random: 0,
+        };
+
+        // Clock differences within the tolerated skew, either way.
+        assert!(nonce(now_millis() - max_age - skew / 2).is_fresh());
+        assert!(nonce(now_millis() + skew / 2).is_fresh());
+
+        assert!(!nonce(now_millis() - max_age - skew - 1000).is_fresh());
+        assert!(!nonce(now_millis() + skew + 1000).is_fresh());
+    }
+}
@@ -30,6 +30,8 @@ impl PlainMessage {
             dst: &self.dst,
             dst_key: Some(&self.dst_key),
             variant: &self.variant,
+            nonce: None,
+            traced: false,
         }
     }
 }
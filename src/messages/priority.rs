@@ -0,0 +1,52 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+/// Priority class of an outgoing message. When the outgoing sends are saturated, messages of
+/// higher priority classes get a larger share of the send slots.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub(crate) enum Priority {
+    /// Votes and DKG messages.
+    Consensus,
+    /// Joining, relocation and section state updates.
+    Membership,
+    /// Exchange of knowledge about other sections.
+    SectionKnowledge,
+    /// User and client messages.
+    UserData,
+}
+
+impl Priority {
+    /// All the priority classes, from the highest to the lowest.
+    pub const ALL: [Self; 4] = [
+        Self::Consensus,
+        Self::Membership,
+        Self::SectionKnowledge,
+        Self::UserData,
+    ];
+
+    /// Number of the pending sends of this class that can be resumed in one round of the
+    /// scheduler.
+    pub fn weight(self) -> usize {
+        match self {
+            Self::Consensus => 8,
+            Self::Membership => 4,
+            Self::SectionKnowledge => 2,
+            Self::UserData => 1,
+        }
+    }
+
+    /// Name of the class, as a label for statistics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Consensus => "Consensus",
+            Self::Membership => "Membership",
+            Self::SectionKnowledge => "SectionKnowledge",
+            Self::UserData => "UserData",
+        }
+    }
+}
@@ -86,6 +86,15 @@ impl SrcAuthority {
         }
     }
 
+    // If this location is `Node` or `BlsShare`, returns the public key of the node. Otherwise
+    // error.
+    pub(crate) fn public_key(&self) -> Result<&PublicKey> {
+        match self {
+            Self::Section { .. } => Err(Error::InvalidSrcLocation),
+            Self::Node { public_key, .. } | Self::BlsShare { public_key, .. } => Ok(public_key),
+        }
+    }
+
     // If this is `Section`, returns the prefix.
     pub(crate) fn as_section_prefix(&self) -> Result<&Prefix> {
         match self {
@@ -6,27 +6,36 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{Message, MessageHash, VerifyStatus};
+use super::{Fragment, Message, MessageHash, Priority, VerifyStatus};
 use crate::{
     consensus::{DkgFailureProof, DkgFailureProofSet, DkgKey, ProofShare, Proven, Vote},
+    correlation_id::CorrelationId,
     crypto::Signature,
     error::{Error, Result},
     network::Network,
+    peer::Peer,
     relocation::{RelocateDetails, RelocatePayload, RelocatePromise},
     section::{EldersInfo, MemberInfo, Section, SectionProofChain},
 };
 use bls_dkg::key_gen::message::Message as DkgMessage;
 use bytes::Bytes;
 use hex_fmt::HexFmt;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::VecDeque,
     fmt::{self, Debug, Formatter},
+    net::SocketAddr,
 };
+use xor_name::{Prefix, XorName};
 
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 #[allow(clippy::large_enum_variant)]
 /// Message variant
+///
+/// On the wire, the variant is a length-prefixed blob holding its kind (index) followed by its
+/// fields, so that messages with a variant added in a newer version can still be deserialized (as
+/// `Unknown`), relayed and dropped instead of failing the whole message. New variants must only
+/// ever be added at the end, just before `Unknown`.
 pub(crate) enum Variant {
     /// Inform neighbours about our new section.
     NeighbourInfo {
@@ -38,7 +47,39 @@ pub(crate) enum Variant {
         nonce: MessageHash,
     },
     /// User-facing message
-    UserMessage(Bytes),
+    UserMessage {
+        /// Id assigned by routing, kept when the message is sent again, so the destination
+        /// delivers it only once.
+        id: u64,
+        content: Bytes,
+        /// Id to pair the message with its request or responses, if any.
+        correlation_id: Option<CorrelationId>,
+    },
+    /// Part of a user-facing message too large to be sent as a single `UserMessage`.
+    UserMessageFragment(Fragment),
+    /// User-facing message for all the members of a prefix. Sent to each section overlapping the
+    /// prefix, whose elders then forward it to the adults within the prefix.
+    UserMessageMulticast { prefix: Prefix, content: Bytes },
+    /// User-facing message for the single member closest to the destination name. Sent to the
+    /// section of that name, whose elders then forward it to that member.
+    UserMessageAnycast {
+        /// Id assigned by routing, as for `UserMessage`.
+        id: u64,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+    },
+    /// Sent by the recipient of a `UserMessageAnycast` back to its source node to tell which node
+    /// it was delivered to. Acknowledges the message the same way `UserMessageAck` does.
+    AnycastDelivered {
+        /// Hash of the delivered message.
+        hash: MessageHash,
+    },
+    /// Sent by the destination of a `UserMessage` or `UserMessageFragment` back to its source node
+    /// to confirm it was received.
+    UserMessageAck {
+        /// Hash of the acknowledged message.
+        hash: MessageHash,
+    },
     /// Message sent to newly joined node containing the necessary info to become a member of our
     /// section.
     NodeApproval {
@@ -109,16 +150,161 @@ pub(crate) enum Variant {
         content: Vote,
         proof_share: ProofShare,
     },
-    /// Challenge sent from existing elder nodes to the joining peer for resource proofing.
+    /// Deprecated, replaced by `JoinChallenge`. Kept so the kinds of the following variants don't
+    /// change. Never sent and ignored when received.
     ResourceChallenge {
         data_size: usize,
         difficulty: u8,
         nonce: [u8; 32],
         nonce_signature: Signature,
     },
+    /// Request of a member to leave the section voluntarily, e.g. before a planned restart. The
+    /// elders vote it offline without waiting for its connection to be lost.
+    Leave,
+    /// Sent by an elder to the member that requested to leave, once the section agreed on it.
+    LeaveAck,
+    /// Response to a `JoinRequest` with a protocol version different from ours. Contains our
+    /// version.
+    IncompatibleProtocolVersion { version: u16 },
+    /// Request to an elder to introduce us to a member of our section we can't connect to, so
+    /// we can punch a hole through the NATs between us.
+    ConnectRequest { target: XorName },
+    /// Sent by an elder to both the sender and the target of a `ConnectRequest`, with the
+    /// address it sees the other one at. Both then connect to each other at the same time.
+    ConnectIntroduction { peer: Peer },
+    /// Message to be forwarded by an elder to the member at `dst`, because punching a hole to it
+    /// failed. Contains the serialized `Message`.
+    Relay { dst: SocketAddr, message: Bytes },
+    /// Challenge sent from existing elder nodes to the joining peer, created by their
+    /// `JoinProof`.
+    JoinChallenge { challenge: Bytes },
+    /// Keepalive probe. The recipient replies with `ProbeAck` carrying the same nonce, so the
+    /// sender can measure the round-trip time.
+    Probe { nonce: u64 },
+    /// Reply to a `Probe`.
+    ProbeAck { nonce: u64 },
+    /// Variant this node doesn't know, e.g. because it was added in a newer version. Never
+    /// created locally, only when deserializing. Serializes back to the same bytes, so the message
+    /// signature still verifies.
+    #[serde(skip)]
+    Unknown { kind: u32, bytes: Vec<u8> },
+}
+
+impl Serialize for Variant {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = if let Self::Unknown { kind, bytes } = self {
+            kind.to_le_bytes().iter().chain(bytes).copied().collect()
+        } else {
+            bincode::serialize(&Known(self)).map_err(S::Error::custom)?
+        };
+
+        bytes.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        if let Ok(KnownOwned(variant)) = bincode::deserialize(&bytes) {
+            return Ok(variant);
+        }
+
+        if bytes.len() < KIND_SIZE {
+            return Err(D::Error::custom("variant too short"));
+        }
+
+        let (kind, bytes) = bytes.split_at(KIND_SIZE);
+        let mut kind_bytes = [0; KIND_SIZE];
+        kind_bytes.copy_from_slice(kind);
+
+        Ok(Self::Unknown {
+            kind: u32::from_le_bytes(kind_bytes),
+            bytes: bytes.to_vec(),
+        })
+    }
+}
+
+// Size of the variant index as encoded by bincode.
+const KIND_SIZE: usize = 4;
+
+/// Version of the messaging protocol. Must be increased on every change of the messages which is
+/// not backwards compatible.
+pub(crate) const PROTOCOL_VERSION: u16 = 5;
+
+// Kind of every known variant on the wire, in the order they are declared in `Variant`. Must be
+// kept in sync with it. The kinds needed by the code are taken from here instead of hard-coded.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+enum Kind {
+    NeighbourInfo,
+    UserMessage,
+    UserMessageFragment,
+    UserMessageMulticast,
+    UserMessageAnycast,
+    AnycastDelivered,
+    UserMessageAck,
+    NodeApproval,
+    Sync,
+    Relocate,
+    RelocatePromise,
+    JoinRequest,
+    JoinRetry,
+    BouncedUntrustedMessage,
+    BouncedUnknownMessage,
+    DKGStart,
+    DKGMessage,
+    DKGFailureObservation,
+    DKGFailureAgreement,
+    Vote,
+    ResourceChallenge,
+    Leave,
+    LeaveAck,
+    IncompatibleProtocolVersion,
+    ConnectRequest,
+    ConnectIntroduction,
+    Relay,
+    JoinChallenge,
+    Probe,
+    ProbeAck,
+}
+
+// Kind of `JoinRequest`, so the protocol version of the join requests we can't deserialize can
+// still be read.
+const JOIN_REQUEST_KIND: u32 = Kind::JoinRequest as u32;
+
+// Wrappers to (de)serialize the known variants using the derived implementation.
+struct Known<'a>(&'a Variant);
+
+impl<'a> Serialize for Known<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Variant::serialize(self.0, serializer)
+    }
+}
+
+struct KnownOwned(Variant);
+
+impl<'de> Deserialize<'de> for KnownOwned {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Variant::deserialize(deserializer).map(Self)
+    }
 }
 
 impl Variant {
+    /// Protocol version of the joining peer, if this is a join request. Also works for the join
+    /// requests of other versions that deserialize as `Unknown`, as the version is always the
+    /// first field of `JoinRequest`.
+    pub(crate) fn join_request_version(&self) -> Option<u16> {
+        match self {
+            Self::JoinRequest(req) => Some(req.protocol_version),
+            Self::Unknown {
+                kind: JOIN_REQUEST_KIND,
+                bytes,
+            } if bytes.len() >= 2 => Some(u16::from_le_bytes([bytes[0], bytes[1]])),
+            _ => None,
+        }
+    }
+
     pub(crate) fn verify<'a, I>(
         &self,
         proof_chain: Option<&SectionProofChain>,
@@ -157,6 +343,79 @@ impl Variant {
             _ => Ok(VerifyStatus::Full),
         }
     }
+
+    pub(crate) fn priority(&self) -> Priority {
+        match self {
+            Self::Vote { .. }
+            | Self::DKGStart { .. }
+            | Self::DKGMessage { .. }
+            | Self::DKGFailureObservation { .. }
+            | Self::DKGFailureAgreement { .. } => Priority::Consensus,
+            Self::NodeApproval { .. }
+            | Self::Sync { .. }
+            | Self::Relocate(_)
+            | Self::RelocatePromise(_)
+            | Self::JoinRequest(_)
+            | Self::JoinRetry { .. }
+            | Self::JoinChallenge { .. }
+            | Self::ResourceChallenge { .. }
+            | Self::Probe { .. }
+            | Self::ProbeAck { .. }
+            | Self::Leave
+            | Self::LeaveAck
+            | Self::IncompatibleProtocolVersion { .. }
+            | Self::ConnectRequest { .. }
+            | Self::ConnectIntroduction { .. }
+            | Self::Relay { .. } => Priority::Membership,
+            Self::NeighbourInfo { .. }
+            | Self::BouncedUntrustedMessage(_)
+            | Self::BouncedUnknownMessage { .. } => Priority::SectionKnowledge,
+            Self::UserMessage { .. }
+            | Self::UserMessageFragment(_)
+            | Self::UserMessageMulticast { .. }
+            | Self::UserMessageAnycast { .. }
+            | Self::AnycastDelivered { .. }
+            | Self::UserMessageAck { .. }
+            | Self::Unknown { .. } => Priority::UserData,
+        }
+    }
+
+    /// Name of the variant, as a label for statistics.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::NeighbourInfo { .. } => "NeighbourInfo",
+            Self::UserMessage { .. } => "UserMessage",
+            Self::UserMessageFragment(_) => "UserMessageFragment",
+            Self::UserMessageMulticast { .. } => "UserMessageMulticast",
+            Self::UserMessageAnycast { .. } => "UserMessageAnycast",
+            Self::AnycastDelivered { .. } => "AnycastDelivered",
+            Self::UserMessageAck { .. } => "UserMessageAck",
+            Self::NodeApproval { .. } => "NodeApproval",
+            Self::Sync { .. } => "Sync",
+            Self::Relocate(_) => "Relocate",
+            Self::RelocatePromise(_) => "RelocatePromise",
+            Self::JoinRequest(_) => "JoinRequest",
+            Self::JoinRetry { .. } => "JoinRetry",
+            Self::BouncedUntrustedMessage(_) => "BouncedUntrustedMessage",
+            Self::BouncedUnknownMessage { .. } => "BouncedUnknownMessage",
+            Self::DKGStart { .. } => "DKGStart",
+            Self::DKGMessage { .. } => "DKGMessage",
+            Self::DKGFailureObservation { .. } => "DKGFailureObservation",
+            Self::DKGFailureAgreement { .. } => "DKGFailureAgreement",
+            Self::Vote { .. } => "Vote",
+            Self::ResourceChallenge { .. } => "ResourceChallenge",
+            Self::Leave => "Leave",
+            Self::LeaveAck => "LeaveAck",
+            Self::IncompatibleProtocolVersion { .. } => "IncompatibleProtocolVersion",
+            Self::ConnectRequest { .. } => "ConnectRequest",
+            Self::ConnectIntroduction { .. } => "ConnectIntroduction",
+            Self::Relay { .. } => "Relay",
+            Self::JoinChallenge { .. } => "JoinChallenge",
+            Self::Probe { .. } => "Probe",
+            Self::ProbeAck { .. } => "ProbeAck",
+            Self::Unknown { .. } => "Unknown",
+        }
+    }
 }
 
 impl Debug for Variant {
@@ -167,7 +426,45 @@ impl Debug for Variant {
                 .field("elders_info", elders_info)
                 .field("nonce", nonce)
                 .finish(),
-            Self::UserMessage(payload) => write!(f, "UserMessage({:10})", HexFmt(payload)),
+            Self::UserMessage {
+                id,
+                content,
+                correlation_id,
+            } => f
+                .debug_struct("UserMessage")
+                .field("id", id)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .field("correlation_id", correlation_id)
+                .finish(),
+            Self::UserMessageFragment(fragment) => fragment.fmt(f),
+            Self::UserMessageMulticast { prefix, content } => f
+                .debug_struct("UserMessageMulticast")
+                .field("prefix", prefix)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .finish(),
+            Self::UserMessageAnycast {
+                id,
+                content,
+                correlation_id,
+            } => f
+                .debug_struct("UserMessageAnycast")
+                .field("id", id)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .field("correlation_id", correlation_id)
+                .finish(),
+            Self::AnycastDelivered { hash } => f
+                .debug_struct("AnycastDelivered")
+                .field("hash", hash)
+                .finish(),
+            Self::Unknown { kind, bytes } => f
+                .debug_struct("Unknown")
+                .field("kind", kind)
+                .field("bytes", &format_args!("{:10}", HexFmt(bytes)))
+                .finish(),
+            Self::UserMessageAck { hash } => f
+                .debug_struct("UserMessageAck")
+                .field("hash", hash)
+                .finish(),
             Self::NodeApproval {
                 elders_info,
                 member_info,
@@ -199,7 +496,7 @@ impl Debug for Variant {
             Self::BouncedUnknownMessage { src_key, message } => f
                 .debug_struct("BouncedUnknownMessage")
                 .field("src_key", src_key)
-                .field("message_hash", &MessageHash::from_bytes(message))
+                .field("message_hash", &Message::hash_of(message))
                 .finish(),
             Self::DKGStart {
                 dkg_key,
@@ -246,34 +543,56 @@ impl Debug for Variant {
                 .field("data_size", data_size)
                 .field("difficulty", difficulty)
                 .finish(),
+            Self::Leave => write!(f, "Leave"),
+            Self::LeaveAck => write!(f, "LeaveAck"),
+            Self::IncompatibleProtocolVersion { version } => f
+                .debug_struct("IncompatibleProtocolVersion")
+                .field("version", version)
+                .finish(),
+            Self::ConnectRequest { target } => f
+                .debug_struct("ConnectRequest")
+                .field("target", target)
+                .finish(),
+            Self::ConnectIntroduction { peer } => f
+                .debug_struct("ConnectIntroduction")
+                .field("peer", peer)
+                .finish(),
+            Self::Relay { dst, message } => f
+                .debug_struct("Relay")
+                .field("dst", dst)
+                .field("message", &format_args!("{:10}", HexFmt(message)))
+                .finish(),
+            Self::JoinChallenge { challenge } => f
+                .debug_struct("JoinChallenge")
+                .field("challenge", &format_args!("{:10}", HexFmt(challenge)))
+                .finish(),
+            Self::Probe { nonce } => f.debug_struct("Probe").field("nonce", nonce).finish(),
+            Self::ProbeAck { nonce } => f.debug_struct("ProbeAck").field("nonce", nonce).finish(),
         }
     }
 }
 
-/// Joining peer's proof of resolvement of given resource proofing challenge.
-#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub(crate) struct ResourceProofResponse {
-    pub(crate) solution: u64,
-    pub(crate) data: VecDeque<u8>,
-    pub(crate) nonce: [u8; 32],
-    pub(crate) nonce_signature: Signature,
-}
-
 /// Request to join a section
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub(crate) struct JoinRequest {
+    /// Protocol version of the peer. Must stay the first field, see `join_request_version`.
+    pub protocol_version: u16,
+    /// Identifier of the network the peer wants to join.
+    pub network_id: u64,
     /// The public key of the section to join.
     pub section_key: bls::PublicKey,
     /// If the peer is being relocated, contains `RelocatePayload`. Otherwise contains `None`.
     pub relocate_payload: Option<RelocatePayload>,
-    /// Proof of the resouce proofing.
-    pub resource_proof_response: Option<ResourceProofResponse>,
+    /// Response to the `JoinChallenge` of the elder this request is sent to.
+    pub join_proof_response: Option<Bytes>,
 }
 
 impl Debug for JoinRequest {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
         formatter
             .debug_struct("JoinRequest")
+            .field("protocol_version", &self.protocol_version)
+            .field("network_id", &self.network_id)
             .field("section_key", &self.section_key)
             .field(
                 "relocate_payload",
@@ -283,12 +602,65 @@ impl Debug for JoinRequest {
                     .map(|payload| payload.relocate_details()),
             )
             .field(
-                "resource_proof_response",
+                "join_proof_response",
                 &self
-                    .resource_proof_response
+                    .join_proof_response
                     .as_ref()
-                    .map(|proof| proof.solution),
+                    .map(|response| format!("{:10}", HexFmt(response))),
             )
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::convert::TryInto;
+
+    #[test]
+    fn kinds_match_declaration_order() -> Result<()> {
+        let variants = vec![
+            (
+                Variant::UserMessage {
+                    id: rand::random(),
+                    content: Bytes::from_static(b"hello"),
+                    correlation_id: None,
+                },
+                Kind::UserMessage,
+            ),
+            (
+                Variant::JoinRequest(Box::new(JoinRequest {
+                    protocol_version: PROTOCOL_VERSION,
+                    network_id: 0,
+                    section_key: bls::SecretKey::random().public_key(),
+                    relocate_payload: None,
+                    join_proof_response: None,
+                })),
+                Kind::JoinRequest,
+            ),
+            (Variant::Leave, Kind::Leave),
+            (
+                Variant::ConnectRequest {
+                    target: rand::random(),
+                },
+                Kind::ConnectRequest,
+            ),
+            (
+                Variant::JoinChallenge {
+                    challenge: Bytes::from_static(b"challenge"),
+                },
+                Kind::JoinChallenge,
+            ),
+        ];
+
+        for (variant, kind) in variants {
+            // Skip the length prefix.
+            let bytes = bincode::serialize(&variant)?;
+            let actual = u32::from_le_bytes(bytes[8..12].try_into()?);
+            assert_eq!(actual, kind as u32, "{:?}", kind);
+        }
+
+        Ok(())
+    }
+}
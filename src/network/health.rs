@@ -0,0 +1,31 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use xor_name::Prefix;
+
+/// Report on the completeness of our knowledge of the network.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetworkHealth {
+    /// Parts of our neighbourhood not covered by any section we know of.
+    pub missing_neighbours: Vec<Prefix>,
+    /// Known sections (including ours) that have fewer elders than `NetworkParams::elder_size`.
+    pub small_sections: Vec<Prefix>,
+    /// Known sections whose section key we don't know, so we can't verify messages from them.
+    pub unreachable_sections: Vec<Prefix>,
+    /// Whether our section has enough mature members to split.
+    pub split_expected: bool,
+}
+
+impl NetworkHealth {
+    /// Returns whether no problem was detected.
+    pub fn is_healthy(&self) -> bool {
+        self.missing_neighbours.is_empty()
+            && self.small_sections.is_empty()
+            && self.unreachable_sections.is_empty()
+    }
+}
@@ -6,13 +6,17 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+mod health;
 mod prefix_map;
 mod stats;
 
+pub use self::health::NetworkHealth;
 use self::{prefix_map::PrefixMap, stats::NetworkStats};
 use crate::{
     consensus::Proven,
+    network_params::NetworkParams,
     peer::Peer,
+    prefix_ext::PrefixExt,
     section::{EldersInfo, SectionProofChain},
 };
 
@@ -154,6 +158,24 @@ impl Network {
         }
     }
 
+    /// Panics if any of the network invariants is violated.
+    #[cfg(feature = "debug-invariants")]
+    pub fn assert_invariants(&self, our_prefix: &Prefix) {
+        for info in self.neighbours.iter() {
+            assert!(
+                !info.value.prefix.is_compatible(our_prefix),
+                "neighbour ({:b}) overlaps our section ({:b})",
+                info.value.prefix,
+                our_prefix
+            );
+            assert!(
+                info.self_verify(),
+                "elders info of neighbour ({:b}) is not valid",
+                info.value.prefix
+            );
+        }
+    }
+
     /// Returns the known section keys.
     pub fn keys(&self) -> impl Iterator<Item = (&Prefix, &bls::PublicKey)> {
         self.keys
@@ -249,6 +271,42 @@ impl Network {
         }
     }
 
+    /// Returns report on the completeness of our knowledge of the network.
+    pub fn health(
+        &self,
+        network_params: &NetworkParams,
+        our: &EldersInfo,
+        split_expected: bool,
+    ) -> NetworkHealth {
+        // Our neighbourhood is partitioned by the siblings of our prefix and of all its ancestors.
+        let missing_neighbours = our
+            .prefix
+            .self_and_ancestors()
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| prefix.sibling())
+            .filter(|region| !self.prefixes().any(|prefix| prefix.is_compatible(region)))
+            .collect();
+
+        let small_sections = iter::once(our)
+            .chain(self.all())
+            .filter(|info| info.elders.len() < network_params.elder_size)
+            .map(|info| info.prefix)
+            .collect();
+
+        let unreachable_sections = self
+            .prefixes()
+            .filter(|prefix| self.key_by_prefix(prefix).is_none())
+            .copied()
+            .collect();
+
+        NetworkHealth {
+            missing_neighbours,
+            small_sections,
+            unreachable_sections,
+            split_expected,
+        }
+    }
+
     // Compute an estimate of the total number of elders in the network from the size of our
     // routing table.
     //
@@ -310,10 +368,7 @@ where
             return false;
         }
 
-        let child0 = other.pushed(false);
-        let child1 = other.pushed(true);
-
-        check(our, &child0, known) && check(our, &child1, known)
+        other.children().all(|child| check(our, &child, known))
     }
 
     check(our, other, &known)
@@ -479,6 +534,35 @@ mod tests {
         assert!(map.prefixes().all(|&prefix| prefix != p1));
     }
 
+    #[test]
+    fn health() {
+        let sk = bls::SecretKey::random();
+
+        let p00: Prefix = "00".parse().unwrap();
+        let p01: Prefix = "01".parse().unwrap();
+        let p1: Prefix = "1".parse().unwrap();
+
+        let our_info = gen_proven_elders_info(&sk, p00).value;
+        let mut map = Network::new();
+        let _ = map.update_neighbour_info(gen_proven_elders_info(&sk, p01));
+
+        let health = map.health(&NetworkParams::default(), &our_info, false);
+        assert_eq!(health.missing_neighbours, vec![p1]);
+        assert!(health.small_sections.is_empty());
+        assert_eq!(health.unreachable_sections, vec![p01]);
+        assert!(!health.is_healthy());
+
+        let _ = map.update_neighbour_info(gen_proven_elders_info(&sk, p1));
+        for prefix in &[p01, p1] {
+            let proven = consensus::test_utils::proven(&sk, (*prefix, gen_key())).unwrap();
+            let _ = map.update_their_key(proven);
+        }
+
+        assert!(map
+            .health(&NetworkParams::default(), &our_info, false)
+            .is_healthy());
+    }
+
     // Create a `Network` and apply a series of `update_keys` calls to it, then verify the stored
     // keys are as expected.
     //
@@ -6,6 +6,7 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+use crate::prefix_ext::PrefixExt;
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Borrow,
@@ -58,9 +59,9 @@ where
             return Some(entry);
         }
 
-        let parent_prefix = entry.borrow().popped();
+        let ancestors = entry.borrow().ancestors();
         let old = self.0.replace(Entry(entry));
-        self.prune(parent_prefix);
+        self.prune(ancestors);
         old.map(|entry| entry.0)
     }
 
@@ -77,18 +78,9 @@ where
     /// Get the entry at `prefix` or any of its ancestors. In case of multiple matches, returns the
     /// one with the longest prefix.
     pub fn get_equal_or_ancestor(&self, prefix: &Prefix) -> Option<&T> {
-        let mut prefix = *prefix;
-        loop {
-            if let Some(entry) = self.get(&prefix) {
-                return Some(entry);
-            }
-
-            if prefix.is_empty() {
-                return None;
-            }
-
-            prefix = prefix.popped();
-        }
+        prefix
+            .self_and_ancestors()
+            .find_map(|prefix| self.get(&prefix))
     }
 
     /// Get the entry at the prefix that matches `name`. In case of multiple matches, returns the
@@ -124,21 +116,18 @@ where
             .map(|entry| &entry.0)
     }
 
-    // Remove `prefix` and any of its ancestors if they are covered by their descendants.
+    // Remove any of `prefixes` if they are covered by their descendants.
     // For example, if `(00)` and `(01)` are both in the map, we can remove `(0)` and `()`.
-    fn prune(&mut self, mut prefix: Prefix) {
+    fn prune<I>(&mut self, prefixes: I)
+    where
+        I: IntoIterator<Item = Prefix>,
+    {
         // TODO: can this be optimized?
 
-        loop {
+        for prefix in prefixes {
             if prefix.is_covered_by(self.descendants(&prefix).map(|entry| entry.borrow())) {
                 let _ = self.0.remove(&prefix);
             }
-
-            if prefix.is_empty() {
-                break;
-            } else {
-                prefix = prefix.popped();
-            }
         }
     }
 }
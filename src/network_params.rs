@@ -0,0 +1,59 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{ELDER_SIZE, RECOMMENDED_SECTION_SIZE};
+
+/// Parameters of the network. Unless noted otherwise, all the nodes in the network must use the
+/// same ones. The defaults are meant for production, testnets can use smaller values to run with
+/// small sections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkParams {
+    /// Number of elders per section.
+    pub elder_size: usize,
+    /// Recommended section size. sn_routing will keep adding nodes until the section reaches this
+    /// size. This number also determines when split happens - if both post-split sections would
+    /// have at least this number of nodes.
+    pub recommended_section_size: usize,
+    /// Number of extra nodes, on top of `recommended_section_size`, both post-split sections must
+    /// have for the split to happen. Prevents repeated splits and merges when the section size
+    /// oscillates around the threshold due to churn.
+    pub split_buffer: usize,
+    /// Maximum number of times a message can be relayed before it's dropped. Prevents messages
+    /// from looping forever due to a malformed destination or a routing bug.
+    pub max_hop_count: u8,
+    /// Number of routes to send consensus and membership messages for other sections over, to
+    /// improve their delivery during heavy churn. The routes start at different sections, so they
+    /// don't share their first hop. The duplicates are dropped at the destination. Can differ
+    /// between nodes.
+    pub redundant_routes: usize,
+    /// Identifier of the network, e.g. a hash of its name. Join requests of nodes configured with
+    /// a different one are dropped before any section info is sent to them, so nodes of
+    /// different networks (e.g. a testnet and the production network) never mix.
+    pub network_id: u64,
+}
+
+impl NetworkParams {
+    /// Minimum number of mature members each of the post-split sections must have for a split to
+    /// happen.
+    pub fn split_threshold(&self) -> usize {
+        self.recommended_section_size + self.split_buffer
+    }
+}
+
+impl Default for NetworkParams {
+    fn default() -> Self {
+        Self {
+            elder_size: ELDER_SIZE,
+            recommended_section_size: RECOMMENDED_SECTION_SIZE,
+            split_buffer: 0,
+            max_hop_count: 32,
+            redundant_routes: 1,
+            network_id: 0,
+        }
+    }
+}
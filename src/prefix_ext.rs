@@ -0,0 +1,176 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Helpers for navigating the prefix tree.
+
+use std::iter::FusedIterator;
+use xor_name::{Prefix, XOR_NAME_LEN};
+
+const MAX_BIT_COUNT: usize = 8 * XOR_NAME_LEN;
+
+/// Extension methods on `Prefix`.
+pub(crate) trait PrefixExt {
+    /// Returns an iterator over all the ancestors of this prefix, starting with its parent and
+    /// ending with the empty prefix.
+    fn ancestors(&self) -> Ancestors;
+
+    /// Returns an iterator over this prefix followed by all its ancestors.
+    fn self_and_ancestors(&self) -> Ancestors;
+
+    /// Returns an iterator over all the descendants of this prefix that are exactly `depth` bits
+    /// longer, in ascending order. The descendants are truncated at the maximum prefix length.
+    /// They are generated lazily, as there are `2^depth` of them.
+    fn descendants(&self, depth: usize) -> Descendants;
+
+    /// Returns the two direct children of this prefix, or nothing if the prefix already has the
+    /// maximum length.
+    fn children(&self) -> Descendants {
+        self.descendants(1)
+    }
+}
+
+impl PrefixExt for Prefix {
+    fn ancestors(&self) -> Ancestors {
+        let mut ancestors = self.self_and_ancestors();
+        let _ = ancestors.next();
+        ancestors
+    }
+
+    fn self_and_ancestors(&self) -> Ancestors {
+        Ancestors { next: Some(*self) }
+    }
+
+    fn descendants(&self, depth: usize) -> Descendants {
+        let depth = depth.min(MAX_BIT_COUNT - self.bit_count());
+
+        Descendants {
+            next: if depth > 0 {
+                Some(pushed_n(*self, false, depth))
+            } else {
+                None
+            },
+            base_bit_count: self.bit_count(),
+        }
+    }
+}
+
+/// Iterator over the descendants of a prefix of a given depth, see `PrefixExt::descendants`.
+#[derive(Clone)]
+pub(crate) struct Descendants {
+    next: Option<Prefix>,
+    base_bit_count: usize,
+}
+
+impl Iterator for Descendants {
+    type Item = Prefix;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+
+        // The successor is the current prefix plus one, treating the bits below the base prefix
+        // as a binary number: flip the trailing ones to zeros and the last zero to one.
+        let mut prefix = current;
+        while prefix.bit_count() > self.base_bit_count && last_bit(&prefix) {
+            prefix = prefix.popped();
+        }
+
+        self.next = if prefix.bit_count() > self.base_bit_count {
+            let trailing = current.bit_count() - prefix.bit_count();
+            Some(pushed_n(prefix.popped().pushed(true), false, trailing))
+        } else {
+            None
+        };
+
+        Some(current)
+    }
+}
+
+impl FusedIterator for Descendants {}
+
+fn pushed_n(mut prefix: Prefix, bit: bool, n: usize) -> Prefix {
+    for _ in 0..n {
+        prefix = prefix.pushed(bit);
+    }
+    prefix
+}
+
+// Returns the last bit of the non-empty `prefix`.
+fn last_bit(prefix: &Prefix) -> bool {
+    prefix.name().bit((prefix.bit_count() - 1) as u8)
+}
+
+/// Iterator over a prefix and its ancestors, see `PrefixExt::ancestors`.
+#[derive(Clone)]
+pub(crate) struct Ancestors {
+    next: Option<Prefix>,
+}
+
+impl Iterator for Ancestors {
+    type Item = Prefix;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        self.next = if current.is_empty() {
+            None
+        } else {
+            Some(current.popped())
+        };
+
+        Some(current)
+    }
+}
+
+impl FusedIterator for Ancestors {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ancestors() {
+        assert_eq!(
+            prefix("101").ancestors().collect::<Vec<_>>(),
+            [prefix("10"), prefix("1"), prefix("")]
+        );
+        assert_eq!(prefix("").ancestors().next(), None);
+        assert_eq!(
+            prefix("01").self_and_ancestors().collect::<Vec<_>>(),
+            [prefix("01"), prefix("0"), prefix("")]
+        );
+    }
+
+    #[test]
+    fn descendants() {
+        assert_eq!(
+            prefix("1").descendants(2).collect::<Vec<_>>(),
+            [prefix("100"), prefix("101"), prefix("110"), prefix("111")]
+        );
+        assert_eq!(
+            prefix("").children().collect::<Vec<_>>(),
+            [prefix("0"), prefix("1")]
+        );
+        assert_eq!(prefix("0").descendants(0).next(), None);
+    }
+
+    #[test]
+    fn descendants_are_lazy() {
+        let mut descendants = prefix("").descendants(MAX_BIT_COUNT);
+        assert_eq!(
+            descendants.next().map(|prefix| prefix.bit_count()),
+            Some(MAX_BIT_COUNT)
+        );
+        assert_eq!(
+            descendants.next(),
+            Some(pushed_n(Prefix::default(), false, MAX_BIT_COUNT - 1).pushed(true))
+        );
+    }
+
+    fn prefix(s: &str) -> Prefix {
+        s.parse().expect("invalid prefix")
+    }
+}
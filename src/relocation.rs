@@ -15,13 +15,14 @@ use crate::{
     network::Network,
     peer::Peer,
     section::{MemberInfo, Section},
+    xor_name_ext::XorNameExt,
 };
 use bytes::Bytes;
 use serde::{de::Error as SerdeDeError, Deserialize, Deserializer, Serialize, Serializer};
 use sn_messaging::MessageType;
-use std::net::SocketAddr;
+use std::{convert::TryFrom, net::SocketAddr};
 use tokio::sync::mpsc;
-use xor_name::XorName;
+use xor_name::{Prefix, XorName};
 
 /// Find all nodes to relocate after a churn event and create the relocate actions for them.
 pub(crate) fn actions(
@@ -266,6 +267,30 @@ fn destination(relocating_name: &XorName, churn_name: &XorName) -> XorName {
     XorName(crypto::sha3_256(&combined_name.0))
 }
 
+// Compute the destination for a newly joining node with `name` to be relocated to. `signature` is
+// the section signature of the vote that approved the node, so the node can't predict it. The
+// destination never falls into `our_prefix`, so the node always ends up in another section.
+// Returns `None` if `our_prefix` is empty, as there is no other section then.
+pub(crate) fn join_destination(
+    name: &XorName,
+    signature: &bls::Signature,
+    our_prefix: &Prefix,
+) -> Option<XorName> {
+    let last_bit = our_prefix.bit_count().checked_sub(1)?;
+    let last_bit = u8::try_from(last_bit).ok()?;
+
+    let mut bytes = name.0.to_vec();
+    bytes.extend_from_slice(&signature.to_bytes());
+    let destination = XorName(crypto::sha3_256(&bytes));
+
+    if our_prefix.matches(&destination) {
+        // Move it to our sibling's subtree.
+        Some(destination.with_flipped_bit(last_bit))
+    } else {
+        Some(destination)
+    }
+}
+
 // TODO: move this to the xor-name crate as `BitXor` impl.
 fn xor(lhs: &XorName, rhs: &XorName) -> XorName {
     let mut output = XorName::default();
@@ -318,6 +343,39 @@ mod tests {
         assert_eq!(trailing_zeros(&[2, 0]), 9);
     }
 
+    #[test]
+    fn join_destination_outside_our_section() {
+        let our_prefix: Prefix = "01".parse().unwrap();
+        let sk = bls::SecretKey::random();
+
+        for _ in 0..20 {
+            let name: XorName = rand::random();
+            let signature = sk.sign(&name.0);
+            let destination = join_destination(&name, &signature, &our_prefix).unwrap();
+            assert!(!our_prefix.matches(&destination));
+        }
+    }
+
+    #[test]
+    fn join_destination_with_extreme_prefix_lengths() {
+        let sk = bls::SecretKey::random();
+        let name: XorName = rand::random();
+        let signature = sk.sign(&name.0);
+
+        assert_eq!(
+            join_destination(&name, &signature, &Prefix::default()),
+            None
+        );
+
+        let full_prefix = Prefix::new(256, rand::random());
+        let destination = join_destination(&name, &signature, &full_prefix).unwrap();
+        assert!(!full_prefix.matches(&destination));
+
+        let full_prefix = Prefix::new(256, destination);
+        let destination = join_destination(&name, &signature, &full_prefix).unwrap();
+        assert!(!full_prefix.matches(&destination));
+    }
+
     const MAX_AGE: u8 = MIN_AGE + 4;
 
     proptest! {
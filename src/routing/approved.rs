@@ -7,23 +7,38 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 use super::{
+    audit_log::AuditLog,
+    blacklist::{BlacklistEntry, Violation},
+    client_rate_limiter::{ClientRateLimiter, ClientRateLimits, ClientUsage, Consume},
+    command::{self, Command},
+    delivery_tracker::{DeliveryTracker, TimeoutOutcome, ACK_TIMEOUT, MAX_DELIVERY_ATTEMPTS},
     enduser_registry::{EndUserRegistry, SocketId},
-    Command, SplitBarrier,
+    join_challenges::ResourceProofConfig,
+    join_proof::{JoinProof, ResourceProofJoin},
+    liveness::Liveness,
+    rendezvous::Rendezvous,
+    stats::{ChurnKind, StatsRecorder},
+    storage::{Storage, StoredState},
+    SplitBarrier,
 };
 use crate::{
     consensus::{
-        DkgCommands, DkgFailureProof, DkgFailureProofSet, DkgKey, DkgVoter, Proof, ProofShare,
-        Proven, Vote, VoteAccumulationError, VoteAccumulator,
+        DkgCommands, DkgFailureProof, DkgFailureProofSet, DkgKey, DkgVoter, PendingAccumulations,
+        Proof, ProofShare, Proven, Vote, VoteAccumulationError, VoteAccumulator,
     },
-    crypto, delivery_group,
+    correlation_id::CorrelationId,
+    crypto::{self, PublicKey},
+    delivery_group,
     error::{Error, Result},
     event::{Event, NodeElderChange},
     message_filter::MessageFilter,
     messages::{
-        JoinRequest, Message, MessageHash, MessageStatus, PlainMessage, ResourceProofResponse,
-        SrcAuthority, Variant, VerifyStatus,
+        derived_user_message_id, Fragment, FragmentAssembler, JoinRequest, Message, MessageHash,
+        MessageStatus, PlainMessage, Priority, SrcAuthority, Variant, VerifyStatus,
+        MAX_FRAGMENT_SIZE, PROTOCOL_VERSION,
     },
-    network::Network,
+    network::{Network, NetworkHealth},
+    network_params::NetworkParams,
     node::Node,
     peer::Peer,
     relocation::{
@@ -34,14 +49,12 @@ use crate::{
         EldersInfo, MemberInfo, PeerState, Section, SectionKeyShare, SectionKeysProvider,
         SectionProofChain, MIN_AGE,
     },
-    ELDER_SIZE, RECOMMENDED_SECTION_SIZE,
 };
 use bls_dkg::key_gen::message::Message as DkgMessage;
 use bls_signature_aggregator::{Error as AggregatorError, SignatureAggregator};
 use bytes::Bytes;
-use ed25519_dalek::Verifier;
+use hex_fmt::HexFmt;
 use itertools::Itertools;
-use resource_proof::ResourceProof;
 use sn_data_types::PublicKey as EndUserPK;
 use sn_messaging::{
     client::Message as ClientMessage,
@@ -51,13 +64,28 @@ use sn_messaging::{
     },
     DstLocation, EndUser, MessageType, SrcLocation,
 };
-use std::{cmp, net::SocketAddr, slice};
-use tokio::sync::mpsc;
+use std::{
+    cmp,
+    collections::{BTreeSet, HashMap},
+    fmt::Write as _,
+    iter, mem,
+    net::SocketAddr,
+    slice,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::Span;
 use xor_name::{Prefix, XorName};
 
-pub(crate) const RESOURCE_PROOF_DATA_SIZE: usize = 64;
-pub(crate) const RESOURCE_PROOF_DIFFICULTY: u8 = 2;
 const KEY_CACHE_SIZE: u8 = 5;
+// Interval at which our elders exchange their section info with the neighbour sections.
+const NEIGHBOUR_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const ACCUMULATION_CLEANUP_INTERVAL: Duration = Duration::from_secs(30);
+// Interval at which we probe the liveness of our peers.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+// Interval at which the changes to the stored state are written, except the section key changes,
+// which are written right away.
+const STORAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
 
 // The approved stage - node is a full member of a section and is performing its duties according
 // to its persona (adult or elder).
@@ -67,6 +95,8 @@ pub(crate) struct Approved {
     network: Network,
     section_keys_provider: SectionKeysProvider,
     message_accumulator: SignatureAggregator,
+    // Messages for accumulation at destination that didn't get enough signature shares yet.
+    pending_messages: PendingAccumulations<PendingMessage>,
     vote_accumulator: VoteAccumulator,
     split_barrier: SplitBarrier,
     // Voter for DKG
@@ -75,8 +105,29 @@ pub(crate) struct Approved {
     msg_filter: MessageFilter,
     pub(super) event_tx: mpsc::UnboundedSender<Event>,
     joins_allowed: bool,
-    resource_proof: ResourceProof,
+    join_proof: Box<dyn JoinProof>,
     end_users: EndUserRegistry,
+    storage: Option<Storage>,
+    // Whether the state changed since it was last stored.
+    state_dirty: bool,
+    storage_flush_timer_token: u64,
+    neighbour_refresh_timer_token: u64,
+    accumulation_cleanup_timer_token: u64,
+    keepalive_timer_token: u64,
+    audit_log: Option<AuditLog>,
+    network_params: NetworkParams,
+    delivery_tracker: DeliveryTracker,
+    fragment_assembler: FragmentAssembler,
+    client_rate_limiter: ClientRateLimiter,
+    // Members that requested to leave, to acknowledge once they are voted offline.
+    leaving_members: BTreeSet<XorName>,
+    // Notified once our own request to leave is acknowledged.
+    leave_ack_tx: Option<oneshot::Sender<()>>,
+    rendezvous: Rendezvous,
+    liveness: Liveness,
+    // Probes waiting for their ack, by nonce, with the probed peer.
+    pending_probes: HashMap<u64, (SocketAddr, oneshot::Sender<()>)>,
+    stats: StatsRecorder,
 }
 
 impl Approved {
@@ -102,15 +153,150 @@ impl Approved {
             section_keys_provider,
             vote_accumulator: Default::default(),
             message_accumulator: Default::default(),
+            pending_messages: Default::default(),
             split_barrier: Default::default(),
             dkg_voter: Default::default(),
             relocate_state: None,
             msg_filter: MessageFilter::new(),
             event_tx,
             joins_allowed: true,
-            resource_proof: ResourceProof::new(RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY),
+            join_proof: Box::new(ResourceProofJoin::new(ResourceProofConfig::default())),
             end_users: EndUserRegistry::new(),
+            storage: None,
+            state_dirty: false,
+            storage_flush_timer_token: command::next_timer_token(),
+            neighbour_refresh_timer_token: command::next_timer_token(),
+            accumulation_cleanup_timer_token: command::next_timer_token(),
+            keepalive_timer_token: command::next_timer_token(),
+            audit_log: None,
+            network_params: NetworkParams::default(),
+            delivery_tracker: DeliveryTracker::new(),
+            fragment_assembler: FragmentAssembler::new(),
+            client_rate_limiter: ClientRateLimiter::new(ClientRateLimits::default()),
+            leaving_members: BTreeSet::new(),
+            leave_ack_tx: None,
+            rendezvous: Rendezvous::new(),
+            liveness: Liveness::default(),
+            pending_probes: HashMap::new(),
+            stats: StatsRecorder::default(),
+        }
+    }
+
+    pub fn with_network_params(self, network_params: NetworkParams) -> Self {
+        Self {
+            network_params,
+            ..self
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_network(self, network: Network) -> Self {
+        Self { network, ..self }
+    }
+
+    pub fn network_params(&self) -> &NetworkParams {
+        &self.network_params
+    }
+
+    pub fn with_client_rate_limits(self, limits: ClientRateLimits) -> Self {
+        Self {
+            client_rate_limiter: ClientRateLimiter::new(limits),
+            ..self
+        }
+    }
+
+    pub fn with_stats(self, stats: StatsRecorder) -> Self {
+        Self { stats, ..self }
+    }
+
+    pub fn with_audit_log(self, audit_log: Option<AuditLog>) -> Self {
+        Self { audit_log, ..self }
+    }
+
+    pub fn client_rate_limits(&self) -> &ClientRateLimits {
+        self.client_rate_limiter.limits()
+    }
+
+    pub fn with_join_proof(self, join_proof: Box<dyn JoinProof>) -> Self {
+        Self { join_proof, ..self }
+    }
+
+    // Take out the join proof, e.g. to pass it to the new state after relocation, leaving the
+    // default one in its place.
+    pub fn take_join_proof(&mut self) -> Box<dyn JoinProof> {
+        mem::replace(
+            &mut self.join_proof,
+            Box::new(ResourceProofJoin::new(ResourceProofConfig::default())),
+        )
+    }
+
+    pub fn try_consume_client_quota(&mut self, client: SocketAddr, size: usize) -> Consume {
+        self.client_rate_limiter.try_consume(client, size)
+    }
+
+    pub fn client_usage(&mut self, client: &SocketAddr) -> ClientUsage {
+        self.client_rate_limiter.usage(client)
+    }
+
+    // Start recording every consensused vote into the audit log.
+    pub fn enable_audit_log(&mut self) {
+        if self.audit_log.is_none() {
+            self.audit_log = Some(AuditLog::new());
+        }
+    }
+
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit_log.as_ref()
+    }
+
+    // Take out the audit log, e.g. to pass it to the new state after relocation.
+    pub fn take_audit_log(&mut self) -> Option<AuditLog> {
+        self.audit_log.take()
+    }
+
+    // Recover the section and network knowledge previously stored in `storage` (if any) and keep
+    // storing it there whenever it changes from now on. Stored state that fails the integrity
+    // checks or that is not consistent with our current section chain is discarded.
+    pub fn recover_from(&mut self, storage: Storage) {
+        match storage.load() {
+            Ok(Some(state)) => {
+                if !state.section.prefix().matches(&self.node.name()) {
+                    debug!("Ignoring stored state - not our section");
+                } else if let Err(error) = self.section.merge(state.section) {
+                    warn!("Ignoring stored state - inconsistent section: {}", error);
+                } else {
+                    self.network.merge(state.network, self.section.chain());
+
+                    // Shares that are invalid under our current section key are rejected by the
+                    // accumulator. Votes can't reach quorum here, as they were still pending
+                    // when stored.
+                    for (vote, proof_share) in state.votes {
+                        let _ = self.vote_accumulator.add(vote, proof_share);
+                    }
+
+                    info!("Recovered stored state from {}", storage.path().display());
+                }
+            }
+            Ok(None) => (),
+            Err(error) => warn!(
+                "Failed to load stored state from {}: {}",
+                storage.path().display(),
+                error
+            ),
         }
+
+        self.store_to(storage);
+    }
+
+    // Keep storing the section and network knowledge to `storage` whenever it changes, starting
+    // now. Used to carry the storage over to the new state after relocation.
+    pub fn store_to(&mut self, storage: Storage) {
+        self.storage = Some(storage);
+        self.store_state();
+    }
+
+    pub fn take_storage(&mut self) -> Option<Storage> {
+        self.storage.take()
     }
 
     pub fn get_enduser_by_addr(&self, sender: &SocketAddr) -> Option<&EndUser> {
@@ -128,6 +314,11 @@ impl Approved {
         self.end_users.get_all_socket_addr(end_user)
     }
 
+    /// Number of client connections.
+    pub fn client_count(&self) -> usize {
+        self.end_users.all_socket_addrs().count()
+    }
+
     pub fn node(&self) -> &Node {
         &self.node
     }
@@ -140,6 +331,129 @@ impl Approved {
         &self.network
     }
 
+    pub fn closest_nodes_to<'a>(
+        &'a self,
+        name: &'a XorName,
+    ) -> impl Iterator<Item = &'a Peer> + 'a {
+        delivery_group::closest_nodes_to(name, &self.section, &self.network)
+    }
+
+    pub fn network_health(&self) -> NetworkHealth {
+        self.network.health(
+            &self.network_params,
+            self.section.elders_info(),
+            self.section
+                .is_split_expected(&self.network_params, &self.node.name()),
+        )
+    }
+
+    /// Human-readable dump of the state of this node: our section with its chain and members, the
+    /// other sections we know, the signatures still being accumulated and the liveness of our
+    /// peers. For postmortem analysis.
+    pub fn dump_state(&self) -> String {
+        let now = Instant::now();
+        let section = &self.section;
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# Node");
+        let _ = writeln!(out, "name: {:x}", self.node.name());
+        let _ = writeln!(out, "age: {}", self.node.age);
+        let _ = writeln!(out, "addr: {}", self.node.addr);
+        let _ = writeln!(out, "elder: {}", self.is_elder());
+        let _ = writeln!(out, "joins allowed: {}", self.joins_allowed);
+
+        let _ = writeln!(out, "\n# Section chain");
+        for (index, key) in section.chain().keys().enumerate() {
+            let _ = writeln!(out, "{}: {:x}", index, HexFmt(&key.to_bytes()));
+        }
+
+        let _ = writeln!(out, "\n# Section ({:b})", section.prefix());
+        for peer in section.elders_info().peers() {
+            let _ = writeln!(
+                out,
+                "elder {:x} at {} age {}",
+                peer.name(),
+                peer.addr(),
+                peer.age()
+            );
+        }
+        for info in section.members().all() {
+            let _ = writeln!(
+                out,
+                "member {:x} at {} age {}: {:?}",
+                info.peer.name(),
+                info.peer.addr(),
+                info.peer.age(),
+                info.state
+            );
+        }
+
+        let _ = writeln!(out, "\n# Network");
+        for elders_info in self.network.all() {
+            let prefix = &elders_info.prefix;
+            let key = self
+                .network
+                .key_by_prefix(prefix)
+                .map(|key| format!("{:x}", HexFmt(&key.to_bytes())))
+                .unwrap_or_else(|| "unknown".to_string());
+            let _ = writeln!(
+                out,
+                "section ({:b}) key {} knows our key #{}",
+                prefix,
+                key,
+                self.network.knowledge_by_section(prefix)
+            );
+            for peer in elders_info.peers() {
+                let _ = writeln!(out, "  elder {:x} at {}", peer.name(), peer.addr());
+            }
+        }
+
+        let _ = writeln!(out, "\n# Pending accumulations");
+        for (vote, deadline) in self.vote_accumulator.pending() {
+            let expires_in = deadline.saturating_duration_since(now);
+            let _ = writeln!(out, "vote (expires in {:?}): {:?}", expires_in, vote);
+        }
+        for (msg, deadline) in self.pending_messages.iter() {
+            let expires_in = deadline.saturating_duration_since(now);
+            let _ = writeln!(out, "message (expires in {:?}): {:?}", expires_in, msg);
+        }
+
+        let _ = writeln!(out, "\n# Peer liveness");
+        for (peer, rtt, failures) in self.liveness.iter() {
+            let _ = writeln!(
+                out,
+                "{} rtt {:?}, {} failed probes in a row",
+                peer, rtt, failures
+            );
+        }
+
+        out
+    }
+
+    /// Tracing span carrying the identity of this node and the version of its section as
+    /// structured fields. Lets the logs of multiple nodes running within the same process, for
+    /// example in integration tests, be filtered and correlated.
+    pub fn span(&self) -> Span {
+        trace_span!(
+            "node",
+            name = %self.node.name(),
+            prefix = format_args!("({:b})", self.section.prefix()),
+            section_key = self.section.chain().last_key_index(),
+            age = self.node.age,
+            elder = self.is_elder(),
+        )
+    }
+
+    // Bring the fields of the current span created by `span` up to date after a change of our
+    // section, so the rest of the logs within it carry the new section context. No-op outside of
+    // such span.
+    fn update_span(&self) {
+        let span = Span::current();
+        let _ = span.record("prefix", &format_args!("({:b})", self.section.prefix()));
+        let _ = span.record("section_key", &self.section.chain().last_key_index());
+        let _ = span.record("elder", &self.is_elder());
+    }
+
     /// Is this node an elder?
     pub fn is_elder(&self) -> bool {
         self.section.is_elder(&self.node.name())
@@ -194,28 +508,90 @@ impl Approved {
         }
     }
 
+    /// Returns whether the message with the given hash was already handled or relayed by us.
+    pub fn is_known_message(&self, hash: &MessageHash) -> bool {
+        self.msg_filter.contains_incoming_hash(hash)
+    }
+
     pub async fn handle_message(
         &mut self,
         sender: Option<SocketAddr>,
         msg: Message,
     ) -> Result<Vec<Command>> {
+        self.handle_message_impl(sender, msg, false).await
+    }
+
+    // Handle a message from a captured trace. Its nonce is as old as the trace, so unlike
+    // `handle_message`, the stale nonce and replay checks are skipped and its sender isn't
+    // blacklisted for it.
+    pub async fn replay_message(
+        &mut self,
+        sender: SocketAddr,
+        msg: Message,
+    ) -> Result<Vec<Command>> {
+        self.handle_message_impl(Some(sender), msg, true).await
+    }
+
+    async fn handle_message_impl(
+        &mut self,
+        sender: Option<SocketAddr>,
+        msg: Message,
+        replayed: bool,
+    ) -> Result<Vec<Command>> {
+        self.stats.record_received(msg.variant().name());
+
         let mut commands = vec![];
 
         // Check if the message is for us.
         let in_dst_location = msg.dst().contains(&self.node.name(), self.section.prefix());
-        if !in_dst_location || msg.dst().is_section() {
+        // Multicast and anycast messages reach adults from our elders, so there is no point
+        // relaying them back.
+        let forwarded_to_adult = in_dst_location
+            && !self.is_elder()
+            && matches!(
+                msg.variant(),
+                Variant::UserMessageMulticast { .. } | Variant::UserMessageAnycast { .. }
+            );
+        if !in_dst_location || (msg.dst().is_section() && !forwarded_to_adult) {
             // Relay closer to the destination or
             // broadcast to the rest of our section.
-            commands.extend(self.relay_message(&msg)?);
+            if let Some(command) = self.relay_message(&msg)? {
+                self.stats.record_relayed();
+                commands.push(command);
+            }
         }
         if !in_dst_location {
-            // Message not for us.
+            // Message not for us. Remember it so we don't relay it again when it reaches us via
+            // another route.
+            self.msg_filter.insert_incoming(&msg);
+            return Ok(commands);
+        }
+
+        trace_hop(&msg, "delivered");
+
+        if !replayed && self.msg_filter.has_stale_nonce(&msg) {
+            debug!("not handling message - stale nonce: {:?}", msg);
             return Ok(commands);
         }
 
-        // Filter messages which were already handled
-        if self.msg_filter.contains_incoming(&msg) {
+        // Messages already handled are dropped. If it's a replay, the node responsible for it is
+        // blacklisted, not one that just relayed the message.
+        if !replayed && self.msg_filter.contains_incoming(&msg) {
             trace!("not handling message - already handled: {:?}", msg);
+            if msg.nonce().is_some() {
+                if let Some(entry) = sender.and_then(|sender| self.replayed_by(&sender, &msg)) {
+                    commands.push(Command::Blacklist {
+                        entry,
+                        violation: Violation::Replay,
+                    });
+                }
+            }
+            return Ok(commands);
+        }
+
+        // Not acknowledged, so user messages are sent again later.
+        if !replayed && self.msg_filter.is_nonced_full(&msg) {
+            warn_limited!("not handling message - too many recent messages: {:?}", msg);
             return Ok(commands);
         }
 
@@ -251,17 +627,8 @@ impl Approved {
                 debug!("Received GetSectionQuery({}) from {}", name, sender);
 
                 let response = if self.section.prefix().matches(&name) {
-                    if let Ok(pk_set) = self.public_key_set() {
-                        GetSectionResponse::Success(SectionInfo {
-                            prefix: self.section.elders_info().prefix,
-                            pk_set,
-                            elders: self
-                                .section
-                                .elders_info()
-                                .peers()
-                                .map(|peer| (*peer.name(), *peer.addr()))
-                                .collect(),
-                        })
+                    if let Ok(info) = self.our_section_info() {
+                        GetSectionResponse::Success(info)
                     } else {
                         GetSectionResponse::SectionInfoUpdate(TargetSectionError::NoSectionPkSet)
                     }
@@ -283,6 +650,7 @@ impl Approved {
                     recipients: vec![sender],
                     delivery_group_size: 1,
                     message: MessageType::SectionInfo(response),
+                    priority: Priority::SectionKnowledge,
                 }]
             }
             SectionInfoMsg::RegisterEndUserCmd {
@@ -307,6 +675,7 @@ impl Approved {
                     recipients: vec![sender],
                     delivery_group_size: 1,
                     message: MessageType::SectionInfo(response),
+                    priority: Priority::SectionKnowledge,
                 }]
             }
             SectionInfoMsg::GetSectionResponse(_) => {
@@ -327,7 +696,114 @@ impl Approved {
         }
     }
 
+    fn our_section_info(&self) -> Result<SectionInfo> {
+        Ok(SectionInfo {
+            prefix: self.section.elders_info().prefix,
+            pk_set: self.public_key_set()?,
+            elders: self
+                .section
+                .elders_info()
+                .peers()
+                .map(|peer| (*peer.name(), *peer.addr()))
+                .collect(),
+        })
+    }
+
+    // Push our updated section info to the connected clients, so they can update the elders and
+    // the section key they know.
+    fn update_end_users(&self) -> Option<Command> {
+        let recipients: Vec<_> = self.end_users.all_socket_addrs().copied().collect();
+        if recipients.is_empty() {
+            return None;
+        }
+
+        let info = self.our_section_info().ok()?;
+        trace!("Sending updated {:?} to {} clients", info, recipients.len());
+
+        Some(Command::SendMessage {
+            delivery_group_size: recipients.len(),
+            recipients,
+            message: MessageType::SectionInfo(SectionInfoMsg::GetSectionResponse(
+                GetSectionResponse::Success(info),
+            )),
+            priority: Priority::SectionKnowledge,
+        })
+    }
+
     pub fn handle_timeout(&mut self, token: u64) -> Result<Vec<Command>> {
+        if token == self.neighbour_refresh_timer_token {
+            return self.refresh_neighbours();
+        }
+
+        if token == self.accumulation_cleanup_timer_token {
+            self.remove_expired_accumulations();
+            return Ok(vec![self.schedule_accumulation_cleanup()]);
+        }
+
+        if token == self.storage_flush_timer_token {
+            if self.state_dirty {
+                self.store_state();
+            }
+            return Ok(vec![self.schedule_storage_flush()]);
+        }
+
+        if token == self.keepalive_timer_token {
+            let probe = self.probe_peers();
+            return Ok(probe
+                .into_iter()
+                .chain(iter::once(self.schedule_keepalive()))
+                .collect());
+        }
+
+        match self.delivery_tracker.handle_timeout(token) {
+            TimeoutOutcome::Unknown => (),
+            TimeoutOutcome::Delivered => return Ok(vec![]),
+            TimeoutOutcome::Retry(pending) => {
+                // Resend it as a new message, as the relays drop the messages they already saw.
+                let message = self.renew_message(&pending.message)?;
+                debug!(
+                    "Message {:?} not acknowledged - sending it again as {:?} (attempt {})",
+                    pending.message.hash(),
+                    message.hash(),
+                    pending.attempts + 1
+                );
+                return self.send_message_for_ack(message, pending.attempts + 1, pending.tried);
+            }
+            TimeoutOutcome::GiveUp(message) => {
+                warn!(
+                    "Message {:?} not acknowledged after {} attempts - giving up",
+                    message.hash(),
+                    MAX_DELIVERY_ATTEMPTS
+                );
+                let failed = match message.variant() {
+                    Variant::UserMessage {
+                        content,
+                        correlation_id,
+                        ..
+                    }
+                    | Variant::UserMessageAnycast {
+                        content,
+                        correlation_id,
+                        ..
+                    } => Some((content.clone(), *correlation_id)),
+                    // Report the whole message, only once for all its fragments.
+                    Variant::UserMessageFragment(fragment) => self
+                        .delivery_tracker
+                        .take_fragmented(fragment.id)
+                        .map(|content| (content, fragment.correlation_id)),
+                    _ => None,
+                };
+                if let Some((content, correlation_id)) = failed {
+                    self.send_event(Event::MessageDeliveryFailed {
+                        content,
+                        dst: *message.dst(),
+                        correlation_id,
+                    });
+                }
+                return Ok(vec![]);
+            }
+        }
+
         self.dkg_voter
             .handle_timeout(&self.node.keypair, token)
             .into_commands(&self.node)
@@ -336,12 +812,19 @@ impl Approved {
     // Insert the vote into the vote accumulator and handle it if accumulated.
     pub fn handle_vote(&mut self, vote: Vote, proof_share: ProofShare) -> Result<Vec<Command>> {
         match self.vote_accumulator.add(vote, proof_share) {
-            Ok((vote, proof)) => Ok(vec![Command::HandleConsensus { vote, proof }]),
+            Ok((vote, proof)) => {
+                self.stats.record_accumulation(true);
+                Ok(vec![Command::HandleConsensus { vote, proof }])
+            }
             Err(VoteAccumulationError::Aggregation(
                 bls_signature_aggregator::Error::NotEnoughShares,
-            )) => Ok(vec![]),
+            )) => {
+                self.state_dirty = true;
+                Ok(vec![])
+            }
             Err(error) => {
-                error!("Failed to add vote: {}", error);
+                self.stats.record_accumulation(false);
+                error_limited!("Failed to add vote: {}", error);
                 Err(Error::InvalidSignatureShare)
             }
         }
@@ -350,7 +833,13 @@ impl Approved {
     pub fn handle_consensus(&mut self, vote: Vote, proof: Proof) -> Result<Vec<Command>> {
         debug!("handle consensus on {:?}", vote);
 
-        match vote {
+        let audit = if self.audit_log.is_some() {
+            Some((vote.clone(), proof.clone()))
+        } else {
+            None
+        };
+
+        let commands = match vote {
             Vote::Online {
                 member_info,
                 previous_name,
@@ -376,48 +865,391 @@ impl Approved {
                 self.joins_allowed = joins_allowed;
                 Ok(vec![])
             }
+        }?;
+
+        // The vote is already applied at this point, so failing to log it must not make us lose
+        // the commands resulting from it.
+        if let (Some(audit_log), Some((vote, proof))) = (&mut self.audit_log, audit) {
+            if let Err(error) = audit_log.append(&vote, &proof, &self.section) {
+                error!("Failed to append {:?} to the audit log: {}", vote, error);
+            }
+        }
+
+        self.state_dirty = true;
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants();
+
+        Ok(commands)
+    }
+
+    pub fn handle_connection_lost(&self, addr: SocketAddr) -> Option<Command> {
+        if !self.is_elder() {
+            return None;
+        }
+
+        if let Some(peer) = self.section.find_joined_member_by_addr(&addr) {
+            trace!("Lost connection to {}", peer);
+        } else {
+            return None;
+        }
+
+        // Probe the peer connection. If it succeeds, the connection loss was just temporary.
+        // Otherwise the failure counts towards voting the peer offline.
+        Some(Command::Probe(vec![addr]))
+    }
+
+    pub fn handle_peer_lost(&mut self, addr: &SocketAddr) -> Result<Vec<Command>> {
+        let name = if let Some(peer) = self.section.find_joined_member_by_addr(addr) {
+            debug!("Lost known peer {}", peer);
+            *peer.name()
+        } else {
+            trace!("Lost unknown peer {}", addr);
+            return Ok(vec![]);
+        };
+
+        // The peer might just not accept inbound connections. Try to punch a hole to it first.
+        if let Some(command) = self.request_rendezvous(addr, &name)? {
+            return Ok(vec![command]);
+        }
+
+        // A failed send alone doesn't mean the peer is gone, so probe it. Except the peers we
+        // relay to which we can't probe directly - failing to send to them through the relay
+        // counts as a failed probe.
+        if self.rendezvous.relay(addr).is_some() {
+            self.handle_probe_results(vec![(*addr, None)])
+        } else {
+            Ok(vec![Command::Probe(vec![*addr])])
+        }
+    }
+
+    // Probe the liveness of our peers: all the other members if we are elder, otherwise just the
+    // elders. Skips the peers we relay to, as they don't accept our connections.
+    fn probe_peers(&mut self) -> Option<Command> {
+        let section = &self.section;
+        self.liveness
+            .retain(|addr| section.find_joined_member_by_addr(addr).is_some());
+
+        let our_name = self.node.name();
+        let peers: Vec<_> = if self.is_elder() {
+            self.section.active_members().collect()
+        } else {
+            self.section.elders_info().peers().collect()
+        };
+        let peers: Vec<_> = peers
+            .into_iter()
+            .filter(|peer| *peer.name() != our_name)
+            .map(Peer::addr)
+            .filter(|addr| self.rendezvous.relay(addr).is_none())
+            .copied()
+            .collect();
+
+        if peers.is_empty() {
+            None
+        } else {
+            Some(Command::Probe(peers))
         }
     }
 
-    pub fn handle_connection_lost(&self, addr: SocketAddr) -> Option<Command> {
-        if !self.is_elder() {
-            return None;
+    // Handle the outcome of probing the peers: the round-trip time or `None` if the probe failed.
+    // Votes the members that failed too many probes in a row offline.
+    pub fn handle_probe_results(
+        &mut self,
+        results: Vec<(SocketAddr, Option<Duration>)>,
+    ) -> Result<Vec<Command>> {
+        let mut commands = vec![];
+
+        for (addr, rtt) in results {
+            if let Some(rtt) = rtt {
+                self.liveness.record_success(addr, rtt);
+                continue;
+            }
+
+            if !self.liveness.record_failure(addr) {
+                trace!("Probe of {} failed", addr);
+                continue;
+            }
+
+            if !self.is_elder() {
+                continue;
+            }
+
+            let info = if let Some(peer) = self.section.find_joined_member_by_addr(&addr) {
+                debug!("Peer {} failed too many probes", peer);
+                self.section.members().get(peer.name()).cloned()
+            } else {
+                None
+            };
+
+            if let Some(info) = info {
+                commands.extend(self.vote(Vote::Offline(info.leave()?))?);
+            }
+        }
+
+        Ok(commands)
+    }
+
+    // Create a probe of the peer at `addr`. Returns its nonce, the serialized message to send and
+    // the receiver notified when the peer acknowledges it.
+    pub fn start_probe(&mut self, addr: SocketAddr) -> Result<(u64, Bytes, oneshot::Receiver<()>)> {
+        let nonce = rand::random();
+        let message = Message::single_src(
+            &self.node,
+            DstLocation::Direct,
+            Variant::Probe { nonce },
+            None,
+            None,
+        )?;
+
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let _ = self.pending_probes.insert(nonce, (addr, ack_tx));
+
+        Ok((nonce, message.to_bytes(), ack_rx))
+    }
+
+    // Stop waiting for the ack of the probe with the given nonce, e.g. because it timed out.
+    pub fn finish_probe(&mut self, nonce: u64) {
+        let _ = self.pending_probes.remove(&nonce);
+    }
+
+    fn handle_probe(&self, sender: Option<SocketAddr>, nonce: u64) -> Result<Vec<Command>> {
+        let sender = sender.ok_or(Error::InvalidSrcLocation)?;
+        Ok(vec![self.send_direct_message(
+            &sender,
+            Variant::ProbeAck { nonce },
+        )?])
+    }
+
+    fn handle_probe_ack(&mut self, sender: Option<SocketAddr>, nonce: u64) {
+        // Only the probed peer can acknowledge the probe.
+        match self.pending_probes.get(&nonce) {
+            Some((addr, _)) if Some(*addr) == sender => (),
+            _ => {
+                trace!("Ignoring unexpected ProbeAck from {:?}", sender);
+                return;
+            }
+        }
+
+        if let Some((_, ack_tx)) = self.pending_probes.remove(&nonce) {
+            let _ = ack_tx.send(());
+        }
+    }
+
+    // Smoothed round-trip time to the peer, as measured by the keepalive probes.
+    pub fn peer_rtt(&self, addr: &SocketAddr) -> Option<Duration> {
+        self.liveness.rtt(addr)
+    }
+
+    // Ask an elder to introduce us to the member with the given address and name, unless we asked
+    // recently or already relay our messages to it. The elder must be connected to both of us, so
+    // pick the one closest to the member.
+    fn request_rendezvous(&mut self, addr: &SocketAddr, name: &XorName) -> Result<Option<Command>> {
+        let our_name = self.node.name();
+        let relay = if let Some(relay) = self
+            .section
+            .elders_info()
+            .peers()
+            .filter(|peer| *peer.name() != our_name && peer.name() != name)
+            .min_by(|lhs, rhs| name.cmp_distance(lhs.name(), rhs.name()))
+        {
+            *relay.addr()
+        } else {
+            return Ok(None);
+        };
+
+        if !self.rendezvous.start(*addr) {
+            return Ok(None);
+        }
+
+        debug!("Requesting rendezvous with {} through {}", addr, relay);
+        let variant = Variant::ConnectRequest { target: *name };
+        Ok(Some(self.send_direct_message(&relay, variant)?))
+    }
+
+    // Introduce the sender of a `ConnectRequest` and its target to each other, with the addresses
+    // we see them at.
+    fn handle_connect_request(
+        &self,
+        sender: Option<SocketAddr>,
+        src_name: &XorName,
+        target: &XorName,
+    ) -> Result<Vec<Command>> {
+        let sender = sender.ok_or(Error::InvalidSrcLocation)?;
+        let (src, target) = match (
+            self.section.members().get(src_name),
+            self.section.members().get(target),
+        ) {
+            (Some(src), Some(target))
+                if src.state == PeerState::Joined && target.state == PeerState::Joined =>
+            {
+                (Peer::new(*src_name, sender, src.peer.age()), target.peer)
+            }
+            _ => {
+                trace!(
+                    "Ignoring ConnectRequest from {} to {} - not both our members",
+                    src_name,
+                    target
+                );
+                return Ok(vec![]);
+            }
+        };
+
+        Ok(vec![
+            self.send_direct_message(target.addr(), Variant::ConnectIntroduction { peer: src })?,
+            self.send_direct_message(&sender, Variant::ConnectIntroduction { peer: target })?,
+        ])
+    }
+
+    fn handle_connect_introduction(
+        &self,
+        sender: Option<SocketAddr>,
+        src_name: &XorName,
+        peer: &Peer,
+    ) -> Result<Vec<Command>> {
+        let relay = sender.ok_or(Error::InvalidSrcLocation)?;
+        if !self.section.is_elder(src_name) {
+            trace!("Ignoring ConnectIntroduction from non-elder {}", src_name);
+            return Ok(vec![]);
+        }
+
+        Ok(vec![Command::Punch {
+            peer: *peer.addr(),
+            relay,
+        }])
+    }
+
+    pub fn handle_punch_result(&mut self, peer: SocketAddr, relay: SocketAddr, success: bool) {
+        if success {
+            debug!("Punched a hole to {}", peer);
+        } else {
+            debug!(
+                "Failed to punch a hole to {}, relaying through {}",
+                peer, relay
+            );
+        }
+
+        self.rendezvous.handle_punch_result(peer, relay, success)
+    }
+
+    // Forward the message relayed by one of our members to another one of them.
+    fn handle_relay(
+        &self,
+        sender: Option<SocketAddr>,
+        dst: &SocketAddr,
+        message: Bytes,
+    ) -> Result<Vec<Command>> {
+        let sender = sender.ok_or(Error::InvalidSrcLocation)?;
+        let sender_name = if let (Some(peer), Some(_)) = (
+            self.section.find_joined_member_by_addr(&sender),
+            self.section.find_joined_member_by_addr(dst),
+        ) {
+            *peer.name()
+        } else {
+            trace!(
+                "Ignoring Relay from {} to {} - not our members",
+                sender,
+                dst
+            );
+            return Ok(vec![]);
+        };
+
+        // The recipient sees the message coming from us, so only relay messages the sender signed
+        // itself, and only fresh ones, so we can't be used to pass on forged or replayed messages.
+        let message = Message::from_bytes(message).map_err(|_| Error::InvalidMessage)?;
+        if message.src().to_node_name().ok() != Some(sender_name)
+            || self.msg_filter.has_stale_nonce(&message)
+        {
+            warn_limited!(
+                "Ignoring Relay from {} to {} - not a fresh message of the sender",
+                sender,
+                dst
+            );
+            return Ok(vec![]);
         }
 
-        if let Some(peer) = self.section.find_joined_member_by_addr(&addr) {
-            trace!("Lost connection to {}", peer);
+        Ok(vec![Command::send_message_to_node(
+            dst,
+            message.to_bytes(),
+            message.priority(),
+        )])
+    }
+
+    // Whether the direct `msg` could have been relayed to us by the node with `relay_key`: relays
+    // are our elders, passing on the messages of our other members.
+    pub fn is_relayed_by(&self, relay_key: &PublicKey, msg: &Message) -> bool {
+        let signer = if let Ok(name) = msg.src().to_node_name() {
+            name
         } else {
-            return None;
+            return false;
+        };
+
+        self.section.is_elder(&crypto::name(relay_key)) && self.section.members().is_joined(&signer)
+    }
+
+    // The elder relaying our messages to `recipient`, if punching a hole to it failed.
+    pub fn relay_for(&self, recipient: &SocketAddr) -> Option<SocketAddr> {
+        self.rendezvous.relay(recipient)
+    }
+
+    // Wrap the serialized `message` for relaying to `dst`.
+    pub fn wrap_for_relay(&self, dst: SocketAddr, message: Bytes) -> Result<Bytes> {
+        let variant = Variant::Relay { dst, message };
+        let message = Message::single_src(&self.node, DstLocation::Direct, variant, None, None)?;
+        Ok(message.to_bytes())
+    }
+
+    // Ask our section to let us leave. `ack_tx` is notified once one of our elders confirms the
+    // section agreed on it.
+    pub fn leave(&mut self, ack_tx: oneshot::Sender<()>) -> Result<Vec<Command>> {
+        let our_name = self.node.name();
+        let targets: Vec<_> = self
+            .section
+            .elders_info()
+            .peers()
+            .filter(|peer| *peer.name() != our_name)
+            .map(Peer::addr)
+            .copied()
+            .collect();
+
+        if targets.is_empty() {
+            // No one else to agree on it.
+            let _ = ack_tx.send(());
+            return Ok(vec![]);
         }
 
-        // Try to send a "ping" message to probe the peer connection. If it succeeds, the
-        // connection loss was just temporary. Otherwise the peer is assumed lost and we will vote
-        // it offline.
-        Some(Command::SendMessage {
-            recipients: vec![addr],
-            delivery_group_size: 1,
-            message: MessageType::Ping,
-        })
+        self.leave_ack_tx = Some(ack_tx);
+
+        let message =
+            Message::single_src(&self.node, DstLocation::Direct, Variant::Leave, None, None)?;
+        Ok(vec![Command::send_message_to_nodes(
+            &targets,
+            targets.len(),
+            message.to_bytes(),
+            message.priority(),
+        )])
     }
 
-    pub fn handle_peer_lost(&self, addr: &SocketAddr) -> Result<Vec<Command>> {
-        let name = if let Some(peer) = self.section.find_joined_member_by_addr(addr) {
-            debug!("Lost known peer {}", peer);
-            *peer.name()
+    fn handle_leave_request(&mut self, name: &XorName) -> Result<Vec<Command>> {
+        let info = if let Some(info) = self.section.members().get(name) {
+            info.clone().leave_voluntarily()?
         } else {
-            trace!("Lost unknown peer {}", addr);
+            trace!("Ignoring Leave from unknown node {}", name);
             return Ok(vec![]);
         };
 
-        if !self.is_elder() {
-            return Ok(vec![]);
+        debug!("Member {} requested to leave", name);
+        let _ = self.leaving_members.insert(*name);
+        self.vote(Vote::Offline(info))
+    }
+
+    fn handle_leave_ack(&mut self, name: &XorName) {
+        if !self.section.is_elder(name) {
+            trace!("Ignoring LeaveAck from non-elder {}", name);
+            return;
         }
 
-        if let Some(info) = self.section.members().get(&name) {
-            let info = info.clone().leave()?;
-            self.vote(Vote::Offline(info))
-        } else {
-            Ok(vec![])
+        if let Some(ack_tx) = self.leave_ack_tx.take() {
+            let _ = ack_tx.send(());
         }
     }
 
@@ -451,7 +1283,7 @@ impl Approved {
             proofs,
         };
         let message = Message::single_src(&self.node, DstLocation::Direct, variant, None, None)?;
-        Ok(self.send_message_to_our_elders(message.to_bytes()))
+        Ok(self.send_message_to_our_elders(message.to_bytes(), message.priority()))
     }
 
     // Send vote to all our elders.
@@ -521,6 +1353,7 @@ impl Approved {
                 &others,
                 others.len(),
                 message.to_bytes(),
+                message.priority(),
             ));
         }
 
@@ -560,6 +1393,31 @@ impl Approved {
     // Message handling
     ////////////////////////////////////////////////////////////////////////////
 
+    // The node responsible for the replayed `msg` received from `sender`: the sender if it signed
+    // it. A direct message from another one of our members was relayed by it, so its signer is.
+    // Other messages are routed, so nobody can be blamed for them.
+    fn replayed_by(&self, sender: &SocketAddr, msg: &Message) -> Option<BlacklistEntry> {
+        let signer = msg.src().to_node_name().ok();
+        let sender_name = self
+            .section
+            .find_joined_member_by_addr(sender)
+            .map(|peer| *peer.name());
+
+        if sender_name.is_some() && sender_name == signer {
+            return Some(BlacklistEntry::Addr(*sender));
+        }
+
+        if let DstLocation::Direct = msg.dst() {
+            if sender_name.is_some() {
+                signer.map(BlacklistEntry::Name)
+            } else {
+                Some(BlacklistEntry::Addr(*sender))
+            }
+        } else {
+            None
+        }
+    }
+
     fn decide_message_status(&self, msg: &Message) -> Result<MessageStatus> {
         match msg.variant() {
             Variant::NeighbourInfo { .. } => {
@@ -567,13 +1425,30 @@ impl Approved {
                     return Ok(MessageStatus::Unknown);
                 }
             }
-            Variant::UserMessage(_) => {
+            Variant::ResourceChallenge { .. } => {
+                trace!("Dropping deprecated ResourceChallenge");
+                return Ok(MessageStatus::Useless);
+            }
+            Variant::Unknown { kind, .. } => {
+                // Join requests of other protocol versions are handled to reply to them.
+                if msg.variant().join_request_version().is_none() {
+                    debug!("Dropping message with unknown variant kind {}", kind);
+                    return Ok(MessageStatus::Useless);
+                }
+            }
+            Variant::UserMessage { .. } | Variant::UserMessageFragment(_) => {
                 if !self.should_handle_user_message(msg.dst()) {
                     return Ok(MessageStatus::Unknown);
                 }
             }
+            Variant::UserMessageMulticast { prefix, .. } => {
+                if !self.is_elder() && !prefix.matches(&self.node.name()) {
+                    return Ok(MessageStatus::Useless);
+                }
+            }
             Variant::JoinRequest(req) => {
-                if !self.should_handle_join_request(req) {
+                if req.protocol_version == PROTOCOL_VERSION && !self.should_handle_join_request(req)
+                {
                     // Note: We don't bounce this message because the current bounce-resend
                     // mechanism wouldn't preserve the original SocketAddr which is needed for
                     // properly handling this message.
@@ -587,7 +1462,24 @@ impl Approved {
                     return Ok(MessageStatus::Useless);
                 }
             }
-            Variant::NodeApproval { .. } | Variant::JoinRetry { .. } => {
+            Variant::Leave | Variant::ConnectRequest { .. } | Variant::Relay { .. } => {
+                if !self.is_elder() {
+                    return Ok(MessageStatus::Useless);
+                }
+            }
+            Variant::LeaveAck => {
+                if self.leave_ack_tx.is_none() {
+                    return Ok(MessageStatus::Useless);
+                }
+            }
+            Variant::ProbeAck { nonce } => {
+                if !self.pending_probes.contains_key(nonce) {
+                    return Ok(MessageStatus::Useless);
+                }
+            }
+            Variant::NodeApproval { .. }
+            | Variant::JoinRetry { .. }
+            | Variant::IncompatibleProtocolVersion { .. } => {
                 // Skip validation of these. We will validate them inside the bootstrap task.
                 return Ok(MessageStatus::Useful);
             }
@@ -615,14 +1507,25 @@ impl Approved {
                     }
                 }
             }
-            Variant::Sync { .. }
+            Variant::UserMessageAck { .. }
+            | Variant::UserMessageAnycast { .. }
+            | Variant::AnycastDelivered { .. }
+            | Variant::Sync { .. }
             | Variant::Relocate(_)
             | Variant::BouncedUntrustedMessage(_)
             | Variant::BouncedUnknownMessage { .. }
             | Variant::DKGMessage { .. }
             | Variant::DKGFailureObservation { .. }
             | Variant::DKGFailureAgreement { .. }
-            | Variant::ResourceChallenge { .. } => {}
+            | Variant::JoinChallenge { .. }
+            | Variant::ConnectIntroduction { .. } => {}
+        }
+
+        if self.is_lagging_behind(msg) {
+            // The sender knows a newer key of our section than we do. Bounce the message back
+            // with our latest key so we get brought up to date (see
+            // `handle_bounced_unknown_message`) and the message gets resent.
+            return Ok(MessageStatus::Unknown);
         }
 
         if self.verify_message(msg)? {
@@ -663,11 +1566,39 @@ impl Approved {
             Variant::RelocatePromise(promise) => {
                 self.handle_relocate_promise(*promise, msg.to_bytes())
             }
+            Variant::JoinRequest(join_request)
+                if join_request.protocol_version != PROTOCOL_VERSION =>
+            {
+                self.reject_incompatible_join_request(sender, join_request.protocol_version)
+            }
             Variant::JoinRequest(join_request) => {
                 let sender = sender.ok_or(Error::InvalidSrcLocation)?;
                 self.handle_join_request(msg.src().to_node_peer(sender)?, *join_request.clone())
             }
-            Variant::UserMessage(content) => self.handle_user_message(&msg, content.clone()),
+            Variant::UserMessage {
+                id,
+                content,
+                correlation_id,
+            } => self.handle_user_message(&msg, *id, content.clone(), *correlation_id),
+            Variant::UserMessageFragment(fragment) => {
+                self.handle_user_message_fragment(&msg, fragment.clone())
+            }
+            Variant::UserMessageMulticast { prefix, content } => {
+                Ok(self.handle_user_message_multicast(&msg, prefix, content.clone()))
+            }
+            Variant::UserMessageAnycast {
+                id,
+                content,
+                correlation_id,
+            } => self.handle_user_message_anycast(&msg, *id, content.clone(), *correlation_id),
+            Variant::AnycastDelivered { hash } => {
+                self.handle_anycast_delivered(msg.src().to_node_name()?, hash);
+                Ok(vec![])
+            }
+            Variant::UserMessageAck { hash, .. } => {
+                self.handle_user_message_ack(msg.src().to_node_name()?, hash);
+                Ok(vec![])
+            }
             Variant::BouncedUntrustedMessage(message) => {
                 let sender = sender.ok_or(Error::InvalidSrcLocation)?;
                 Ok(self
@@ -720,9 +1651,28 @@ impl Approved {
                 commands.extend(result?);
                 Ok(commands)
             }
+            Variant::Leave => self.handle_leave_request(&msg.src().to_node_name()?),
+            Variant::LeaveAck => {
+                self.handle_leave_ack(&msg.src().to_node_name()?);
+                Ok(vec![])
+            }
+            Variant::Probe { nonce } => self.handle_probe(sender, *nonce),
+            Variant::ProbeAck { nonce } => {
+                self.handle_probe_ack(sender, *nonce);
+                Ok(vec![])
+            }
+            Variant::ConnectRequest { target } => {
+                self.handle_connect_request(sender, &msg.src().to_node_name()?, target)
+            }
+            Variant::ConnectIntroduction { peer } => {
+                self.handle_connect_introduction(sender, &msg.src().to_node_name()?, peer)
+            }
+            Variant::Relay { dst, message } => self.handle_relay(sender, dst, message.clone()),
+            Variant::ResourceChallenge { .. } => Ok(vec![]),
             Variant::NodeApproval { .. }
             | Variant::JoinRetry { .. }
-            | Variant::ResourceChallenge { .. } => {
+            | Variant::JoinChallenge { .. }
+            | Variant::IncompatibleProtocolVersion { .. } => {
                 if let Some(RelocateState::InProgress(message_tx)) = &mut self.relocate_state {
                     if let Some(sender) = sender {
                         trace!("Forwarding {:?} to the bootstrap task", msg);
@@ -737,12 +1687,46 @@ impl Approved {
 
                 Ok(vec![])
             }
+            Variant::Unknown { .. } => {
+                if let Some(version) = msg.variant().join_request_version() {
+                    self.reject_incompatible_join_request(sender, version)
+                } else {
+                    Ok(vec![])
+                }
+            }
         }
     }
 
+    // Tell the joining peer it can't join because of its protocol version, so it can fail with a
+    // clear error instead of waiting for a response forever.
+    fn reject_incompatible_join_request(
+        &self,
+        sender: Option<SocketAddr>,
+        version: u16,
+    ) -> Result<Vec<Command>> {
+        let sender = sender.ok_or(Error::InvalidSrcLocation)?;
+        debug!(
+            "Rejecting JoinRequest from {} - incompatible protocol version {}",
+            sender, version
+        );
+
+        let variant = Variant::IncompatibleProtocolVersion {
+            version: PROTOCOL_VERSION,
+        };
+        Ok(vec![self.send_direct_message(&sender, variant)?])
+    }
+
     // Ignore `JoinRequest` if we are not elder unless the join request is outdated in which case we
     // reply with `BootstrapResponse::Join` with the up-to-date info (see `handle_join_request`).
     fn should_handle_join_request(&self, req: &JoinRequest) -> bool {
+        if req.network_id != self.network_params.network_id {
+            debug!(
+                "Ignoring JoinRequest for network {} - ours is {}",
+                req.network_id, self.network_params.network_id
+            );
+            return false;
+        }
+
         self.is_elder() || req.section_key != *self.section.chain().last_key()
     }
 
@@ -790,6 +1774,40 @@ impl Approved {
         }
     }
 
+    // Returns whether the message references a key of our section which is newer than our latest
+    // one.
+    fn is_lagging_behind(&self, msg: &Message) -> bool {
+        match msg.variant() {
+            // These are the messages used to bring us up to date, never bounce them.
+            Variant::Sync { .. }
+            | Variant::BouncedUntrustedMessage(_)
+            | Variant::BouncedUnknownMessage { .. } => return false,
+            _ => (),
+        }
+
+        let our_chain = self.section.chain();
+
+        // Only elders keep the whole section chain, so only they can tell that an unknown
+        // `dst_key` is newer and not older than any key they know.
+        if self.is_elder() {
+            if let Some(dst_key) = msg.dst_key() {
+                if !our_chain.has_key(dst_key) && !self.network.has_key(dst_key) {
+                    return true;
+                }
+            }
+        }
+
+        // Message from our section signed with a key that succeeds our latest one.
+        match (msg.src().as_section_prefix(), msg.proof_chain()) {
+            (Ok(prefix), Ok(proof_chain)) => {
+                prefix.matches(&self.node.name())
+                    && proof_chain.has_key(our_chain.last_key())
+                    && !our_chain.has_key(proof_chain.last_key())
+            }
+            _ => false,
+        }
+    }
+
     fn verify_message(&self, msg: &Message) -> Result<bool> {
         let known_keys = self
             .section
@@ -836,12 +1854,13 @@ impl Approved {
             None,
             Some(bounce_dst_key),
         )?;
+        let priority = bounce_msg.priority();
         let bounce_msg = bounce_msg.to_bytes();
 
         if let Some(sender) = sender {
-            Ok(Command::send_message_to_node(&sender, bounce_msg))
+            Ok(Command::send_message_to_node(&sender, bounce_msg, priority))
         } else {
-            Ok(self.send_message_to_our_elders(bounce_msg))
+            Ok(self.send_message_to_our_elders(bounce_msg, priority))
         }
     }
 
@@ -863,6 +1882,7 @@ impl Approved {
             None,
             None,
         )?;
+        let priority = bounce_msg.priority();
         let bounce_msg = bounce_msg.to_bytes();
 
         // If the message came from one of our elders then bounce it only to them to avoid message
@@ -875,9 +1895,9 @@ impl Approved {
         });
 
         if let Some(sender) = our_elder_sender {
-            Ok(Command::send_message_to_node(&sender, bounce_msg))
+            Ok(Command::send_message_to_node(&sender, bounce_msg, priority))
         } else {
-            Ok(self.send_message_to_our_elders(bounce_msg))
+            Ok(self.send_message_to_our_elders(bounce_msg, priority))
         }
     }
 
@@ -906,6 +1926,7 @@ impl Approved {
             Some(Command::send_message_to_node(
                 sender.addr(),
                 resend_msg.to_bytes(),
+                resend_msg.priority(),
             ))
         } else {
             trace!("    ...missing dst key, discarding");
@@ -925,7 +1946,7 @@ impl Approved {
             trace!(
                 "Received BouncedUnknownMessage({:?}) from {:?} \
                  - peer is up to date or ahead of us, discarding",
-                MessageHash::from_bytes(&bounced_msg_bytes),
+                Message::hash_of(&bounced_msg_bytes),
                 sender
             );
             return Ok(vec![]);
@@ -934,7 +1955,7 @@ impl Approved {
         trace!(
             "Received BouncedUnknownMessage({:?}) from {:?} \
              - peer is lagging behind, resending with Sync",
-            MessageHash::from_bytes(&bounced_msg_bytes),
+            Message::hash_of(&bounced_msg_bytes),
             sender,
         );
         // First send Sync to update the peer, then resend the message itself. If the messages
@@ -948,7 +1969,11 @@ impl Approved {
                     network: self.network.clone(),
                 },
             )?,
-            Command::send_message_to_node(sender.addr(), bounced_msg_bytes),
+            Command::send_message_to_node(
+                sender.addr(),
+                bounced_msg_bytes,
+                Priority::SectionKnowledge,
+            ),
         ])
     }
 
@@ -980,7 +2005,13 @@ impl Approved {
         Ok(commands)
     }
 
-    fn handle_user_message(&mut self, msg: &Message, content: Bytes) -> Result<Vec<Command>> {
+    fn handle_user_message(
+        &mut self,
+        msg: &Message,
+        id: u64,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+    ) -> Result<Vec<Command>> {
         let src = msg.src().clone();
         let dst = *msg.dst();
         if let DstLocation::EndUser(end_user) = &dst {
@@ -1003,6 +2034,7 @@ impl Approved {
                 recipients,
                 delivery_group_size: 1,
                 message: MessageType::ClientMessage(ClientMessage::from(content)?),
+                priority: Priority::UserData,
             }]);
         }
         if let DstLocation::AccumulatingNode(_name) = &dst {
@@ -1013,14 +2045,18 @@ impl Approved {
                     .add(&signed_bytes, proof_share.clone())
                 {
                     Ok(proof) => {
+                        self.stats.record_accumulation(true);
+                        self.pending_messages.remove(&signed_bytes);
                         trace!("Successfully aggregated signatures for message: {:?}", msg);
                         let key = msg.proof_chain_last_key()?;
                         if key.verify(&proof.signature, signed_bytes) {
-                            self.send_event(Event::MessageReceived {
+                            let _ = self.deliver_user_message(
+                                id,
                                 content,
-                                src: src.src_location(),
+                                src.src_location(),
                                 dst,
-                            });
+                                correlation_id,
+                            );
                         } else {
                             trace!(
                                 "Aggregated signature is invalid. Handling message {:?} skipped",
@@ -1028,8 +2064,17 @@ impl Approved {
                             );
                         }
                     }
-                    Err(AggregatorError::NotEnoughShares) => {}
+                    Err(AggregatorError::NotEnoughShares) => {
+                        self.pending_messages
+                            .insert_with(&signed_bytes, || PendingMessage {
+                                hash: *msg.hash(),
+                                src: src.src_location(),
+                                dst,
+                                correlation_id,
+                            });
+                    }
                     Err(err) => {
+                        self.stats.record_accumulation(false);
                         trace!("Error accumulating message at destination: {:?}", err);
                     }
                 }
@@ -1039,12 +2084,286 @@ impl Approved {
             }
         }
 
-        self.send_event(Event::MessageReceived {
-            content,
-            src: src.src_location(),
-            dst,
+        let _ = self.deliver_user_message(id, content, src.src_location(), dst, correlation_id);
+
+        Ok(self.ack_user_message(msg)?.into_iter().collect())
+    }
+
+    // Raise `MessageReceived` for a user message, unless it was already delivered, because the
+    // source sent it again. The copies are told apart by the correlation id if the message has
+    // one, otherwise by the id routing assigned to it. Returns whether the message was delivered.
+    fn deliver_user_message(
+        &mut self,
+        id: u64,
+        content: Bytes,
+        src: SrcLocation,
+        dst: DstLocation,
+        correlation_id: Option<CorrelationId>,
+    ) -> bool {
+        if !self
+            .msg_filter
+            .insert_user_message(&src, id, correlation_id.as_ref(), &content)
+        {
+            trace!(
+                "Not delivering message {:x} ({:?}) from {:?} - already delivered",
+                id,
+                correlation_id,
+                src
+            );
+            return false;
+        }
+
+        self.send_event(Event::MessageReceived {
+            content,
+            src,
+            dst,
+            correlation_id,
+        });
+        true
+    }
+
+    fn handle_user_message_fragment(
+        &mut self,
+        msg: &Message,
+        fragment: Fragment,
+    ) -> Result<Vec<Command>> {
+        let src = msg.src().src_location();
+        let dst = *msg.dst();
+        if !matches!(dst, DstLocation::Node(_) | DstLocation::Section(_)) {
+            return Err(Error::InvalidDstLocation);
+        }
+
+        let id = fragment.id;
+        match self.fragment_assembler.add(src, fragment) {
+            Ok(Some((content, correlation_id))) => {
+                let _ = self.deliver_user_message(id, content, src, dst, correlation_id);
+            }
+            Ok(None) => (),
+            Err(error) => {
+                // Not acknowledged, so the source retries later.
+                warn!("Dropping user message fragment {:?}: {}", msg, error);
+                return Ok(vec![]);
+            }
+        }
+
+        Ok(self.ack_user_message(msg)?.into_iter().collect())
+    }
+
+    fn handle_user_message_multicast(
+        &mut self,
+        msg: &Message,
+        prefix: &Prefix,
+        content: Bytes,
+    ) -> Vec<Command> {
+        let mut commands = vec![];
+
+        // Adults don't receive section messages, so forward it to those within the prefix.
+        if self.is_elder() {
+            let recipients: Vec<_> = self
+                .section
+                .adults()
+                .filter(|peer| prefix.matches(peer.name()))
+                .map(|peer| *peer.addr())
+                .collect();
+            if !recipients.is_empty() {
+                commands.push(Command::send_message_to_nodes(
+                    &recipients,
+                    recipients.len(),
+                    msg.to_relayed_bytes(),
+                    msg.priority(),
+                ));
+            }
+        }
+
+        if prefix.matches(&self.node.name()) {
+            self.send_event(Event::MulticastReceived {
+                content,
+                src: msg.src().src_location(),
+                prefix: *prefix,
+            });
+        }
+
+        commands
+    }
+
+    fn handle_user_message_anycast(
+        &mut self,
+        msg: &Message,
+        id: u64,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+    ) -> Result<Vec<Command>> {
+        let target = if let DstLocation::Section(target) = msg.dst() {
+            *target
+        } else {
+            return Err(Error::InvalidDstLocation);
+        };
+
+        // Elders forward the message to the member closest to the target. Adults only receive it
+        // when they are that member.
+        if self.is_elder() {
+            let closest = self
+                .section
+                .active_members()
+                .min_by(|lhs, rhs| target.cmp_distance(lhs.name(), rhs.name()))
+                .copied();
+            if let Some(closest) = closest {
+                if *closest.name() != self.node.name() {
+                    return Ok(vec![Command::send_message_to_nodes(
+                        slice::from_ref(closest.addr()),
+                        1,
+                        msg.to_relayed_bytes(),
+                        msg.priority(),
+                    )]);
+                }
+            }
+        }
+
+        let src = msg.src().src_location();
+        let delivered = self.deliver_user_message(id, content, src, *msg.dst(), correlation_id);
+
+        let src_name = msg.src().to_node_name()?;
+        if src_name == self.node.name() {
+            let _ = self.delivery_tracker.handle_ack(msg.hash());
+            if delivered {
+                self.send_event(Event::AnycastDelivered {
+                    recipient: src_name,
+                    correlation_id,
+                });
+            }
+            return Ok(vec![]);
+        }
+
+        // Reported even if already delivered, as the source is retrying for lack of the report.
+        let variant = Variant::AnycastDelivered { hash: *msg.hash() };
+        let report =
+            Message::single_src(&self.node, DstLocation::Node(src_name), variant, None, None)?;
+        Ok(self.relay_message(&report)?.into_iter().collect())
+    }
+
+    // Acknowledge the received user message to its source node, so it doesn't send it again.
+    fn ack_user_message(&mut self, msg: &Message) -> Result<Option<Command>> {
+        let src_name = if let SrcAuthority::Node { .. } = msg.src() {
+            msg.src().to_node_name()?
+        } else {
+            return Ok(None);
+        };
+
+        if src_name == self.node.name() {
+            return Ok(None);
+        }
+
+        // The source only waits for ack of section messages sent outside of its own section.
+        if msg.dst().is_section() && self.section.prefix().matches(&src_name) {
+            return Ok(None);
+        }
+
+        let variant = Variant::UserMessageAck { hash: *msg.hash() };
+        let ack =
+            Message::single_src(&self.node, DstLocation::Node(src_name), variant, None, None)?;
+        self.relay_message(&ack)
+    }
+
+    fn handle_user_message_ack(&mut self, sender: XorName, hash: &MessageHash) {
+        let msg = if let Some(msg) = self.delivery_tracker.pending(hash) {
+            msg
+        } else {
+            return;
+        };
+
+        let dst = *msg.dst();
+        let priority = msg.priority();
+        let (fragment_id, correlation_id) = match msg.variant() {
+            Variant::UserMessage { correlation_id, .. } => (None, *correlation_id),
+            Variant::UserMessageFragment(fragment) => (Some(fragment.id), fragment.correlation_id),
+            _ => (None, None),
+        };
+
+        // Only the destination of the message can acknowledge it.
+        let valid = match msg.dst() {
+            DstLocation::Node(name) => *name == sender,
+            DstLocation::Section(name) => iter::once(self.section.elders_info())
+                .chain(self.network.all())
+                .any(|info| info.prefix.matches(name) && info.elders.contains_key(&sender)),
+            _ => false,
+        };
+
+        if !valid {
+            debug!(
+                "Ignoring ack of {:?} from {} - not the message destination",
+                hash, sender
+            );
+            return;
+        }
+
+        let round_trip = self.delivery_tracker.round_trip(hash);
+        if !self.delivery_tracker.handle_ack(hash) {
+            return;
+        }
+
+        trace!("Message {:?} acknowledged by {}", hash, sender);
+
+        // Measured by our own clock, from the send to the ack, so the clocks of the other nodes
+        // don't matter.
+        if let Some(round_trip) = round_trip {
+            self.stats.record_latency(priority.name(), round_trip);
+        }
+
+        // A message sent as fragments is delivered once all of them are acknowledged.
+        let delivered = fragment_id
+            .map(|id| self.delivery_tracker.fragment_acked(id))
+            .unwrap_or(true);
+
+        // Only the messages sent with a correlation id are confirmed, as there is no other way to
+        // tell them apart.
+        if let Some(correlation_id) = correlation_id.filter(|_| delivered) {
+            self.send_event(Event::MessageDelivered {
+                dst,
+                correlation_id,
+            });
+        }
+    }
+
+    fn handle_anycast_delivered(&mut self, sender: XorName, hash: &MessageHash) {
+        let msg = if let Some(msg) = self.delivery_tracker.pending(hash) {
+            msg
+        } else {
+            return;
+        };
+
+        let (target, correlation_id) = match (msg.dst(), msg.variant()) {
+            (DstLocation::Section(target), Variant::UserMessageAnycast { correlation_id, .. }) => {
+                (*target, *correlation_id)
+            }
+            _ => return,
+        };
+        let priority = msg.priority();
+
+        // Only a member of the target section can receive the message.
+        let valid = iter::once(self.section.prefix())
+            .chain(self.network.prefixes())
+            .any(|prefix| prefix.matches(&target) && prefix.matches(&sender));
+        if !valid {
+            debug!(
+                "Ignoring delivery report of {:?} from {} - not in the target section",
+                hash, sender
+            );
+            return;
+        }
+
+        let round_trip = self.delivery_tracker.round_trip(hash);
+        if !self.delivery_tracker.handle_ack(hash) {
+            return;
+        }
+
+        if let Some(round_trip) = round_trip {
+            self.stats.record_latency(priority.name(), round_trip);
+        }
+
+        self.send_event(Event::AnycastDelivered {
+            recipient: sender,
+            correlation_id,
         });
-        Ok(vec![])
     }
 
     fn handle_sync(&mut self, section: Section, network: Network) -> Result<Vec<Command>> {
@@ -1126,7 +2445,7 @@ impl Approved {
 
             // We are no longer elder. Send the promise back already.
             if !self.is_elder() {
-                commands.push(self.send_message_to_our_elders(msg_bytes));
+                commands.push(self.send_message_to_our_elders(msg_bytes, Priority::Membership));
             }
 
             return Ok(commands);
@@ -1242,16 +2561,21 @@ impl Approved {
 
         // Require resource proof only if joining as a new node.
         if previous_name.is_none() {
-            if let Some(response) = join_request.resource_proof_response {
-                if !self.validate_resource_proof_response(peer.name(), response) {
+            if let Some(response) = join_request.join_proof_response {
+                if !self.join_proof.verify(peer.name(), &response) {
                     debug!(
-                        "Ignoring JoinRequest from {} - invalid resource proof response",
+                        "Ignoring JoinRequest from {} - invalid join proof response",
                         peer
                     );
                     return Ok(vec![]);
                 }
             } else {
-                return Ok(vec![self.send_resource_proof_challenge(&peer)?]);
+                let challenge = self
+                    .join_proof
+                    .challenge(peer.name(), self.section.prefix())?;
+                let variant = Variant::JoinChallenge { challenge };
+                trace!("Sending {:?} to {}", variant, peer);
+                return Ok(vec![self.send_direct_message(peer.addr(), variant)?]);
             }
         }
 
@@ -1262,44 +2586,6 @@ impl Approved {
         })
     }
 
-    fn validate_resource_proof_response(
-        &self,
-        peer_name: &XorName,
-        response: ResourceProofResponse,
-    ) -> bool {
-        let serialized = if let Ok(serialized) = bincode::serialize(&(peer_name, &response.nonce)) {
-            serialized
-        } else {
-            return false;
-        };
-
-        if self
-            .node
-            .keypair
-            .public
-            .verify(&serialized, &response.nonce_signature)
-            .is_err()
-        {
-            return false;
-        }
-
-        self.resource_proof
-            .validate_all(&response.nonce, &response.data, response.solution)
-    }
-
-    fn send_resource_proof_challenge(&self, peer: &Peer) -> Result<Command> {
-        let nonce: [u8; 32] = rand::random();
-        let serialized = bincode::serialize(&(peer.name(), &nonce))?;
-        let response = Variant::ResourceChallenge {
-            data_size: RESOURCE_PROOF_DATA_SIZE,
-            difficulty: RESOURCE_PROOF_DIFFICULTY,
-            nonce,
-            nonce_signature: crypto::sign(&serialized, &self.node.keypair),
-        };
-
-        self.send_direct_message(peer.addr(), response)
-    }
-
     fn handle_dkg_start(
         &mut self,
         dkg_key: DkgKey,
@@ -1358,7 +2644,7 @@ impl Approved {
 
         if !self
             .section
-            .promote_and_demote_elders(&self.node.name())
+            .promote_and_demote_elders(&self.network_params, &self.node.name())
             .contains(&elders_info)
         {
             trace!(
@@ -1381,7 +2667,10 @@ impl Approved {
     fn promote_and_demote_elders(&mut self) -> Result<Vec<Command>> {
         let mut commands = vec![];
 
-        for info in self.section.promote_and_demote_elders(&self.node.name()) {
+        for info in self
+            .section
+            .promote_and_demote_elders(&self.network_params, &self.node.name())
+        {
             commands.extend(self.send_dkg_start(info)?);
         }
 
@@ -1396,7 +2685,7 @@ impl Approved {
         let mut commands = vec![];
 
         // Do not carry out relocation when there is not enough elder nodes.
-        if self.section.elders_info().elders.len() < ELDER_SIZE {
+        if self.section.elders_info().elders.len() < self.network_params.elder_size {
             return Ok(commands);
         }
 
@@ -1447,11 +2736,34 @@ impl Approved {
         self.send_relocate(peer, details)
     }
 
+    // Destination to relocate a newly joining node to, derived from the signature of its approval
+    // so nodes can't choose their section by choosing their name. `None` if it should join our
+    // section instead.
+    fn new_peer_destination(&self, name: &XorName, signature: &bls::Signature) -> Option<XorName> {
+        // Do not carry out relocation when there is not enough elder nodes.
+        if self.section.elders_info().elders.len() < self.network_params.elder_size {
+            return None;
+        }
+
+        relocation::join_destination(name, signature, self.section.prefix())
+    }
+
+    // Relocate a newly joining node to `destination` without making it a member of our section.
+    fn relocate_new_peer(&self, peer: &Peer, destination: XorName) -> Result<Vec<Command>> {
+        let details =
+            RelocateDetails::with_age(&self.section, &self.network, peer, destination, peer.age());
+
+        trace!("Relocating new node {:?} to {}", peer, details.destination);
+
+        self.send_relocate(peer, details)
+    }
+
     // Are we in the startup phase? Startup phase is when the network consists of only one section
     // and it has no more than `recommended_section_size` members.
     fn is_in_startup_phase(&self) -> bool {
         self.section.prefix().is_empty()
-            && self.section.members().joined().count() <= RECOMMENDED_SECTION_SIZE
+            && self.section.members().joined().count()
+                <= self.network_params.recommended_section_size
     }
 
     fn handle_online_event(
@@ -1465,10 +2777,10 @@ impl Approved {
 
         let is_startup_phase = self.is_in_startup_phase();
 
-        if let Some(old_info) = self.section.members().get_proven(new_info.peer.name()) {
+        if let Some(old_info) = self.section.members().find_proven(new_info.peer.name()) {
             // This node is rejoin with same name.
 
-            if old_info.value.state != PeerState::Left {
+            if !old_info.value.state.is_left() {
                 debug!(
                     "Ignoring Online node {} - {:?} not Left.",
                     new_info.peer.name(),
@@ -1478,7 +2790,12 @@ impl Approved {
                 return Ok(commands);
             }
 
-            let new_age = cmp::max(MIN_AGE, old_info.value.peer.age() / 2);
+            // A node that asked to leave, e.g. for a planned restart, is not penalized.
+            let new_age = if old_info.value.state == PeerState::LeftVoluntarily {
+                old_info.value.peer.age()
+            } else {
+                cmp::max(MIN_AGE, old_info.value.peer.age() / 2)
+            };
 
             if new_age > MIN_AGE {
                 // TODO: consider handling the relocation inside the bootstrap phase, to avoid
@@ -1488,6 +2805,10 @@ impl Approved {
 
                 return Ok(commands);
             }
+
+            // Too young to rejoin. Must not be treated as a new node either.
+            info!("ignore Online: {:?}", new_info.peer);
+            return Ok(commands);
         }
 
         let new_info = Proven {
@@ -1495,12 +2816,28 @@ impl Approved {
             proof,
         };
 
+        // Newly joining nodes are relocated before they become members, so they never count
+        // towards our section.
+        if previous_name.is_none() && !is_startup_phase {
+            if let Some(destination) =
+                self.new_peer_destination(new_info.value.peer.name(), &new_info.proof.signature)
+            {
+                let peer = new_info.value.peer;
+                commands.push(self.send_node_approval(new_info, their_knowledge)?);
+                commands.extend(self.relocate_new_peer(&peer, destination)?);
+
+                return Ok(commands);
+            }
+        }
+
         if !self.section.update_member(new_info.clone()) {
             info!("ignore Online: {:?}", new_info.value.peer);
             return Ok(vec![]);
         }
 
         info!("handle Online: {:?}", new_info.value.peer);
+        self.stats
+            .record_churn(*self.section.prefix(), ChurnKind::Join);
 
         self.send_event(Event::MemberJoined {
             name: *new_info.value.peer.name(),
@@ -1528,6 +2865,11 @@ impl Approved {
 
         let peer = member_info.peer;
         let age = peer.age();
+        let kind = if let PeerState::Relocated(_) = member_info.state {
+            ChurnKind::Relocation
+        } else {
+            ChurnKind::Leave
+        };
         let signature = proof.signature.clone();
 
         if !self.section.update_member(Proven {
@@ -1539,6 +2881,13 @@ impl Approved {
         }
 
         info!("handle Offline: {:?}", peer);
+        self.stats.record_churn(*self.section.prefix(), kind);
+
+        self.rendezvous.remove(peer.addr());
+
+        if self.leaving_members.remove(peer.name()) {
+            commands.push(self.send_direct_message(peer.addr(), Variant::LeaveAck)?);
+        }
 
         commands.extend(self.relocate_peers(peer.name(), &signature)?);
         commands.extend(self.promote_and_demote_elders()?);
@@ -1567,7 +2916,7 @@ impl Approved {
             // Our section
             if self
                 .section
-                .promote_and_demote_elders(&self.node.name())
+                .promote_and_demote_elders(&self.network_params, &self.node.name())
                 .contains(&elders_info.value)
             {
                 if prefix_is_extension {
@@ -1684,6 +3033,7 @@ impl Approved {
         let old_is_elder = self.is_elder();
         let old_last_key = *self.section.chain().last_key();
         let old_prefix = *self.section.prefix();
+        let old_roles = self.section.roles();
 
         self.section.merge(section)?;
         self.network.merge(network, self.section.chain());
@@ -1697,6 +3047,7 @@ impl Approved {
 
         if new_prefix != old_prefix {
             info!("Split");
+            self.stats.record_churn(old_prefix, ChurnKind::Split);
 
             if new_is_elder && self.section_keys_provider.has_key_share() {
                 // We can update the sibling knowledge already because we know they also reached
@@ -1710,6 +3061,7 @@ impl Approved {
         }
 
         if new_last_key != old_last_key {
+            self.update_span();
             self.msg_filter.reset();
 
             if new_is_elder {
@@ -1726,6 +3078,8 @@ impl Approved {
                     commands.extend(self.vote(Vote::JoinsAllowed(self.joins_allowed))?);
                 }
 
+                commands.extend(self.update_end_users());
+
                 self.print_network_stats();
             }
 
@@ -1761,13 +3115,57 @@ impl Approved {
             });
         }
 
+        for (name, role) in self.section.roles() {
+            match old_roles.get(&name) {
+                Some(previous_role) if *previous_role != role => {
+                    self.send_event(Event::MemberRoleChanged {
+                        name,
+                        previous_role: *previous_role,
+                        role,
+                    })
+                }
+                _ => (),
+            }
+        }
+
         if !new_is_elder {
             commands.extend(self.return_relocate_promise());
         }
 
+        // Losing a new section key on restart would leave us unable to trust our section.
+        if new_last_key != old_last_key {
+            self.store_state();
+        } else {
+            self.state_dirty = true;
+        }
+
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants();
+
         Ok(commands)
     }
 
+    #[cfg(feature = "debug-invariants")]
+    fn assert_invariants(&self) {
+        self.section.assert_invariants();
+        self.network.assert_invariants(self.section.prefix());
+    }
+
+    // Snapshot the state to store and write it in the background, so the disk write doesn't
+    // happen while the state is locked. Other than on section key changes, called on a timer
+    // once the state changed, so the many votes and consensuses during churn coalesce into one
+    // write.
+    fn store_state(&mut self) {
+        self.state_dirty = false;
+        if let Some(storage) = &self.storage {
+            storage.store_in_background(StoredState {
+                section: self.section.clone(),
+                network: self.network.clone(),
+                votes: self.vote_accumulator.pending_shares(),
+            });
+        }
+    }
+
     /* FIXME: bring back unresponsiveness detection
     // Detect non-responsive peers and vote them out.
     fn vote_for_remove_unresponsive_peers(&mut self, core: &mut Core) -> Result<()> {
@@ -1833,7 +3231,11 @@ impl Approved {
             None,
         )?;
 
-        Ok(Command::send_message_to_node(&addr, message.to_bytes()))
+        Ok(Command::send_message_to_node(
+            &addr,
+            message.to_bytes(),
+            message.priority(),
+        ))
     }
 
     fn send_sync(&mut self, section: Section, network: Network) -> Result<Vec<Command>> {
@@ -1848,6 +3250,7 @@ impl Approved {
                 &recipients,
                 recipients.len(),
                 message.to_bytes(),
+                message.priority(),
             ))
         };
 
@@ -1911,18 +3314,148 @@ impl Approved {
     fn return_relocate_promise(&self) -> Option<Command> {
         // TODO: keep sending this periodically until we get relocated.
         if let Some(RelocateState::Delayed(bytes)) = &self.relocate_state {
-            Some(self.send_message_to_our_elders(bytes.clone()))
+            Some(self.send_message_to_our_elders(bytes.clone(), Priority::Membership))
         } else {
             None
         }
     }
 
+    // Schedule all the periodic timers of this node. Called on start and again after relocation,
+    // as the timers of the state replaced by the relocation are not handled anymore.
+    pub fn schedule_periodic_timers(&mut self) -> Vec<Command> {
+        vec![
+            self.schedule_neighbour_refresh(),
+            self.schedule_accumulation_cleanup(),
+            self.schedule_keepalive(),
+            self.schedule_storage_flush(),
+        ]
+    }
+
+    // Schedule the next write of the state changed since it was last stored.
+    pub fn schedule_storage_flush(&mut self) -> Command {
+        self.storage_flush_timer_token = command::next_timer_token();
+        Command::ScheduleTimeout {
+            duration: STORAGE_FLUSH_INTERVAL,
+            token: self.storage_flush_timer_token,
+        }
+    }
+
+    // Schedule the next periodic exchange of section info with our neighbours.
+    pub fn schedule_neighbour_refresh(&mut self) -> Command {
+        self.neighbour_refresh_timer_token = command::next_timer_token();
+        Command::ScheduleTimeout {
+            duration: NEIGHBOUR_REFRESH_INTERVAL,
+            token: self.neighbour_refresh_timer_token,
+        }
+    }
+
+    // Schedule the next periodic removal of the signature accumulations that failed to reach
+    // quorum.
+    pub fn schedule_accumulation_cleanup(&mut self) -> Command {
+        self.accumulation_cleanup_timer_token = command::next_timer_token();
+        Command::ScheduleTimeout {
+            duration: ACCUMULATION_CLEANUP_INTERVAL,
+            token: self.accumulation_cleanup_timer_token,
+        }
+    }
+
+    // Schedule the next periodic probe of the liveness of our peers.
+    pub fn schedule_keepalive(&mut self) -> Command {
+        self.keepalive_timer_token = command::next_timer_token();
+        Command::ScheduleTimeout {
+            duration: KEEPALIVE_INTERVAL,
+            token: self.keepalive_timer_token,
+        }
+    }
+
+    fn remove_expired_accumulations(&mut self) {
+        let now = Instant::now();
+
+        for vote in self.vote_accumulator.remove_expired(now) {
+            self.stats.record_accumulation(false);
+            warn!("Vote expired without reaching quorum: {:?}", vote);
+        }
+
+        for msg in self.pending_messages.remove_expired(now) {
+            self.stats.record_accumulation(false);
+            warn!(
+                "Message {:?} from {:?} expired without accumulating enough signatures",
+                msg.hash, msg.src
+            );
+            self.send_event(Event::MessageAccumulationExpired {
+                src: msg.src,
+                dst: msg.dst,
+                correlation_id: msg.correlation_id,
+            });
+        }
+    }
+
+    // Send our section info to all our neighbours. The message carries the latest key of theirs we
+    // know of, so if our knowledge of them is outdated they detect it and send us their latest
+    // info back (see `update_section_knowledge`).
+    fn refresh_neighbours(&mut self) -> Result<Vec<Command>> {
+        let mut commands = vec![self.schedule_neighbour_refresh()];
+
+        if !self.is_elder() {
+            return Ok(commands);
+        }
+
+        let nonce = MessageHash::from_bytes(&self.neighbour_refresh_timer_token.to_be_bytes());
+        let our_prefix = *self.section.prefix();
+        let neighbours: Vec<_> = self
+            .network
+            .prefixes()
+            .filter(|prefix| prefix.is_neighbour(&our_prefix))
+            .copied()
+            .collect();
+
+        for prefix in neighbours {
+            let dst_key = self.network.key_by_prefix(&prefix).copied();
+
+            if self.network.knowledge_by_section(&prefix) < self.section.chain().last_key_index() {
+                // Don't rely on a single delivery group relaying our info to them. Send it to
+                // all their elders instead, so they catch up with us as soon as possible.
+                debug!("Neighbour ({:b}) has outdated knowledge of us", prefix);
+
+                let recipients: Vec<_> = self
+                    .network
+                    .get(&prefix)
+                    .into_iter()
+                    .flat_map(|elders_info| elders_info.peers())
+                    .map(|peer| *peer.addr())
+                    .collect();
+                let msg = self.neighbour_info_message(prefix, nonce, dst_key)?;
+
+                commands.push(Command::send_message_to_nodes(
+                    &recipients,
+                    recipients.len(),
+                    msg.to_relayed_bytes(),
+                    msg.priority(),
+                ));
+            } else {
+                commands.extend(self.send_neighbour_info(prefix, nonce, dst_key)?);
+            }
+        }
+
+        Ok(commands)
+    }
+
     fn send_neighbour_info(
         &mut self,
         dst: Prefix,
         nonce: MessageHash,
         dst_key: Option<bls::PublicKey>,
     ) -> Result<Option<Command>> {
+        let msg = self.neighbour_info_message(dst, nonce, dst_key)?;
+        self.relay_message(&msg)
+    }
+
+    fn neighbour_info_message(
+        &self,
+        dst: Prefix,
+        nonce: MessageHash,
+        dst_key: Option<bls::PublicKey>,
+    ) -> Result<Message> {
         let proof_chain = self
             .section
             .create_proof_chain_for_our_info(Some(self.network.knowledge_by_section(&dst)));
@@ -1931,15 +3464,13 @@ impl Approved {
             nonce,
         };
         trace!("sending NeighbourInfo {:?}", variant);
-        let msg = Message::single_src(
+        Ok(Message::single_src(
             &self.node,
             DstLocation::Section(dst.name()),
             variant,
             Some(proof_chain),
             dst_key,
-        )?;
-
-        self.relay_message(&msg)
+        )?)
     }
 
     fn send_dkg_start(&self, elders_info: EldersInfo) -> Result<Vec<Command>> {
@@ -1968,12 +3499,33 @@ impl Approved {
     // Send message over the network.
     pub fn relay_message(&mut self, msg: &Message) -> Result<Option<Command>> {
         let (targets, dg_size) = delivery_group::delivery_targets(
+            &self.network_params,
             msg.dst(),
             &self.node.name(),
             &self.section,
             &self.network,
         )?;
 
+        // Send critical messages for other sections over multiple routes starting at different
+        // sections, each of which relays it independently.
+        let (targets, dg_size) = match (msg.dst(), msg.priority()) {
+            (DstLocation::Section(name), Priority::Consensus)
+            | (DstLocation::Section(name), Priority::Membership)
+                if self.network_params.redundant_routes > 1
+                    && !self.section.prefix().matches(name) =>
+            {
+                delivery_group::add_redundant_routes(
+                    &self.network_params,
+                    (targets, dg_size),
+                    name,
+                    self.network_params.redundant_routes,
+                    &self.section,
+                    &self.network,
+                )
+            }
+            _ => (targets, dg_size),
+        };
+
         let targets: Vec<_> = targets
             .into_iter()
             .filter(|peer| self.msg_filter.filter_outgoing(msg, peer.name()).is_new())
@@ -1983,19 +3535,99 @@ impl Approved {
             return Ok(None);
         }
 
+        let dg_size = cmp::min(dg_size, targets.len());
+
+        if msg.hop_count() >= self.network_params.max_hop_count {
+            warn!(
+                "Dropping message {:?} - reached the hop limit of {}",
+                msg.hash(),
+                self.network_params.max_hop_count
+            );
+            trace_hop(msg, "dropped");
+            return Ok(None);
+        }
+
         trace!("relay {:?} to {:?}", msg, targets);
+        trace_hop(msg, "relayed");
 
         let targets: Vec<_> = targets.into_iter().map(|node| *node.addr()).collect();
-        let command = Command::send_message_to_nodes(&targets, dg_size, msg.to_bytes());
+        let command = Command::send_message_to_nodes(
+            &targets,
+            dg_size,
+            msg.to_relayed_bytes(),
+            msg.priority(),
+        );
 
         Ok(Some(command))
     }
 
+    // Copy of a message we originated with a fresh nonce and thus a different hash. The variant is
+    // kept, including the id of a user message, so the destination can tell it's the same one.
+    fn renew_message(&self, msg: &Message) -> Result<Message> {
+        let variant = msg.variant().clone();
+        if msg.is_traced() {
+            Ok(Message::single_src_traced(&self.node, *msg.dst(), variant)?)
+        } else {
+            Ok(Message::single_src(
+                &self.node,
+                *msg.dst(),
+                variant,
+                None,
+                None,
+            )?)
+        }
+    }
+
+    // Send a message we originated and wait for the destination to acknowledge it. Retries prefer
+    // the delivery targets not tried before, so the message takes an alternate route.
+    fn send_message_for_ack(
+        &mut self,
+        msg: Message,
+        attempts: usize,
+        mut tried: BTreeSet<XorName>,
+    ) -> Result<Vec<Command>> {
+        let (mut targets, dg_size) = delivery_group::delivery_targets(
+            &self.network_params,
+            msg.dst(),
+            &self.node.name(),
+            &self.section,
+            &self.network,
+        )?;
+
+        // Stable sort, so the targets keep their priority otherwise.
+        targets.sort_by_key(|peer| tried.contains(peer.name()));
+
+        for peer in &targets {
+            let _ = self.msg_filter.filter_outgoing(&msg, peer.name());
+        }
+        tried.extend(targets.iter().take(dg_size).map(|peer| *peer.name()));
+
+        let mut commands = vec![];
+        if !targets.is_empty() {
+            trace_hop(&msg, "sent");
+            let targets: Vec<_> = targets.iter().map(|peer| *peer.addr()).collect();
+            commands.push(Command::send_message_to_nodes(
+                &targets,
+                dg_size,
+                msg.to_bytes(),
+                msg.priority(),
+            ));
+        }
+
+        let token = self.delivery_tracker.track(msg, attempts, tried);
+        commands.push(Command::ScheduleTimeout {
+            duration: ACK_TIMEOUT,
+            token,
+        });
+
+        Ok(commands)
+    }
+
     pub fn check_key_status(&self, bls_pk: &bls::PublicKey) -> Result<(), TargetSectionError> {
         // Whenever there is EldersInfo change candidate, it is considered as having ongoing DKG.
         if !self
             .section
-            .promote_and_demote_elders(&self.node.name())
+            .promote_and_demote_elders(&self.network_params, &self.node.name())
             .is_empty()
         {
             return Err(TargetSectionError::DkgInProgress);
@@ -2036,6 +3668,8 @@ impl Approved {
         src: SrcLocation,
         dst: DstLocation,
         content: Bytes,
+        correlation_id: Option<CorrelationId>,
+        traced: bool,
     ) -> Result<Vec<Command>> {
         if !src.contains(&self.node.name()) {
             error!(
@@ -2058,48 +3692,229 @@ impl Approved {
             return Err(Error::InvalidSrcLocation);
         }
 
+        // Only the messages signed by a single node carry the trace flag.
+        if traced && !matches!(src, SrcLocation::Node(_)) {
+            error!(
+                "Not sending user message {:?} -> {:?}: only a single node src can be traced",
+                src, dst
+            );
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        if traced && matches!(dst, DstLocation::AccumulatingNode(_)) {
+            error!(
+                "Not sending user message {:?} -> {:?}: dst accumulation can't be traced",
+                src, dst
+            );
+            return Err(Error::InvalidDstLocation);
+        }
+
         match src {
             SrcLocation::Node(_) => {
                 // If the source is a single node, we don't even need to vote, so let's cut this short.
-                let msg = if let DstLocation::AccumulatingNode(name) = dst {
-                    Message::for_dst_accumulation(
+                let msgs = if let DstLocation::AccumulatingNode(name) = dst {
+                    // The messages of all the senders must be the same to accumulate.
+                    let id = derived_user_message_id(&dst, &content, correlation_id.as_ref())?;
+                    vec![Message::for_dst_accumulation(
                         &self.node,
                         self.section_keys_provider.key_share()?,
                         name,
+                        id,
                         content,
+                        correlation_id,
                         self.section().create_proof_chain_for_our_info(None),
                         None,
-                    )?
+                    )?]
                 } else {
-                    let variant = Variant::UserMessage(content);
-                    Message::single_src(&self.node, dst, variant, None, None)?
+                    let mut msgs = vec![];
+                    let id = rand::random();
+                    for variant in self.user_message_variants(&dst, id, content, correlation_id)? {
+                        let msg = if traced {
+                            Message::single_src_traced(&self.node, dst, variant)?
+                        } else {
+                            Message::single_src(&self.node, dst, variant, None, None)?
+                        };
+                        msgs.push(msg);
+                    }
+                    msgs
                 };
+
                 let mut commands = vec![];
 
-                if dst.contains(&self.node.name(), self.section.prefix()) {
-                    commands.push(Command::HandleMessage {
-                        sender: Some(self.node.addr),
-                        message: msg.clone(),
-                    });
+                for msg in msgs {
+                    if dst.contains(&self.node.name(), self.section.prefix()) {
+                        commands.push(Command::HandleMessage {
+                            sender: Some(self.node.addr),
+                            message: msg.clone(),
+                        });
+                        commands.extend(self.relay_message(&msg)?);
+                    } else if let DstLocation::AccumulatingNode(_) | DstLocation::EndUser(_) = dst {
+                        // Clients never acknowledge messages, so don't wait for it.
+                        commands.extend(self.relay_message(&msg)?);
+                    } else {
+                        commands.extend(self.send_message_for_ack(msg, 1, BTreeSet::new())?);
+                    }
                 }
 
-                commands.extend(self.relay_message(&msg)?);
-
                 Ok(commands)
             }
             SrcLocation::Section(_) => {
-                let variant = Variant::UserMessage(content);
-                let vote = self.create_send_message_vote(dst, variant, None)?;
                 let recipients = delivery_group::signature_targets(
+                    &self.network_params,
                     &dst,
                     self.section.elders_info().peers().copied(),
                 );
-                self.send_vote(&recipients, vote)
+
+                let id = derived_user_message_id(&dst, &content, correlation_id.as_ref())?;
+                let mut commands = vec![];
+                for variant in self.user_message_variants(&dst, id, content, correlation_id)? {
+                    let vote = self.create_send_message_vote(dst, variant, None)?;
+                    commands.extend(self.send_vote(&recipients, vote)?);
+                }
+
+                Ok(commands)
             }
             SrcLocation::EndUser(_) => Err(Error::InvalidSrcLocation),
         }
     }
 
+    pub fn multicast_user_message(
+        &mut self,
+        src: SrcLocation,
+        prefix: Prefix,
+        content: Bytes,
+    ) -> Result<Vec<Command>> {
+        if !src.contains(&self.node.name()) {
+            error!(
+                "Not sending multicast message {:?} -> {:?}: not part of the source location",
+                src, prefix
+            );
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        // Send a copy to every section we know of that overlaps the prefix.
+        let mut dst_names: BTreeSet<_> = iter::once(self.section.prefix())
+            .chain(self.network.prefixes())
+            .filter(|section_prefix| section_prefix.is_compatible(&prefix))
+            .map(|section_prefix| {
+                if section_prefix.bit_count() > prefix.bit_count() {
+                    section_prefix.name()
+                } else {
+                    prefix.name()
+                }
+            })
+            .collect();
+        if dst_names.is_empty() {
+            let _ = dst_names.insert(prefix.name());
+        }
+        let dsts = dst_names.into_iter().map(DstLocation::Section);
+
+        let variant = Variant::UserMessageMulticast { prefix, content };
+        let mut commands = vec![];
+
+        match src {
+            SrcLocation::Node(_) => {
+                for dst in dsts {
+                    let msg = Message::single_src(&self.node, dst, variant.clone(), None, None)?;
+                    if dst.contains(&self.node.name(), self.section.prefix()) {
+                        commands.push(Command::HandleMessage {
+                            sender: Some(self.node.addr),
+                            message: msg.clone(),
+                        });
+                    }
+                    commands.extend(self.relay_message(&msg)?);
+                }
+            }
+            SrcLocation::Section(_) => {
+                for dst in dsts {
+                    let vote = self.create_send_message_vote(dst, variant.clone(), None)?;
+                    let recipients = delivery_group::signature_targets(
+                        &self.network_params,
+                        &dst,
+                        self.section.elders_info().peers().copied(),
+                    );
+                    commands.extend(self.send_vote(&recipients, vote)?);
+                }
+            }
+            SrcLocation::EndUser(_) => return Err(Error::InvalidSrcLocation),
+        }
+
+        Ok(commands)
+    }
+
+    pub fn anycast_user_message(
+        &mut self,
+        target: XorName,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+    ) -> Result<Vec<Command>> {
+        let dst = DstLocation::Section(target);
+        let variant = Variant::UserMessageAnycast {
+            id: rand::random(),
+            content,
+            correlation_id,
+        };
+        let msg = Message::single_src(&self.node, dst, variant, None, None)?;
+
+        // Only elders pick the recipient, so as non-elder let our elders handle it even if the
+        // target is in our section.
+        if !self.is_elder() || !dst.contains(&self.node.name(), self.section.prefix()) {
+            return self.send_message_for_ack(msg, 1, BTreeSet::new());
+        }
+
+        let mut commands = vec![Command::HandleMessage {
+            sender: Some(self.node.addr),
+            message: msg.clone(),
+        }];
+        commands.extend(self.relay_message(&msg)?);
+
+        // The recipient reports the delivery even if we forwarded the message to it ourselves.
+        let token = self.delivery_tracker.track(msg, 1, BTreeSet::new());
+        commands.push(Command::ScheduleTimeout {
+            duration: ACK_TIMEOUT,
+            token,
+        });
+
+        Ok(commands)
+    }
+
+    // Wrap the content of a user message with the given id into the variants of the messages to
+    // send. Content too large for a single message is split into fragments, which the destination
+    // reassembles.
+    fn user_message_variants(
+        &mut self,
+        dst: &DstLocation,
+        id: u64,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+    ) -> Result<Vec<Variant>> {
+        // Messages for clients are forwarded to them whole, so they are never fragmented.
+        if content.len() <= MAX_FRAGMENT_SIZE
+            || !matches!(dst, DstLocation::Node(_) | DstLocation::Section(_))
+        {
+            return Ok(vec![Variant::UserMessage {
+                id,
+                content,
+                correlation_id,
+            }]);
+        }
+
+        let fragments = Fragment::split(id, content.clone(), correlation_id).map_err(|error| {
+            error!("Not sending user message to {:?}: {}", dst, error);
+            Error::MessageTooLarge
+        })?;
+
+        if let Some(fragment) = fragments.first() {
+            self.delivery_tracker
+                .track_fragmented(fragment.id, content, fragments.len());
+        }
+
+        Ok(fragments
+            .into_iter()
+            .map(Variant::UserMessageFragment)
+            .collect())
+    }
+
     fn create_send_message_vote(
         &self,
         dst: DstLocation,
@@ -2152,12 +3967,16 @@ impl Approved {
 
     fn send_direct_message(&self, recipient: &SocketAddr, variant: Variant) -> Result<Command> {
         let message = Message::single_src(&self.node, DstLocation::Direct, variant, None, None)?;
-        Ok(Command::send_message_to_node(recipient, message.to_bytes()))
+        Ok(Command::send_message_to_node(
+            recipient,
+            message.to_bytes(),
+            message.priority(),
+        ))
     }
 
     // TODO: consider changing this so it sends only to a subset of the elders
     // (say 1/3 of the ones closest to our name or so)
-    fn send_message_to_our_elders(&self, msg: Bytes) -> Command {
+    fn send_message_to_our_elders(&self, msg: Bytes, priority: Priority) -> Command {
         let targets: Vec<_> = self
             .section
             .elders_info()
@@ -2165,7 +3984,7 @@ impl Approved {
             .map(Peer::addr)
             .copied()
             .collect();
-        Command::send_message_to_nodes(&targets, targets.len(), msg)
+        Command::send_message_to_nodes(&targets, targets.len(), msg, priority)
     }
 
     ////////////////////////////////////////////////////////////////////////////
@@ -2251,3 +4070,26 @@ impl Approved {
             .print()
     }
 }
+
+// Message for accumulation at destination still missing signature shares. Only what is needed to
+// report its expiry is kept, not the message itself.
+#[derive(Debug)]
+struct PendingMessage {
+    hash: MessageHash,
+    src: SrcLocation,
+    dst: DstLocation,
+    correlation_id: Option<CorrelationId>,
+}
+
+// Log a hop of a traced message. Grepping the logs of the network for its hash reconstructs the
+// path the message took, with the node span telling which node each hop happened at.
+fn trace_hop(msg: &Message, action: &'static str) {
+    if msg.is_traced() {
+        info!(
+            msg_hash = ?msg.hash(),
+            hop = msg.hop_count(),
+            action,
+            "Traced message"
+        );
+    }
+}
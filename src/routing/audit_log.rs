@@ -0,0 +1,144 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    consensus::{Proof, Vote},
+    crypto,
+    error::Result,
+    section::Section,
+};
+use hex_fmt::HexFmt;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+// Maximum number of entries kept in the log. When full, the oldest entries are dropped.
+const MAX_ENTRIES: usize = 10_000;
+
+// Append-only log of the votes our section reached consensus on, for offline analysis of forks
+// or membership disputes. Only the most recent `MAX_ENTRIES` votes are kept.
+pub(crate) struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+    capacity: usize,
+    next_index: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct AuditEntry {
+    // Sequence number of the entry in the log.
+    index: u64,
+    // Human readable description of the consensused vote.
+    vote: String,
+    // Hex-encoded BLS public key the vote was signed with.
+    public_key: String,
+    // Hex-encoded BLS signature of the vote.
+    signature: String,
+    // Hex-encoded SHA3-256 hash of our section state after the vote was handled.
+    section_hash: String,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::with_capacity(MAX_ENTRIES)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            next_index: 0,
+        }
+    }
+
+    // Append an entry for `vote` to the log. `section` is our section state after handling the
+    // vote.
+    pub fn append(&mut self, vote: &Vote, proof: &Proof, section: &Section) -> Result<()> {
+        let section_hash = crypto::sha3_256(&bincode::serialize(section)?);
+
+        if self.entries.len() >= self.capacity {
+            let _ = self.entries.pop_front();
+        }
+
+        self.entries.push_back(AuditEntry {
+            index: self.next_index,
+            vote: format!("{:?}", vote),
+            public_key: format!("{:x}", HexFmt(&proof.public_key.to_bytes())),
+            signature: format!("{:x}", HexFmt(&proof.signature.to_bytes())),
+            section_hash: format!("{:x}", HexFmt(&section_hash)),
+        });
+        self.next_index += 1;
+
+        Ok(())
+    }
+
+    // Export the whole log as a JSON array, oldest entry first.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.entries)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::test_utils::{prove, proven},
+        section::{test_utils::gen_elders_info, SectionProofChain},
+    };
+    use anyhow::Result;
+    use xor_name::Prefix;
+
+    #[test]
+    fn append_and_export() -> Result<()> {
+        let section = gen_section()?;
+        let sk = bls::SecretKey::random();
+        let vote = Vote::JoinsAllowed(false);
+        let proof = prove(&sk, &vote)?;
+
+        let mut log = AuditLog::new();
+        log.append(&vote, &proof, &section)?;
+        log.append(&vote, &proof, &section)?;
+
+        let json: serde_json::Value = serde_json::from_str(&log.to_json()?)?;
+        let entries = json.as_array().expect("not an array");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["index"], 0);
+        assert_eq!(entries[1]["index"], 1);
+        assert_eq!(entries[0]["section_hash"], entries[1]["section_hash"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn oldest_entries_dropped_when_full() -> Result<()> {
+        let section = gen_section()?;
+        let sk = bls::SecretKey::random();
+        let vote = Vote::JoinsAllowed(true);
+        let proof = prove(&sk, &vote)?;
+
+        let mut log = AuditLog::with_capacity(2);
+        for _ in 0..3 {
+            log.append(&vote, &proof, &section)?;
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&log.to_json()?)?;
+        let entries = json.as_array().expect("not an array");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["index"], 1);
+        assert_eq!(entries[1]["index"], 2);
+
+        Ok(())
+    }
+
+    fn gen_section() -> Result<Section> {
+        let sk = bls::SecretKey::random();
+        let (elders_info, _) = gen_elders_info(Prefix::default(), 3);
+        Ok(Section::new(
+            SectionProofChain::new(sk.public_key()),
+            proven(&sk, elders_info)?,
+        )?)
+    }
+}
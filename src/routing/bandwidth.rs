@@ -0,0 +1,164 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::messages::Message;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Category of the traffic exchanged with a peer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrafficCategory {
+    /// Node messages exchanged directly between their source and us: votes, section state
+    /// updates, keepalive probes and so on.
+    Gossip,
+    /// Node messages relayed on behalf of other nodes, either by us to the peer or by the peer to
+    /// us.
+    Relay,
+    /// Messages exchanged with clients.
+    Client,
+}
+
+impl TrafficCategory {
+    // Category of the serialized node message: relayed ones already made at least one hop.
+    pub(crate) fn of_node_message(msg_bytes: &[u8]) -> Self {
+        if Message::hop_count_of(msg_bytes) > 0 {
+            Self::Relay
+        } else {
+            Self::Gossip
+        }
+    }
+}
+
+/// Bytes sent to and received from a peer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Traffic {
+    /// Bytes sent to the peer.
+    pub sent: u64,
+    /// Bytes received from the peer.
+    pub received: u64,
+}
+
+impl Traffic {
+    /// Bytes sent and received together.
+    pub fn total(&self) -> u64 {
+        self.sent.saturating_add(self.received)
+    }
+}
+
+/// Bytes exchanged with a peer since it connected, by the category of the traffic.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PeerBandwidth {
+    /// Traffic of the `TrafficCategory::Gossip` category.
+    pub gossip: Traffic,
+    /// Traffic of the `TrafficCategory::Relay` category.
+    pub relay: Traffic,
+    /// Traffic of the `TrafficCategory::Client` category.
+    pub client: Traffic,
+}
+
+impl PeerBandwidth {
+    /// Traffic of all the categories together.
+    pub fn total(&self) -> Traffic {
+        Traffic {
+            sent: self
+                .gossip
+                .sent
+                .saturating_add(self.relay.sent)
+                .saturating_add(self.client.sent),
+            received: self
+                .gossip
+                .received
+                .saturating_add(self.relay.received)
+                .saturating_add(self.client.received),
+        }
+    }
+
+    fn category_mut(&mut self, category: TrafficCategory) -> &mut Traffic {
+        match category {
+            TrafficCategory::Gossip => &mut self.gossip,
+            TrafficCategory::Relay => &mut self.relay,
+            TrafficCategory::Client => &mut self.client,
+        }
+    }
+}
+
+// Bytes exchanged with each connected peer. Shared with the incoming messages handler, which
+// forgets the peers once they disconnect.
+#[derive(Clone, Default)]
+pub(crate) struct BandwidthTracker(Arc<Mutex<HashMap<SocketAddr, PeerBandwidth>>>);
+
+impl BandwidthTracker {
+    pub fn record_sent(&self, peer: SocketAddr, category: TrafficCategory, bytes: usize) {
+        let mut peers = self.lock();
+        let traffic = peers.entry(peer).or_default().category_mut(category);
+        traffic.sent = traffic.sent.saturating_add(bytes as u64);
+    }
+
+    pub fn record_received(&self, peer: SocketAddr, category: TrafficCategory, bytes: usize) {
+        let mut peers = self.lock();
+        let traffic = peers.entry(peer).or_default().category_mut(category);
+        traffic.received = traffic.received.saturating_add(bytes as u64);
+    }
+
+    pub fn remove(&self, peer: &SocketAddr) {
+        let _ = self.lock().remove(peer);
+    }
+
+    pub fn get(&self, peer: &SocketAddr) -> Option<PeerBandwidth> {
+        self.lock().get(peer).copied()
+    }
+
+    // All the peers, the ones we exchanged the most bytes with first.
+    pub fn all(&self) -> Vec<(SocketAddr, PeerBandwidth)> {
+        let mut peers: Vec<_> = self
+            .lock()
+            .iter()
+            .map(|(peer, bandwidth)| (*peer, *bandwidth))
+            .collect();
+        peers.sort_by_key(|(_, bandwidth)| Reverse(bandwidth.total().total()));
+        peers
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<SocketAddr, PeerBandwidth>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn record_per_peer_and_category() {
+        let tracker = BandwidthTracker::default();
+        let peer0 = gen_addr();
+        let peer1 = gen_addr();
+
+        tracker.record_sent(peer0, TrafficCategory::Gossip, 10);
+        tracker.record_received(peer0, TrafficCategory::Relay, 20);
+        tracker.record_received(peer1, TrafficCategory::Client, 100);
+        tracker.record_sent(peer1, TrafficCategory::Client, 5);
+
+        let bandwidth = tracker.get(&peer0).unwrap_or_default();
+        assert_eq!(bandwidth.gossip.sent, 10);
+        assert_eq!(bandwidth.relay.received, 20);
+        assert_eq!(bandwidth.client, Traffic::default());
+        assert_eq!(bandwidth.total().total(), 30);
+
+        let all: Vec<_> = tracker.all().into_iter().map(|(peer, _)| peer).collect();
+        assert_eq!(all, [peer1, peer0]);
+
+        tracker.remove(&peer1);
+        assert_eq!(tracker.get(&peer1), None);
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use xor_name::XorName;
+
+/// Peer on the blacklist, identified either by its name or by the address it connects from.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BlacklistEntry {
+    /// Node with the given name, whatever address it connects from.
+    Name(XorName),
+    /// Any node or client connecting from the given address.
+    Addr(SocketAddr),
+}
+
+/// Protocol violation which gets the offending peer blacklisted automatically.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Violation {
+    /// Message with a signature that doesn't verify.
+    InvalidSignature,
+    /// Replay of a message signed by the sender itself and already handled.
+    Replay,
+    /// Client sending way past its quota after being throttled.
+    Flooding,
+    /// Message exceeding the size limits.
+    OversizedMessage,
+}
+
+/// How long the peers are blacklisted for, per the kind of the protocol violation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BlacklistConfig {
+    /// Duration for `Violation::InvalidSignature`.
+    pub invalid_signature: Duration,
+    /// Duration for `Violation::Replay`.
+    pub replay: Duration,
+    /// Duration for `Violation::Flooding`.
+    pub flooding: Duration,
+    /// Duration for `Violation::OversizedMessage`.
+    pub oversized_message: Duration,
+}
+
+impl BlacklistConfig {
+    fn duration(&self, violation: Violation) -> Duration {
+        match violation {
+            Violation::InvalidSignature => self.invalid_signature,
+            Violation::Replay => self.replay,
+            Violation::Flooding => self.flooding,
+            Violation::OversizedMessage => self.oversized_message,
+        }
+    }
+}
+
+impl Default for BlacklistConfig {
+    fn default() -> Self {
+        Self {
+            invalid_signature: Duration::from_secs(60 * 60),
+            replay: Duration::from_secs(10 * 60),
+            flooding: Duration::from_secs(10 * 60),
+            oversized_message: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+// Peers whose connections, messages and join requests are refused until their entry expires.
+// Shared between the incoming messages handler and the rest of the node.
+#[derive(Clone, Default)]
+pub(crate) struct Blacklist(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    config: BlacklistConfig,
+    // Time each entry expires at.
+    entries: HashMap<BlacklistEntry, Instant>,
+}
+
+impl Blacklist {
+    pub fn set_config(&self, config: BlacklistConfig) {
+        self.lock().config = config;
+    }
+
+    // Blacklist the peer for the duration configured for the violation.
+    pub fn report(&self, entry: BlacklistEntry, violation: Violation) {
+        let duration = self.lock().config.duration(violation);
+        warn!(
+            "Blacklisting {:?} for {:?} due to {:?}",
+            entry, duration, violation
+        );
+        self.insert(entry, duration)
+    }
+
+    // Blacklist the peer for `duration`. Doesn't shorten an existing entry.
+    pub fn insert(&self, entry: BlacklistEntry, duration: Duration) {
+        let now = Instant::now();
+        let until = now + duration;
+
+        let mut inner = self.lock();
+        inner.entries.retain(|_, expiry| *expiry > now);
+
+        let expiry = inner.entries.entry(entry).or_insert(until);
+        if *expiry < until {
+            *expiry = until;
+        }
+    }
+
+    // Remove the peer from the blacklist. Returns whether it was on it.
+    pub fn remove(&self, entry: &BlacklistEntry) -> bool {
+        self.lock()
+            .entries
+            .remove(entry)
+            .map(|expiry| expiry > Instant::now())
+            .unwrap_or(false)
+    }
+
+    pub fn contains(&self, entry: &BlacklistEntry) -> bool {
+        self.lock()
+            .entries
+            .get(entry)
+            .map(|expiry| *expiry > Instant::now())
+            .unwrap_or(false)
+    }
+
+    fn lock(&self) -> MutexGuard<Inner> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn expiry_and_removal() {
+        let blacklist = Blacklist::default();
+        blacklist.set_config(BlacklistConfig {
+            replay: Duration::from_secs(0),
+            ..BlacklistConfig::default()
+        });
+
+        let name = BlacklistEntry::Name(rand::random());
+        let addr = BlacklistEntry::Addr(gen_addr());
+
+        blacklist.report(name, Violation::Replay);
+        assert!(!blacklist.contains(&name));
+
+        blacklist.report(addr, Violation::InvalidSignature);
+        assert!(blacklist.contains(&addr));
+        // A shorter duration doesn't shorten the existing entry.
+        blacklist.insert(addr, Duration::from_secs(0));
+        assert!(blacklist.contains(&addr));
+
+        assert!(blacklist.remove(&addr));
+        assert!(!blacklist.contains(&addr));
+        assert!(!blacklist.remove(&addr));
+    }
+}
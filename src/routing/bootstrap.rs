@@ -6,21 +6,19 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{comm::ConnectionEvent, Comm};
+use super::{bandwidth::TrafficCategory, comm::ConnectionEvent, join_proof::JoinProof, Comm};
 use crate::{
     consensus::Proven,
-    crypto::{self, Signature},
+    crypto,
     error::{Error, Result},
-    messages::{JoinRequest, Message, ResourceProofResponse, Variant, VerifyStatus},
+    messages::{JoinRequest, Message, Priority, Variant, VerifyStatus, PROTOCOL_VERSION},
     node::Node,
-    peer::Peer,
     relocation::{RelocatePayload, SignedRelocateDetails},
     section::{EldersInfo, Section},
     SectionProofChain,
 };
 use bytes::Bytes;
 use futures::future;
-use resource_proof::ResourceProof;
 use sn_messaging::{
     node::NodeMessage,
     section_info::{GetSectionResponse, Message as SectionInfoMsg, SectionInfo},
@@ -47,13 +45,17 @@ pub(crate) async fn initial(
     comm: &Comm,
     incoming_conns: &mut mpsc::Receiver<ConnectionEvent>,
     bootstrap_addr: SocketAddr,
+    network_id: u64,
+    join_proof: &dyn JoinProof,
 ) -> Result<(Node, Section, Vec<(Message, SocketAddr)>)> {
     let (send_tx, send_rx) = mpsc::channel(1);
     let recv_rx = MessageReceiver::Raw(incoming_conns);
 
     let span = trace_span!("bootstrap::initial", name = %node.name());
 
-    let state = State::new(node, send_tx, recv_rx);
+    let state = State::new(node, send_tx, recv_rx)
+        .with_network_id(network_id)
+        .with_join_proof(join_proof);
 
     future::join(
         state.run(vec![bootstrap_addr], None),
@@ -75,13 +77,16 @@ pub(crate) async fn relocate(
     recv_rx: mpsc::Receiver<(MessageType, SocketAddr)>,
     bootstrap_addrs: Vec<SocketAddr>,
     relocate_details: SignedRelocateDetails,
+    network_id: u64,
 ) -> Result<(Node, Section, Vec<(Message, SocketAddr)>)> {
     let (send_tx, send_rx) = mpsc::channel(1);
     let recv_rx = MessageReceiver::Deserialized(recv_rx);
 
     let span = trace_span!("bootstrap::relocate", name = %node.name());
 
-    let state = State::new(node, send_tx, recv_rx);
+    let state = State::new(node, send_tx, recv_rx)
+        .with_network_id(network_id)
+        .with_comm(comm);
 
     future::join(
         state.run(bootstrap_addrs, Some(relocate_details)),
@@ -98,6 +103,11 @@ struct State<'a> {
     // Receiver for incoming messages.
     recv_rx: MessageReceiver<'a>,
     node: Node,
+    network_id: u64,
+    // Responds to the join challenges. Not needed when relocating.
+    join_proof: Option<&'a dyn JoinProof>,
+    // Switched to our new keypair when relocating.
+    comm: Option<&'a Comm>,
     // Backlog for unknown messages
     backlog: VecDeque<(Message, SocketAddr)>,
 }
@@ -112,10 +122,31 @@ impl<'a> State<'a> {
             send_tx,
             recv_rx,
             node,
+            network_id: 0,
+            join_proof: None,
+            comm: None,
             backlog: VecDeque::with_capacity(BACKLOG_CAPACITY),
         }
     }
 
+    fn with_network_id(self, network_id: u64) -> Self {
+        Self { network_id, ..self }
+    }
+
+    fn with_join_proof(self, join_proof: &'a dyn JoinProof) -> Self {
+        Self {
+            join_proof: Some(join_proof),
+            ..self
+        }
+    }
+
+    fn with_comm(self, comm: &'a Comm) -> Self {
+        Self {
+            comm: Some(comm),
+            ..self
+        }
+    }
+
     async fn run(
         mut self,
         bootstrap_addrs: Vec<SocketAddr>,
@@ -255,6 +286,16 @@ impl<'a> State<'a> {
         info!("Changing name to {}", new_name);
         self.node = Node::new(new_keypair, self.node.addr).with_age(age);
 
+        // The transport may have authenticated our connections by the previous keypair.
+        if let Some(comm) = self.comm {
+            if let Err(error) = comm.rekey(&self.node.keypair) {
+                error!(
+                    "Failed to switch the transport to the new keypair: {}",
+                    error
+                );
+            }
+        }
+
         relocate_payload
     }
 
@@ -264,20 +305,22 @@ impl<'a> State<'a> {
     async fn join(
         mut self,
         mut section_key: bls::PublicKey,
-        elders: BTreeMap<XorName, SocketAddr>,
+        mut elders: BTreeMap<XorName, SocketAddr>,
         relocate_payload: Option<RelocatePayload>,
     ) -> Result<(Node, Section, Vec<(Message, SocketAddr)>)> {
         let join_request = JoinRequest {
+            protocol_version: PROTOCOL_VERSION,
+            network_id: self.network_id,
             section_key,
             relocate_payload: relocate_payload.clone(),
-            resource_proof_response: None,
+            join_proof_response: None,
         };
-        let recipients = elders.into_iter().map(|(_, addr)| addr).collect();
+        let recipients = elders.values().copied().collect();
         self.send_join_requests(join_request, recipients).await?;
 
         loop {
             let (response, sender) = self
-                .receive_join_response(relocate_payload.as_ref())
+                .receive_join_response(relocate_payload.as_ref(), &elders)
                 .await?;
 
             match response {
@@ -307,11 +350,17 @@ impl<'a> State<'a> {
                         );
                         section_key = new_section_key;
                         let join_request = JoinRequest {
+                            protocol_version: PROTOCOL_VERSION,
+                            network_id: self.network_id,
                             section_key,
                             relocate_payload: relocate_payload.clone(),
-                            resource_proof_response: None,
+                            join_proof_response: None,
                         };
-                        let recipients = elders_info.peers().map(Peer::addr).copied().collect();
+                        elders = elders_info
+                            .peers()
+                            .map(|peer| (*peer.name(), *peer.addr()))
+                            .collect();
+                        let recipients = elders.values().copied().collect();
                         self.send_join_requests(join_request, recipients).await?;
                     } else {
                         warn!(
@@ -320,26 +369,23 @@ impl<'a> State<'a> {
                         );
                     }
                 }
-                JoinResponse::ResourceChallenge {
-                    data_size,
-                    difficulty,
-                    nonce,
-                    nonce_signature,
-                } => {
-                    let rp = ResourceProof::new(data_size, difficulty);
-                    let data = rp.create_proof_data(&nonce);
-                    let mut prover = rp.create_prover(data.clone());
-                    let solution = prover.solve();
+                JoinResponse::Challenge(challenge) => {
+                    let response = if let Some(response) = self
+                        .join_proof
+                        .and_then(|join_proof| join_proof.respond(&self.node.name(), &challenge))
+                    {
+                        response
+                    } else {
+                        warn!("Failed to respond to JoinChallenge from {}", sender);
+                        continue;
+                    };
 
                     let join_request = JoinRequest {
+                        protocol_version: PROTOCOL_VERSION,
+                        network_id: self.network_id,
                         section_key,
                         relocate_payload: relocate_payload.clone(),
-                        resource_proof_response: Some(ResourceProofResponse {
-                            solution,
-                            data,
-                            nonce,
-                            nonce_signature,
-                        }),
+                        join_proof_response: Some(response),
                     };
                     let recipients = vec![sender];
                     self.send_join_requests(join_request, recipients).await?;
@@ -367,9 +413,11 @@ impl<'a> State<'a> {
         Ok(())
     }
 
+    // Wait for the response to the join request sent to `elders`.
     async fn receive_join_response(
         &mut self,
         relocate_payload: Option<&RelocatePayload>,
+        elders: &BTreeMap<XorName, SocketAddr>,
     ) -> Result<(JoinResponse, SocketAddr)> {
         while let Some((message, sender)) = self.recv_rx.next().await {
             let message = match message {
@@ -398,14 +446,9 @@ impl<'a> State<'a> {
                         sender,
                     ));
                 }
-                Variant::ResourceChallenge {
-                    data_size,
-                    difficulty,
-                    nonce,
-                    nonce_signature,
-                } => {
+                Variant::JoinChallenge { challenge } => {
                     if relocate_payload.is_some() {
-                        trace!("Ignore ResourceChallenge when relocating");
+                        trace!("Ignore JoinChallenge when relocating");
                         continue;
                     }
 
@@ -413,15 +456,7 @@ impl<'a> State<'a> {
                         continue;
                     }
 
-                    return Ok((
-                        JoinResponse::ResourceChallenge {
-                            data_size: *data_size,
-                            difficulty: *difficulty,
-                            nonce: *nonce,
-                            nonce_signature: *nonce_signature,
-                        },
-                        sender,
-                    ));
+                    return Ok((JoinResponse::Challenge(challenge.clone()), sender));
                 }
                 Variant::NodeApproval {
                     elders_info,
@@ -459,6 +494,38 @@ impl<'a> State<'a> {
                         sender,
                     ));
                 }
+                Variant::IncompatibleProtocolVersion { version } => {
+                    if !self.verify_message(&message, None) {
+                        continue;
+                    }
+
+                    // Anyone can send this to abort our bootstrap, so only believe the elders we
+                    // asked to join and keep waiting for their response otherwise.
+                    let from_elder = message
+                        .src()
+                        .to_node_name()
+                        .map(|name| elders.get(&name) == Some(&sender))
+                        .unwrap_or(false);
+                    if !from_elder {
+                        warn!(
+                            "Ignore IncompatibleProtocolVersion from {} - not an elder we sent \
+                             JoinRequest to",
+                            sender
+                        );
+                        continue;
+                    }
+
+                    error!(
+                        "Join rejected by {} - incompatible protocol version (ours: {}, theirs: {})",
+                        sender, PROTOCOL_VERSION, version
+                    );
+
+                    return Err(Error::IncompatibleProtocolVersion {
+                        ours: PROTOCOL_VERSION,
+                        theirs: *version,
+                    });
+                }
+                Variant::ResourceChallenge { .. } => trace!("Ignore deprecated ResourceChallenge"),
 
                 _ => self.backlog_message(message, sender),
             }
@@ -508,12 +575,7 @@ enum JoinResponse {
         elders_info: EldersInfo,
         section_key: bls::PublicKey,
     },
-    ResourceChallenge {
-        data_size: usize,
-        difficulty: u8,
-        nonce: [u8; 32],
-        nonce_signature: Signature,
-    },
+    Challenge(Bytes),
 }
 
 // Receiver of incoming messages that can be backed either by a raw `qp2p::ConnectionEvent` receiver
@@ -552,7 +614,15 @@ async fn send_messages(mut rx: mpsc::Receiver<(MessageType, Vec<SocketAddr>)>, c
     while let Some((message, recipients)) = rx.recv().await {
         match message.serialize() {
             Ok(msg_bytes) => {
-                let _ = comm.send(&recipients, recipients.len(), msg_bytes).await;
+                let _ = comm
+                    .send(
+                        &recipients,
+                        recipients.len(),
+                        msg_bytes,
+                        Priority::Membership,
+                        TrafficCategory::Gossip,
+                    )
+                    .await;
             }
             Err(error) => error!(
                 "Failed to send message {:?} to {:?}: {}",
@@ -566,7 +636,7 @@ async fn send_messages(mut rx: mpsc::Receiver<(MessageType, Vec<SocketAddr>)>, c
 mod tests {
     use super::*;
     use crate::{
-        consensus::test_utils::*, routing::tests::SecretKeySet, section::test_utils::*,
+        consensus::test_utils::*, peer::Peer, routing::tests::SecretKeySet, section::test_utils::*,
         section::MemberInfo, ELDER_SIZE, MIN_AGE,
     };
     use anyhow::{Error, Result};
@@ -851,6 +921,73 @@ mod tests {
         test_result
     }
 
+    #[tokio::test]
+    async fn incompatible_protocol_version_only_from_elders() -> Result<()> {
+        let (send_tx, _send_rx) = mpsc::channel(1);
+        let (mut recv_tx, recv_rx) = mpsc::channel(1);
+        let recv_rx = MessageReceiver::Deserialized(recv_rx);
+
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let state = State::new(node, send_tx, recv_rx);
+
+        let (_, elder_nodes) = gen_elders_info(Default::default(), ELDER_SIZE);
+        let elders = elder_nodes
+            .iter()
+            .map(|node| (node.name(), node.addr))
+            .collect();
+        let section_key = bls::SecretKey::random().public_key();
+        let join_task = state.join(section_key, elders, None);
+
+        let test_task = async {
+            let send = |sender: &Node, addr, version| -> Result<_> {
+                let message = Message::single_src(
+                    sender,
+                    DstLocation::Direct,
+                    Variant::IncompatibleProtocolVersion { version },
+                    None,
+                    None,
+                )?;
+                Ok((
+                    MessageType::NodeMessage(NodeMessage::new(message.to_bytes())),
+                    addr,
+                ))
+            };
+
+            // Neither a node we didn't ask, nor someone else using the address of an elder.
+            let outsider = Node::new(crypto::gen_keypair(), gen_addr());
+            recv_tx
+                .send(send(&outsider, outsider.addr, PROTOCOL_VERSION + 1)?)
+                .await?;
+            recv_tx
+                .send(send(&outsider, elder_nodes[0].addr, PROTOCOL_VERSION + 1)?)
+                .await?;
+
+            recv_tx
+                .send(send(
+                    &elder_nodes[1],
+                    elder_nodes[1].addr,
+                    PROTOCOL_VERSION + 2,
+                )?)
+                .await?;
+            future::pending::<Result<()>>().await
+        };
+
+        futures::pin_mut!(join_task);
+        futures::pin_mut!(test_task);
+
+        match future::select(join_task, test_task).await {
+            Either::Left((result, _)) => assert_matches!(
+                result,
+                Err(crate::error::Error::IncompatibleProtocolVersion { theirs, .. }) => {
+                    assert_eq!(theirs, PROTOCOL_VERSION + 2)
+                }
+            ),
+            Either::Right((output, _)) => output?,
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn invalid_join_response_rejoin() -> Result<()> {
         let (send_tx, mut send_rx) = mpsc::channel(1);
@@ -0,0 +1,222 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::storage::{read_with_digest, write_with_digest};
+use crate::error::{Error, Result};
+use rand::seq::SliceRandom;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// Maximum number of contacts kept in the cache.
+const CAPACITY: usize = 200;
+// Contacts not connected to for longer than this are no longer used for bootstrapping.
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// Minimal interval between writes of the cache file, so frequent sends don't keep rewriting it.
+const STORE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Peers we recently connected to successfully, persisted so the node can bootstrap through them
+// after restart instead of relying on the hard-coded contacts only.
+//
+// The file has the same format as the one of `Storage`, with the payload being the
+// bincode-serialized map of the contacts to the time of the last successful connection to them.
+pub(crate) struct BootstrapCache {
+    path: PathBuf,
+    // Time of the last successful connection to each contact, in seconds since the unix epoch.
+    contacts: HashMap<SocketAddr, u64>,
+    last_store: Option<Instant>,
+    // Whether the contacts changed since they were last stored.
+    dirty: bool,
+}
+
+impl BootstrapCache {
+    // Load the cache stored at `path`. Starts empty if nothing was stored there yet or if the
+    // stored cache is corrupted.
+    pub fn load(path: PathBuf) -> Self {
+        let contacts = match read(&path) {
+            Ok(contacts) => contacts,
+            Err(error) => {
+                warn!(
+                    "Failed to load bootstrap cache from {}: {}",
+                    path.display(),
+                    error
+                );
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            contacts,
+            last_store: None,
+            dirty: false,
+        }
+    }
+
+    // Contacts connected to within `MAX_AGE`, shuffled so the restarting nodes don't all hit the
+    // same ones first.
+    pub fn contacts(&self) -> Vec<SocketAddr> {
+        let now = now();
+        let mut contacts: Vec<_> = self
+            .contacts
+            .iter()
+            .filter(|(_, time)| now.saturating_sub(**time) <= MAX_AGE.as_secs())
+            .map(|(addr, _)| *addr)
+            .collect();
+        contacts.shuffle(&mut rand::thread_rng());
+        contacts
+    }
+
+    // Record a successful connection to `addr`. Evicts the least recently connected contact if
+    // the cache is full. Only updates the cache in memory, see `take_pending_store`.
+    pub fn insert(&mut self, addr: SocketAddr) {
+        let _ = self.contacts.insert(addr, now());
+
+        if self.contacts.len() > CAPACITY {
+            if let Some(oldest) = self
+                .contacts
+                .iter()
+                .min_by_key(|(_, time)| **time)
+                .map(|(addr, _)| *addr)
+            {
+                let _ = self.contacts.remove(&oldest);
+            }
+        }
+
+        self.dirty = true;
+    }
+
+    // If the cache changed since it was last stored, unless that was less than `STORE_INTERVAL`
+    // ago, returns the path and the payload to store it with `write`. Marks the cache as stored,
+    // so the caller can do the actual write elsewhere, without holding the cache.
+    pub fn take_pending_store(&mut self) -> Option<(PathBuf, Vec<u8>)> {
+        if !self.dirty
+            || self
+                .last_store
+                .map(|time| time.elapsed() < STORE_INTERVAL)
+                .unwrap_or(false)
+        {
+            return None;
+        }
+
+        self.take_store()
+    }
+
+    // Store the cache right away, if it changed since it was last stored.
+    pub fn store(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        if let Some((path, payload)) = self.take_store() {
+            write(&path, &payload)
+        }
+    }
+
+    fn take_store(&mut self) -> Option<(PathBuf, Vec<u8>)> {
+        self.last_store = Some(Instant::now());
+        self.dirty = false;
+
+        match bincode::serialize(&self.contacts) {
+            Ok(payload) => Some((self.path.clone(), payload)),
+            Err(error) => {
+                warn!("Failed to serialize bootstrap cache: {}", error);
+                None
+            }
+        }
+    }
+}
+
+// Write the payload returned by `BootstrapCache::take_pending_store` to `path`. Blocks on the
+// file system.
+pub(crate) fn write(path: &Path, payload: &[u8]) {
+    if let Err(error) = write_with_digest(path, payload) {
+        warn!(
+            "Failed to store bootstrap cache to {}: {}",
+            path.display(),
+            error
+        );
+    }
+}
+
+fn read(path: &Path) -> Result<HashMap<SocketAddr, u64>> {
+    if let Some(payload) = read_with_digest(path)? {
+        bincode::deserialize(&payload).map_err(|_| Error::InvalidStoredState)
+    } else {
+        Ok(HashMap::new())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn store_and_load() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "sn_routing_bootstrap_cache_{}",
+            rand::random::<u64>()
+        ));
+
+        let mut cache = BootstrapCache::load(path.clone());
+        assert!(cache.contacts().is_empty());
+
+        let fresh = gen_addr();
+        let stale = gen_addr();
+        cache.insert(fresh);
+        let _ = cache.contacts.insert(stale, now() - MAX_AGE.as_secs() - 1);
+        cache.store();
+
+        let cache = BootstrapCache::load(path.clone());
+        assert_eq!(cache.contacts(), vec![fresh]);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+    #[test]
+    fn pending_store_only_when_dirty_and_due() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "sn_routing_bootstrap_cache_{}",
+            rand::random::<u64>()
+        ));
+
+        let mut cache = BootstrapCache::load(path.clone());
+        assert!(cache.take_pending_store().is_none());
+
+        let addr = gen_addr();
+        cache.insert(addr);
+        let (store_path, payload) = cache
+            .take_pending_store()
+            .expect("first change not pending");
+        assert_eq!(store_path, path);
+
+        // Stored less than `STORE_INTERVAL` ago.
+        cache.insert(gen_addr());
+        assert!(cache.take_pending_store().is_none());
+
+        write(&store_path, &payload);
+        let loaded = BootstrapCache::load(path.clone());
+        assert_eq!(loaded.contacts(), vec![addr]);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
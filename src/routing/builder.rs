@@ -0,0 +1,256 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{
+    BlacklistConfig, ClientRateLimits, Config, ConnectionLimits, ContactSource, EventBufferConfig,
+    EventStream, JoinProof, ResourceProofConfig, Routing, Transport,
+};
+use crate::{
+    error::Result, message_size_limits::MessageSizeLimits, network_params::NetworkParams,
+    TransportConfig,
+};
+use ed25519_dalek::Keypair;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+/// Builder for a routing node, as an alternative to filling in a [`Config`] directly. All the
+/// settings not set explicitly keep their defaults.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() -> sn_routing::Result<()> {
+/// use sn_routing::NodeBuilder;
+///
+/// let (node, mut events) = NodeBuilder::new()
+///     .bootstrap_contacts(vec!["127.0.0.1:12000".parse().unwrap()])
+///     .recommended_section_size(10)
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct NodeBuilder {
+    config: Config,
+}
+
+impl NodeBuilder {
+    /// Creates a builder with the default config.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new network as its first node instead of joining an existing one.
+    pub fn first(mut self) -> Self {
+        self.config.first = true;
+        self
+    }
+
+    /// Use the given keypair instead of a randomly generated one.
+    pub fn keypair(mut self, keypair: Keypair) -> Self {
+        self.config.keypair = Some(keypair);
+        self
+    }
+
+    /// Contacts to bootstrap to. Replaces the ones in the transport config.
+    pub fn bootstrap_contacts<I>(mut self, contacts: I) -> Self
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        self.config.transport_config.hard_coded_contacts = contacts.into_iter().collect();
+        self
+    }
+
+    /// Transport to use instead of the default QUIC one. See [`Config::transport`].
+    pub fn transport(mut self, transport: Box<dyn Transport>) -> Self {
+        self.config.transport = Some(transport);
+        self
+    }
+
+    /// Sources of the contacts to bootstrap through. See [`Config::contact_sources`].
+    pub fn contact_sources<I>(mut self, sources: I) -> Self
+    where
+        I: IntoIterator<Item = ContactSource>,
+    {
+        self.config.contact_sources = sources.into_iter().collect();
+        self
+    }
+
+    /// How long to try to connect to the bootstrap contacts. See [`Config::bootstrap_timeout`].
+    pub fn bootstrap_timeout(mut self, timeout: Duration) -> Self {
+        self.config.bootstrap_timeout = timeout;
+        self
+    }
+
+    /// Configuration of the underlying network transport.
+    pub fn transport_config(mut self, transport_config: TransportConfig) -> Self {
+        self.config.transport_config = transport_config;
+        self
+    }
+
+    /// Persist the section and network knowledge of the node to the given file.
+    pub fn storage_path(mut self, path: PathBuf) -> Self {
+        self.config.storage_path = Some(path);
+        self
+    }
+
+    /// Persist the keypair of the node to the given file, so it restarts with the same identity.
+    /// See [`Config::identity_path`].
+    pub fn identity_path(mut self, path: PathBuf) -> Self {
+        self.config.identity_path = Some(path);
+        self
+    }
+
+    /// Record the votes the section reaches consensus on into an audit log.
+    pub fn audit_log(mut self) -> Self {
+        self.config.audit_log = true;
+        self
+    }
+
+    /// Capture every message the node sends and receives into the given file. See
+    /// [`Config::message_trace_path`].
+    pub fn message_trace_path(mut self, path: PathBuf) -> Self {
+        self.config.message_trace_path = Some(path);
+        self
+    }
+
+    /// Encrypt and authenticate every connection with a Noise handshake. See [`Config::noise`].
+    pub fn noise(mut self) -> Self {
+        self.config.noise = true;
+        self
+    }
+
+    /// Parameters of the network. Must be the same for all the nodes in the network.
+    pub fn network_params(mut self, network_params: NetworkParams) -> Self {
+        self.config.network_params = network_params;
+        self
+    }
+
+    /// Recommended section size. See [`NetworkParams::recommended_section_size`].
+    pub fn recommended_section_size(mut self, size: usize) -> Self {
+        self.config.network_params.recommended_section_size = size;
+        self
+    }
+
+    /// Number of elders per section. See [`NetworkParams::elder_size`].
+    pub fn elder_size(mut self, size: usize) -> Self {
+        self.config.network_params.elder_size = size;
+        self
+    }
+
+    /// Maximum sizes of the incoming messages.
+    pub fn message_size_limits(mut self, limits: MessageSizeLimits) -> Self {
+        self.config.message_size_limits = limits;
+        self
+    }
+
+    /// Quotas on the messages from the clients connected to the node.
+    pub fn client_rate_limits(mut self, limits: ClientRateLimits) -> Self {
+        self.config.client_rate_limits = limits;
+        self
+    }
+
+    /// Limit the incoming connections and join requests from a single IP address.
+    pub fn connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.config.connection_limits = Some(limits);
+        self
+    }
+
+    /// Resource proof required from the nodes joining through this node.
+    pub fn resource_proof(mut self, config: ResourceProofConfig) -> Self {
+        self.config.resource_proof = config;
+        self
+    }
+
+    /// Scheme of the proof required from the joining nodes. See [`Config::join_proof`].
+    pub fn join_proof(mut self, join_proof: Box<dyn JoinProof>) -> Self {
+        self.config.join_proof = Some(join_proof);
+        self
+    }
+
+    /// Record the peers the node connects to, to bootstrap through them after restart. See
+    /// [`Config::bootstrap_cache_path`].
+    pub fn bootstrap_cache_path(mut self, path: PathBuf) -> Self {
+        self.config.bootstrap_cache_path = Some(path);
+        self
+    }
+
+    /// Bound the events not yet taken from the `EventStream`. See [`Config::event_buffer`].
+    pub fn event_buffer(mut self, event_buffer: EventBufferConfig) -> Self {
+        self.config.event_buffer = Some(event_buffer);
+        self
+    }
+
+    /// Dump the state of the node into the given file whenever the process receives `SIGUSR1`.
+    /// See [`Config::state_dump_path`].
+    pub fn state_dump_path(mut self, path: PathBuf) -> Self {
+        self.config.state_dump_path = Some(path);
+        self
+    }
+
+    /// How long the peers violating the protocol are blacklisted for.
+    pub fn blacklist(mut self, config: BlacklistConfig) -> Self {
+        self.config.blacklist = config;
+        self
+    }
+
+    /// Serve the metrics of the node at the given local address. See [`Config::metrics_addr`].
+    #[cfg(feature = "metrics")]
+    pub fn metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Serve snapshots of the node state at the given local address. See
+    /// [`Config::introspection_addr`].
+    #[cfg(feature = "introspection")]
+    pub fn introspection_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.introspection_addr = Some(addr);
+        self
+    }
+
+    /// Returns the config built so far.
+    pub fn config(self) -> Config {
+        self.config
+    }
+
+    /// Creates the node and bootstraps it to the network. See [`Routing::new`].
+    pub async fn build(self) -> Result<(Routing, EventStream)> {
+        Routing::new(self.config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn build_config() {
+        let contact = gen_addr();
+        let config = NodeBuilder::new()
+            .first()
+            .bootstrap_contacts(vec![contact])
+            .recommended_section_size(20)
+            .elder_size(7)
+            .config();
+
+        assert!(config.first);
+        assert!(config.keypair.is_none());
+        assert_eq!(
+            config.transport_config.hard_coded_contacts,
+            vec![contact].into_iter().collect()
+        );
+        assert_eq!(config.network_params.recommended_section_size, 20);
+        assert_eq!(config.network_params.elder_size, 7);
+        assert_eq!(
+            config.network_params.max_hop_count,
+            NetworkParams::default().max_hop_count
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use lru_time_cache::LruCache;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+// Buckets of clients idle for this long are dropped. By then they are full again anyway.
+const IDLE_CLIENT_EXPIRY: Duration = Duration::from_secs(10 * 60);
+// Maximum number of client buckets kept. The least recently active clients are dropped first, so
+// they start again with a full bucket, but still within the aggregate quota.
+const MAX_CLIENTS: usize = 10_000;
+// Clients sending more than this many times their burst in throttled messages are flooding.
+const FLOODING_FACTOR: f64 = 4.0;
+
+/// Token bucket quota, in bytes of the client messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    /// Rate at which the quota replenishes, in bytes per second.
+    pub rate: f64,
+    /// Maximum amount that can be used at once, in bytes. Messages larger than this are always
+    /// throttled, so it should not be lower than `MessageSizeLimits::client`.
+    pub burst: f64,
+}
+
+/// Limits on the messages the clients connected to this node can send to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClientRateLimits {
+    /// Quota of every single client.
+    pub per_client: Quota,
+    /// Quota shared by all the clients.
+    pub aggregate: Quota,
+}
+
+impl Default for ClientRateLimits {
+    fn default() -> Self {
+        Self {
+            per_client: Quota {
+                rate: 2.0 * 1024.0 * 1024.0,
+                burst: 16.0 * 1024.0 * 1024.0,
+            },
+            aggregate: Quota {
+                rate: 64.0 * 1024.0 * 1024.0,
+                burst: 256.0 * 1024.0 * 1024.0,
+            },
+        }
+    }
+}
+
+/// Current usage of the quotas by a client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClientUsage {
+    /// Bytes the client can send right now, before being throttled by its own quota.
+    pub available: f64,
+    /// Bytes all the clients together can send right now.
+    pub aggregate_available: f64,
+}
+
+pub(crate) struct ClientRateLimiter {
+    limits: ClientRateLimits,
+    clients: LruCache<SocketAddr, Bucket>,
+    aggregate: Bucket,
+}
+
+impl ClientRateLimiter {
+    pub fn new(limits: ClientRateLimits) -> Self {
+        Self {
+            limits,
+            clients: LruCache::with_expiry_duration_and_capacity(IDLE_CLIENT_EXPIRY, MAX_CLIENTS),
+            aggregate: Bucket::new(&limits.aggregate),
+        }
+    }
+
+    pub fn limits(&self) -> &ClientRateLimits {
+        &self.limits
+    }
+
+    /// Takes `size` bytes from the quotas of `client`, if available.
+    pub fn try_consume(&mut self, client: SocketAddr, size: usize) -> Consume {
+        let now = Instant::now();
+        let size = size as f64;
+        let limits = &self.limits;
+
+        if !self.clients.contains_key(&client) {
+            let _ = self.clients.insert(client, Bucket::new(&limits.per_client));
+        }
+        let bucket = if let Some(bucket) = self.clients.get_mut(&client) {
+            bucket
+        } else {
+            return Consume::Allowed;
+        };
+
+        bucket.refill(&limits.per_client, now);
+        self.aggregate.refill(&limits.aggregate, now);
+
+        if bucket.tokens < size || self.aggregate.tokens < size {
+            // Keeping on sending while throttled by its own quota is flooding. Throttling by the
+            // aggregate quota isn't the client's fault.
+            if bucket.tokens < size {
+                bucket.dropped += size;
+                if bucket.dropped > FLOODING_FACTOR * limits.per_client.burst {
+                    bucket.dropped = 0.0;
+                    return Consume::Flooding;
+                }
+            }
+
+            // Report only the first of a series of throttled messages.
+            return if bucket.throttled {
+                Consume::StillThrottled
+            } else {
+                bucket.throttled = true;
+                Consume::Throttled
+            };
+        }
+
+        bucket.tokens -= size;
+        bucket.throttled = false;
+        bucket.dropped = 0.0;
+        self.aggregate.tokens -= size;
+
+        Consume::Allowed
+    }
+
+    pub fn usage(&mut self, client: &SocketAddr) -> ClientUsage {
+        let now = Instant::now();
+        let limits = &self.limits;
+
+        self.aggregate.refill(&limits.aggregate, now);
+        let available = if let Some(bucket) = self.clients.get_mut(client) {
+            bucket.refill(&limits.per_client, now);
+            bucket.tokens
+        } else {
+            limits.per_client.burst
+        };
+
+        ClientUsage {
+            available,
+            aggregate_available: self.aggregate.tokens,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Consume {
+    Allowed,
+    // The message is throttled and it's the first one since the last allowed one.
+    Throttled,
+    StillThrottled,
+    // The client sent more than `FLOODING_FACTOR` times its burst in throttled messages.
+    Flooding,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    throttled: bool,
+    // Bytes of the messages throttled since the last allowed one.
+    dropped: f64,
+}
+
+impl Bucket {
+    fn new(quota: &Quota) -> Self {
+        Self {
+            tokens: quota.burst,
+            updated: Instant::now(),
+            throttled: false,
+            dropped: 0.0,
+        }
+    }
+
+    fn refill(&mut self, quota: &Quota, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * quota.rate).min(quota.burst);
+        self.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn per_client_quota() {
+        let mut limiter = ClientRateLimiter::new(limits(100.0, 1000.0));
+        let client0 = gen_addr();
+        let client1 = gen_addr();
+
+        assert_eq!(limiter.try_consume(client0, 80), Consume::Allowed);
+        assert_eq!(limiter.try_consume(client0, 80), Consume::Throttled);
+        assert_eq!(limiter.try_consume(client0, 80), Consume::StillThrottled);
+        for _ in 0..3 {
+            assert_eq!(limiter.try_consume(client0, 80), Consume::StillThrottled);
+        }
+        assert_eq!(limiter.try_consume(client0, 80), Consume::Flooding);
+
+        // Other clients are not affected.
+        assert_eq!(limiter.try_consume(client1, 80), Consume::Allowed);
+        assert!(limiter.usage(&client0).available < 100.0);
+    }
+
+    #[test]
+    fn aggregate_quota() {
+        let mut limiter = ClientRateLimiter::new(limits(100.0, 150.0));
+
+        assert_eq!(limiter.try_consume(gen_addr(), 100), Consume::Allowed);
+        assert_eq!(limiter.try_consume(gen_addr(), 100), Consume::Throttled);
+        assert!(limiter.usage(&gen_addr()).aggregate_available < 100.0);
+    }
+
+    fn limits(per_client_burst: f64, aggregate_burst: f64) -> ClientRateLimits {
+        ClientRateLimits {
+            per_client: Quota {
+                rate: 1.0,
+                burst: per_client_burst,
+            },
+            aggregate: Quota {
+                rate: 1.0,
+                burst: aggregate_burst,
+            },
+        }
+    }
+}
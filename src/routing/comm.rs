@@ -6,95 +6,148 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use crate::error::{Error, Result};
+#[cfg(feature = "metrics")]
+use super::metrics::Metrics;
+use super::{
+    bandwidth::{BandwidthTracker, TrafficCategory},
+    blacklist::{Blacklist, BlacklistEntry, Violation},
+    bootstrap_cache::{self, BootstrapCache},
+    connection_limiter::ConnectionLimiter,
+    contact_source,
+    message_trace::{MessageRecorder, TraceDirection},
+    send_queue::SendQueue,
+    stats::StatsRecorder,
+    transport::{Transport, TransportError, TransportEvent},
+};
+use crate::{
+    crypto::{Keypair, PublicKey},
+    error::{Error, Result},
+    message_size_limits::MessageSizeLimits,
+    messages::Priority,
+};
 use bytes::Bytes;
-use futures::stream::{FuturesUnordered, StreamExt};
+use futures::stream::{BoxStream, FuturesUnordered, StreamExt};
 use hex_fmt::HexFmt;
-use qp2p::{Endpoint, QuicP2p};
 use std::{
     fmt::{self, Debug, Formatter},
     net::SocketAddr,
-    sync::RwLock,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::Duration,
 };
 use thiserror::Error;
-use tokio::{sync::mpsc, task};
+use tokio::{sync::mpsc, task, time};
+
+// Maximum number of messages being sent at the same time. Any further sends wait for their turn
+// according to their priority.
+const MAX_CONCURRENT_SENDS: usize = 64;
 
 // Communication component of the node to interact with other nodes.
 pub(crate) struct Comm {
-    _quic_p2p: QuicP2p,
-    endpoint: Endpoint,
+    transport: Arc<dyn Transport>,
+    send_queue: SendQueue,
     // Sender for connection events. Kept here so we can clone it and pass it to the incoming
     // messages handler every time we establish new connection. It's kept in an `Option` so we can
     // take it out and drop it on `terminate` which together with all the incoming message handlers
     // terminating closes the corresponding receiver.
     event_tx: RwLock<Option<mpsc::Sender<ConnectionEvent>>>,
+    message_size_limits: MessageSizeLimits,
+    blacklist: Blacklist,
+    connection_limiter: ConnectionLimiter,
+    bandwidth: BandwidthTracker,
+    recorder: MessageRecorder,
+    stats: StatsRecorder,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    // Records the peers we successfully send to, if enabled.
+    bootstrap_cache: Option<Mutex<BootstrapCache>>,
 }
 
 impl Comm {
-    pub async fn new(
-        transport_config: qp2p::Config,
+    pub fn new(
+        mut transport: Box<dyn Transport>,
+        message_size_limits: MessageSizeLimits,
         event_tx: mpsc::Sender<ConnectionEvent>,
-    ) -> Result<Self> {
-        let quic_p2p = QuicP2p::with_config(Some(transport_config), Default::default(), true)?;
-
-        // Don't bootstrap, just create an endpoint to listen to
-        // the incoming messages from other nodes.
-        // This also returns the a channel where we can listen for
-        // disconnection events.
-        let (endpoint, _incoming_connections, incoming_messages, disconnections) =
-            quic_p2p.new_endpoint().await?;
-
-        let _ = task::spawn(handle_incoming_messages(
-            incoming_messages,
-            event_tx.clone(),
-        ));
-
-        let _ = task::spawn(handle_disconnection_events(
-            disconnections,
+    ) -> Self {
+        let blacklist = Blacklist::default();
+        let connection_limiter = ConnectionLimiter::default();
+        let bandwidth = BandwidthTracker::default();
+        let events = transport.events();
+        let transport: Arc<dyn Transport> = Arc::from(transport);
+        let _ = task::spawn(handle_transport_events(
+            events,
             event_tx.clone(),
+            message_size_limits.max(),
+            blacklist.clone(),
+            connection_limiter.clone(),
+            bandwidth.clone(),
+            Arc::downgrade(&transport),
         ));
 
-        Ok(Self {
-            _quic_p2p: quic_p2p,
-            endpoint,
+        Self {
+            transport,
+            send_queue: SendQueue::new(MAX_CONCURRENT_SENDS),
             event_tx: RwLock::new(Some(event_tx)),
-        })
+            message_size_limits,
+            blacklist,
+            connection_limiter,
+            bandwidth,
+            recorder: MessageRecorder::default(),
+            stats: StatsRecorder::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::default(),
+            bootstrap_cache: None,
+        }
     }
 
+    // Connect to all the `contacts` reachable from our address family at the same time, returning
+    // the first one we manage to connect to. Fails if we can't connect to any of them within
+    // `timeout`.
     pub async fn bootstrap(
-        transport_config: qp2p::Config,
+        transport: Box<dyn Transport>,
+        contacts: &[SocketAddr],
+        timeout: Duration,
+        message_size_limits: MessageSizeLimits,
         event_tx: mpsc::Sender<ConnectionEvent>,
     ) -> Result<(Self, SocketAddr)> {
-        let quic_p2p = QuicP2p::with_config(Some(transport_config), Default::default(), true)?;
+        let comm = Self::new(transport, message_size_limits, event_tx);
+        let contacts = contact_source::order_by_family(contacts, &comm.our_connection_info());
 
-        // Bootstrap to the network returning the connection to a node.
-        // We can use the returned channels to listen for incoming messages and disconnection events
-        let (endpoint, _incoming_connections, incoming_messages, disconnections, bootstrap_addr) =
-            quic_p2p.bootstrap().await?;
+        let transport = &comm.transport;
+        let mut tasks: FuturesUnordered<_> = contacts
+            .iter()
+            .map(|addr| async move { (transport.connect(addr).await, *addr) })
+            .collect();
 
-        let _ = task::spawn(handle_incoming_messages(
-            incoming_messages,
-            event_tx.clone(),
-        ));
+        let connect = async {
+            while let Some((result, addr)) = tasks.next().await {
+                match result {
+                    Ok(()) => return Ok(addr),
+                    Err(error) => debug!("Failed to connect to contact {}: {}", addr, error),
+                }
+            }
 
-        let _ = task::spawn(handle_disconnection_events(
-            disconnections,
-            event_tx.clone(),
-        ));
+            Err(Error::BootstrapFailed)
+        };
 
-        Ok((
-            Self {
-                _quic_p2p: quic_p2p,
-                endpoint,
-                event_tx: RwLock::new(Some(event_tx)),
-            },
-            bootstrap_addr,
-        ))
+        let bootstrap_addr = time::timeout(timeout, connect)
+            .await
+            .unwrap_or(Err(Error::BootstrapFailed))?;
+        drop(tasks);
+
+        Ok((comm, bootstrap_addr))
+    }
+
+    // Record the peers we successfully send to into `bootstrap_cache`.
+    pub fn with_bootstrap_cache(mut self, bootstrap_cache: BootstrapCache) -> Self {
+        self.bootstrap_cache = Some(Mutex::new(bootstrap_cache));
+        self
     }
 
-    // Close all existing connections and stop accepting new ones.
+    // Close all existing connections and stop accepting new ones. The messages recorded so far
+    // are all still written to the trace, in the background.
     pub fn terminate(&self) {
-        self.endpoint.close();
+        self.transport.close();
+        self.recorder.close();
         let _ = self
             .event_tx
             .write()
@@ -102,8 +155,71 @@ impl Comm {
             .take();
     }
 
+    // Wait until all the sends in progress or pending finish.
+    pub async fn flush(&self) {
+        self.send_queue.wait_idle().await
+    }
+
     pub fn our_connection_info(&self) -> SocketAddr {
-        self.endpoint.socket_addr()
+        self.transport.local_addr()
+    }
+
+    pub fn message_size_limits(&self) -> &MessageSizeLimits {
+        &self.message_size_limits
+    }
+
+    /// Peers whose messages are dropped. Shared with the incoming messages handler.
+    pub fn blacklist(&self) -> &Blacklist {
+        &self.blacklist
+    }
+
+    /// Per-IP limits on the incoming connections and join requests. Shared with the incoming
+    /// messages handler.
+    pub fn connection_limiter(&self) -> &ConnectionLimiter {
+        &self.connection_limiter
+    }
+
+    /// Bytes exchanged with each connected peer. Shared with the incoming messages handler.
+    pub fn bandwidth(&self) -> &BandwidthTracker {
+        &self.bandwidth
+    }
+
+    /// Records the messages sent and received, once enabled. Shared with the incoming messages
+    /// handler.
+    pub fn recorder(&self) -> &MessageRecorder {
+        &self.recorder
+    }
+
+    /// Limits the concurrent outgoing sends.
+    pub fn send_queue(&self) -> &SendQueue {
+        &self.send_queue
+    }
+
+    /// Counts of the messages and accumulations handled by the node. Shared with the node state.
+    pub fn stats(&self) -> &StatsRecorder {
+        &self.stats
+    }
+
+    /// Counters exported by the metrics endpoint. Shared with the incoming messages handler.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Public key of the node the connection to the given peer was authenticated as, if the
+    /// transport authenticates the peers.
+    pub fn peer_key(&self, addr: &SocketAddr) -> Option<PublicKey> {
+        self.transport.peer_key(addr)
+    }
+
+    /// Switches the transport to the new keypair of our node.
+    pub fn rekey(&self, keypair: &Keypair) -> Result<(), TransportError> {
+        self.transport.rekey(keypair)
+    }
+
+    /// Connects to the given peer, unless already connected.
+    pub async fn connect(&self, addr: &SocketAddr) -> Result<(), TransportError> {
+        self.transport.connect(addr).await
     }
 
     /// Sends a message on an existing connection. If no such connection exists, returns an error.
@@ -111,14 +227,23 @@ impl Comm {
         &self,
         recipient: &SocketAddr,
         msg: Bytes,
+        priority: Priority,
+        category: TrafficCategory,
     ) -> Result<(), SendError> {
-        self.endpoint
-            .send_message(msg, recipient)
+        let _permit = self.send_queue.acquire(priority).await;
+        let len = msg.len();
+        self.transport
+            .send(recipient, msg.clone())
             .await
             .map_err(|err| {
-                error!("{}", err);
+                error_limited!("{}", err);
                 SendError
-            })
+            })?;
+        self.bandwidth.record_sent(*recipient, category, len);
+        self.recorder.record(TraceDirection::Sent, *recipient, &msg);
+        #[cfg(feature = "metrics")]
+        self.metrics.record_sent(category);
+        Ok(())
     }
 
     /// Sends a message to multiple recipients. Attempts to send to `delivery_group_size`
@@ -129,11 +254,15 @@ impl Comm {
     /// Returns `Ok` if all of `delivery_group_size` sends succeeded and `Err` if less that
     /// `delivery_group_size` succeeded. Also returns all the failed recipients which can be used
     /// by the caller to identify lost peers.
+    ///
+    /// The bytes sent to each recipient are accounted to it under `category`.
     pub async fn send(
         &self,
         recipients: &[SocketAddr],
         delivery_group_size: usize,
         msg: Bytes,
+        priority: Priority,
+        category: TrafficCategory,
     ) -> (Result<(), SendError>, Vec<SocketAddr>) {
         trace!(
             "Sending message ({} bytes) to {} of {:?}",
@@ -143,7 +272,7 @@ impl Comm {
         );
 
         if recipients.len() < delivery_group_size {
-            warn!(
+            warn_limited!(
                 "Less than delivery_group_size valid recipients - delivery_group_size: {}, recipients: {:?}",
                 delivery_group_size,
                 recipients,
@@ -155,7 +284,10 @@ impl Comm {
         // Run all the sends concurrently (using `FuturesUnordered`). If any of them fails, pick
         // the next recipient and try to send to them. Proceed until the needed number of sends
         // succeeds or if there are no more recipients to pick.
-        let send = |recipient, msg| async move { (self.send_to(recipient, msg).await, recipient) };
+        let send = |recipient, msg| async move {
+            let _permit = self.send_queue.acquire(priority).await;
+            (self.send_to(recipient, msg).await, recipient)
+        };
 
         let mut tasks: FuturesUnordered<_> = recipients[0..delivery_group_size]
             .iter()
@@ -168,8 +300,15 @@ impl Comm {
 
         while let Some((result, addr)) = tasks.next().await {
             match result {
-                Ok(()) => successes += 1,
-                Err(qp2p::Error::Connection(qp2p::ConnectionError::LocallyClosed)) => {
+                Ok(()) => {
+                    successes += 1;
+                    self.record_contact(*addr);
+                    self.bandwidth.record_sent(*addr, category, msg.len());
+                    self.recorder.record(TraceDirection::Sent, *addr, &msg);
+                    #[cfg(feature = "metrics")]
+                    self.metrics.record_sent(category);
+                }
+                Err(TransportError::Closed) => {
                     // The connection was closed by us which means we are terminating so let's cut
                     // this short.
                     return (Err(SendError), vec![]);
@@ -202,28 +341,47 @@ impl Comm {
         (result, failed_recipients)
     }
 
+    // Record `addr` in the bootstrap cache. The cache is stored from time to time, on a blocking
+    // thread, so sending doesn't wait for the file system.
+    fn record_contact(&self, addr: SocketAddr) {
+        let cache = if let Some(cache) = &self.bootstrap_cache {
+            cache
+        } else {
+            return;
+        };
+
+        let pending = {
+            let mut cache = cache.lock().unwrap_or_else(|err| err.into_inner());
+            cache.insert(addr);
+            cache.take_pending_store()
+        };
+
+        if let Some((path, payload)) = pending {
+            let _ = task::spawn_blocking(move || bootstrap_cache::write(&path, &payload));
+        }
+    }
+
     // Low-level send
-    async fn send_to(&self, recipient: &SocketAddr, msg: Bytes) -> Result<(), qp2p::Error> {
+    async fn send_to(&self, recipient: &SocketAddr, msg: Bytes) -> Result<(), TransportError> {
         // This will attempt to use a cached connection
-        if self
-            .endpoint
-            .send_message(msg.clone(), recipient)
-            .await
-            .is_ok()
-        {
+        if self.transport.send(recipient, msg.clone()).await.is_ok() {
             return Ok(());
         }
 
         // If the sending of a message failed the connection would no longer
         // exist in the pool. So we connect again and then send the message.
-        self.endpoint.connect_to(recipient).await?;
-        self.endpoint.send_message(msg, recipient).await
+        self.transport.connect(recipient).await?;
+        self.transport.send(recipient, msg).await
     }
 }
 
 impl Drop for Comm {
     fn drop(&mut self) {
-        self.endpoint.close()
+        self.transport.close();
+
+        if let Some(cache) = &self.bootstrap_cache {
+            cache.lock().unwrap_or_else(|err| err.into_inner()).store()
+        }
     }
 }
 
@@ -251,33 +409,74 @@ impl Debug for ConnectionEvent {
     }
 }
 
-async fn handle_disconnection_events(
-    mut disconnections: qp2p::DisconnectionEvents,
+async fn handle_transport_events(
+    mut events: BoxStream<'static, TransportEvent>,
     mut event_tx: mpsc::Sender<ConnectionEvent>,
+    max_message_size: usize,
+    blacklist: Blacklist,
+    connection_limiter: ConnectionLimiter,
+    bandwidth: BandwidthTracker,
+    // Weak, so this handler doesn't keep the transport alive after `Comm` is dropped.
+    transport: Weak<dyn Transport>,
 ) {
-    while let Some(peer_addr) = disconnections.next().await {
-        let _ = event_tx
-            .send(ConnectionEvent::Disconnected(peer_addr))
-            .await;
+    while let Some(event) = events.next().await {
+        let event = match event {
+            TransportEvent::Connected(addr) => {
+                if blacklist.contains(&BlacklistEntry::Addr(addr)) {
+                    trace!("Closing connection from blacklisted peer {}", addr);
+                    disconnect(&transport, &addr);
+                } else if !connection_limiter.accept_connection(addr) {
+                    debug!("Closing connection from {} exceeding the limits", addr);
+                    disconnect(&transport, &addr);
+                }
+                continue;
+            }
+            TransportEvent::Received(src, msg) => {
+                if blacklist.contains(&BlacklistEntry::Addr(src)) {
+                    trace!("Dropping message from blacklisted peer {}", src);
+                    continue;
+                }
+
+                if msg.len() > max_message_size {
+                    warn_limited!(
+                        "Dropping message ({} bytes) exceeding the size limit from {}",
+                        msg.len(),
+                        src
+                    );
+                    blacklist.report(BlacklistEntry::Addr(src), Violation::OversizedMessage);
+                    continue;
+                }
+
+                ConnectionEvent::Received((src, msg))
+            }
+            TransportEvent::Disconnected(addr) => {
+                connection_limiter.remove_connection(&addr);
+                bandwidth.remove(&addr);
+                ConnectionEvent::Disconnected(addr)
+            }
+        };
+
+        let _ = event_tx.send(event).await;
     }
 }
 
-async fn handle_incoming_messages(
-    mut incoming_msgs: qp2p::IncomingMessages,
-    mut event_tx: mpsc::Sender<ConnectionEvent>,
-) {
-    while let Some((src, msg)) = incoming_msgs.next().await {
-        let _ = event_tx.send(ConnectionEvent::Received((src, msg))).await;
+fn disconnect(transport: &Weak<dyn Transport>, addr: &SocketAddr) {
+    if let Some(transport) = transport.upgrade() {
+        transport.disconnect(addr);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::routing::{connection_limiter::ConnectionLimits, transport::QuicTransport};
     use anyhow::Result;
     use assert_matches::assert_matches;
-    use futures::future;
-    use qp2p::Config;
+    use futures::{
+        future::{self, BoxFuture, FutureExt},
+        stream,
+    };
+    use qp2p::{Config, QuicP2p};
     use std::{net::Ipv4Addr, slice, time::Duration};
     use tokio::{net::UdpSocket, sync::mpsc, time};
 
@@ -286,15 +485,25 @@ mod tests {
     #[tokio::test]
     async fn successful_send() -> Result<()> {
         let (tx, _rx) = mpsc::channel(1);
-        let comm = Comm::new(transport_config(), tx).await?;
+        let comm = Comm::new(
+            quic(transport_config()).await?,
+            MessageSizeLimits::default(),
+            tx,
+        );
 
         let mut peer0 = Peer::new().await?;
         let mut peer1 = Peer::new().await?;
 
         let message = Bytes::from_static(b"hello world");
-        comm.send(&[peer0.addr, peer1.addr], 2, message.clone())
-            .await
-            .0?;
+        comm.send(
+            &[peer0.addr, peer1.addr],
+            2,
+            message.clone(),
+            Priority::UserData,
+            TrafficCategory::Gossip,
+        )
+        .await
+        .0?;
 
         assert_eq!(peer0.rx.recv().await, Some(message.clone()));
         assert_eq!(peer1.rx.recv().await, Some(message));
@@ -305,15 +514,25 @@ mod tests {
     #[tokio::test]
     async fn successful_send_to_subset() -> Result<()> {
         let (tx, _rx) = mpsc::channel(1);
-        let comm = Comm::new(transport_config(), tx).await?;
+        let comm = Comm::new(
+            quic(transport_config()).await?,
+            MessageSizeLimits::default(),
+            tx,
+        );
 
         let mut peer0 = Peer::new().await?;
         let mut peer1 = Peer::new().await?;
 
         let message = Bytes::from_static(b"hello world");
-        comm.send(&[peer0.addr, peer1.addr], 1, message.clone())
-            .await
-            .0?;
+        comm.send(
+            &[peer0.addr, peer1.addr],
+            1,
+            message.clone(),
+            Priority::UserData,
+            TrafficCategory::Gossip,
+        )
+        .await
+        .0?;
 
         assert_eq!(peer0.rx.recv().await, Some(message));
 
@@ -329,18 +548,27 @@ mod tests {
     async fn failed_send() -> Result<()> {
         let (tx, _rx) = mpsc::channel(1);
         let comm = Comm::new(
-            Config {
+            quic(Config {
                 // This makes this test faster.
                 idle_timeout_msec: Some(1),
                 ..transport_config()
-            },
+            })
+            .await?,
+            MessageSizeLimits::default(),
             tx,
-        )
-        .await?;
+        );
         let invalid_addr = get_invalid_addr().await?;
 
         let message = Bytes::from_static(b"hello world");
-        let (result, failed_recipients) = comm.send(&[invalid_addr], 1, message.clone()).await;
+        let (result, failed_recipients) = comm
+            .send(
+                &[invalid_addr],
+                1,
+                message.clone(),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
+            .await;
         assert!(result.is_err());
         assert_eq!(failed_recipients, [invalid_addr]);
 
@@ -351,20 +579,27 @@ mod tests {
     async fn successful_send_after_failed_attempts() -> Result<()> {
         let (tx, _rx) = mpsc::channel(1);
         let comm = Comm::new(
-            Config {
+            quic(Config {
                 idle_timeout_msec: Some(1),
                 ..transport_config()
-            },
+            })
+            .await?,
+            MessageSizeLimits::default(),
             tx,
-        )
-        .await?;
+        );
         let mut peer = Peer::new().await?;
         let invalid_addr = get_invalid_addr().await?;
 
         let message = Bytes::from_static(b"hello world");
-        comm.send(&[invalid_addr, peer.addr], 1, message.clone())
-            .await
-            .0?;
+        comm.send(
+            &[invalid_addr, peer.addr],
+            1,
+            message.clone(),
+            Priority::UserData,
+            TrafficCategory::Gossip,
+        )
+        .await
+        .0?;
 
         assert_eq!(peer.rx.recv().await, Some(message));
 
@@ -375,19 +610,26 @@ mod tests {
     async fn partially_successful_send() -> Result<()> {
         let (tx, _rx) = mpsc::channel(1);
         let comm = Comm::new(
-            Config {
+            quic(Config {
                 idle_timeout_msec: Some(1),
                 ..transport_config()
-            },
+            })
+            .await?,
+            MessageSizeLimits::default(),
             tx,
-        )
-        .await?;
+        );
         let mut peer = Peer::new().await?;
         let invalid_addr = get_invalid_addr().await?;
 
         let message = Bytes::from_static(b"hello world");
         let (result, failed_recipients) = comm
-            .send(&[invalid_addr, peer.addr], 2, message.clone())
+            .send(
+                &[invalid_addr, peer.addr],
+                2,
+                message.clone(),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
             .await;
 
         assert!(result.is_err());
@@ -400,7 +642,11 @@ mod tests {
     #[tokio::test]
     async fn send_after_reconnect() -> Result<()> {
         let (tx, _rx) = mpsc::channel(1);
-        let send_comm = Comm::new(transport_config(), tx).await?;
+        let send_comm = Comm::new(
+            quic(transport_config()).await?,
+            MessageSizeLimits::default(),
+            tx,
+        );
 
         let recv_transport = QuicP2p::with_config(Some(transport_config()), &[], false)?;
         let (mut recv_endpoint, _, mut incoming_msgs, _) = recv_transport.new_endpoint().await?;
@@ -409,7 +655,13 @@ mod tests {
         // Send the first message.
         let msg0 = Bytes::from_static(b"zero");
         send_comm
-            .send(slice::from_ref(&recv_addr), 1, msg0.clone())
+            .send(
+                slice::from_ref(&recv_addr),
+                1,
+                msg0.clone(),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
             .await
             .0?;
 
@@ -428,7 +680,13 @@ mod tests {
         // Send the second message.
         let msg1 = Bytes::from_static(b"one");
         send_comm
-            .send(slice::from_ref(&recv_addr), 1, msg1.clone())
+            .send(
+                slice::from_ref(&recv_addr),
+                1,
+                msg1.clone(),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
             .await
             .0?;
 
@@ -447,16 +705,30 @@ mod tests {
     #[tokio::test]
     async fn incoming_connection_lost() -> Result<()> {
         let (tx, mut rx0) = mpsc::channel(1);
-        let comm0 = Comm::new(transport_config(), tx).await?;
+        let comm0 = Comm::new(
+            quic(transport_config()).await?,
+            MessageSizeLimits::default(),
+            tx,
+        );
         let addr0 = comm0.our_connection_info();
 
         let (tx, _rx) = mpsc::channel(1);
-        let comm1 = Comm::new(transport_config(), tx).await?;
+        let comm1 = Comm::new(
+            quic(transport_config()).await?,
+            MessageSizeLimits::default(),
+            tx,
+        );
         let addr1 = comm1.our_connection_info();
 
         // Send a message to establish the connection
         comm1
-            .send(slice::from_ref(&addr0), 1, Bytes::from_static(b"hello"))
+            .send(
+                slice::from_ref(&addr0),
+                1,
+                Bytes::from_static(b"hello"),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
             .await
             .0?;
         assert_matches!(rx0.recv().await, Some(ConnectionEvent::Received(_)));
@@ -471,6 +743,181 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn oversized_message_blocks_sender() -> Result<()> {
+        let (tx, mut rx0) = mpsc::channel(1);
+        let limits = MessageSizeLimits {
+            node: 16,
+            client: 16,
+            section_info: 16,
+        };
+        let comm0 = Comm::new(quic(transport_config()).await?, limits, tx);
+        let addr0 = comm0.our_connection_info();
+
+        let (tx, _rx) = mpsc::channel(1);
+        let comm1 = Comm::new(
+            quic(transport_config()).await?,
+            MessageSizeLimits::default(),
+            tx,
+        );
+
+        comm1
+            .send(
+                slice::from_ref(&addr0),
+                1,
+                Bytes::from(vec![0; 17]),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
+            .await
+            .0?;
+        // Even messages within the limit are dropped now.
+        comm1
+            .send(
+                slice::from_ref(&addr0),
+                1,
+                Bytes::from_static(b"hello"),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
+            .await
+            .0?;
+
+        assert!(time::timeout(TIMEOUT, rx0.recv())
+            .await
+            .unwrap_or_default()
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn custom_transport() -> Result<()> {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let transport = MemoryTransport {
+            addr: get_invalid_addr().await?,
+            reachable: get_invalid_addr().await?,
+            events_rx: Some(events_rx),
+            disconnected: Default::default(),
+        };
+        let reachable = transport.reachable;
+        let unreachable = get_invalid_addr().await?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let comm = Comm::new(Box::new(transport), MessageSizeLimits::default(), tx);
+
+        let (result, failed_recipients) = comm
+            .send(
+                &[unreachable, reachable],
+                1,
+                Bytes::from_static(b"hello"),
+                Priority::UserData,
+                TrafficCategory::Gossip,
+            )
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(failed_recipients, [unreachable]);
+
+        let _ = events_tx.send(TransportEvent::Disconnected(reachable));
+        assert_matches!(
+            rx.recv().await,
+            Some(ConnectionEvent::Disconnected(addr)) => assert_eq!(addr, reachable)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn excess_connections_are_closed() -> Result<()> {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let transport = MemoryTransport {
+            addr: get_invalid_addr().await?,
+            reachable: get_invalid_addr().await?,
+            events_rx: Some(events_rx),
+            disconnected: Default::default(),
+        };
+        let disconnected = transport.disconnected.clone();
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let comm = Comm::new(Box::new(transport), MessageSizeLimits::default(), tx);
+        comm.connection_limiter().set_limits(ConnectionLimits {
+            max_connections: 1,
+            ..ConnectionLimits::default()
+        });
+
+        let addr0: SocketAddr = "192.0.2.1:1000".parse()?;
+        let addr1: SocketAddr = "192.0.2.1:1001".parse()?;
+        let _ = events_tx.send(TransportEvent::Connected(addr0));
+        let _ = events_tx.send(TransportEvent::Connected(addr1));
+        let _ = events_tx.send(TransportEvent::Received(
+            addr0,
+            Bytes::from_static(b"hello"),
+        ));
+
+        // The events are handled in order, so by now the excess connection is closed.
+        assert_matches!(rx.recv().await, Some(ConnectionEvent::Received((addr, _))) => {
+            assert_eq!(addr, addr0)
+        });
+        assert_eq!(*disconnected.lock().unwrap(), [addr1]);
+
+        Ok(())
+    }
+
+    // Transport which can only send to a single peer.
+    #[derive(Debug)]
+    struct MemoryTransport {
+        addr: SocketAddr,
+        reachable: SocketAddr,
+        events_rx: Option<mpsc::UnboundedReceiver<TransportEvent>>,
+        disconnected: Arc<Mutex<Vec<SocketAddr>>>,
+    }
+
+    impl Transport for MemoryTransport {
+        fn local_addr(&self) -> SocketAddr {
+            self.addr
+        }
+
+        fn connect<'a>(
+            &'a self,
+            addr: &'a SocketAddr,
+        ) -> BoxFuture<'a, Result<(), TransportError>> {
+            let result = if *addr == self.reachable {
+                Ok(())
+            } else {
+                Err(TransportError::Other("unreachable".into()))
+            };
+            future::ready(result).boxed()
+        }
+
+        fn send<'a>(
+            &'a self,
+            addr: &'a SocketAddr,
+            _msg: Bytes,
+        ) -> BoxFuture<'a, Result<(), TransportError>> {
+            self.connect(addr)
+        }
+
+        fn disconnect(&self, addr: &SocketAddr) {
+            self.disconnected.lock().unwrap().push(*addr)
+        }
+
+        fn events(&mut self) -> BoxStream<'static, TransportEvent> {
+            let events_rx = self.events_rx.take();
+            stream::unfold(events_rx, |events_rx| async move {
+                let mut events_rx = events_rx?;
+                let event = events_rx.recv().await?;
+                Some((event, Some(events_rx)))
+            })
+            .boxed()
+        }
+
+        fn close(&self) {}
+    }
+
+    async fn quic(transport_config: Config) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(QuicTransport::new(transport_config).await?))
+    }
+
     fn transport_config() -> Config {
         Config {
             local_ip: Some(Ipv4Addr::LOCALHOST.into()),
@@ -6,9 +6,11 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+use super::blacklist::{BlacklistEntry, Violation};
 use crate::{
     consensus::{DkgFailureProofSet, ProofShare, Vote},
-    messages::Message,
+    correlation_id::CorrelationId,
+    messages::{Message, Priority},
     relocation::SignedRelocateDetails,
     section::{EldersInfo, SectionKeyShare},
 };
@@ -27,6 +29,7 @@ use std::{
     time::Duration,
 };
 use tokio::sync::mpsc;
+use xor_name::{Prefix, XorName};
 
 /// Command for node.
 #[allow(clippy::large_enum_variant)]
@@ -38,6 +41,11 @@ pub(crate) enum Command {
         sender: Option<SocketAddr>,
         message: Message,
     },
+    /// Handle `message` from `sender` replayed from a captured trace, see `Routing::replay`.
+    ReplayMessage {
+        sender: SocketAddr,
+        message: Message,
+    },
     /// Handle network info message.
     HandleSectionInfoMsg {
         sender: SocketAddr,
@@ -69,12 +77,28 @@ pub(crate) enum Command {
         recipients: Vec<SocketAddr>,
         delivery_group_size: usize,
         message: MessageType,
+        priority: Priority,
     },
-    /// Send `UserMessage` with the given source and destination.
+    /// Send `UserMessage` with the given source and destination, optionally traced across the
+    /// hops it takes.
     SendUserMessage {
         src: SrcLocation,
         dst: DstLocation,
         content: Bytes,
+        correlation_id: Option<CorrelationId>,
+        traced: bool,
+    },
+    /// Send `UserMessageMulticast` with the given source to all the members of the given prefix.
+    MulticastUserMessage {
+        src: SrcLocation,
+        prefix: Prefix,
+        content: Bytes,
+    },
+    /// Send `UserMessageAnycast` from our node to the single node closest to the given name.
+    AnycastUserMessage {
+        target: XorName,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
     },
     /// Schedule a timeout after the given duration. When the timeout expires, a `HandleTimeout`
     /// command is raised. The token is used to identify the timeout.
@@ -90,12 +114,26 @@ pub(crate) enum Command {
     },
     /// Attempt to set JoinsAllowed flag.
     SetJoinsAllowed(bool),
+    /// Connect to the peer introduced to us by the `relay` elder, at the same time as it connects
+    /// to us, to punch a hole through the NATs between us.
+    Punch { peer: SocketAddr, relay: SocketAddr },
+    /// Probe the liveness of the given peers by pinging them and measuring the round-trip time.
+    Probe(Vec<SocketAddr>),
+    /// Blacklist the peer for violating the protocol.
+    Blacklist {
+        entry: BlacklistEntry,
+        violation: Violation,
+    },
 }
 
 impl Command {
     /// Convenience method to create `Command::SendMessage` with a single recipient.
-    pub fn send_message_to_node(recipient: &SocketAddr, message_bytes: Bytes) -> Self {
-        Self::send_message_to_nodes(slice::from_ref(recipient), 1, message_bytes)
+    pub fn send_message_to_node(
+        recipient: &SocketAddr,
+        message_bytes: Bytes,
+        priority: Priority,
+    ) -> Self {
+        Self::send_message_to_nodes(slice::from_ref(recipient), 1, message_bytes, priority)
     }
 
     /// Convenience method to create `Command::SendMessage` with multiple recipients.
@@ -103,12 +141,14 @@ impl Command {
         recipients: &[SocketAddr],
         delivery_group_size: usize,
         message_bytes: Bytes,
+        priority: Priority,
     ) -> Self {
         let node_msg = NodeMessage::new(message_bytes);
         Self::SendMessage {
             recipients: recipients.to_vec(),
             delivery_group_size,
             message: MessageType::NodeMessage(node_msg),
+            priority,
         }
     }
 }
@@ -121,6 +161,11 @@ impl Debug for Command {
                 .field("sender", sender)
                 .field("message", message)
                 .finish(),
+            Self::ReplayMessage { sender, message } => f
+                .debug_struct("ReplayMessage")
+                .field("sender", sender)
+                .field("message", message)
+                .finish(),
             Self::HandleSectionInfoMsg { sender, message } => f
                 .debug_struct("HandleSectionInfoMsg")
                 .field("sender", sender)
@@ -161,17 +206,47 @@ impl Debug for Command {
                 recipients,
                 delivery_group_size,
                 message,
+                priority,
             } => f
                 .debug_struct("SendMessage")
                 .field("recipients", recipients)
                 .field("delivery_group_size", delivery_group_size)
                 .field("message", message)
+                .field("priority", priority)
                 .finish(),
-            Self::SendUserMessage { src, dst, content } => f
+            Self::SendUserMessage {
+                src,
+                dst,
+                content,
+                correlation_id,
+                traced,
+            } => f
                 .debug_struct("SendUserMessage")
                 .field("src", src)
                 .field("dst", dst)
                 .field("content", &format_args!("{:10}", HexFmt(content)))
+                .field("correlation_id", correlation_id)
+                .field("traced", traced)
+                .finish(),
+            Self::MulticastUserMessage {
+                src,
+                prefix,
+                content,
+            } => f
+                .debug_struct("MulticastUserMessage")
+                .field("src", src)
+                .field("prefix", prefix)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .finish(),
+            Self::AnycastUserMessage {
+                target,
+                content,
+                correlation_id,
+            } => f
+                .debug_struct("AnycastUserMessage")
+                .field("target", target)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .field("correlation_id", correlation_id)
                 .finish(),
             Self::ScheduleTimeout { duration, token } => f
                 .debug_struct("ScheduleTimeout")
@@ -191,6 +266,17 @@ impl Debug for Command {
                 .debug_tuple("SetJoinsAllowed")
                 .field(joins_allowed)
                 .finish(),
+            Self::Punch { peer, relay } => f
+                .debug_struct("Punch")
+                .field("peer", peer)
+                .field("relay", relay)
+                .finish(),
+            Self::Probe(peers) => f.debug_tuple("Probe").field(peers).finish(),
+            Self::Blacklist { entry, violation } => f
+                .debug_struct("Blacklist")
+                .field("entry", entry)
+                .field("violation", violation)
+                .finish(),
         }
     }
 }
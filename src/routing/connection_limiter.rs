@@ -0,0 +1,201 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use lru_time_cache::LruCache;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Limits on the connections and join requests from a single IP address, so a single host can't
+/// exhaust the resources of the node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnectionLimits {
+    /// Maximum number of connections from the same IP address open at the same time.
+    pub max_connections: usize,
+    /// Maximum number of new connections from the same IP address within `window`.
+    pub max_new_connections: usize,
+    /// Maximum number of join requests from the same IP address within `window`.
+    pub max_join_requests: usize,
+    /// Time window of `max_new_connections` and `max_join_requests`.
+    pub window: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: 16,
+            max_new_connections: 32,
+            max_join_requests: 4,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+// Per-IP counters of the incoming connections and join requests. Shared between the incoming
+// messages handler and the rest of the node.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionLimiter(Arc<Mutex<Option<Inner>>>);
+
+struct Inner {
+    limits: ConnectionLimits,
+    open: HashMap<IpAddr, HashSet<SocketAddr>>,
+    new_connections: LruCache<IpAddr, VecDeque<Instant>>,
+    join_requests: LruCache<IpAddr, VecDeque<Instant>>,
+}
+
+impl ConnectionLimiter {
+    // Start enforcing the limits. Nothing is limited until then.
+    pub fn set_limits(&self, limits: ConnectionLimits) {
+        *self.lock() = Some(Inner {
+            limits,
+            open: HashMap::new(),
+            new_connections: LruCache::with_expiry_duration(limits.window),
+            join_requests: LruCache::with_expiry_duration(limits.window),
+        });
+    }
+
+    // Count the new incoming connection from `addr`. Returns whether it's within the limits,
+    // otherwise it should be closed.
+    pub fn accept_connection(&self, addr: SocketAddr) -> bool {
+        let mut guard = self.lock();
+        let inner = if let Some(inner) = &mut *guard {
+            inner
+        } else {
+            return true;
+        };
+
+        let ip = addr.ip();
+        let open = inner.open.get(&ip).map(HashSet::len).unwrap_or(0);
+        if open >= inner.limits.max_connections {
+            return false;
+        }
+
+        if !count(
+            &mut inner.new_connections,
+            ip,
+            inner.limits.max_new_connections,
+            inner.limits.window,
+        ) {
+            return false;
+        }
+
+        let _ = inner.open.entry(ip).or_default().insert(addr);
+        true
+    }
+
+    pub fn remove_connection(&self, addr: &SocketAddr) {
+        if let Some(inner) = &mut *self.lock() {
+            let ip = addr.ip();
+            if let Some(open) = inner.open.get_mut(&ip) {
+                let _ = open.remove(addr);
+                if open.is_empty() {
+                    let _ = inner.open.remove(&ip);
+                }
+            }
+        }
+    }
+
+    // Count the join request from `addr`. Returns whether it's within the limits, otherwise it
+    // should be dropped.
+    pub fn accept_join_request(&self, addr: &SocketAddr) -> bool {
+        if let Some(inner) = &mut *self.lock() {
+            count(
+                &mut inner.join_requests,
+                addr.ip(),
+                inner.limits.max_join_requests,
+                inner.limits.window,
+            )
+        } else {
+            true
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Option<Inner>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// Record an event from `ip` unless there already were `max` of them within `window`. Returns
+// whether it was recorded.
+fn count(
+    counters: &mut LruCache<IpAddr, VecDeque<Instant>>,
+    ip: IpAddr,
+    max: usize,
+    window: Duration,
+) -> bool {
+    let now = Instant::now();
+    if !counters.contains_key(&ip) {
+        let _ = counters.insert(ip, VecDeque::new());
+    }
+    let times = if let Some(times) = counters.get_mut(&ip) {
+        times
+    } else {
+        return true;
+    };
+
+    while times
+        .front()
+        .map(|time| now.saturating_duration_since(*time) >= window)
+        .unwrap_or(false)
+    {
+        let _ = times.pop_front();
+    }
+
+    if times.len() >= max {
+        false
+    } else {
+        times.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_connections_per_ip() {
+        let limiter = ConnectionLimiter::default();
+        let a0: SocketAddr = "192.0.2.1:1000".parse().unwrap();
+        let a1: SocketAddr = "192.0.2.1:1001".parse().unwrap();
+        let a2: SocketAddr = "192.0.2.1:1002".parse().unwrap();
+        let b: SocketAddr = "192.0.2.2:1000".parse().unwrap();
+
+        // Not limited until the limits are set.
+        for _ in 0..10 {
+            assert!(limiter.accept_connection(a0));
+        }
+
+        limiter.set_limits(ConnectionLimits {
+            max_connections: 2,
+            max_new_connections: 3,
+            max_join_requests: 1,
+            window: Duration::from_secs(60),
+        });
+
+        assert!(limiter.accept_connection(a0));
+        assert!(limiter.accept_connection(a1));
+        // Too many open connections.
+        assert!(!limiter.accept_connection(a2));
+        // Other IPs are not affected.
+        assert!(limiter.accept_connection(b));
+
+        limiter.remove_connection(&a1);
+        assert!(limiter.accept_connection(a2));
+        limiter.remove_connection(&a2);
+        // Too many new connections.
+        assert!(!limiter.accept_connection(a1));
+
+        assert!(limiter.accept_join_request(&a0));
+        assert!(!limiter.accept_join_request(&a1));
+        assert!(limiter.accept_join_request(&b));
+    }
+}
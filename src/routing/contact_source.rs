@@ -0,0 +1,187 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{bootstrap_cache::BootstrapCache, storage::Storage};
+use crate::{
+    error::{Error, Result},
+    peer::Peer,
+};
+use std::{
+    collections::HashSet,
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+/// Source of the contacts a node bootstraps through. The contacts of all the configured sources
+/// are tried at the same time and the node joins through the first one it manages to connect to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ContactSource {
+    /// JSON file with an array of contact addresses, e.g.
+    /// `["1.2.3.4:5678", "[2001:db8::1]:5678"]`.
+    File(PathBuf),
+    /// The peers known from before a restart: the elders of the section stored at
+    /// `Config::storage_path` and the peers in the cache at `Config::bootstrap_cache_path`.
+    Cache,
+    /// The hard-coded contacts of `Config::transport_config`.
+    Seeds,
+}
+
+// Collect the contacts of `sources`, in their order and without duplicates. A source that fails
+// to load is skipped, so the others can still be used.
+pub(crate) fn resolve(
+    sources: &[ContactSource],
+    seeds: impl IntoIterator<Item = SocketAddr>,
+    storage: Option<&Storage>,
+    bootstrap_cache: Option<&BootstrapCache>,
+) -> Vec<SocketAddr> {
+    let seeds: Vec<_> = seeds.into_iter().collect();
+    let mut seen = HashSet::new();
+    let mut contacts = vec![];
+
+    for source in sources {
+        let source_contacts = match source {
+            ContactSource::File(path) => match read_file(path) {
+                Ok(contacts) => contacts,
+                Err(error) => {
+                    warn!(
+                        "Failed to read bootstrap contacts from {}: {}",
+                        path.display(),
+                        error
+                    );
+                    continue;
+                }
+            },
+            ContactSource::Cache => {
+                let mut source_contacts = vec![];
+
+                match storage.map(Storage::load) {
+                    Some(Ok(Some(state))) => source_contacts
+                        .extend(state.section.elders_info().peers().map(Peer::addr).copied()),
+                    Some(Err(error)) => warn!("Failed to load stored section: {}", error),
+                    Some(Ok(None)) | None => (),
+                }
+
+                if let Some(bootstrap_cache) = bootstrap_cache {
+                    source_contacts.extend(bootstrap_cache.contacts());
+                }
+
+                source_contacts
+            }
+            ContactSource::Seeds => seeds.clone(),
+        };
+
+        contacts.extend(
+            source_contacts
+                .into_iter()
+                .map(canonical)
+                .filter(|contact| seen.insert(*contact)),
+        );
+    }
+
+    contacts
+}
+
+// Order `contacts` for connecting to them from an endpoint bound to `local`. Contacts of the same
+// address family as `local` go first, keeping their order otherwise. IPv6 contacts are dropped
+// when `local` is IPv4 as they are unreachable from it, while IPv4 contacts are kept when `local`
+// is IPv6, as a dual-stack socket reaches them too.
+pub(crate) fn order_by_family(contacts: &[SocketAddr], local: &SocketAddr) -> Vec<SocketAddr> {
+    let (same, other): (Vec<_>, Vec<_>) = contacts
+        .iter()
+        .copied()
+        .partition(|contact| contact.is_ipv4() == local.is_ipv4());
+
+    if local.is_ipv4() && !other.is_empty() {
+        debug!(
+            "Skipping IPv6 bootstrap contacts unreachable from {}: {:?}",
+            local, other
+        );
+        same
+    } else {
+        same.into_iter().chain(other).collect()
+    }
+}
+
+// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) converted to plain IPv4 ones, so the same contact
+// isn't listed under both forms.
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(addr_v6) => match addr_v6.ip().segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => SocketAddr::new(
+                Ipv4Addr::new((high >> 8) as u8, high as u8, (low >> 8) as u8, low as u8).into(),
+                addr_v6.port(),
+            ),
+            _ => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<SocketAddr>> {
+    Ok(serde_json::from_slice(
+        &fs::read(path).map_err(Error::ContactsFile)?,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+    use anyhow::Result;
+
+    #[test]
+    fn resolve_in_order_without_duplicates() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "sn_routing_contacts_{}.json",
+            rand::random::<u64>()
+        ));
+
+        let a = gen_addr();
+        let b = gen_addr();
+        let c = gen_addr();
+        fs::write(&path, serde_json::to_vec(&[b, a])?)?;
+
+        let missing = path.with_extension("missing");
+        let sources = [
+            ContactSource::File(missing),
+            ContactSource::File(path.clone()),
+            ContactSource::Cache,
+            ContactSource::Seeds,
+        ];
+
+        let contacts = resolve(&sources, vec![a, c], None, None);
+        assert_eq!(contacts, vec![b, a, c]);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_ipv4_mapped_as_ipv4() {
+        let v4: SocketAddr = "192.0.2.1:1000".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:192.0.2.1]:1000".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:1000".parse().unwrap();
+
+        let contacts = resolve(&[ContactSource::Seeds], vec![mapped, v4, v6], None, None);
+        assert_eq!(contacts, vec![v4, v6]);
+    }
+
+    #[test]
+    fn order_contacts_by_family() {
+        let v4 = gen_addr();
+        let v6: SocketAddr = "[2001:db8::1]:1000".parse().unwrap();
+        let contacts = [v6, v4];
+
+        let local_v4: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        assert_eq!(order_by_family(&contacts, &local_v4), vec![v4]);
+
+        let local_v6: SocketAddr = "[::]:0".parse().unwrap();
+        assert_eq!(order_by_family(&contacts, &local_v6), vec![v6, v4]);
+    }
+}
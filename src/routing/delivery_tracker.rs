@@ -0,0 +1,270 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::command;
+use crate::messages::{Message, MessageHash};
+use bytes::Bytes;
+use lru_time_cache::LruCache;
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+use xor_name::XorName;
+
+/// How long to wait for an ack before sending the message again.
+pub(crate) const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many times to send a message before giving up on its delivery.
+pub(crate) const MAX_DELIVERY_ATTEMPTS: usize = 3;
+
+/// Keeps track of the user messages we sent and are waiting to be acknowledged by their
+/// destination.
+///
+/// # Usage
+///
+/// After sending a message, call `track` and schedule a timeout with the returned token. Call
+/// `handle_ack` when an ack arrives and `handle_timeout` when the timeout expires. Depending on
+/// the outcome, either send the message again (and `track` it again) or give up.
+///
+/// A message is sent again as a renewed copy with a different hash, which the destination
+/// acknowledges on its own, so acks are never resent.
+pub(crate) struct DeliveryTracker {
+    pending: HashMap<MessageHash, PendingDelivery>,
+    timers: HashMap<u64, MessageHash>,
+    // Messages sent as multiple fragments, by fragment id.
+    fragmented: LruCache<u64, Fragmented>,
+}
+
+impl DeliveryTracker {
+    pub fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+            timers: HashMap::new(),
+            fragmented: LruCache::with_expiry_duration(
+                ACK_TIMEOUT * (2 * MAX_DELIVERY_ATTEMPTS as u32),
+            ),
+        }
+    }
+
+    /// Start (or continue) waiting for the ack of `message` which was just sent for the
+    /// `attempts`-th time. `tried` are the names of the peers it was sent to so far. Returns the
+    /// token of the timeout to schedule.
+    pub fn track(&mut self, message: Message, attempts: usize, tried: BTreeSet<XorName>) -> u64 {
+        let token = command::next_timer_token();
+        let hash = *message.hash();
+        let _ = self.timers.insert(token, hash);
+        let _ = self.pending.insert(
+            hash,
+            PendingDelivery {
+                message,
+                attempts,
+                tried,
+                sent_at: Instant::now(),
+            },
+        );
+        token
+    }
+
+    /// Returns the message with the given hash if it's still waiting for an ack.
+    pub fn pending(&self, hash: &MessageHash) -> Option<&Message> {
+        self.pending.get(hash).map(|pending| &pending.message)
+    }
+
+    /// Time since the message with the given hash was sent, if it's waiting for an ack and was sent
+    /// only once. Once sent again, its ack could be for any of the copies.
+    pub fn round_trip(&self, hash: &MessageHash) -> Option<Duration> {
+        self.pending
+            .get(hash)
+            .filter(|pending| pending.attempts == 1)
+            .map(|pending| pending.sent_at.elapsed())
+    }
+
+    /// Stop waiting for the ack of the message with the given hash. Returns whether we were
+    /// waiting for it.
+    pub fn handle_ack(&mut self, hash: &MessageHash) -> bool {
+        self.pending.remove(hash).is_some()
+    }
+
+    pub fn handle_timeout(&mut self, token: u64) -> TimeoutOutcome {
+        let hash = if let Some(hash) = self.timers.remove(&token) {
+            hash
+        } else {
+            return TimeoutOutcome::Unknown;
+        };
+
+        let pending = if let Some(pending) = self.pending.remove(&hash) {
+            pending
+        } else {
+            return TimeoutOutcome::Delivered;
+        };
+
+        if pending.attempts >= MAX_DELIVERY_ATTEMPTS {
+            TimeoutOutcome::GiveUp(pending.message)
+        } else {
+            TimeoutOutcome::Retry(pending)
+        }
+    }
+
+    /// Remember the whole content of a message sent as `count` fragments with the given id, so it
+    /// can be reported if their delivery fails.
+    pub fn track_fragmented(&mut self, id: u64, content: Bytes, count: usize) {
+        let _ = self.fragmented.insert(
+            id,
+            Fragmented {
+                content,
+                unacked: count,
+            },
+        );
+    }
+
+    /// Returns the content of the message sent as fragments with the given id, unless it was
+    /// already taken.
+    pub fn take_fragmented(&mut self, id: u64) -> Option<Bytes> {
+        self.fragmented
+            .remove(&id)
+            .map(|fragmented| fragmented.content)
+    }
+
+    /// Called when a fragment of the message with the given id is acknowledged. Returns whether
+    /// all its fragments are acknowledged now, in which case the message is no longer tracked.
+    pub fn fragment_acked(&mut self, id: u64) -> bool {
+        let fragmented = if let Some(fragmented) = self.fragmented.get_mut(&id) {
+            fragmented
+        } else {
+            return false;
+        };
+
+        fragmented.unacked = fragmented.unacked.saturating_sub(1);
+        if fragmented.unacked > 0 {
+            return false;
+        }
+
+        let _ = self.fragmented.remove(&id);
+        true
+    }
+}
+
+impl Default for DeliveryTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) enum TimeoutOutcome {
+    /// The timeout is not for any message we track.
+    Unknown,
+    /// The message has been acknowledged in the meantime.
+    Delivered,
+    /// The message should be sent again.
+    Retry(PendingDelivery),
+    /// The message was not acknowledged after the maximum number of attempts.
+    GiveUp(Message),
+}
+
+pub(crate) struct PendingDelivery {
+    pub message: Message,
+    pub attempts: usize,
+    pub tried: BTreeSet<XorName>,
+    sent_at: Instant,
+}
+
+struct Fragmented {
+    content: Bytes,
+    unacked: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto, messages::Variant, node::Node, section::test_utils::gen_addr};
+    use anyhow::Result;
+    use bytes::Bytes;
+    use sn_messaging::DstLocation;
+
+    #[test]
+    fn retry_until_max_attempts() -> Result<()> {
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let mut tracker = DeliveryTracker::new();
+        let mut message = user_message(&node)?;
+
+        let mut token = tracker.track(message.clone(), 1, BTreeSet::new());
+        for attempts in 1..MAX_DELIVERY_ATTEMPTS {
+            match tracker.handle_timeout(token) {
+                TimeoutOutcome::Retry(pending) => {
+                    assert_eq!(pending.message, message);
+                    assert_eq!(pending.attempts, attempts);
+
+                    // Sent again as a renewed copy, which keeps the variant (including the id the
+                    // destination deduplicates on), but has a different hash.
+                    let renewed = Message::single_src(
+                        &node,
+                        *pending.message.dst(),
+                        pending.message.variant().clone(),
+                        None,
+                        None,
+                    )?;
+                    assert_ne!(renewed.hash(), message.hash());
+
+                    token = tracker.track(renewed.clone(), attempts + 1, pending.tried);
+                    assert!(tracker.pending(message.hash()).is_none());
+                    assert!(tracker.pending(renewed.hash()).is_some());
+                    // Unknown which copy an ack would be for.
+                    assert_eq!(tracker.round_trip(renewed.hash()), None);
+
+                    message = renewed;
+                }
+                _ => panic!("expected retry"),
+            }
+        }
+
+        assert!(matches!(
+            tracker.handle_timeout(token),
+            TimeoutOutcome::GiveUp(given_up) if given_up == message
+        ));
+        assert!(tracker.pending(message.hash()).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn acked_message_is_not_retried() -> Result<()> {
+        let mut tracker = DeliveryTracker::new();
+        let message = user_message(&Node::new(crypto::gen_keypair(), gen_addr()))?;
+
+        let token = tracker.track(message.clone(), 1, BTreeSet::new());
+        assert!(tracker.round_trip(message.hash()).is_some());
+        assert!(tracker.handle_ack(message.hash()));
+        assert_eq!(tracker.round_trip(message.hash()), None);
+        assert!(!tracker.handle_ack(message.hash()));
+
+        assert!(matches!(
+            tracker.handle_timeout(token),
+            TimeoutOutcome::Delivered
+        ));
+        assert!(matches!(
+            tracker.handle_timeout(token),
+            TimeoutOutcome::Unknown
+        ));
+
+        Ok(())
+    }
+
+    fn user_message(node: &Node) -> Result<Message> {
+        let variant = Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        };
+        Ok(Message::single_src(
+            node,
+            DstLocation::Node(rand::random()),
+            variant,
+            None,
+            None,
+        )?)
+    }
+}
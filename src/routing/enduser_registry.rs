@@ -37,6 +37,10 @@ impl EndUserRegistry {
         self.socket_id_mapping.get(&socket_id)
     }
 
+    pub fn all_socket_addrs(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.clients.keys()
+    }
+
     pub fn get_all_socket_addr<'a>(
         &'a self,
         end_user_pk: &'a EndUserPK,
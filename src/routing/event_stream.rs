@@ -7,20 +7,296 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 use crate::event::Event;
-use tokio::sync::mpsc;
+use futures::{future, stream::Stream};
+use std::{
+    collections::VecDeque,
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+use tokio::{sync::mpsc, task};
 
-/// Stream of routing node events
+/// Bounds the number of events buffered for a consumer that doesn't keep up with them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EventBufferConfig {
+    /// Maximum number of buffered events.
+    pub capacity: usize,
+    /// What to do when the buffer is full.
+    pub overflow: EventOverflow,
+}
+
+/// Policy for when the event buffer is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventOverflow {
+    /// Pause handling of messages, timers and other work until the consumer takes some events.
+    /// No event is lost, but the node stops responding to the network while paused.
+    Block,
+    /// Drop the oldest buffered event. The number of dropped events is reported by
+    /// `EventStream::dropped_events`.
+    DropOldest,
+}
+
+/// Stream of routing node events. Besides `next`, it implements `futures::Stream`, so it can be
+/// used with the `StreamExt` combinators or in `select!`.
 pub struct EventStream {
-    events_rx: mpsc::UnboundedReceiver<Event>,
+    inner: Inner,
+}
+
+enum Inner {
+    Unbounded(mpsc::UnboundedReceiver<Event>),
+    Bounded(EventBuffer),
 }
 
 impl EventStream {
     pub(crate) fn new(events_rx: mpsc::UnboundedReceiver<Event>) -> Self {
-        Self { events_rx }
+        Self {
+            inner: Inner::Unbounded(events_rx),
+        }
+    }
+
+    /// Creates the stream with the events from `events_rx` going through a buffer bounded
+    /// according to `config`. The returned `EventBuffer` is to be used to apply backpressure.
+    pub(crate) fn bounded(
+        mut events_rx: mpsc::UnboundedReceiver<Event>,
+        config: EventBufferConfig,
+    ) -> (Self, EventBuffer) {
+        let buffer = EventBuffer::new(config);
+
+        // Move the events to the buffer as soon as they are sent, so the policy applies to them.
+        let tx = buffer.clone();
+        let _ = task::spawn(async move {
+            while let Some(event) = events_rx.recv().await {
+                tx.push(event).await;
+            }
+            tx.close();
+        });
+
+        let stream = Self {
+            inner: Inner::Bounded(buffer.clone()),
+        };
+
+        (stream, buffer)
     }
 
     /// Returns next event
     pub async fn next(&mut self) -> Option<Event> {
-        self.events_rx.recv().await
+        future::poll_fn(|cx| self.poll_event(cx)).await
+    }
+
+    /// Number of events dropped so far because the consumer didn't keep up with them.
+    pub fn dropped_events(&self) -> u64 {
+        match &self.inner {
+            Inner::Unbounded(_) => 0,
+            Inner::Bounded(buffer) => buffer.lock().dropped,
+        }
+    }
+
+    fn poll_event(&mut self, cx: &mut Context) -> Poll<Option<Event>> {
+        match &mut self.inner {
+            Inner::Unbounded(events_rx) => events_rx.poll_recv(cx),
+            Inner::Bounded(buffer) => buffer.poll_pop(cx),
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.poll_event(cx)
+    }
+}
+
+// Events waiting for the consumer, shared by the consumer and the node.
+#[derive(Clone)]
+pub(crate) struct EventBuffer(Arc<Mutex<Shared>>);
+
+struct Shared {
+    config: EventBufferConfig,
+    events: VecDeque<Event>,
+    dropped: u64,
+    closed: bool,
+    // Consumer waiting for an event.
+    consumer: Option<Waker>,
+    // Tasks waiting for space in the buffer.
+    blocked: Vec<Waker>,
+}
+
+impl EventBuffer {
+    fn new(config: EventBufferConfig) -> Self {
+        Self(Arc::new(Mutex::new(Shared {
+            config,
+            events: VecDeque::new(),
+            dropped: 0,
+            closed: false,
+            consumer: None,
+            blocked: vec![],
+        })))
+    }
+
+    /// Number of events waiting for the consumer.
+    pub fn len(&self) -> usize {
+        self.lock().events.len()
+    }
+
+    /// With the `Block` policy, waits until the buffer is not full. Returns immediately otherwise.
+    pub async fn wait_for_space(&self) {
+        future::poll_fn(|cx| {
+            let mut shared = self.lock();
+            if shared.config.overflow != EventOverflow::Block
+                || shared.closed
+                || shared.events.len() < shared.config.capacity
+            {
+                Poll::Ready(())
+            } else {
+                shared.blocked.push(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    // Adds `event` to the buffer. With the `Block` policy, waits for space first, so the buffer
+    // never holds more than its capacity.
+    async fn push(&self, event: Event) {
+        self.wait_for_space().await;
+
+        let mut shared = self.lock();
+        if shared.config.overflow == EventOverflow::DropOldest
+            && shared.events.len() >= shared.config.capacity
+        {
+            let _ = shared.events.pop_front();
+            shared.dropped += 1;
+        }
+
+        shared.events.push_back(event);
+        if let Some(waker) = shared.consumer.take() {
+            waker.wake();
+        }
+    }
+
+    fn close(&self) {
+        let mut shared = self.lock();
+        shared.closed = true;
+        if let Some(waker) = shared.consumer.take() {
+            waker.wake();
+        }
+        for waker in mem::take(&mut shared.blocked) {
+            waker.wake();
+        }
+    }
+
+    fn poll_pop(&self, cx: &mut Context) -> Poll<Option<Event>> {
+        let mut shared = self.lock();
+        if let Some(event) = shared.events.pop_front() {
+            for waker in mem::take(&mut shared.blocked) {
+                waker.wake();
+            }
+            Poll::Ready(Some(event))
+        } else if shared.closed {
+            Poll::Ready(None)
+        } else {
+            shared.consumer = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Shared> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{poll, stream::StreamExt};
+
+    #[tokio::test]
+    async fn stream_of_events() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let stream = EventStream::new(rx);
+
+        let _ = tx.send(Event::RelocationStarted {
+            previous_name: rand::random(),
+        });
+        let _ = tx.send(Event::Demoted);
+        drop(tx);
+
+        let events: Vec<_> = stream.collect().await;
+        assert_eq!(events.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let buffer = EventBuffer::new(EventBufferConfig {
+            capacity: 1,
+            overflow: EventOverflow::DropOldest,
+        });
+        let mut stream = EventStream {
+            inner: Inner::Bounded(buffer.clone()),
+        };
+
+        buffer.push(Event::Demoted).await;
+        buffer.push(Event::PromotedToAdult).await;
+        // Not blocking with this policy.
+        buffer.wait_for_space().await;
+        buffer.close();
+
+        assert_eq!(stream.dropped_events(), 1);
+        assert!(matches!(stream.next().await, Some(Event::PromotedToAdult)));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn block_until_consumed() {
+        let buffer = EventBuffer::new(EventBufferConfig {
+            capacity: 1,
+            overflow: EventOverflow::Block,
+        });
+        let mut stream = EventStream {
+            inner: Inner::Bounded(buffer.clone()),
+        };
+
+        buffer.push(Event::Demoted).await;
+        let mut wait = Box::pin(buffer.wait_for_space());
+        assert!(poll!(&mut wait).is_pending());
+
+        assert!(matches!(stream.next().await, Some(Event::Demoted)));
+        wait.await;
+        assert_eq!(stream.dropped_events(), 0);
+    }
+
+    #[tokio::test]
+    async fn block_keeps_capacity() {
+        let capacity = 2;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (mut stream, buffer) = EventStream::bounded(
+            rx,
+            EventBufferConfig {
+                capacity,
+                overflow: EventOverflow::Block,
+            },
+        );
+
+        let count = 5;
+        for _ in 0..count {
+            let _ = tx.send(Event::Demoted);
+        }
+        drop(tx);
+
+        // Give the pump task the chance to move as many events as it can.
+        for _ in 0..10 {
+            task::yield_now().await;
+            assert!(buffer.len() <= capacity);
+        }
+
+        let mut received = 0;
+        while stream.next().await.is_some() {
+            assert!(buffer.len() <= capacity);
+            received += 1;
+        }
+        assert_eq!(received, count);
+        assert_eq!(stream.dropped_events(), 0);
     }
 }
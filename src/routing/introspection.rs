@@ -0,0 +1,200 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{approved::Approved, stage::Stage};
+use crate::{
+    error::{Error, Result},
+    messages::Priority,
+    peer::Peer,
+};
+use hex_fmt::HexFmt;
+use serde::Serialize;
+use std::{collections::BTreeMap, io, net::SocketAddr, sync::Arc};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    task,
+};
+
+// Snapshot of the state of the node, returned as JSON to whoever connects to the introspection
+// socket.
+#[derive(Serialize)]
+struct Snapshot {
+    name: String,
+    age: u8,
+    addr: SocketAddr,
+    is_elder: bool,
+    // Prefix of our section, in binary.
+    prefix: String,
+    // Index of the current key of our section in its chain, which counts the changes of elders.
+    section_key_index: u64,
+    // Hex-encoded current key of our section.
+    section_key: String,
+    elders: Vec<PeerSnapshot>,
+    members: usize,
+    // Prefixes of the other sections we know the elders of.
+    known_sections: Vec<String>,
+    connected_peers: Vec<SocketAddr>,
+    clients: usize,
+    // Outgoing sends in progress.
+    sends_in_progress: usize,
+    // Outgoing sends waiting for a slot, by priority class.
+    pending_sends: BTreeMap<&'static str, usize>,
+    // Events not yet taken by the consumer, if they are buffered with a bound.
+    pending_events: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct PeerSnapshot {
+    name: String,
+    addr: SocketAddr,
+    age: u8,
+}
+
+impl PeerSnapshot {
+    fn new(peer: &Peer) -> Self {
+        Self {
+            name: format!("{:x}", peer.name()),
+            addr: *peer.addr(),
+            age: peer.age(),
+        }
+    }
+}
+
+impl Snapshot {
+    fn new(state: &Approved) -> Self {
+        let node = state.node();
+        let section = state.section();
+
+        Self {
+            name: format!("{:x}", node.name()),
+            age: node.age,
+            addr: node.addr,
+            is_elder: state.is_elder(),
+            prefix: format!("{:b}", section.prefix()),
+            section_key_index: section.chain().last_key_index(),
+            section_key: format!("{:x}", HexFmt(&section.chain().last_key().to_bytes())),
+            elders: section
+                .elders_info()
+                .elders
+                .values()
+                .map(PeerSnapshot::new)
+                .collect(),
+            members: section.members().joined().count(),
+            known_sections: state
+                .network()
+                .all()
+                .map(|elders_info| format!("{:b}", elders_info.prefix))
+                .collect(),
+            connected_peers: vec![],
+            clients: state.client_count(),
+            sends_in_progress: 0,
+            pending_sends: BTreeMap::new(),
+            pending_events: None,
+        }
+    }
+
+    async fn of_stage(stage: &Stage) -> Self {
+        let mut snapshot = Self::new(&*stage.state.lock().await);
+
+        snapshot.connected_peers = stage
+            .comm
+            .bandwidth()
+            .all()
+            .into_iter()
+            .map(|(addr, _)| addr)
+            .collect();
+        snapshot.connected_peers.sort();
+
+        let (in_progress, pending) = stage.comm.send_queue().depth();
+        snapshot.sends_in_progress = in_progress;
+        snapshot.pending_sends = Priority::ALL
+            .iter()
+            .map(|priority| (priority.name(), pending[*priority as usize]))
+            .collect();
+        snapshot.pending_events = stage.pending_events();
+
+        snapshot
+    }
+}
+
+// Bind the introspection socket. Only loopback addresses are allowed, as the snapshot is not meant
+// to be exposed to the network.
+pub(crate) async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    if !addr.ip().is_loopback() {
+        return Err(Error::Introspection(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a loopback address", addr),
+        )));
+    }
+
+    TcpListener::bind(addr).await.map_err(Error::Introspection)
+}
+
+// Send a JSON snapshot of the node to everyone who connects to `listener`, then close the
+// connection. E.g. `nc localhost <port>`.
+pub(crate) async fn serve(mut listener: TcpListener, stage: Arc<Stage>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                error!("Failed to accept introspection connection: {}", error);
+                continue;
+            }
+        };
+
+        let _ = task::spawn(send_snapshot(stream, stage.clone()));
+    }
+}
+
+async fn send_snapshot(mut stream: TcpStream, stage: Arc<Stage>) {
+    let snapshot = Snapshot::of_stage(&stage).await;
+    let json = match serde_json::to_vec_pretty(&snapshot) {
+        Ok(json) => json,
+        Err(error) => {
+            error!("Failed to serialize node snapshot: {}", error);
+            return;
+        }
+    };
+
+    if let Err(error) = stream.write_all(&json).await {
+        trace!("Failed to send node snapshot: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto, node::Node, section::test_utils::gen_addr};
+    use anyhow::Result;
+    use assert_matches::assert_matches;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn snapshot() -> Result<()> {
+        let (event_tx, _) = mpsc::unbounded_channel();
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let state = Approved::first_node(node.clone(), event_tx)?;
+
+        let json = serde_json::to_value(&Snapshot::new(&state))?;
+        assert_eq!(json["name"], format!("{:x}", node.name()));
+        assert_eq!(json["is_elder"], true);
+        assert_eq!(json["prefix"], "");
+        assert_eq!(json["section_key_index"], 0);
+        assert_eq!(json["elders"].as_array().map(Vec::len), Some(1));
+        assert_eq!(json["members"], 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bind_non_loopback() {
+        let addr = SocketAddr::from(([192, 0, 2, 1], 0));
+        assert_matches!(bind(addr).await, Err(Error::Introspection(_)));
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use lru_time_cache::LruCache;
+use resource_proof::ResourceProof;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub(crate) const RESOURCE_PROOF_DATA_SIZE: usize = 64;
+pub(crate) const RESOURCE_PROOF_DIFFICULTY: u8 = 2;
+
+// How long a joining node has to respond to our challenge.
+const CHALLENGE_EXPIRY: Duration = Duration::from_secs(5 * 60);
+// Window over which the join rate is measured.
+const JOIN_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Resource proof this node requires from the nodes joining through it. The difficulty rises with
+/// the rate of join requests the node receives, to raise the cost of joining under sybil pressure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResourceProofConfig {
+    /// Size of the proof data, in bytes.
+    pub data_size: usize,
+    /// Difficulty when the join rate is low.
+    pub min_difficulty: u8,
+    /// Highest difficulty the join rate can raise it to.
+    pub max_difficulty: u8,
+    /// The difficulty rises by one for every this many challenges sent within the last minute.
+    pub join_rate_step: usize,
+}
+
+impl Default for ResourceProofConfig {
+    fn default() -> Self {
+        Self {
+            data_size: RESOURCE_PROOF_DATA_SIZE,
+            min_difficulty: RESOURCE_PROOF_DIFFICULTY,
+            max_difficulty: 8,
+            join_rate_step: 10,
+        }
+    }
+}
+
+// Resource proof challenges sent to the joining nodes.
+pub(crate) struct JoinChallenges {
+    config: ResourceProofConfig,
+    // Difficulty of the challenges, by their nonce.
+    sent: LruCache<[u8; 32], u8>,
+    // When the challenges within the last `JOIN_RATE_WINDOW` were sent.
+    recent: VecDeque<Instant>,
+}
+
+impl JoinChallenges {
+    pub fn new(config: ResourceProofConfig) -> Self {
+        Self {
+            config,
+            sent: LruCache::with_expiry_duration(CHALLENGE_EXPIRY),
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ResourceProofConfig {
+        &self.config
+    }
+
+    // Difficulty for a new challenge, according to the current join rate.
+    pub fn difficulty(&mut self) -> u8 {
+        self.prune_recent(Instant::now());
+
+        let step = self.config.join_rate_step.max(1);
+        let raise = (self.recent.len() / step).min(u8::MAX as usize) as u8;
+        self.config
+            .min_difficulty
+            .saturating_add(raise)
+            .min(self.config.max_difficulty.max(self.config.min_difficulty))
+    }
+
+    // Record a challenge with the given nonce and difficulty was sent.
+    pub fn insert(&mut self, nonce: [u8; 32], difficulty: u8) {
+        let _ = self.sent.insert(nonce, difficulty);
+        self.recent.push_back(Instant::now());
+    }
+
+    // Validate the solution of the challenge with the given nonce. The challenge is expected to
+    // be signed by us already. Every challenge can be used once only, and a challenge we no longer
+    // remember (because it expired or was used already) is rejected, so a solution can't be reused
+    // by replaying it.
+    pub fn validate(&mut self, nonce: &[u8; 32], data: &VecDeque<u8>, solution: u64) -> bool {
+        let difficulty = if let Some(difficulty) = self.sent.remove(nonce) {
+            difficulty
+        } else {
+            return false;
+        };
+
+        ResourceProof::new(self.config.data_size, difficulty).validate_all(nonce, data, solution)
+    }
+
+    fn prune_recent(&mut self, now: Instant) {
+        while let Some(time) = self.recent.front() {
+            if now.saturating_duration_since(*time) > JOIN_RATE_WINDOW {
+                let _ = self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn difficulty_rises_with_join_rate() {
+        let mut challenges = JoinChallenges::new(ResourceProofConfig {
+            data_size: 16,
+            min_difficulty: 1,
+            max_difficulty: 3,
+            join_rate_step: 2,
+        });
+
+        let mut difficulties = vec![];
+        for _ in 0..8 {
+            let difficulty = challenges.difficulty();
+            challenges.insert(rand::random(), difficulty);
+            difficulties.push(difficulty);
+        }
+
+        assert_eq!(difficulties, vec![1, 1, 2, 2, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn validate_with_difficulty_of_challenge() {
+        let config = ResourceProofConfig {
+            data_size: 16,
+            min_difficulty: 1,
+            max_difficulty: 2,
+            join_rate_step: 1,
+        };
+        let mut challenges = JoinChallenges::new(config);
+
+        let nonce = rand::random();
+        let difficulty = challenges.difficulty();
+        challenges.insert(nonce, difficulty);
+
+        // The difficulty rose in the meantime, but the challenge still uses the original one.
+        assert_eq!(challenges.difficulty(), 2);
+
+        let rp = ResourceProof::new(config.data_size, difficulty);
+        let data = rp.create_proof_data(&nonce);
+        let solution = rp.create_prover(data.clone()).solve();
+        assert!(challenges.validate(&nonce, &data, solution));
+
+        // Used challenges are rejected.
+        assert!(!challenges.validate(&nonce, &data, solution));
+    }
+
+    #[test]
+    fn reject_evicted_challenge() {
+        let config = ResourceProofConfig {
+            data_size: 16,
+            min_difficulty: 1,
+            max_difficulty: 1,
+            join_rate_step: 1,
+        };
+        let mut challenges = JoinChallenges::new(config);
+
+        let nonce = rand::random();
+        let difficulty = challenges.difficulty();
+        challenges.insert(nonce, difficulty);
+
+        let rp = ResourceProof::new(config.data_size, difficulty);
+        let data = rp.create_proof_data(&nonce);
+        let solution = rp.create_prover(data.clone()).solve();
+
+        // Expired, so rejected even though its difficulty is the current one.
+        let _ = challenges.sent.remove(&nonce);
+        assert_eq!(challenges.difficulty(), difficulty);
+        assert!(!challenges.validate(&nonce, &data, solution));
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::join_challenges::{JoinChallenges, ResourceProofConfig};
+use crate::{crypto, error::Result};
+use bytes::Bytes;
+use ed25519_dalek::{Keypair, Signature, Verifier};
+use resource_proof::ResourceProof;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Formatter},
+};
+use xor_name::{Prefix, XorName};
+
+/// Proof a new node must provide to join a section, e.g. a resource proof (the default), a signed
+/// invitation or a proof of stake. The elder the candidate sends its join request to creates a
+/// challenge, the candidate responds to it and the same elder verifies the response before voting
+/// the candidate online. All the nodes of a network must use the same scheme.
+pub trait JoinProof: Debug + Send + Sync {
+    /// Creates the challenge for the candidate with the given name joining the section with the
+    /// given prefix.
+    fn challenge(&mut self, candidate: &XorName, prefix: &Prefix) -> Result<Bytes>;
+
+    /// Verifies the response of the candidate with the given name to a challenge created by
+    /// `challenge`.
+    fn verify(&mut self, candidate: &XorName, response: &[u8]) -> bool;
+
+    /// Computes the response to a challenge received when joining as the candidate with the given
+    /// name. Returns `None` if the challenge can't be responded to.
+    fn respond(&self, candidate: &XorName, challenge: &[u8]) -> Option<Bytes>;
+}
+
+// The default join proof: the candidate must find a solution of a resource proof whose difficulty
+// rises with the join rate (see `JoinChallenges`).
+pub(crate) struct ResourceProofJoin {
+    challenges: JoinChallenges,
+    // Signs the challenges, so we only accept responses to our own ones.
+    keypair: Keypair,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Challenge {
+    data_size: usize,
+    difficulty: u8,
+    nonce: [u8; 32],
+    nonce_signature: Signature,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Response {
+    solution: u64,
+    data: VecDeque<u8>,
+    nonce: [u8; 32],
+    nonce_signature: Signature,
+}
+
+impl ResourceProofJoin {
+    pub fn new(config: ResourceProofConfig) -> Self {
+        Self {
+            challenges: JoinChallenges::new(config),
+            keypair: crypto::gen_keypair(),
+        }
+    }
+}
+
+impl JoinProof for ResourceProofJoin {
+    fn challenge(&mut self, candidate: &XorName, _prefix: &Prefix) -> Result<Bytes> {
+        let nonce: [u8; 32] = rand::random();
+        let serialized = bincode::serialize(&(candidate, &nonce))?;
+        let difficulty = self.challenges.difficulty();
+        self.challenges.insert(nonce, difficulty);
+
+        let challenge = Challenge {
+            data_size: self.challenges.config().data_size,
+            difficulty,
+            nonce,
+            nonce_signature: crypto::sign(&serialized, &self.keypair),
+        };
+
+        Ok(bincode::serialize(&challenge)?.into())
+    }
+
+    fn verify(&mut self, candidate: &XorName, response: &[u8]) -> bool {
+        let response: Response = if let Ok(response) = bincode::deserialize(response) {
+            response
+        } else {
+            return false;
+        };
+
+        let serialized = if let Ok(serialized) = bincode::serialize(&(candidate, &response.nonce)) {
+            serialized
+        } else {
+            return false;
+        };
+
+        if self
+            .keypair
+            .public
+            .verify(&serialized, &response.nonce_signature)
+            .is_err()
+        {
+            return false;
+        }
+
+        self.challenges
+            .validate(&response.nonce, &response.data, response.solution)
+    }
+
+    fn respond(&self, _candidate: &XorName, challenge: &[u8]) -> Option<Bytes> {
+        let challenge: Challenge = bincode::deserialize(challenge).ok()?;
+
+        let rp = ResourceProof::new(challenge.data_size, challenge.difficulty);
+        let data = rp.create_proof_data(&challenge.nonce);
+        let mut prover = rp.create_prover(data.clone());
+        let solution = prover.solve();
+
+        let response = Response {
+            solution,
+            data,
+            nonce: challenge.nonce,
+            nonce_signature: challenge.nonce_signature,
+        };
+
+        bincode::serialize(&response).ok().map(Bytes::from)
+    }
+}
+
+impl Debug for ResourceProofJoin {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ResourceProofJoin")
+            .field("config", self.challenges.config())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn respond_and_verify() -> Result<()> {
+        let mut join_proof = ResourceProofJoin::new(ResourceProofConfig::default());
+        let candidate: XorName = rand::random();
+
+        let challenge = join_proof.challenge(&candidate, &Prefix::default())?;
+        let response = join_proof
+            .respond(&candidate, &challenge)
+            .expect("failed to respond");
+
+        // Only the candidate the challenge was created for can use the response.
+        assert!(!join_proof.verify(&rand::random(), &response));
+        assert!(join_proof.verify(&candidate, &response));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+// How long to wait for a probe to be acknowledged before considering it failed.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// Number of consecutive failed probes after which a peer is considered offline.
+const MAX_FAILED_PROBES: usize = 3;
+
+// Results of the keepalive probes of our peers: their round-trip time and how many probes in a
+// row failed.
+#[derive(Default)]
+pub(crate) struct Liveness {
+    peers: HashMap<SocketAddr, PeerLiveness>,
+}
+
+#[derive(Default)]
+struct PeerLiveness {
+    // Smoothed round-trip time.
+    rtt: Option<Duration>,
+    failures: usize,
+}
+
+impl Liveness {
+    // Record a probe acknowledged after `rtt`.
+    pub fn record_success(&mut self, peer: SocketAddr, rtt: Duration) {
+        let liveness = self.peers.entry(peer).or_default();
+        liveness.failures = 0;
+        // Exponential moving average with weight 1/8, as for TCP (RFC 6298).
+        liveness.rtt = Some(match liveness.rtt {
+            Some(old) => (old * 7 + rtt) / 8,
+            None => rtt,
+        });
+    }
+
+    // Record a failed probe. Returns whether the peer is now considered offline.
+    pub fn record_failure(&mut self, peer: SocketAddr) -> bool {
+        let liveness = self.peers.entry(peer).or_default();
+        liveness.failures += 1;
+        liveness.failures >= MAX_FAILED_PROBES
+    }
+
+    pub fn rtt(&self, peer: &SocketAddr) -> Option<Duration> {
+        self.peers.get(peer).and_then(|liveness| liveness.rtt)
+    }
+
+    // The probed peers, with their round-trip time and the number of probes in a row that failed.
+    pub fn iter(&self) -> impl Iterator<Item = (&SocketAddr, Option<Duration>, usize)> {
+        self.peers
+            .iter()
+            .map(|(peer, liveness)| (peer, liveness.rtt, liveness.failures))
+    }
+
+    // Forget the peers not satisfying the predicate, e.g. because they are no longer our members.
+    pub fn retain<F>(&mut self, mut predicate: F)
+    where
+        F: FnMut(&SocketAddr) -> bool,
+    {
+        self.peers.retain(|peer, _| predicate(peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn offline_after_consecutive_failures() {
+        let mut liveness = Liveness::default();
+        let peer = gen_addr();
+
+        assert!(!liveness.record_failure(peer));
+        assert!(!liveness.record_failure(peer));
+        // A success resets the count.
+        liveness.record_success(peer, Duration::from_millis(80));
+        assert_eq!(liveness.rtt(&peer), Some(Duration::from_millis(80)));
+
+        liveness.record_success(peer, Duration::from_millis(160));
+        assert_eq!(liveness.rtt(&peer), Some(Duration::from_millis(90)));
+
+        assert!(!liveness.record_failure(peer));
+        assert!(!liveness.record_failure(peer));
+        assert!(liveness.record_failure(peer));
+    }
+}
@@ -0,0 +1,762 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::transport::{Transport, TransportError, TransportEvent};
+use crate::crypto::{self, Keypair};
+use bytes::Bytes;
+use futures::{
+    channel::mpsc,
+    future::{BoxFuture, FutureExt},
+    stream::{self, BoxStream, StreamExt},
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::{self, Debug, Formatter},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+// Environment variable to set the seed of the new memory networks with, to reproduce a test run.
+const SEED_ENV_VAR: &str = "SN_ROUTING_SEED";
+
+/// Network of in-memory transports, for testing without any real networking.
+///
+/// Every `MemoryTransport` created by `transport` gets its own address and can connect and send
+/// to the others of the same network. Messages are passed through channels, so they can be used
+/// without any async runtime, e.g. from plain `#[test]`s with `futures::executor::block_on`. The
+/// only exception is a network with latency, whose sends wait on the tokio timer.
+///
+/// The latency and the loss of the messages can be configured for every link separately, see
+/// `set_link`, and the network can be split into groups of transports unable to reach each other,
+/// see `partition`. Specific messages can be dropped or delayed by filters, see `add_filter`.
+///
+/// All the randomness of the network, including the keypairs of the nodes created by `keypair`,
+/// is derived from a single seed, printed if the test panics. It's random unless set by
+/// `set_seed` or the `SN_ROUTING_SEED` environment variable.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork(Arc<Mutex<Network>>);
+
+struct Network {
+    default_link: LinkConfig,
+    links: HashMap<(SocketAddr, SocketAddr), LinkConfig>,
+    seed: u64,
+    // Each link has its own generator so its randomness doesn't depend on the order the other
+    // links are used in.
+    link_rngs: HashMap<(SocketAddr, SocketAddr), ChaChaRng>,
+    keypair_rng: ChaChaRng,
+    // Group of every partitioned transport. The ones not in any group form one more group.
+    partition: HashMap<SocketAddr, usize>,
+    filters: Vec<(FilterId, Box<Filter>)>,
+    last_filter_id: u64,
+    last_port: u16,
+    endpoints: HashMap<SocketAddr, Endpoint>,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        let seed = env::var(SEED_ENV_VAR)
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+
+        Self {
+            default_link: LinkConfig::default(),
+            links: HashMap::new(),
+            seed,
+            link_rngs: HashMap::new(),
+            keypair_rng: ChaChaRng::seed_from_u64(seed),
+            partition: HashMap::new(),
+            filters: vec![],
+            last_filter_id: 0,
+            last_port: 0,
+            endpoints: HashMap::new(),
+        }
+    }
+}
+
+/// Properties of the link messages travel over from one memory transport to another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkConfig {
+    /// Delay of every message.
+    pub latency: Latency,
+    /// Probability of a message being silently lost, between 0 and 1.
+    pub drop_probability: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Latency::Fixed(Duration::from_secs(0)),
+            drop_probability: 0.0,
+        }
+    }
+}
+
+/// Distribution of the delay of the messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Latency {
+    /// Every message is delayed by the same duration.
+    Fixed(Duration),
+    /// Every message is delayed by a duration picked uniformly between `min` and `max`, so the
+    /// messages can arrive out of order.
+    Uniform {
+        /// Shortest delay.
+        min: Duration,
+        /// Longest delay.
+        max: Duration,
+    },
+}
+
+impl Latency {
+    fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        match *self {
+            Self::Fixed(latency) => latency,
+            Self::Uniform { min, max } if min < max => {
+                let nanos = rng.gen_range(min.as_nanos() as u64, max.as_nanos() as u64);
+                Duration::from_nanos(nanos)
+            }
+            Self::Uniform { min, .. } => min,
+        }
+    }
+}
+
+/// What to do with a message matched by a filter installed with `MemoryNetwork::add_filter`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FilterAction {
+    /// Deliver the message as usual.
+    Deliver,
+    /// Silently lose the message. It's still reported as sent to the sender.
+    Drop,
+    /// Deliver the message after this delay, on top of the latency of the link.
+    Delay(Duration),
+}
+
+/// Identifies a filter installed with `MemoryNetwork::add_filter`, to remove it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct FilterId(u64);
+
+type Filter = dyn Fn(&SocketAddr, &SocketAddr, &Bytes) -> FilterAction + Send;
+
+struct Endpoint {
+    events_tx: mpsc::UnboundedSender<TransportEvent>,
+    connections: HashSet<SocketAddr>,
+}
+
+impl MemoryNetwork {
+    /// Creates a network delivering the messages immediately.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a network delivering every message after `latency`.
+    pub fn with_latency(latency: Duration) -> Self {
+        let network = Self::default();
+        network.set_default_link(LinkConfig {
+            latency: Latency::Fixed(latency),
+            ..LinkConfig::default()
+        });
+        network
+    }
+
+    /// Seed all the randomness of the network is derived from.
+    pub fn seed(&self) -> u64 {
+        self.lock().seed
+    }
+
+    /// Reseeds all the randomness of the network, to make it reproducible. Should be called
+    /// before the network is used.
+    pub fn set_seed(&self, seed: u64) {
+        let mut network = self.lock();
+        network.seed = seed;
+        network.link_rngs.clear();
+        network.keypair_rng = ChaChaRng::seed_from_u64(seed);
+    }
+
+    /// Generates a keypair for a node of this network, derived from the seed of the network.
+    pub fn keypair(&self) -> Keypair {
+        Keypair::generate(&mut self.lock().keypair_rng)
+    }
+
+    /// Configures the links without their own config set by `set_link`.
+    pub fn set_default_link(&self, config: LinkConfig) {
+        self.lock().default_link = config;
+    }
+
+    /// Configures the link the messages from `src` to `dst` travel over. The opposite direction is
+    /// configured separately.
+    pub fn set_link(&self, src: SocketAddr, dst: SocketAddr, config: LinkConfig) {
+        let _ = self.lock().links.insert((src, dst), config);
+    }
+
+    /// Reverts the link from `src` to `dst` to the default config.
+    pub fn reset_link(&self, src: &SocketAddr, dst: &SocketAddr) {
+        let _ = self.lock().links.remove(&(*src, *dst));
+    }
+
+    /// Splits the network into the given groups of transports, identified by their addresses. The
+    /// transports can't reach the ones in other groups: their connections are lost and they fail
+    /// to connect again until `heal` is called. The transports not in any of the groups form one
+    /// more group. Replaces any previous partition.
+    pub fn partition<I, J>(&self, groups: I)
+    where
+        I: IntoIterator<Item = J>,
+        J: IntoIterator<Item = SocketAddr>,
+    {
+        let mut network = self.lock();
+        network.partition = groups
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, group)| group.into_iter().map(move |addr| (addr, index)))
+            .collect();
+
+        let severed: Vec<_> = network
+            .endpoints
+            .iter()
+            .flat_map(|(addr, endpoint)| {
+                endpoint.connections.iter().map(move |peer| (*addr, *peer))
+            })
+            .filter(|(addr, peer)| !network.reachable(addr, peer))
+            .collect();
+
+        for (addr, peer) in severed {
+            if let Some(endpoint) = network.endpoints.get_mut(&addr) {
+                let _ = endpoint.connections.remove(&peer);
+                let _ = endpoint
+                    .events_tx
+                    .unbounded_send(TransportEvent::Disconnected(peer));
+            }
+        }
+    }
+
+    /// Removes the partition, so all the transports can reach each other again.
+    pub fn heal(&self) {
+        self.lock().partition.clear()
+    }
+
+    /// Installs a filter deciding what to do with every message sent on this network, given its
+    /// sender, its recipient and its bytes. The filters are called in the order they were added
+    /// and the first one not returning `FilterAction::Deliver` decides. Returns the id to remove
+    /// the filter with. The filters are called with the network locked, so they must not use it.
+    ///
+    /// Messages can be matched by type by deserializing them, e.g. to drop all the messages of a
+    /// given variant between two nodes and exercise the retry paths.
+    pub fn add_filter<F>(&self, filter: F) -> FilterId
+    where
+        F: Fn(&SocketAddr, &SocketAddr, &Bytes) -> FilterAction + Send + 'static,
+    {
+        let mut network = self.lock();
+        network.last_filter_id += 1;
+        let id = FilterId(network.last_filter_id);
+        network.filters.push((id, Box::new(filter)));
+        id
+    }
+
+    /// Removes the filter with `id`. Returns whether it was installed.
+    pub fn remove_filter(&self, id: FilterId) -> bool {
+        let mut network = self.lock();
+        let len = network.filters.len();
+        network.filters.retain(|(filter_id, _)| *filter_id != id);
+        network.filters.len() < len
+    }
+
+    /// Removes all the filters.
+    pub fn clear_filters(&self) {
+        self.lock().filters.clear()
+    }
+
+    /// Creates a new transport on this network.
+    pub fn transport(&self) -> MemoryTransport {
+        let (events_tx, events_rx) = mpsc::unbounded();
+
+        let mut network = self.lock();
+        network.last_port = network
+            .last_port
+            .checked_add(1)
+            .expect("too many memory transports");
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, network.last_port));
+        let _ = network.endpoints.insert(
+            addr,
+            Endpoint {
+                events_tx,
+                connections: HashSet::new(),
+            },
+        );
+
+        MemoryTransport {
+            network: self.clone(),
+            addr,
+            events_rx: Some(events_rx),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Network> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Network {
+    fn reachable(&self, a: &SocketAddr, b: &SocketAddr) -> bool {
+        self.partition.get(a) == self.partition.get(b)
+    }
+
+    fn filter(&self, src: &SocketAddr, dst: &SocketAddr, msg: &Bytes) -> FilterAction {
+        self.filters
+            .iter()
+            .map(|(_, filter)| filter(src, dst, msg))
+            .find(|action| *action != FilterAction::Deliver)
+            .unwrap_or(FilterAction::Deliver)
+    }
+
+    fn link_rng(&mut self, src: SocketAddr, dst: SocketAddr) -> &mut ChaChaRng {
+        let seed = self.seed;
+        self.link_rngs.entry((src, dst)).or_insert_with(|| {
+            let link = format!("{} {} {}", seed, src, dst);
+            ChaChaRng::from_seed(crypto::sha3_256(link.as_bytes()))
+        })
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            error!(
+                "MemoryNetwork seed: {} (rerun with {}={} to reproduce)",
+                self.seed, SEED_ENV_VAR, self.seed
+            );
+        }
+    }
+}
+
+impl Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let network = self.lock();
+        f.debug_struct("MemoryNetwork")
+            .field("default_link", &network.default_link)
+            .field("links", &network.links)
+            .field("partition", &network.partition)
+            .field("filters", &network.filters.len())
+            .field("endpoints", &network.endpoints.len())
+            .finish()
+    }
+}
+
+/// Transport over a `MemoryNetwork`. Closed when dropped.
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    events_rx: Option<mpsc::UnboundedReceiver<TransportEvent>>,
+}
+
+impl Transport for MemoryTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn connect<'a>(&'a self, addr: &'a SocketAddr) -> BoxFuture<'a, Result<(), TransportError>> {
+        let result = (|| {
+            let mut network = self.network.lock();
+            if !network.endpoints.contains_key(&self.addr) {
+                return Err(TransportError::Closed);
+            }
+
+            if !network.reachable(&self.addr, addr) {
+                return Err(unreachable());
+            }
+
+            let peer = network.endpoints.get_mut(addr).ok_or_else(unreachable)?;
+            if peer.connections.insert(self.addr) {
+                let _ = peer
+                    .events_tx
+                    .unbounded_send(TransportEvent::Connected(self.addr));
+            }
+
+            if let Some(us) = network.endpoints.get_mut(&self.addr) {
+                let _ = us.connections.insert(*addr);
+            }
+
+            Ok(())
+        })();
+
+        async move { result }.boxed()
+    }
+
+    fn send<'a>(
+        &'a self,
+        addr: &'a SocketAddr,
+        msg: Bytes,
+    ) -> BoxFuture<'a, Result<(), TransportError>> {
+        async move {
+            let (events_tx, latency) = {
+                let mut network = self.network.lock();
+                let us = network
+                    .endpoints
+                    .get(&self.addr)
+                    .ok_or(TransportError::Closed)?;
+                if !us.connections.contains(addr) {
+                    return Err(unreachable());
+                }
+
+                let events_tx = network
+                    .endpoints
+                    .get(addr)
+                    .ok_or_else(unreachable)?
+                    .events_tx
+                    .clone();
+
+                let extra_delay = match network.filter(&self.addr, addr, &msg) {
+                    FilterAction::Deliver => Duration::from_secs(0),
+                    FilterAction::Drop => {
+                        trace!("Filtering out message from {} to {}", self.addr, addr);
+                        return Ok(());
+                    }
+                    FilterAction::Delay(delay) => delay,
+                };
+
+                let link = *network
+                    .links
+                    .get(&(self.addr, *addr))
+                    .unwrap_or(&network.default_link);
+                let rng = network.link_rng(self.addr, *addr);
+                if rng.gen_bool(link.drop_probability.max(0.0).min(1.0)) {
+                    trace!("Dropping message from {} to {}", self.addr, addr);
+                    return Ok(());
+                }
+
+                (events_tx, link.latency.sample(rng) + extra_delay)
+            };
+
+            if latency > Duration::from_secs(0) {
+                tokio::time::delay_for(latency).await;
+            }
+
+            events_tx
+                .unbounded_send(TransportEvent::Received(self.addr, msg))
+                .map_err(|_| unreachable())
+        }
+        .boxed()
+    }
+
+    fn disconnect(&self, addr: &SocketAddr) {
+        let mut network = self.network.lock();
+        if let Some(us) = network.endpoints.get_mut(&self.addr) {
+            let _ = us.connections.remove(addr);
+        }
+
+        if let Some(peer) = network.endpoints.get_mut(addr) {
+            if peer.connections.remove(&self.addr) {
+                let _ = peer
+                    .events_tx
+                    .unbounded_send(TransportEvent::Disconnected(self.addr));
+            }
+        }
+    }
+
+    fn events(&mut self) -> BoxStream<'static, TransportEvent> {
+        if let Some(events_rx) = self.events_rx.take() {
+            events_rx.boxed()
+        } else {
+            stream::empty().boxed()
+        }
+    }
+
+    fn close(&self) {
+        let mut network = self.network.lock();
+        let us = if let Some(us) = network.endpoints.remove(&self.addr) {
+            us
+        } else {
+            return;
+        };
+
+        for addr in us.connections {
+            if let Some(peer) = network.endpoints.get_mut(&addr) {
+                let _ = peer.connections.remove(&self.addr);
+                let _ = peer
+                    .events_tx
+                    .unbounded_send(TransportEvent::Disconnected(self.addr));
+            }
+        }
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.close()
+    }
+}
+
+impl Debug for MemoryTransport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("MemoryTransport")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+fn unreachable() -> TransportError {
+    TransportError::Other("peer unreachable".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use futures::{executor::block_on, future};
+    use std::time::Instant;
+
+    #[test]
+    fn connect_send_and_close() {
+        let network = MemoryNetwork::new();
+        let mut transport0 = network.transport();
+        let mut transport1 = network.transport();
+        let addr0 = transport0.local_addr();
+        let addr1 = transport1.local_addr();
+        let mut events0 = transport0.events();
+        let mut events1 = transport1.events();
+
+        // Sending requires a connection.
+        let msg = Bytes::from_static(b"hello");
+        assert!(block_on(transport0.send(&addr1, msg.clone())).is_err());
+
+        block_on(transport0.connect(&addr1)).unwrap();
+        assert_matches!(
+            block_on(events1.next()),
+            Some(TransportEvent::Connected(addr)) => assert_eq!(addr, addr0)
+        );
+
+        block_on(transport0.send(&addr1, msg.clone())).unwrap();
+        block_on(transport1.send(&addr0, msg.clone())).unwrap();
+        assert_matches!(
+            block_on(events1.next()),
+            Some(TransportEvent::Received(addr, received)) => {
+                assert_eq!(addr, addr0);
+                assert_eq!(received, msg);
+            }
+        );
+        assert_matches!(
+            block_on(events0.next()),
+            Some(TransportEvent::Received(addr, _)) => assert_eq!(addr, addr1)
+        );
+
+        drop(transport1);
+        assert_matches!(
+            block_on(events0.next()),
+            Some(TransportEvent::Disconnected(addr)) => assert_eq!(addr, addr1)
+        );
+        assert!(block_on(transport0.connect(&addr1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn latency() {
+        let latency = Duration::from_millis(50);
+        let network = MemoryNetwork::with_latency(latency);
+        let transport0 = network.transport();
+        let mut transport1 = network.transport();
+        let addr1 = transport1.local_addr();
+        let mut events1 = transport1.events();
+
+        transport0.connect(&addr1).await.unwrap();
+        assert_matches!(events1.next().await, Some(TransportEvent::Connected(_)));
+
+        let start = Instant::now();
+        transport0
+            .send(&addr1, Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        assert_matches!(events1.next().await, Some(TransportEvent::Received(..)));
+        assert!(start.elapsed() >= latency);
+    }
+
+    #[test]
+    fn message_loss() {
+        let network = MemoryNetwork::new();
+        network.set_seed(0);
+        let mut transport0 = network.transport();
+        let mut transport1 = network.transport();
+        let addr0 = transport0.local_addr();
+        let addr1 = transport1.local_addr();
+        let mut events0 = transport0.events();
+        let mut events1 = transport1.events();
+
+        network.set_link(
+            addr0,
+            addr1,
+            LinkConfig {
+                drop_probability: 1.0,
+                ..LinkConfig::default()
+            },
+        );
+
+        block_on(transport0.connect(&addr1)).unwrap();
+        assert_matches!(block_on(events1.next()), Some(TransportEvent::Connected(_)));
+
+        // Lost messages are reported as sent.
+        let msg = Bytes::from_static(b"hello");
+        block_on(transport0.send(&addr1, msg.clone())).unwrap();
+        // Only the link from 0 to 1 loses the messages.
+        block_on(transport1.send(&addr0, msg.clone())).unwrap();
+        assert_matches!(block_on(events0.next()), Some(TransportEvent::Received(..)));
+
+        network.reset_link(&addr0, &addr1);
+        block_on(transport0.send(&addr1, Bytes::from_static(b"again"))).unwrap();
+        assert_matches!(
+            block_on(events1.next()),
+            Some(TransportEvent::Received(_, received)) => assert_eq!(&received[..], b"again")
+        );
+    }
+
+    #[test]
+    fn partition_and_heal() {
+        let network = MemoryNetwork::new();
+        let mut transport0 = network.transport();
+        let transport1 = network.transport();
+        let transport2 = network.transport();
+        let addr0 = transport0.local_addr();
+        let addr1 = transport1.local_addr();
+        let addr2 = transport2.local_addr();
+        let mut events0 = transport0.events();
+
+        block_on(transport1.connect(&addr0)).unwrap();
+        assert_matches!(block_on(events0.next()), Some(TransportEvent::Connected(_)));
+        block_on(transport1.connect(&addr2)).unwrap();
+
+        network.partition(vec![vec![addr0]]);
+        assert_matches!(
+            block_on(events0.next()),
+            Some(TransportEvent::Disconnected(addr)) => assert_eq!(addr, addr1)
+        );
+
+        let msg = Bytes::from_static(b"hello");
+        assert!(block_on(transport1.send(&addr0, msg.clone())).is_err());
+        assert!(block_on(transport1.connect(&addr0)).is_err());
+        // The transports of the same group still reach each other.
+        block_on(transport1.send(&addr2, msg.clone())).unwrap();
+
+        network.heal();
+        block_on(transport1.connect(&addr0)).unwrap();
+        block_on(transport1.send(&addr0, msg)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        let network = MemoryNetwork::new();
+        let transport0 = network.transport();
+        let mut transport1 = network.transport();
+        let addr0 = transport0.local_addr();
+        let addr1 = transport1.local_addr();
+        let mut events1 = transport1.events();
+
+        transport0.connect(&addr1).await.unwrap();
+        assert_matches!(events1.next().await, Some(TransportEvent::Connected(_)));
+
+        let delay = Duration::from_millis(50);
+        let drop_id = network.add_filter(move |src, _, msg| {
+            if *src == addr0 && msg.starts_with(b"drop") {
+                FilterAction::Drop
+            } else {
+                FilterAction::Deliver
+            }
+        });
+        let _ = network.add_filter(move |_, _, msg| {
+            if msg.starts_with(b"slow") {
+                FilterAction::Delay(delay)
+            } else {
+                FilterAction::Deliver
+            }
+        });
+
+        // Filtered out messages are reported as sent.
+        transport0
+            .send(&addr1, Bytes::from_static(b"drop me"))
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        transport0
+            .send(&addr1, Bytes::from_static(b"slow"))
+            .await
+            .unwrap();
+        assert_matches!(
+            events1.next().await,
+            Some(TransportEvent::Received(_, received)) => assert_eq!(&received[..], b"slow")
+        );
+        assert!(start.elapsed() >= delay);
+
+        assert!(network.remove_filter(drop_id));
+        assert!(!network.remove_filter(drop_id));
+        transport0
+            .send(&addr1, Bytes::from_static(b"drop me"))
+            .await
+            .unwrap();
+        assert_matches!(
+            events1.next().await,
+            Some(TransportEvent::Received(_, received)) => assert_eq!(&received[..], b"drop me")
+        );
+    }
+
+    #[test]
+    fn same_seed_same_randomness() {
+        let run = |seed| {
+            let network = MemoryNetwork::new();
+            network.set_seed(seed);
+            network.set_default_link(LinkConfig {
+                drop_probability: 0.5,
+                ..LinkConfig::default()
+            });
+
+            let keypair = network.keypair();
+            let transport0 = network.transport();
+            let mut transport1 = network.transport();
+            let addr1 = transport1.local_addr();
+            let mut events1 = transport1.events();
+
+            block_on(transport0.connect(&addr1)).unwrap();
+            assert_matches!(block_on(events1.next()), Some(TransportEvent::Connected(_)));
+
+            for index in 0..32u8 {
+                block_on(transport0.send(&addr1, Bytes::from(vec![index]))).unwrap();
+            }
+            drop(transport0);
+
+            let received: Vec<_> = block_on(
+                events1
+                    .take_while(|event| {
+                        future::ready(matches!(event, TransportEvent::Received(..)))
+                    })
+                    .collect(),
+            );
+
+            (keypair.public, received.len())
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7).0, run(8).0);
+    }
+
+    #[test]
+    fn uniform_latency() {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let min = Duration::from_millis(10);
+        let max = Duration::from_millis(20);
+        let latency = Latency::Uniform { min, max };
+
+        for _ in 0..100 {
+            let sample = latency.sample(&mut rng);
+            assert!(sample >= min && sample < max);
+        }
+
+        assert_eq!(
+            Latency::Uniform { min: max, max: min }.sample(&mut rng),
+            max
+        );
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::error::{Error, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    iter,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{runtime::Handle, task};
+
+// Records waiting to be written. Further ones are dropped until the writer catches up.
+const MAX_PENDING_RECORDS: usize = 10_000;
+
+/// Whether a traced message was sent or received by the node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TraceDirection {
+    /// Sent by the node to the peer.
+    Sent,
+    /// Received by the node from the peer.
+    Received,
+}
+
+/// Message sent or received by a node, as captured by its recorder.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// Time the message was sent or received at, since the unix epoch.
+    pub timestamp: Duration,
+    /// Whether the message was sent or received.
+    pub direction: TraceDirection,
+    /// Address of the peer the message was sent to or received from.
+    pub peer: SocketAddr,
+    /// The message, exactly as sent or received over the wire.
+    pub bytes: Vec<u8>,
+}
+
+/// Messages captured by a node with `Config::message_trace_path` set, to be analysed or replayed
+/// with `Routing::replay` offline.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MessageTrace {
+    records: Vec<TraceRecord>,
+}
+
+impl MessageTrace {
+    /// Loads the trace captured into the file at `path`. A truncated last record, e.g. after the
+    /// node crashed while writing it, is ignored.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path).map_err(Error::MessageTrace)?);
+        let mut records = vec![];
+
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(record) => records.push(record),
+                Err(error) => match *error {
+                    bincode::ErrorKind::Io(ref error)
+                        if error.kind() == io::ErrorKind::UnexpectedEof =>
+                    {
+                        break
+                    }
+                    _ => return Err(Error::Bincode(error)),
+                },
+            }
+        }
+
+        Ok(Self { records })
+    }
+
+    /// All the captured messages, in the order they were sent or received.
+    pub fn records(&self) -> &[TraceRecord] {
+        &self.records
+    }
+
+    /// The received messages only, in the order they were received.
+    pub fn received(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.direction == TraceDirection::Received)
+    }
+}
+
+// Appends every message sent or received by the node to the trace file, once opened. The records
+// are handed to a background thread writing them, so the disk doesn't hold up the node. Shared
+// between the communication layer and the incoming messages handler.
+#[derive(Clone, Default)]
+pub(crate) struct MessageRecorder(Arc<Mutex<Option<Writer>>>);
+
+struct Writer {
+    tx: SyncSender<TraceRecord>,
+    thread: JoinHandle<()>,
+}
+
+impl MessageRecorder {
+    // Start recording into the file at `path`, appending to any trace already there.
+    pub fn open(&self, path: &Path) -> Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(Error::MessageTrace)?;
+        let (tx, rx) = mpsc::sync_channel(MAX_PENDING_RECORDS);
+        let thread = thread::spawn(move || write_records(rx, BufWriter::new(file)));
+
+        // The previous writer, if any, finishes once its sender is dropped.
+        *self.lock() = Some(Writer { tx, thread });
+        Ok(())
+    }
+
+    pub fn record(&self, direction: TraceDirection, peer: SocketAddr, bytes: &Bytes) {
+        let writer = self.lock();
+        let writer = if let Some(writer) = &*writer {
+            writer
+        } else {
+            return;
+        };
+
+        let record = TraceRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            direction,
+            peer,
+            bytes: bytes.to_vec(),
+        };
+
+        match writer.tx.try_send(record) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                warn_limited!("Message trace writer behind, dropping a record");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error_limited!("Message trace writer stopped, dropping a record");
+            }
+        }
+    }
+
+    // Stop recording. The records already taken are still written. Outside of the async runtime
+    // it waits for that, inside it's waited for on a blocking thread, not to hold up the runtime.
+    pub fn close(&self) {
+        if let Some(Writer { tx, thread }) = self.lock().take() {
+            drop(tx);
+            if Handle::try_current().is_ok() {
+                let _ = task::spawn_blocking(move || thread.join());
+            } else {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Option<Writer>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// Write the records until the recorder is dropped or reopened. The file is flushed whenever no
+// more records are pending, so the trace survives a crash of the node.
+fn write_records(rx: Receiver<TraceRecord>, mut writer: BufWriter<File>) {
+    while let Ok(record) = rx.recv() {
+        let pending = iter::once(record).chain(rx.try_iter().take(MAX_PENDING_RECORDS));
+        let result = pending
+            .map(|record| bincode::serialize_into(&mut writer, &record).map_err(Error::from))
+            .collect::<Result<()>>();
+        let result = result.and_then(|()| writer.flush().map_err(Error::MessageTrace));
+        if let Err(error) = result {
+            error!("Failed to record message trace: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn record_and_load() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "sn_routing_message_trace_{}",
+            rand::random::<u64>()
+        ));
+
+        let recorder = MessageRecorder::default();
+        let peer0 = gen_addr();
+        let peer1 = gen_addr();
+
+        // Nothing recorded until opened.
+        recorder.record(TraceDirection::Sent, peer0, &Bytes::from_static(b"lost"));
+
+        recorder.open(&path)?;
+        recorder.record(TraceDirection::Sent, peer0, &Bytes::from_static(b"ping"));
+        recorder.record(
+            TraceDirection::Received,
+            peer1,
+            &Bytes::from_static(b"pong"),
+        );
+
+        // Wait for the background writer.
+        recorder.close();
+
+        let trace = MessageTrace::load(&path)?;
+        assert_eq!(trace.records().len(), 2);
+        assert_eq!(trace.records()[0].direction, TraceDirection::Sent);
+        assert_eq!(trace.records()[0].peer, peer0);
+        assert_eq!(trace.records()[0].bytes, b"ping");
+
+        let received: Vec<_> = trace.received().collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].peer, peer1);
+        assert_eq!(received[0].bytes, b"pong");
+
+        // A truncated record is ignored.
+        let mut bytes = fs::read(&path)?;
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&path, bytes)?;
+        assert_eq!(MessageTrace::load(&path)?.records().len(), 1);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{approved::Approved, bandwidth::TrafficCategory, stage::Stage};
+use crate::event::Event;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task, time,
+};
+
+// Longest HTTP request we read before answering. Scrapers send short requests.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+// How long a connection may take to send its request and receive the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Connections served at once. Further ones are closed right away.
+const MAX_CONNECTIONS: usize = 16;
+
+// Counters of the node activity, exported in the Prometheus text format by `serve` together with
+// gauges of the node state. Shared between the communication layer, the incoming messages handler
+// and the events forwarder.
+#[derive(Clone, Default)]
+pub(crate) struct Metrics(Arc<Mutex<Counters>>);
+
+#[derive(Default)]
+struct Counters {
+    // Messages received, by their type on the wire.
+    received: BTreeMap<&'static str, u64>,
+    // Messages sent and received, by their traffic category.
+    sent_by_category: BTreeMap<&'static str, u64>,
+    received_by_category: BTreeMap<&'static str, u64>,
+    // Events raised, by their kind.
+    events: BTreeMap<&'static str, u64>,
+}
+
+impl Metrics {
+    pub fn record_received(&self, kind: &'static str, category: TrafficCategory) {
+        let mut counters = self.lock();
+        *counters.received.entry(kind).or_default() += 1;
+        *counters
+            .received_by_category
+            .entry(category_label(category))
+            .or_default() += 1;
+    }
+
+    pub fn record_sent(&self, category: TrafficCategory) {
+        *self
+            .lock()
+            .sent_by_category
+            .entry(category_label(category))
+            .or_default() += 1;
+    }
+
+    // Count the events going from `events_rx` to the returned receiver.
+    pub fn observe_events(
+        &self,
+        mut events_rx: mpsc::UnboundedReceiver<Event>,
+    ) -> mpsc::UnboundedReceiver<Event> {
+        let (events_tx, observed_rx) = mpsc::unbounded_channel();
+        let metrics = self.clone();
+
+        let _ = task::spawn(async move {
+            while let Some(event) = events_rx.recv().await {
+                if let Some(kind) = event_kind(&event) {
+                    *metrics.lock().events.entry(kind).or_default() += 1;
+                }
+
+                if events_tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        observed_rx
+    }
+
+    // The counters and the gauges of `state`, in the Prometheus text format.
+    pub fn render(&self, state: &Approved) -> String {
+        let mut out = String::new();
+
+        {
+            let counters = self.lock();
+            render_counter(
+                &mut out,
+                "sn_routing_messages_received_total",
+                "Messages received, by type.",
+                "type",
+                &counters.received,
+            );
+            render_counter(
+                &mut out,
+                "sn_routing_messages_received_by_category_total",
+                "Messages received, by traffic category.",
+                "category",
+                &counters.received_by_category,
+            );
+            render_counter(
+                &mut out,
+                "sn_routing_messages_sent_total",
+                "Messages sent, by traffic category.",
+                "category",
+                &counters.sent_by_category,
+            );
+            render_counter(
+                &mut out,
+                "sn_routing_events_total",
+                "Events raised, by kind. Counts the section churn.",
+                "kind",
+                &counters.events,
+            );
+        }
+
+        let section = state.section();
+        let gauges = [
+            (
+                "sn_routing_section_members",
+                "Joined members of our section.",
+                section.members().joined().count(),
+            ),
+            (
+                "sn_routing_section_elders",
+                "Elders of our section.",
+                section.elders_info().elders.len(),
+            ),
+            (
+                "sn_routing_section_prefix_len",
+                "Bit count of the prefix of our section.",
+                section.prefix().bit_count(),
+            ),
+            (
+                "sn_routing_section_key_index",
+                "Index of the current key of our section in its chain.",
+                section.chain().last_key_index() as usize,
+            ),
+            (
+                "sn_routing_known_sections",
+                "Other sections we know the elders of.",
+                state.network().all().count(),
+            ),
+            (
+                "sn_routing_clients",
+                "Clients connected to us.",
+                state.client_count(),
+            ),
+            (
+                "sn_routing_is_elder",
+                "Whether we are an elder.",
+                state.is_elder() as usize,
+            ),
+        ];
+
+        for (name, help, value) in &gauges {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        out
+    }
+
+    fn lock(&self) -> MutexGuard<Counters> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// Serve the metrics of the node over HTTP to whoever connects to `listener`, at `/metrics`. The
+// connections are limited in number and duration, so the endpoint can be exposed to the scrapers
+// on the network.
+pub(crate) async fn serve(mut listener: TcpListener, stage: Arc<Stage>) {
+    // Held by every connection being served.
+    let active = Arc::new(());
+
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                error!("Failed to accept metrics connection: {}", error);
+                continue;
+            }
+        };
+
+        if Arc::strong_count(&active) > MAX_CONNECTIONS {
+            trace!(
+                "Too many metrics connections, closing the one from {}",
+                addr
+            );
+            continue;
+        }
+
+        let active = active.clone();
+        let stage = stage.clone();
+        let _ = task::spawn(async move {
+            if time::timeout(REQUEST_TIMEOUT, handle_request(stream, stage))
+                .await
+                .is_err()
+            {
+                trace!("Metrics request from {} timed out", addr);
+            }
+            drop(active);
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, stage: Arc<Stage>) {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+
+    // Read the request line and headers. The body, if any, is ignored.
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(len) => request.extend_from_slice(&buffer[..len]),
+        }
+
+        if request.len() > MAX_REQUEST_LEN {
+            return;
+        }
+    }
+
+    let response = if request.starts_with(b"GET /metrics ") {
+        let body = stage.comm.metrics().render(&*stage.state.lock().await);
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    if let Err(error) = stream.write_all(response.as_bytes()).await {
+        trace!("Failed to send metrics: {}", error);
+    }
+}
+
+// Address the metrics are served at, for the logs.
+pub(crate) fn endpoint(addr: &SocketAddr) -> String {
+    format!("http://{}/metrics", addr)
+}
+
+fn render_counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: &BTreeMap<&'static str, u64>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (value, count) in values {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, count);
+    }
+}
+
+fn category_label(category: TrafficCategory) -> &'static str {
+    match category {
+        TrafficCategory::Gossip => "gossip",
+        TrafficCategory::Relay => "relay",
+        TrafficCategory::Client => "client",
+    }
+}
+
+fn event_kind(event: &Event) -> Option<&'static str> {
+    match event {
+        Event::MemberJoined { .. } => Some("member_joined"),
+        Event::MemberLeft { .. } => Some("member_left"),
+        Event::MemberRoleChanged { .. } => Some("member_role_changed"),
+        Event::EldersChanged { .. } => Some("elders_changed"),
+        Event::PromotedToAdult => Some("promoted_to_adult"),
+        Event::RelocationStarted { .. } => Some("relocation_started"),
+        Event::Relocated { .. } => Some("relocated"),
+        Event::RestartRequired => Some("restart_required"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto, node::Node, section::test_utils::gen_addr};
+    use anyhow::Result;
+    use assert_matches::assert_matches;
+
+    #[tokio::test]
+    async fn render() -> Result<()> {
+        let metrics = Metrics::default();
+        metrics.record_received("node", TrafficCategory::Gossip);
+        metrics.record_received("node", TrafficCategory::Relay);
+        metrics.record_sent(TrafficCategory::Client);
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let mut event_rx = metrics.observe_events(event_rx);
+        event_tx.send(Event::PromotedToAdult)?;
+        assert_matches!(event_rx.recv().await, Some(Event::PromotedToAdult));
+
+        let (event_tx, _) = mpsc::unbounded_channel();
+        let node = Node::new(crypto::gen_keypair(), gen_addr());
+        let state = Approved::first_node(node, event_tx)?;
+
+        let out = metrics.render(&state);
+        assert!(out.contains("sn_routing_messages_received_total{type=\"node\"} 2\n"));
+        assert!(out.contains("sn_routing_messages_sent_total{category=\"client\"} 1\n"));
+        assert!(out.contains("sn_routing_events_total{kind=\"promoted_to_adult\"} 1\n"));
+        assert!(out.contains("sn_routing_section_elders 1\n"));
+        assert!(out.contains("sn_routing_is_elder 1\n"));
+
+        Ok(())
+    }
+}
@@ -9,33 +9,88 @@
 pub(crate) mod command;
 
 mod approved;
+mod audit_log;
+mod bandwidth;
+mod blacklist;
 mod bootstrap;
+mod bootstrap_cache;
+mod builder;
+mod client_rate_limiter;
 mod comm;
+mod connection_limiter;
+mod contact_source;
+mod delivery_tracker;
 mod enduser_registry;
 mod event_stream;
+#[cfg(feature = "introspection")]
+mod introspection;
+mod join_challenges;
+mod join_proof;
+mod liveness;
+// Without the feature, only the unit tests use it, and not all of it.
+#[cfg(any(test, feature = "test-utils"))]
+#[cfg_attr(not(feature = "test-utils"), allow(dead_code))]
+mod memory_transport;
+mod message_trace;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod noise_transport;
+mod rendezvous;
+mod send_queue;
 mod split_barrier;
 mod stage;
+mod stats;
+mod storage;
 #[cfg(test)]
 mod tests;
+mod transport;
 
-pub use self::event_stream::EventStream;
+#[cfg(any(test, feature = "test-utils"))]
+pub use self::memory_transport::{
+    FilterAction, FilterId, Latency, LinkConfig, MemoryNetwork, MemoryTransport,
+};
 use self::{
     approved::Approved,
+    bootstrap_cache::BootstrapCache,
+    client_rate_limiter::Consume,
     comm::{Comm, ConnectionEvent},
     command::Command,
+    join_proof::ResourceProofJoin,
     split_barrier::SplitBarrier,
     stage::Stage,
+    storage::{self, Storage},
+    transport::QuicTransport,
+};
+pub use self::{
+    bandwidth::{PeerBandwidth, Traffic, TrafficCategory},
+    blacklist::{BlacklistConfig, BlacklistEntry, Violation},
+    builder::NodeBuilder,
+    client_rate_limiter::{ClientRateLimits, ClientUsage, Quota},
+    connection_limiter::ConnectionLimits,
+    contact_source::ContactSource,
+    event_stream::{EventBufferConfig, EventOverflow, EventStream},
+    join_challenges::ResourceProofConfig,
+    join_proof::JoinProof,
+    message_trace::{MessageTrace, TraceDirection, TraceRecord},
+    noise_transport::NoiseTransport,
+    stats::{ChurnCounter, ChurnStats, LatencyHistogram, Stats, LATENCY_BUCKETS},
+    transport::{Transport, TransportError, TransportEvent},
 };
 use crate::{
+    correlation_id::CorrelationId,
     crypto,
-    error::Result,
+    error::{Error, Result},
     event::{Event, NodeElderChange},
-    messages::Message,
+    message_size_limits::MessageSizeLimits,
+    messages::{CreateError, Message, Priority, Variant},
+    network::NetworkHealth,
+    network_params::NetworkParams,
     node::Node,
     peer::Peer,
-    section::{EldersInfo, SectionProofChain},
+    section::{Checkpoint, EldersInfo, NodeRole, SectionProofChain},
     TransportConfig, MIN_AGE,
 };
+use bls_signature_aggregator::Proof;
 use bytes::Bytes;
 use ed25519_dalek::{Keypair, PublicKey, Signature, Signer};
 use itertools::Itertools;
@@ -45,10 +100,21 @@ use sn_messaging::{
     section_info::{Error as TargetSectionError, ErrorResponse, Message as SectionInfoMsg},
     DstLocation, EndUser, MessageType, SrcLocation, WireMsg,
 };
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{sync::mpsc, task};
+use tracing::Instrument;
 use xor_name::{Prefix, XorName};
 
+// How long `Routing::close` waits for our section to acknowledge our leaving.
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(30);
+// Default of `Config::bootstrap_timeout`.
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Routing configuration.
 #[derive(Debug)]
 pub struct Config {
@@ -58,6 +124,72 @@ pub struct Config {
     pub keypair: Option<Keypair>,
     /// Configuration for the underlying network transport.
     pub transport_config: TransportConfig,
+    /// Transport to use instead of the default QUIC one configured by `transport_config`.
+    pub transport: Option<Box<dyn Transport>>,
+    /// If true, every connection is encrypted and mutually authenticated by a Noise handshake
+    /// binding it to the keypairs of the nodes, on top of the transport. All the peers of the node,
+    /// clients included, must enable it too.
+    pub noise: bool,
+    /// Path of the file to persist the section and network knowledge of the node to, so it can
+    /// be recovered after restart. If `None`, nothing is persisted.
+    pub storage_path: Option<PathBuf>,
+    /// Path of the file to persist the keypair of the node to, so it restarts with the same
+    /// identity instead of as a brand new node. If the file doesn't exist yet, it's created with
+    /// a newly generated keypair. The new keypair the node gets on relocation replaces it. Ignored
+    /// if `keypair` is set.
+    pub identity_path: Option<PathBuf>,
+    /// Path of the file to record the peers the node successfully connected to, so it can
+    /// bootstrap through them after restart in addition to the hard-coded contacts. Contacts not
+    /// connected to for a week are no longer used. If `None`, no contacts are recorded.
+    pub bootstrap_cache_path: Option<PathBuf>,
+    /// Sources of the contacts to bootstrap through. The contacts of all of them are tried at the
+    /// same time and the node joins through the first one it connects to.
+    pub contact_sources: Vec<ContactSource>,
+    /// How long to try to connect to the bootstrap contacts before giving up with
+    /// `Error::BootstrapFailed`.
+    pub bootstrap_timeout: Duration,
+    /// If true, the node records every vote its section reaches consensus on into an audit log
+    /// which can be exported with `Routing::export_audit_log`. Only the most recent 10000 votes
+    /// are kept.
+    pub audit_log: bool,
+    /// Path of the file to capture every message the node sends and receives into, to analyse it
+    /// or replay it with `Routing::replay` offline. Appended to if it already exists. If `None`,
+    /// nothing is captured.
+    pub message_trace_path: Option<PathBuf>,
+    /// Path of the file to dump the state of the node into whenever the process receives
+    /// `SIGUSR1`, for postmortem analysis. Overwritten on every dump. Unix only. If `None`, the
+    /// signal is not handled. See also `Routing::dump_state`.
+    pub state_dump_path: Option<PathBuf>,
+    /// Parameters of the network. Must be the same for all the nodes in the network.
+    pub network_params: NetworkParams,
+    /// Maximum sizes of the incoming messages.
+    pub message_size_limits: MessageSizeLimits,
+    /// Quotas on the messages from the clients connected to the node.
+    pub client_rate_limits: ClientRateLimits,
+    /// Limits on the incoming connections and join requests from a single IP address. If `None`,
+    /// they are not limited.
+    pub connection_limits: Option<ConnectionLimits>,
+    /// Resource proof required from the nodes joining through this node.
+    pub resource_proof: ResourceProofConfig,
+    /// Scheme of the proof required from the nodes joining the network, instead of the default
+    /// resource proof configured by `resource_proof`. Must be the same for all the nodes in the
+    /// network.
+    pub join_proof: Option<Box<dyn JoinProof>>,
+    /// Bound on the events not yet taken from the `EventStream`, and what to do when it's
+    /// reached. If `None`, the events are buffered without limit.
+    pub event_buffer: Option<EventBufferConfig>,
+    /// How long the peers violating the protocol are blacklisted for.
+    pub blacklist: BlacklistConfig,
+    /// Local address to serve the metrics of the node at, in the Prometheus text format, under
+    /// `/metrics`. Use port 0 to pick a free one, then see `Routing::metrics_addr`. If `None`, the
+    /// metrics are not served.
+    #[cfg(feature = "metrics")]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Local address to serve snapshots of the node state at, as JSON sent to every connection,
+    /// for debugging (e.g. `nc localhost <port>`). Must be a loopback address. Use port 0 to pick
+    /// a free one, then see `Routing::introspection_addr`. If `None`, nothing is served.
+    #[cfg(feature = "introspection")]
+    pub introspection_addr: Option<SocketAddr>,
 }
 
 impl Default for Config {
@@ -66,6 +198,28 @@ impl Default for Config {
             first: false,
             keypair: None,
             transport_config: TransportConfig::default(),
+            transport: None,
+            noise: false,
+            storage_path: None,
+            identity_path: None,
+            bootstrap_cache_path: None,
+            contact_sources: vec![ContactSource::Cache, ContactSource::Seeds],
+            bootstrap_timeout: BOOTSTRAP_TIMEOUT,
+            audit_log: false,
+            message_trace_path: None,
+            state_dump_path: None,
+            network_params: NetworkParams::default(),
+            message_size_limits: MessageSizeLimits::default(),
+            client_rate_limits: ClientRateLimits::default(),
+            connection_limits: None,
+            resource_proof: ResourceProofConfig::default(),
+            join_proof: None,
+            event_buffer: None,
+            blacklist: BlacklistConfig::default(),
+            #[cfg(feature = "metrics")]
+            metrics_addr: None,
+            #[cfg(feature = "introspection")]
+            introspection_addr: None,
         }
     }
 }
@@ -79,6 +233,10 @@ impl Default for Config {
 /// role, and can be any [`SrcLocation`].
 pub struct Routing {
     stage: Arc<Stage>,
+    #[cfg(feature = "metrics")]
+    metrics_addr: Option<SocketAddr>,
+    #[cfg(feature = "introspection")]
+    introspection_addr: Option<SocketAddr>,
 }
 
 impl Routing {
@@ -91,20 +249,62 @@ impl Routing {
     /// NOTE: It's not guaranteed this function ever returns. This can happen due to messages being
     /// lost in transit during bootstrapping, or other reasons. It's the responsibility of the
     /// caller to handle this case, for example by using a timeout.
-    pub async fn new(config: Config) -> Result<(Self, EventStream)> {
-        let keypair = config.keypair.unwrap_or_else(crypto::gen_keypair);
+    pub async fn new(mut config: Config) -> Result<(Self, EventStream)> {
+        let (keypair, identity_path) = match (config.keypair.take(), config.identity_path.take()) {
+            (Some(keypair), _) => (keypair, None),
+            (None, Some(path)) => (storage::load_or_create_keypair(&path)?, Some(path)),
+            (None, None) => (crypto::gen_keypair(), None),
+        };
         let node_name = crypto::name(&keypair.public);
 
+        let resource_proof = config.resource_proof;
+        let join_proof = config
+            .join_proof
+            .take()
+            .unwrap_or_else(|| Box::new(ResourceProofJoin::new(resource_proof)));
+
+        let bootstrap_cache = config.bootstrap_cache_path.take().map(BootstrapCache::load);
+
         let (event_tx, event_rx) = mpsc::unbounded_channel();
         let (connection_event_tx, mut connection_event_rx) = mpsc::channel(1);
 
-        let (state, comm, backlog) = if config.first {
+        let (mut state, comm, backlog) = if config.first {
             info!("{} Starting a new network as the seed node.", node_name);
-            let comm = Comm::new(config.transport_config, connection_event_tx).await?;
+            let transport = create_transport(
+                config.transport.take(),
+                config.transport_config,
+                if config.noise { Some(&keypair) } else { None },
+            )
+            .await?;
+            let comm = Comm::new(transport, config.message_size_limits, connection_event_tx);
+            comm.blacklist().set_config(config.blacklist);
+            if let Some(path) = &config.message_trace_path {
+                comm.recorder().open(path)?;
+            }
+            if let Some(limits) = config.connection_limits {
+                comm.connection_limiter().set_limits(limits);
+            }
+            let comm = if let Some(bootstrap_cache) = bootstrap_cache {
+                comm.with_bootstrap_cache(bootstrap_cache)
+            } else {
+                comm
+            };
             let node = Node::new(keypair, comm.our_connection_info()).with_age(MIN_AGE + 1);
-            let state = Approved::first_node(node, event_tx)?;
+            let state = Approved::first_node(node, event_tx)?
+                .with_network_params(config.network_params)
+                .with_client_rate_limits(config.client_rate_limits)
+                .with_join_proof(join_proof)
+                .with_stats(comm.stats().clone());
             let section = state.section();
 
+            // Other nodes join the network by bootstrapping to our address.
+            info!(
+                "{} Network started with genesis key {:?}. Contact for joining: {}",
+                node_name,
+                section.chain().first_key(),
+                comm.our_connection_info()
+            );
+
             state.send_event(Event::EldersChanged {
                 prefix: *section.prefix(),
                 key: *section.chain().last_key(),
@@ -116,18 +316,85 @@ impl Routing {
             (state, comm, vec![])
         } else {
             info!("{} Bootstrapping a new node.", node_name);
-            let (comm, bootstrap_addr) =
-                Comm::bootstrap(config.transport_config, connection_event_tx).await?;
+
+            let contacts = contact_source::resolve(
+                &config.contact_sources,
+                config.transport_config.hard_coded_contacts.iter().copied(),
+                config.storage_path.clone().map(Storage::new).as_ref(),
+                bootstrap_cache.as_ref(),
+            );
+            config.transport_config.hard_coded_contacts = contacts.iter().copied().collect();
+
+            let transport = create_transport(
+                config.transport.take(),
+                config.transport_config,
+                if config.noise { Some(&keypair) } else { None },
+            )
+            .await?;
+            let (comm, bootstrap_addr) = Comm::bootstrap(
+                transport,
+                &contacts,
+                config.bootstrap_timeout,
+                config.message_size_limits,
+                connection_event_tx,
+            )
+            .await?;
+            comm.blacklist().set_config(config.blacklist);
+            if let Some(path) = &config.message_trace_path {
+                comm.recorder().open(path)?;
+            }
+            if let Some(limits) = config.connection_limits {
+                comm.connection_limiter().set_limits(limits);
+            }
+            let comm = if let Some(mut bootstrap_cache) = bootstrap_cache {
+                bootstrap_cache.insert(bootstrap_addr);
+                comm.with_bootstrap_cache(bootstrap_cache)
+            } else {
+                comm
+            };
             let node = Node::new(keypair, comm.our_connection_info()).with_age(MIN_AGE + 1);
-            let (node, section, backlog) =
-                bootstrap::initial(node, &comm, &mut connection_event_rx, bootstrap_addr).await?;
-            let state = Approved::new(node, section, None, event_tx);
+            let (node, section, backlog) = bootstrap::initial(
+                node,
+                &comm,
+                &mut connection_event_rx,
+                bootstrap_addr,
+                config.network_params.network_id,
+                &*join_proof,
+            )
+            .await?;
+            let state = Approved::new(node, section, None, event_tx)
+                .with_network_params(config.network_params)
+                .with_client_rate_limits(config.client_rate_limits)
+                .with_join_proof(join_proof)
+                .with_stats(comm.stats().clone());
 
             (state, comm, backlog)
         };
 
-        let stage = Arc::new(Stage::new(state, comm));
-        let event_stream = EventStream::new(event_rx);
+        if config.audit_log {
+            state.enable_audit_log();
+        }
+
+        if let Some(path) = config.storage_path {
+            state.recover_from(Storage::new(path));
+        }
+
+        let periodic_timers = state.schedule_periodic_timers();
+
+        #[cfg(feature = "metrics")]
+        let event_rx = comm.metrics().observe_events(event_rx);
+
+        let mut stage = Stage::new(state, comm);
+        if let Some(identity_path) = identity_path {
+            stage = stage.with_identity_path(identity_path);
+        }
+        let (stage, event_stream) = if let Some(event_buffer) = config.event_buffer {
+            let (event_stream, event_buffer) = EventStream::bounded(event_rx, event_buffer);
+            (stage.with_event_buffer(event_buffer), event_stream)
+        } else {
+            (stage, EventStream::new(event_rx))
+        };
+        let stage = Arc::new(stage);
 
         // Process message backlog
         for (message, sender) in backlog {
@@ -140,14 +407,72 @@ impl Routing {
                 .await?;
         }
 
+        // Start the periodic tasks, like the exchange of section info with our neighbours, the
+        // removal of signature accumulations that failed to reach quorum or the probing of the
+        // liveness of our peers.
+        for command in periodic_timers {
+            let _ = task::spawn(stage.clone().handle_commands(command));
+        }
+
+        // Start dumping the state on signal.
+        if let Some(path) = config.state_dump_path {
+            #[cfg(unix)]
+            let _ = task::spawn(dump_state_on_signal(stage.clone(), path));
+            #[cfg(not(unix))]
+            warn!(
+                "Not dumping state to {}: unsupported platform",
+                path.display()
+            );
+        }
+
         // Start listening to incoming connections.
         let _ = task::spawn(handle_connection_events(stage.clone(), connection_event_rx));
 
-        let routing = Self { stage };
+        // Start serving the metrics.
+        #[cfg(feature = "metrics")]
+        let metrics_addr = if let Some(addr) = config.metrics_addr {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .map_err(Error::Metrics)?;
+            let addr = listener.local_addr().map_err(Error::Metrics)?;
+            info!("Serving metrics at {}", metrics::endpoint(&addr));
+            let _ = task::spawn(metrics::serve(listener, stage.clone()));
+            Some(addr)
+        } else {
+            None
+        };
+
+        // Start serving the snapshots of the node state.
+        #[cfg(feature = "introspection")]
+        let introspection_addr = if let Some(addr) = config.introspection_addr {
+            let listener = introspection::bind(addr).await?;
+            let addr = listener.local_addr().map_err(Error::Introspection)?;
+            info!("Serving introspection at {}", addr);
+            let _ = task::spawn(introspection::serve(listener, stage.clone()));
+            Some(addr)
+        } else {
+            None
+        };
+
+        let routing = Self {
+            stage,
+            #[cfg(feature = "metrics")]
+            metrics_addr,
+            #[cfg(feature = "introspection")]
+            introspection_addr,
+        };
 
         Ok((routing, event_stream))
     }
 
+    /// Leaves the network gracefully: asks our section to let us go, waits until it agrees (but
+    /// at most 30 seconds), lets the outgoing messages finish and only then closes all the
+    /// connections. Unlike just dropping the node, this doesn't make our section treat the
+    /// departure as a failure, so it's the preferred way to shut down e.g. for a planned restart.
+    pub async fn close(self) -> Result<()> {
+        self.stage.clone().leave(LEAVE_TIMEOUT).await
+    }
+
     /// Sets the JoinsAllowed flag.
     pub async fn set_joins_allowed(&self, joins_allowed: bool) -> Result<()> {
         let command = Command::SetJoinsAllowed(joins_allowed);
@@ -205,6 +530,75 @@ impl Routing {
         self.stage.comm.our_connection_info()
     }
 
+    /// Address the metrics of the node are served at, if enabled by `Config::metrics_addr`.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    /// Writes a human-readable dump of the state of this node into the file at `path`, for
+    /// postmortem analysis: our section with its chain and members, the other sections we know,
+    /// the signatures still being accumulated and the state of our peers and connections.
+    pub async fn dump_state(&self, path: &Path) -> Result<()> {
+        self.stage.dump_state(path).await
+    }
+
+    /// Address the snapshots of the node state are served at, if enabled by
+    /// `Config::introspection_addr`.
+    #[cfg(feature = "introspection")]
+    pub fn introspection_addr(&self) -> Option<SocketAddr> {
+        self.introspection_addr
+    }
+
+    /// Counts of the node messages, signature accumulations and membership changes handled by
+    /// this node since it started, or since the last `take_stats`.
+    pub fn stats(&self) -> Stats {
+        self.stage.comm.stats().get()
+    }
+
+    /// Like `stats`, but also resets the counts to zero, to sample them over regular intervals.
+    pub fn take_stats(&self) -> Stats {
+        self.stage.comm.stats().take()
+    }
+
+    /// Current usage of the message quotas by the client connected from the given address.
+    pub async fn client_usage(&self, client: &SocketAddr) -> ClientUsage {
+        self.stage.state.lock().await.client_usage(client)
+    }
+
+    /// Round-trip time to the peer at the given address, smoothed over the recent keepalive
+    /// probes. `None` if the peer wasn't probed successfully yet.
+    pub async fn peer_rtt(&self, addr: &SocketAddr) -> Option<Duration> {
+        self.stage.state.lock().await.peer_rtt(addr)
+    }
+
+    /// Refuses the connections, messages and join requests of the given peer for `duration`, on
+    /// top of the peers blacklisted automatically for violating the protocol.
+    pub fn blacklist(&self, entry: BlacklistEntry, duration: Duration) {
+        self.stage.comm.blacklist().insert(entry, duration)
+    }
+
+    /// Removes the given peer from the blacklist. Returns whether it was blacklisted.
+    pub fn unblacklist(&self, entry: &BlacklistEntry) -> bool {
+        self.stage.comm.blacklist().remove(entry)
+    }
+
+    /// Whether the given peer is currently blacklisted.
+    pub fn is_blacklisted(&self, entry: &BlacklistEntry) -> bool {
+        self.stage.comm.blacklist().contains(entry)
+    }
+
+    /// Bytes exchanged with the connected peer at the given address, by the category of the
+    /// traffic. `None` if not connected.
+    pub fn peer_bandwidth(&self, addr: &SocketAddr) -> Option<PeerBandwidth> {
+        self.stage.comm.bandwidth().get(addr)
+    }
+
+    /// Bytes exchanged with all the connected peers, the ones dominating our bandwidth first.
+    pub fn bandwidth_usage(&self) -> Vec<(SocketAddr, PeerBandwidth)> {
+        self.stage.comm.bandwidth().all()
+    }
+
     /// Prefix of our section
     pub async fn our_prefix(&self) -> Prefix {
         *self.stage.state.lock().await.section().prefix()
@@ -220,6 +614,16 @@ impl Routing {
         self.stage.state.lock().await.is_elder()
     }
 
+    /// Returns the role of the given member of our section, or `None` if it's not a current member.
+    pub async fn role_of(&self, name: &XorName) -> Option<NodeRole> {
+        self.stage.state.lock().await.section().role_of(name)
+    }
+
+    /// Returns the role of this node within its section.
+    pub async fn our_role(&self) -> Option<NodeRole> {
+        self.role_of(&self.name().await).await
+    }
+
     /// Returns the information of all the current section elders.
     pub async fn our_elders(&self) -> Vec<Peer> {
         self.stage
@@ -264,6 +668,19 @@ impl Routing {
             .collect()
     }
 
+    /// Returns up to `count` nodes we know (members of our section and elders of other known
+    /// sections) sorted by their distance to `name` (closest first).
+    pub async fn closest_nodes_to(&self, name: &XorName, count: usize) -> Vec<Peer> {
+        self.stage
+            .state
+            .lock()
+            .await
+            .closest_nodes_to(name)
+            .take(count)
+            .copied()
+            .collect()
+    }
+
     /// Returns the info about our section or `None` if we are not joined yet.
     pub async fn our_section(&self) -> EldersInfo {
         self.stage
@@ -275,6 +692,23 @@ impl Routing {
             .clone()
     }
 
+    /// Returns the info about our section with a prefix compatible with `prefix` as it was at the
+    /// given index of our section chain, together with the proof that the section agreed on it.
+    /// Returns `None` if no such info is known, e.g. because it precedes the latest checkpoint.
+    pub async fn our_section_at(
+        &self,
+        prefix: &Prefix,
+        key_index: u64,
+    ) -> Option<(EldersInfo, Proof)> {
+        self.stage
+            .state
+            .lock()
+            .await
+            .section()
+            .elders_info_at(prefix, key_index)
+            .map(|info| (info.value.clone(), info.proof.clone()))
+    }
+
     /// Returns the info about our neighbour sections.
     pub async fn neighbour_sections(&self) -> Vec<EldersInfo> {
         self.stage
@@ -287,6 +721,12 @@ impl Routing {
             .collect()
     }
 
+    /// Returns report on the completeness of our knowledge of the network: missing neighbours,
+    /// undersized or unreachable sections and whether our section is about to split.
+    pub async fn network_health(&self) -> NetworkHealth {
+        self.stage.state.lock().await.network_health()
+    }
+
     /// Returns the last known public key of the section with `prefix`.
     pub async fn section_key(&self, prefix: &Prefix) -> Option<bls::PublicKey> {
         self.stage.state.lock().await.section_key(prefix).copied()
@@ -317,6 +757,77 @@ impl Routing {
         src: SrcLocation,
         dst: DstLocation,
         content: Bytes,
+    ) -> Result<()> {
+        self.send_message_impl(src, dst, content, None, false).await
+    }
+
+    /// Send a message tagged with the given correlation id. The recipient receives the id in
+    /// `Event::MessageReceived` and can reply with the same id so the response can be matched to
+    /// this message. The id is not forwarded to clients.
+    pub async fn send_message_with_correlation_id(
+        &self,
+        src: SrcLocation,
+        dst: DstLocation,
+        content: Bytes,
+        correlation_id: CorrelationId,
+    ) -> Result<()> {
+        self.send_message_impl(src, dst, content, Some(correlation_id), false)
+            .await
+    }
+
+    /// Send a message from our node, traced across the hops it takes: every node relaying or
+    /// delivering it logs its hash and hop count at the info level, which allows reconstructing
+    /// the path of a lost message from the logs of the network, starting with ours.
+    pub async fn send_traced_message(
+        &self,
+        src: SrcLocation,
+        dst: DstLocation,
+        content: Bytes,
+    ) -> Result<()> {
+        self.send_message_impl(src, dst, content, None, true).await
+    }
+
+    /// Send a message to all the members of the given prefix, elders and adults alike. The
+    /// recipients receive it as `Event::MulticastReceived`.
+    pub async fn send_message_to_prefix(
+        &self,
+        src: SrcLocation,
+        prefix: Prefix,
+        content: Bytes,
+    ) -> Result<()> {
+        let command = Command::MulticastUserMessage {
+            src,
+            prefix,
+            content,
+        };
+        self.stage.clone().handle_commands(command).await
+    }
+
+    /// Send a message from our node to the single live node closest to `target`. Once delivered,
+    /// `Event::AnycastDelivered` reports which node received it, carrying the given correlation id
+    /// so it can be matched to this message. The message is sent again until the recipient reports
+    /// it, and `Event::MessageDeliveryFailed` is raised if it never does.
+    pub async fn send_message_to_closest(
+        &self,
+        target: XorName,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+    ) -> Result<()> {
+        let command = Command::AnycastUserMessage {
+            target,
+            content,
+            correlation_id,
+        };
+        self.stage.clone().handle_commands(command).await
+    }
+
+    async fn send_message_impl(
+        &self,
+        src: SrcLocation,
+        dst: DstLocation,
+        content: Bytes,
+        correlation_id: Option<CorrelationId>,
+        traced: bool,
     ) -> Result<()> {
         if let DstLocation::EndUser(EndUser::Client {
             socket_id,
@@ -343,7 +854,13 @@ impl Routing {
                 debug!("Sending user message instead.. (Command::SendUserMessage)");
             }
         }
-        let command = Command::SendUserMessage { src, dst, content };
+        let command = Command::SendUserMessage {
+            src,
+            dst,
+            content,
+            correlation_id,
+            traced,
+        };
         self.stage.clone().handle_commands(command).await
     }
 
@@ -359,6 +876,7 @@ impl Routing {
             recipients: vec![recipient],
             delivery_group_size: 1,
             message: MessageType::ClientMessage(message),
+            priority: Priority::UserData,
         };
         self.stage.clone().handle_commands(command).await
     }
@@ -374,6 +892,50 @@ impl Routing {
         self.stage.state.lock().await.section().chain().clone()
     }
 
+    /// Returns the latest checkpoint of our section, if any. The checkpoint can be used to prove
+    /// the section state without the pruned history preceding it.
+    pub async fn our_checkpoint(&self) -> Option<Checkpoint> {
+        self.stage
+            .state
+            .lock()
+            .await
+            .section()
+            .checkpoint()
+            .cloned()
+    }
+
+    /// Exports the audit log of the most recent votes our section reached consensus on since this
+    /// node started, as a JSON array. Returns `Error::InvalidState` if the audit log is not enabled in
+    /// the `Config`.
+    pub async fn export_audit_log(&self) -> Result<String> {
+        self.stage
+            .state
+            .lock()
+            .await
+            .audit_log()
+            .ok_or(Error::InvalidState)?
+            .to_json()
+    }
+
+    /// Feeds the messages received in `trace` to this node, one by one in the order they were
+    /// originally received, to reproduce the behaviour of the node which captured it offline.
+    /// The node should start from the same state as that node, e.g. with the same keypair and
+    /// section, and use an isolated transport such as a `MemoryTransport` so its own messages
+    /// don't reach the real network. Received messages handled before the node was approved are
+    /// part of the trace too, but are replayed to the approved node. The messages are handled
+    /// however old the trace is, bypassing the nonce, replay and blacklist checks.
+    pub async fn replay(&self, trace: &MessageTrace) {
+        for record in trace.received() {
+            handle_message(
+                self.stage.clone(),
+                Bytes::from(record.bytes.clone()),
+                record.peer,
+                true,
+            )
+            .await;
+        }
+    }
+
     /// Returns our index in the current BLS group if this node is a member of one, or
     /// `Error::MissingSecretKeyShare` otherwise.
     pub async fn our_index(&self) -> Result<usize> {
@@ -387,16 +949,65 @@ impl Drop for Routing {
     }
 }
 
+// Use the given transport, or create the default QUIC one. Secure it with Noise if enabled.
+async fn create_transport(
+    transport: Option<Box<dyn Transport>>,
+    transport_config: TransportConfig,
+    noise_keypair: Option<&Keypair>,
+) -> Result<Box<dyn Transport>> {
+    let transport = if let Some(transport) = transport {
+        transport
+    } else {
+        Box::new(QuicTransport::new(transport_config).await?)
+    };
+
+    if let Some(keypair) = noise_keypair {
+        Ok(Box::new(NoiseTransport::new(transport, keypair)?))
+    } else {
+        Ok(transport)
+    }
+}
+
+#[cfg(unix)]
+async fn dump_state_on_signal(stage: Arc<Stage>, path: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(error) => {
+            error!("Failed to handle SIGUSR1: {}", error);
+            return;
+        }
+    };
+
+    while signals.recv().await.is_some() {
+        match stage.dump_state(&path).await {
+            Ok(()) => info!("State dumped to {}", path.display()),
+            Err(error) => error!("Failed to dump state to {}: {}", path.display(), error),
+        }
+    }
+}
+
 // Listen for incoming connection events and handle them.
 async fn handle_connection_events(
     stage: Arc<Stage>,
     mut incoming_conns: mpsc::Receiver<ConnectionEvent>,
 ) {
     while let Some(event) = incoming_conns.recv().await {
+        // Stop taking in more work while the events consumer is behind.
+        stage.wait_for_event_buffer().await;
+
         match event {
             ConnectionEvent::Received((src, bytes)) => {
-                trace!("New message ({} bytes) received from: {}", bytes.len(), src);
-                handle_message(stage.clone(), bytes, src).await;
+                // The commands resulting from the message are spawned, so they don't end up
+                // nested in this span.
+                let span = stage.state.lock().await.span();
+                async {
+                    trace!("New message ({} bytes) received from: {}", bytes.len(), src);
+                    handle_message(stage.clone(), bytes, src, false).await;
+                }
+                .instrument(span)
+                .await
             }
             ConnectionEvent::Disconnected(addr) => {
                 trace!("Lost connection to {:?}", addr);
@@ -409,15 +1020,63 @@ async fn handle_connection_events(
     }
 }
 
-async fn handle_message(stage: Arc<Stage>, bytes: Bytes, sender: SocketAddr) {
+// Handle a message received from `sender`, or `replayed` from a captured trace. The replayed
+// messages are not recorded again and bypass the blacklist, nonce and replay checks, as they are
+// as old as the trace. Their duplicates are still dropped, like by the node which captured them.
+async fn handle_message(stage: Arc<Stage>, bytes: Bytes, sender: SocketAddr, replayed: bool) {
+    if !replayed {
+        stage
+            .comm
+            .recorder()
+            .record(TraceDirection::Received, sender, &bytes);
+    }
+
+    let size = bytes.len();
     let message_type = match WireMsg::deserialize(bytes) {
         Ok(message_type) => message_type,
         Err(error) => {
-            error!("Failed to deserialize message from {}: {}", sender, error);
+            error_limited!("Failed to deserialize message from {}: {}", sender, error);
             return;
         }
     };
 
+    let category = match &message_type {
+        MessageType::Ping => TrafficCategory::Gossip,
+        MessageType::NodeMessage(NodeMessage(msg_bytes)) => {
+            TrafficCategory::of_node_message(msg_bytes)
+        }
+        MessageType::ClientMessage(_) | MessageType::SectionInfo(_) => TrafficCategory::Client,
+    };
+    stage
+        .comm
+        .bandwidth()
+        .record_received(sender, category, size);
+    #[cfg(feature = "metrics")]
+    stage.comm.metrics().record_received(
+        match &message_type {
+            MessageType::Ping => "ping",
+            MessageType::SectionInfo(_) => "section_info",
+            MessageType::NodeMessage(_) => "node",
+            MessageType::ClientMessage(_) => "client",
+        },
+        category,
+    );
+
+    // The overall limit is already enforced by `Comm`. For node messages, this check still comes
+    // before the message itself is deserialized.
+    if size > stage.comm.message_size_limits().for_message(&message_type) {
+        warn_limited!(
+            "Dropping message ({} bytes) exceeding the size limit of its type from {}",
+            size,
+            sender
+        );
+        stage
+            .comm
+            .blacklist()
+            .report(BlacklistEntry::Addr(sender), Violation::OversizedMessage);
+        return;
+    }
+
     match message_type {
         MessageType::Ping => {
             // Pings are not handled
@@ -427,18 +1086,79 @@ async fn handle_message(stage: Arc<Stage>, bytes: Bytes, sender: SocketAddr) {
             let _ = task::spawn(stage.handle_commands(command));
         }
         MessageType::NodeMessage(NodeMessage(msg_bytes)) => {
-            match Message::from_bytes(Bytes::from(msg_bytes)) {
+            let msg_bytes = Bytes::from(msg_bytes);
+
+            // Drop messages that reached us via multiple routes early, before verifying them.
+            let hash = Message::hash_of(&msg_bytes);
+            if stage.state.lock().await.is_known_message(&hash) {
+                trace!(
+                    "Dropping already handled message {:?} from {}",
+                    hash,
+                    sender
+                );
+                return;
+            }
+
+            match Message::from_bytes(msg_bytes) {
                 Ok(message) => {
-                    let command = Command::HandleMessage {
-                        message,
-                        sender: Some(sender),
+                    // This includes the join requests, whose source is the joining node.
+                    if let (false, Ok(name)) = (replayed, message.src().to_node_name()) {
+                        if stage.comm.blacklist().contains(&BlacklistEntry::Name(name)) {
+                            trace!("Dropping message from blacklisted node {}", name);
+                            return;
+                        }
+                    }
+
+                    // With a transport authenticating the peers, direct messages must be signed by
+                    // the node the sender authenticated as, unless it's one of our elders relaying
+                    // the message of another member. Its signature was verified above.
+                    if let (DstLocation::Direct, Some(peer_key)) =
+                        (message.dst(), stage.comm.peer_key(&sender))
+                    {
+                        if message.src().public_key().ok() != Some(&peer_key)
+                            && !stage.state.lock().await.is_relayed_by(&peer_key, &message)
+                        {
+                            warn_limited!(
+                                "Dropping direct message from {} not signed by its node",
+                                sender
+                            );
+                            stage
+                                .comm
+                                .blacklist()
+                                .report(BlacklistEntry::Addr(sender), Violation::InvalidSignature);
+                            return;
+                        }
+                    }
+
+                    if let Variant::JoinRequest(_) = message.variant() {
+                        if !stage.comm.connection_limiter().accept_join_request(&sender) {
+                            debug!("Dropping join request from {} exceeding the limits", sender);
+                            return;
+                        }
+                    }
+
+                    let command = if replayed {
+                        Command::ReplayMessage { sender, message }
+                    } else {
+                        Command::HandleMessage {
+                            message,
+                            sender: Some(sender),
+                        }
                     };
                     let _ = task::spawn(stage.handle_commands(command));
                 }
+                Err(CreateError::FailedSignature) => {
+                    error_limited!("Invalid signature of node message from {}", sender);
+                    stage
+                        .comm
+                        .blacklist()
+                        .report(BlacklistEntry::Addr(sender), Violation::InvalidSignature);
+                }
                 Err(error) => {
-                    error!(
+                    error_limited!(
                         "Error occurred when deserialising node message bytes from {}: {}",
-                        sender, error
+                        sender,
+                        error
                     );
                 }
             }
@@ -463,12 +1183,35 @@ async fn handle_message(stage: Arc<Stage>, bytes: Bytes, sender: SocketAddr) {
                                 sender, message
                             )),
                         )),
+                        priority: Priority::SectionKnowledge,
                     };
                     let _ = task::spawn(stage.handle_commands(command));
                     return;
                 }
             };
 
+            let consume = stage
+                .state
+                .lock()
+                .await
+                .try_consume_client_quota(sender, size);
+            match consume {
+                Consume::Allowed => (),
+                Consume::Throttled => {
+                    debug!("Throttling client {}", sender);
+                    stage.send_event(Event::ClientThrottled(sender)).await;
+                    return;
+                }
+                Consume::StillThrottled => return,
+                Consume::Flooding => {
+                    stage
+                        .comm
+                        .blacklist()
+                        .report(BlacklistEntry::Addr(sender), Violation::Flooding);
+                    return;
+                }
+            }
+
             if let Some(client_pk) = message.target_section_pk() {
                 if let Some(bls_pk) = client_pk.bls() {
                     if let Err(error) = stage.check_key_status(&bls_pk).await {
@@ -482,6 +1225,7 @@ async fn handle_message(stage: Arc<Stage>, bytes: Bytes, sender: SocketAddr) {
                                     error,
                                 },
                             )),
+                            priority: Priority::SectionKnowledge,
                         };
                         let _ = task::spawn(stage.handle_commands(command));
                         return;
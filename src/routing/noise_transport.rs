@@ -0,0 +1,862 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::transport::{Transport, TransportError, TransportEvent};
+use crate::crypto::{self, Keypair, PublicKey, Signature, Verifier, PUBLIC_KEY_LENGTH};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{
+    channel::oneshot,
+    future::{BoxFuture, FutureExt},
+    stream::{self, BoxStream, StreamExt},
+};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{self, Debug, Formatter},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+// Prefix of the Noise static key signed by the node's keypair.
+const IDENTITY_CONTEXT: &[u8] = b"sn_routing noise static key";
+// How long to wait for the peer to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Data frames received before the handshake completes which are kept until it does.
+const MAX_EARLY_FRAMES: usize = 16;
+// Handshakes in progress at once. Those started by the peers above it are refused.
+const MAX_PENDING_HANDSHAKES: usize = 256;
+// How many of the latest nonces of each session are remembered to detect replayed data frames.
+// The older ones are refused. Large enough for the chunks of the messages sent concurrently.
+const REPLAY_WINDOW: u64 = 4096;
+
+const MAX_NOISE_MSG_LEN: usize = 65535;
+const AEAD_TAG_LEN: usize = 16;
+const MAX_CHUNK_LEN: usize = MAX_NOISE_MSG_LEN - AEAD_TAG_LEN;
+// Nonce and ciphertext length preceding every chunk of a data frame.
+const CHUNK_HEADER_LEN: usize = 8 + 2;
+
+// Kinds of the frames exchanged over the inner transport, in their first byte.
+const HANDSHAKE_1: u8 = 0;
+const HANDSHAKE_2: u8 = 1;
+const HANDSHAKE_3: u8 = 2;
+const DATA: u8 = 3;
+
+type FrameError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Transport encrypting and authenticating all the traffic of another transport.
+///
+/// Every connection starts with a Noise XX handshake in which both peers prove they own the
+/// keypair of their node by signing their Noise static key with it. Only then the connection is
+/// reported and the messages are exchanged, encrypted with the keys of the handshake. All the peers
+/// of the node, clients included, must use it too.
+///
+/// The handshakes are driven by the stream returned from `events`, so `connect` only completes
+/// while the stream is being polled. After `rekey`, all the connections are closed so the peers
+/// authenticate us again with the new keypair.
+pub struct NoiseTransport {
+    inner: Arc<dyn Transport>,
+    shared: Arc<Mutex<Shared>>,
+    events: Option<BoxStream<'static, TransportEvent>>,
+}
+
+struct Shared {
+    static_key: Vec<u8>,
+    // Our node's public key followed by its signature of our Noise static key.
+    identity: Vec<u8>,
+    handshakes: HashMap<SocketAddr, Handshake>,
+    sessions: HashMap<SocketAddr, Session>,
+    // `connect` calls waiting for the handshake with the peer to complete.
+    waiters: HashMap<SocketAddr, Vec<oneshot::Sender<bool>>>,
+}
+
+struct Handshake {
+    state: HandshakeState,
+    early_frames: Vec<Bytes>,
+    started: Instant,
+}
+
+struct Session {
+    state: StatelessTransportState,
+    peer: PublicKey,
+    next_nonce: u64,
+    received: ReplayWindow,
+}
+
+// Nonces of the latest data frames received in a session, so their replays are refused.
+struct ReplayWindow {
+    // One past the highest nonce received.
+    top: u64,
+    // Bit per nonce in `[top - REPLAY_WINDOW, top)`, indexed by the nonce modulo the window.
+    seen: Vec<u64>,
+}
+
+// What to do with a frame received from the inner transport.
+enum Action {
+    Ignore,
+    Reply { frame: Bytes, completed: bool },
+    Report(Vec<TransportEvent>),
+}
+
+impl NoiseTransport {
+    /// Wraps `inner`, authenticating the connections with `keypair`.
+    pub fn new(mut inner: Box<dyn Transport>, keypair: &Keypair) -> Result<Self, TransportError> {
+        let shared = Shared::new(keypair).map_err(other)?;
+        let events = inner.events();
+
+        Ok(Self {
+            inner: Arc::from(inner),
+            shared: Arc::new(Mutex::new(shared)),
+            events: Some(events),
+        })
+    }
+}
+
+impl Transport for NoiseTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.inner.local_addr()
+    }
+
+    fn connect<'a>(&'a self, addr: &'a SocketAddr) -> BoxFuture<'a, Result<(), TransportError>> {
+        async move {
+            if lock(&self.shared).sessions.contains_key(addr) {
+                return Ok(());
+            }
+
+            self.inner.connect(addr).await?;
+
+            let (tx, rx) = oneshot::channel();
+            let frame = {
+                let mut shared = lock(&self.shared);
+                if shared.sessions.contains_key(addr) {
+                    return Ok(());
+                }
+
+                shared.waiters.entry(*addr).or_default().push(tx);
+
+                // Join the handshake in progress, if any.
+                if shared.handshakes.contains_key(addr) {
+                    None
+                } else {
+                    Some(shared.start_handshake(*addr).map_err(other)?)
+                }
+            };
+
+            if let Some(frame) = frame {
+                if let Err(error) = self.inner.send(addr, frame).await {
+                    lock(&self.shared).remove(addr);
+                    return Err(error);
+                }
+            }
+
+            match time::timeout(HANDSHAKE_TIMEOUT, rx).await {
+                Ok(Ok(true)) => Ok(()),
+                Ok(_) => Err(other("Noise handshake failed")),
+                Err(_) => {
+                    lock(&self.shared).remove(addr);
+                    Err(other("Noise handshake timed out"))
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn send<'a>(
+        &'a self,
+        addr: &'a SocketAddr,
+        msg: Bytes,
+    ) -> BoxFuture<'a, Result<(), TransportError>> {
+        async move {
+            let frame = lock(&self.shared).encrypt(addr, &msg)?;
+            self.inner.send(addr, frame).await
+        }
+        .boxed()
+    }
+
+    fn disconnect(&self, addr: &SocketAddr) {
+        lock(&self.shared).remove(addr);
+        self.inner.disconnect(addr)
+    }
+
+    // Public key of the node at `addr`, if we have an authenticated session with it.
+    fn peer_key(&self, addr: &SocketAddr) -> Option<PublicKey> {
+        lock(&self.shared)
+            .sessions
+            .get(addr)
+            .map(|session| session.peer)
+    }
+
+    fn rekey(&self, keypair: &Keypair) -> Result<(), TransportError> {
+        let (static_key, identity) = create_identity(keypair).map_err(other)?;
+
+        let addrs: Vec<_> = {
+            let mut shared = lock(&self.shared);
+            shared.static_key = static_key;
+            shared.identity = identity;
+
+            let addrs: Vec<_> = shared
+                .sessions
+                .keys()
+                .chain(shared.handshakes.keys())
+                .copied()
+                .collect();
+            for addr in &addrs {
+                shared.remove(addr);
+            }
+            addrs
+        };
+
+        // The peers authenticated us by the previous keypair, make them handshake again.
+        for addr in addrs {
+            self.inner.disconnect(&addr);
+        }
+
+        Ok(())
+    }
+
+    fn events(&mut self) -> BoxStream<'static, TransportEvent> {
+        let events = if let Some(events) = self.events.take() {
+            events
+        } else {
+            return stream::empty().boxed();
+        };
+
+        let inner = self.inner.clone();
+        let shared = self.shared.clone();
+
+        events
+            .then(move |event| {
+                let inner = inner.clone();
+                let shared = shared.clone();
+                async move { handle_event(&*inner, &shared, event).await }
+            })
+            .flat_map(stream::iter)
+            .boxed()
+    }
+
+    fn close(&self) {
+        let mut shared = lock(&self.shared);
+        shared.handshakes.clear();
+        shared.sessions.clear();
+        shared.waiters.clear();
+        drop(shared);
+
+        self.inner.close()
+    }
+}
+
+impl Debug for NoiseTransport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("NoiseTransport")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+async fn handle_event(
+    inner: &dyn Transport,
+    shared: &Mutex<Shared>,
+    event: TransportEvent,
+) -> Vec<TransportEvent> {
+    match event {
+        // Reported once the handshake completes.
+        TransportEvent::Connected(_) => vec![],
+        TransportEvent::Disconnected(addr) => {
+            lock(shared).remove(&addr);
+            vec![TransportEvent::Disconnected(addr)]
+        }
+        TransportEvent::Received(addr, frame) => {
+            let action = lock(shared).handle_frame(addr, inner.local_addr(), frame);
+            match action {
+                Ok(Action::Ignore) => vec![],
+                Ok(Action::Reply { frame, completed }) => {
+                    let result = inner.send(&addr, frame).await;
+                    let mut shared = lock(shared);
+                    if let Err(error) = result {
+                        debug!("Failed to send Noise handshake to {}: {}", addr, error);
+                        shared.remove(&addr);
+                    } else if completed {
+                        shared.notify(&addr, true);
+                    }
+                    vec![]
+                }
+                Ok(Action::Report(events)) => events,
+                Err(error) => {
+                    warn!("Invalid Noise frame from {}: {}", addr, error);
+                    lock(shared).remove(&addr);
+                    inner.disconnect(&addr);
+                    vec![]
+                }
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn new(keypair: &Keypair) -> Result<Self, FrameError> {
+        let (static_key, identity) = create_identity(keypair)?;
+        Ok(Self {
+            static_key,
+            identity,
+            handshakes: HashMap::new(),
+            sessions: HashMap::new(),
+            waiters: HashMap::new(),
+        })
+    }
+
+    // Start the handshake with `addr` as its initiator, returning the first frame.
+    fn start_handshake(&mut self, addr: SocketAddr) -> Result<Bytes, FrameError> {
+        let mut state = builder()?
+            .local_private_key(&self.static_key)
+            .build_initiator()?;
+        let frame = write_handshake(&mut state, HANDSHAKE_1, &[])?;
+        let _ = self.handshakes.insert(addr, Handshake::new(state));
+        Ok(frame)
+    }
+
+    fn handle_frame(
+        &mut self,
+        addr: SocketAddr,
+        our_addr: SocketAddr,
+        frame: Bytes,
+    ) -> Result<Action, FrameError> {
+        match frame.first().copied() {
+            Some(HANDSHAKE_1) => {
+                if let Some(handshake) = self.handshakes.get(&addr) {
+                    // Both of us started the handshake at the same time, only the one started by
+                    // the peer with the lower address proceeds.
+                    if handshake.state.is_initiator() && our_addr < addr {
+                        return Ok(Action::Ignore);
+                    }
+                } else {
+                    self.remove_expired_handshakes();
+                    if self.handshakes.len() >= MAX_PENDING_HANDSHAKES {
+                        return Err("too many pending handshakes".into());
+                    }
+                }
+
+                let mut state = builder()?
+                    .local_private_key(&self.static_key)
+                    .build_responder()?;
+                let _ = read_handshake(&mut state, &frame)?;
+                let reply = write_handshake(&mut state, HANDSHAKE_2, &self.identity)?;
+                let _ = self.handshakes.insert(addr, Handshake::new(state));
+
+                Ok(Action::Reply {
+                    frame: reply,
+                    completed: false,
+                })
+            }
+            Some(HANDSHAKE_2) => {
+                let mut handshake = self
+                    .handshakes
+                    .remove(&addr)
+                    .filter(|handshake| handshake.state.is_initiator())
+                    .ok_or("unexpected handshake message")?;
+                let payload = read_handshake(&mut handshake.state, &frame)?;
+                let peer = verify_identity(&payload, handshake.state.get_remote_static())?;
+                let reply = write_handshake(&mut handshake.state, HANDSHAKE_3, &self.identity)?;
+                self.insert_session(addr, handshake.state, peer)?;
+
+                Ok(Action::Reply {
+                    frame: reply,
+                    completed: true,
+                })
+            }
+            Some(HANDSHAKE_3) => {
+                let mut handshake = self
+                    .handshakes
+                    .remove(&addr)
+                    .filter(|handshake| !handshake.state.is_initiator())
+                    .ok_or("unexpected handshake message")?;
+                let payload = read_handshake(&mut handshake.state, &frame)?;
+                let peer = verify_identity(&payload, handshake.state.get_remote_static())?;
+                self.insert_session(addr, handshake.state, peer)?;
+                self.notify(&addr, true);
+
+                let mut events = vec![TransportEvent::Connected(addr)];
+                for frame in handshake.early_frames {
+                    events.push(TransportEvent::Received(addr, self.decrypt(&addr, &frame)?));
+                }
+
+                Ok(Action::Report(events))
+            }
+            Some(DATA) => {
+                if self.sessions.contains_key(&addr) {
+                    match self.decrypt(&addr, &frame) {
+                        Ok(msg) => {
+                            return Ok(Action::Report(vec![TransportEvent::Received(addr, msg)]))
+                        }
+                        Err(error) if !self.handshakes.contains_key(&addr) => return Err(error),
+                        // Encrypted for the session the peer is handshaking again, which it
+                        // already completed on its side.
+                        Err(_) => (),
+                    }
+                }
+
+                // The data may overtake the last handshake message.
+                let handshake = self
+                    .handshakes
+                    .get_mut(&addr)
+                    .filter(|handshake| handshake.early_frames.len() < MAX_EARLY_FRAMES)
+                    .ok_or("data without a session")?;
+                handshake.early_frames.push(frame);
+
+                Ok(Action::Ignore)
+            }
+            _ => Err("unknown frame".into()),
+        }
+    }
+
+    fn insert_session(
+        &mut self,
+        addr: SocketAddr,
+        state: HandshakeState,
+        peer: PublicKey,
+    ) -> Result<(), FrameError> {
+        let state = state.into_stateless_transport_mode()?;
+        let _ = self.sessions.insert(
+            addr,
+            Session {
+                state,
+                peer,
+                next_nonce: 0,
+                received: ReplayWindow::new(),
+            },
+        );
+        Ok(())
+    }
+
+    // Drop the handshakes the peers didn't complete in time. Those we initiated already failed
+    // their `connect` calls by then.
+    fn remove_expired_handshakes(&mut self) {
+        let expired: Vec<_> = self
+            .handshakes
+            .iter()
+            .filter(|(_, handshake)| handshake.started.elapsed() > HANDSHAKE_TIMEOUT)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in expired {
+            trace!("Noise handshake with {} timed out", addr);
+            self.remove(&addr);
+        }
+    }
+
+    // Encrypt the message in chunks short enough for Noise, each with its own nonce.
+    fn encrypt(&mut self, addr: &SocketAddr, msg: &[u8]) -> Result<Bytes, TransportError> {
+        let session = self
+            .sessions
+            .get_mut(addr)
+            .ok_or_else(|| other("no Noise session with the peer"))?;
+
+        let chunks = (msg.len() + MAX_CHUNK_LEN - 1) / MAX_CHUNK_LEN;
+        let mut frame =
+            BytesMut::with_capacity(1 + msg.len() + chunks * (CHUNK_HEADER_LEN + AEAD_TAG_LEN));
+        frame.put_u8(DATA);
+
+        let mut buffer = vec![0; MAX_NOISE_MSG_LEN];
+        for chunk in msg.chunks(MAX_CHUNK_LEN) {
+            let nonce = session.next_nonce;
+            session.next_nonce += 1;
+
+            let len = session
+                .state
+                .write_message(nonce, chunk, &mut buffer)
+                .map_err(other)?;
+            frame.put_u64(nonce);
+            frame.put_u16(len as u16);
+            frame.put_slice(&buffer[..len]);
+        }
+
+        Ok(frame.freeze())
+    }
+
+    // Decrypt a data frame, refusing it if any of its chunks was already received.
+    fn decrypt(&mut self, addr: &SocketAddr, frame: &[u8]) -> Result<Bytes, FrameError> {
+        let session = self
+            .sessions
+            .get_mut(addr)
+            .ok_or("data without a session")?;
+
+        let mut msg = BytesMut::with_capacity(frame.len());
+        let mut buffer = vec![0; MAX_NOISE_MSG_LEN];
+        let mut rest = frame.get(1..).unwrap_or_default();
+        while !rest.is_empty() {
+            if rest.len() < CHUNK_HEADER_LEN {
+                return Err("truncated frame".into());
+            }
+
+            let mut nonce = [0; 8];
+            nonce.copy_from_slice(&rest[..8]);
+            let len = usize::from(u16::from_be_bytes([rest[8], rest[9]]));
+            let ciphertext = rest
+                .get(CHUNK_HEADER_LEN..CHUNK_HEADER_LEN + len)
+                .ok_or("truncated frame")?;
+
+            let nonce = u64::from_be_bytes(nonce);
+            let len = session.state.read_message(nonce, ciphertext, &mut buffer)?;
+            // Only after the chunk is authenticated, so forged ones can't advance the window.
+            if !session.received.insert(nonce) {
+                return Err("replayed frame".into());
+            }
+            msg.put_slice(&buffer[..len]);
+
+            rest = &rest[CHUNK_HEADER_LEN + ciphertext.len()..];
+        }
+
+        Ok(msg.freeze())
+    }
+
+    fn notify(&mut self, addr: &SocketAddr, success: bool) {
+        for tx in self.waiters.remove(addr).unwrap_or_default() {
+            let _ = tx.send(success);
+        }
+    }
+
+    fn remove(&mut self, addr: &SocketAddr) {
+        let _ = self.handshakes.remove(addr);
+        let _ = self.sessions.remove(addr);
+        self.notify(addr, false);
+    }
+}
+
+impl Handshake {
+    fn new(state: HandshakeState) -> Self {
+        Self {
+            state,
+            early_frames: vec![],
+            started: Instant::now(),
+        }
+    }
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        Self {
+            top: 0,
+            seen: vec![0; (REPLAY_WINDOW / 64) as usize],
+        }
+    }
+
+    // Mark the nonce as received, returning false if it already was or is too old to tell.
+    fn insert(&mut self, nonce: u64) -> bool {
+        if nonce >= self.top {
+            // Forget the nonces leaving the window.
+            if nonce - self.top >= REPLAY_WINDOW {
+                self.seen.iter_mut().for_each(|word| *word = 0);
+            } else {
+                for old in self.top..nonce {
+                    self.set(old, false);
+                }
+            }
+            self.top = nonce + 1;
+        } else if self.top - nonce > REPLAY_WINDOW || self.get(nonce) {
+            return false;
+        }
+
+        self.set(nonce, true);
+        true
+    }
+
+    fn get(&self, nonce: u64) -> bool {
+        let index = nonce % REPLAY_WINDOW;
+        self.seen[(index / 64) as usize] & (1 << (index % 64)) != 0
+    }
+
+    fn set(&mut self, nonce: u64, value: bool) {
+        let index = nonce % REPLAY_WINDOW;
+        let word = &mut self.seen[(index / 64) as usize];
+        if value {
+            *word |= 1 << (index % 64);
+        } else {
+            *word &= !(1 << (index % 64));
+        }
+    }
+}
+
+fn builder() -> Result<Builder<'static>, FrameError> {
+    Ok(Builder::new(NOISE_PARAMS.parse()?))
+}
+
+fn write_handshake(
+    state: &mut HandshakeState,
+    kind: u8,
+    payload: &[u8],
+) -> Result<Bytes, FrameError> {
+    let mut buffer = vec![0; MAX_NOISE_MSG_LEN];
+    let len = state.write_message(payload, &mut buffer)?;
+
+    let mut frame = BytesMut::with_capacity(1 + len);
+    frame.put_u8(kind);
+    frame.put_slice(&buffer[..len]);
+    Ok(frame.freeze())
+}
+
+fn read_handshake(state: &mut HandshakeState, frame: &[u8]) -> Result<Vec<u8>, FrameError> {
+    let mut buffer = vec![0; MAX_NOISE_MSG_LEN];
+    let len = state.read_message(frame.get(1..).unwrap_or_default(), &mut buffer)?;
+    buffer.truncate(len);
+    Ok(buffer)
+}
+
+// Check the peer's node keypair signed the Noise static key it used in the handshake, returning
+// the public key of the node.
+fn verify_identity(payload: &[u8], remote_static: Option<&[u8]>) -> Result<PublicKey, FrameError> {
+    let remote_static = remote_static.ok_or("missing static key")?;
+    if payload.len() < PUBLIC_KEY_LENGTH {
+        return Err("invalid identity".into());
+    }
+
+    let (public_key, signature) = payload.split_at(PUBLIC_KEY_LENGTH);
+    let public_key = PublicKey::from_bytes(public_key)?;
+    let signature = Signature::try_from(signature)?;
+    public_key.verify(&identity_context(remote_static), &signature)?;
+
+    Ok(public_key)
+}
+
+// Generate a new Noise static key, returning its private part together with our identity: the
+// public key of our node followed by its signature of the public part.
+fn create_identity(keypair: &Keypair) -> Result<(Vec<u8>, Vec<u8>), FrameError> {
+    let static_keypair = builder()?.generate_keypair()?;
+
+    let mut identity = keypair.public.to_bytes().to_vec();
+    let signature = crypto::sign(&identity_context(&static_keypair.public), keypair);
+    identity.extend_from_slice(&signature.to_bytes());
+
+    Ok((static_keypair.private, identity))
+}
+
+fn identity_context(static_key: &[u8]) -> Vec<u8> {
+    [IDENTITY_CONTEXT, static_key].concat()
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<Shared> {
+    shared.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn other<E: Into<FrameError>>(error: E) -> TransportError {
+    TransportError::Other(error.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::MemoryNetwork;
+    use assert_matches::assert_matches;
+    use tokio::{sync::mpsc, task};
+
+    #[tokio::test]
+    async fn handshake_and_encryption() {
+        let network = MemoryNetwork::new();
+        let keypair0 = crypto::gen_keypair();
+        let keypair1 = crypto::gen_keypair();
+        let (transport0, mut events0) = create(&network, &keypair0);
+        let (transport1, mut events1) = create(&network, &keypair1);
+        let addr0 = transport0.local_addr();
+        let addr1 = transport1.local_addr();
+
+        transport0.connect(&addr1).await.unwrap();
+        assert_matches!(
+            events1.recv().await,
+            Some(TransportEvent::Connected(addr)) => assert_eq!(addr, addr0)
+        );
+        assert_eq!(transport0.peer_key(&addr1), Some(keypair1.public));
+        assert_eq!(transport1.peer_key(&addr0), Some(keypair0.public));
+
+        // Longer than a single Noise message.
+        let msg: Bytes = (0..2 * MAX_NOISE_MSG_LEN).map(|i| i as u8).collect();
+        transport0.send(&addr1, msg.clone()).await.unwrap();
+        transport1.send(&addr0, msg.clone()).await.unwrap();
+        assert_matches!(
+            events1.recv().await,
+            Some(TransportEvent::Received(addr, received)) => {
+                assert_eq!(addr, addr0);
+                assert_eq!(received, msg);
+            }
+        );
+        assert_matches!(
+            events0.recv().await,
+            Some(TransportEvent::Received(addr, received)) => {
+                assert_eq!(addr, addr1);
+                assert_eq!(received, msg);
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rekey() {
+        let network = MemoryNetwork::new();
+        let (transport0, mut events0) = create(&network, &crypto::gen_keypair());
+        let (transport1, _events1) = create(&network, &crypto::gen_keypair());
+        let addr0 = transport0.local_addr();
+        let addr1 = transport1.local_addr();
+
+        transport0.connect(&addr1).await.unwrap();
+
+        let new_keypair = crypto::gen_keypair();
+        transport1.rekey(&new_keypair).unwrap();
+        assert_eq!(transport1.peer_key(&addr0), None);
+        assert_matches!(
+            events0.recv().await,
+            Some(TransportEvent::Disconnected(addr)) => assert_eq!(addr, addr1)
+        );
+
+        transport0.connect(&addr1).await.unwrap();
+        assert_eq!(transport0.peer_key(&addr1), Some(new_keypair.public));
+    }
+
+    #[test]
+    fn replayed_frame() {
+        let addr0 = addr(0);
+        let addr1 = addr(1);
+        let mut shared0 = Shared::new(&crypto::gen_keypair()).unwrap();
+        let mut shared1 = Shared::new(&crypto::gen_keypair()).unwrap();
+
+        let frame = shared0.start_handshake(addr1).unwrap();
+        let frame = reply(shared1.handle_frame(addr0, addr1, frame));
+        let frame = reply(shared0.handle_frame(addr1, addr0, frame));
+        assert!(shared1.handle_frame(addr0, addr1, frame).is_ok());
+
+        let frame = shared0.encrypt(&addr1, b"hello").unwrap();
+        assert_eq!(&shared1.decrypt(&addr0, &frame).unwrap()[..], b"hello");
+        assert!(shared1.decrypt(&addr0, &frame).is_err());
+    }
+
+    #[test]
+    fn data_overtaking_new_handshake() {
+        let addr0 = addr(0);
+        let addr1 = addr(1);
+        let mut shared0 = Shared::new(&crypto::gen_keypair()).unwrap();
+        let mut shared1 = Shared::new(&crypto::gen_keypair()).unwrap();
+
+        let frame = shared0.start_handshake(addr1).unwrap();
+        let frame = reply(shared1.handle_frame(addr0, addr1, frame));
+        let frame = reply(shared0.handle_frame(addr1, addr0, frame));
+        assert!(shared1.handle_frame(addr0, addr1, frame).is_ok());
+
+        // The peer handshakes again while we still have the previous session.
+        let frame = shared0.start_handshake(addr1).unwrap();
+        let frame = reply(shared1.handle_frame(addr0, addr1, frame));
+        let last = reply(shared0.handle_frame(addr1, addr0, frame));
+
+        let data = shared0.encrypt(&addr1, b"hello").unwrap();
+        assert!(matches!(
+            shared1.handle_frame(addr0, addr1, data),
+            Ok(Action::Ignore)
+        ));
+        let events = match shared1.handle_frame(addr0, addr1, last) {
+            Ok(Action::Report(events)) => events,
+            _ => panic!("expected the handshake to complete"),
+        };
+        assert_matches!(
+            events.as_slice(),
+            [TransportEvent::Connected(_), TransportEvent::Received(_, msg)] => {
+                assert_eq!(&msg[..], b"hello")
+            }
+        );
+
+        // Without a handshake in progress, data not authenticated by the session is refused.
+        let data = shared0.encrypt(&addr1, b"hello").unwrap();
+        let mut forged = data.to_vec();
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(shared1
+            .handle_frame(addr0, addr1, Bytes::from(forged))
+            .is_err());
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::new();
+        assert!(window.insert(1));
+        // Out of order.
+        assert!(window.insert(0));
+        assert!(!window.insert(1));
+
+        assert!(window.insert(REPLAY_WINDOW + 1));
+        assert!(window.insert(2));
+        assert!(!window.insert(2));
+        // Too old.
+        assert!(!window.insert(1));
+        assert!(!window.insert(REPLAY_WINDOW + 1));
+    }
+
+    #[test]
+    fn pending_handshakes_limited() {
+        let our_addr = addr(0);
+        let mut shared = Shared::new(&crypto::gen_keypair()).unwrap();
+        let mut peer = Shared::new(&crypto::gen_keypair()).unwrap();
+
+        for port in 1..=MAX_PENDING_HANDSHAKES + 1 {
+            let frame = peer.start_handshake(our_addr).unwrap();
+            let result = shared.handle_frame(addr(port), our_addr, frame);
+            assert_eq!(result.is_ok(), port <= MAX_PENDING_HANDSHAKES);
+        }
+
+        // The handshakes not completed in time make room for the new ones.
+        let started = Instant::now().checked_sub(2 * HANDSHAKE_TIMEOUT).unwrap();
+        for handshake in shared.handshakes.values_mut() {
+            handshake.started = started;
+        }
+
+        let frame = peer.start_handshake(our_addr).unwrap();
+        let port = MAX_PENDING_HANDSHAKES + 2;
+        assert!(shared.handle_frame(addr(port), our_addr, frame).is_ok());
+        assert_eq!(shared.handshakes.len(), 1);
+    }
+
+    #[test]
+    fn forged_identity() {
+        let keypair = crypto::gen_keypair();
+        let static_key = builder().unwrap().generate_keypair().unwrap().public;
+        let other_static_key = builder().unwrap().generate_keypair().unwrap().public;
+
+        let mut payload = keypair.public.to_bytes().to_vec();
+        let signature = crypto::sign(&identity_context(&static_key), &keypair);
+        payload.extend_from_slice(&signature.to_bytes());
+
+        assert_eq!(
+            verify_identity(&payload, Some(&static_key)).unwrap(),
+            keypair.public
+        );
+        assert!(verify_identity(&payload, Some(&other_static_key)).is_err());
+        assert!(verify_identity(&payload[..PUBLIC_KEY_LENGTH], Some(&static_key)).is_err());
+    }
+
+    // Create a transport whose handshakes are driven in the background, returning it together
+    // with its events.
+    fn create(
+        network: &MemoryNetwork,
+        keypair: &Keypair,
+    ) -> (NoiseTransport, mpsc::UnboundedReceiver<TransportEvent>) {
+        let mut transport = NoiseTransport::new(Box::new(network.transport()), keypair).unwrap();
+        let mut events = transport.events();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let _ = task::spawn(async move {
+            while let Some(event) = events.next().await {
+                let _ = tx.send(event);
+            }
+        });
+
+        (transport, rx)
+    }
+
+    fn addr(port: usize) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port as u16))
+    }
+
+    fn reply(action: Result<Action, FrameError>) -> Bytes {
+        match action {
+            Ok(Action::Reply { frame, .. }) => frame,
+            _ => panic!("expected a handshake reply"),
+        }
+    }
+}
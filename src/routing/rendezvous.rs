@@ -0,0 +1,95 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use lru_time_cache::LruCache;
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+// How long to wait for a hole punched to a peer before falling back to relaying.
+pub(crate) const PUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+// How long after a rendezvous with a peer before we try another one with the same peer.
+const RENDEZVOUS_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// NAT traversal of the connections to the peers we can't connect to directly, e.g. because they
+// are behind a NAT that doesn't accept inbound connections.
+//
+// An elder both we and the peer are connected to introduces us to each other with the addresses
+// it sees us at (`ConnectRequest` / `ConnectIntroduction`). We then both connect to each other at
+// the same time, which opens the NATs on both sides (hole punching). If that fails, our messages
+// to the peer are relayed through the elder (`Variant::Relay`).
+pub(crate) struct Rendezvous {
+    // Peers we recently asked to be introduced to.
+    recent: LruCache<SocketAddr, ()>,
+    // Peers we failed to punch a hole to, with the elder relaying our messages to them.
+    relays: HashMap<SocketAddr, SocketAddr>,
+}
+
+impl Rendezvous {
+    pub fn new() -> Self {
+        Self {
+            recent: LruCache::with_expiry_duration(RENDEZVOUS_INTERVAL),
+            relays: HashMap::new(),
+        }
+    }
+
+    // Whether we should ask to be introduced to the given peer, after failing to send to it. Not
+    // if its messages are relayed already or if we asked recently.
+    pub fn start(&mut self, peer: SocketAddr) -> bool {
+        if self.relays.contains_key(&peer) || self.recent.contains_key(&peer) {
+            false
+        } else {
+            let _ = self.recent.insert(peer, ());
+            true
+        }
+    }
+
+    // Record the outcome of punching a hole to the peer introduced by `relay`.
+    pub fn handle_punch_result(&mut self, peer: SocketAddr, relay: SocketAddr, success: bool) {
+        if success {
+            let _ = self.relays.remove(&peer);
+        } else {
+            let _ = self.relays.insert(peer, relay);
+        }
+    }
+
+    // The elder relaying our messages to the given peer, if any.
+    pub fn relay(&self, peer: &SocketAddr) -> Option<SocketAddr> {
+        self.relays.get(peer).copied()
+    }
+
+    // Forget the peer, e.g. because it left the section.
+    pub fn remove(&mut self, peer: &SocketAddr) {
+        let _ = self.recent.remove(peer);
+        let _ = self.relays.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn relay_after_failed_punch() {
+        let mut rendezvous = Rendezvous::new();
+        let peer = gen_addr();
+        let relay = gen_addr();
+
+        assert!(rendezvous.start(peer));
+        // Not again while the first one is recent.
+        assert!(!rendezvous.start(peer));
+
+        rendezvous.handle_punch_result(peer, relay, false);
+        assert_eq!(rendezvous.relay(&peer), Some(relay));
+
+        rendezvous.handle_punch_result(peer, relay, true);
+        assert_eq!(rendezvous.relay(&peer), None);
+
+        rendezvous.remove(&peer);
+        assert!(rendezvous.start(peer));
+    }
+}
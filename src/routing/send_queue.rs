@@ -0,0 +1,328 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::messages::Priority;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::sync::{oneshot, Notify};
+
+// Limits the number of concurrent outgoing sends. Once the limit is reached, the pending sends are
+// resumed by weighted round robin over their priority classes, so bulk user traffic can't delay
+// consensus and membership messages, while lower priority messages still never starve. In
+// addition, a part of the slots is reserved for the other classes, so even long running user data
+// sends can't occupy all of them.
+pub(crate) struct SendQueue {
+    state: Arc<Mutex<State>>,
+}
+
+impl SendQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                capacity,
+                available: capacity,
+                user_data_available: capacity - capacity / 4,
+                pending: Default::default(),
+                credits: weights(),
+                idle: Arc::new(Notify::new()),
+            })),
+        }
+    }
+
+    // Wait until there are no sends in progress or pending.
+    pub async fn wait_idle(&self) {
+        loop {
+            let idle = {
+                let state = lock(&self.state);
+                if state.available == state.capacity {
+                    return;
+                }
+
+                state.idle.clone()
+            };
+
+            idle.notified().await;
+        }
+    }
+
+    // Number of sends in progress, and of the pending ones by priority.
+    pub fn depth(&self) -> (usize, [usize; 4]) {
+        let state = lock(&self.state);
+        let mut pending = [0; 4];
+        for (count, queue) in pending.iter_mut().zip(&state.pending) {
+            *count = queue.len();
+        }
+
+        (state.capacity - state.available, pending)
+    }
+
+    // Wait until a send of the given priority can start. The send can proceed for as long as the
+    // returned permit is kept alive.
+    pub async fn acquire(&self, priority: Priority) -> Permit {
+        loop {
+            let rx = {
+                let mut state = lock(&self.state);
+                if state.available > 0 && state.can_start(priority) {
+                    state.available -= 1;
+                    state.start(priority);
+                    return Permit {
+                        state: self.state.clone(),
+                        priority,
+                    };
+                }
+
+                let (tx, rx) = oneshot::channel();
+                state.pending[priority as usize].push_back(tx);
+                rx
+            };
+
+            if let Ok(permit) = rx.await {
+                return permit;
+            }
+        }
+    }
+}
+
+// Proof that a send slot was acquired. Dropping it hands the slot over to the next pending send.
+pub(crate) struct Permit {
+    state: Arc<Mutex<State>>,
+    priority: Priority,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let (tx, priority) = {
+            let mut state = lock(&self.state);
+            state.finish(self.priority);
+
+            if let Some((tx, priority)) = state.pop_next() {
+                state.start(priority);
+                (tx, priority)
+            } else {
+                state.available += 1;
+                if state.available == state.capacity {
+                    state.idle.notify();
+                }
+                return;
+            }
+        };
+
+        // If the pending send was cancelled in the meantime, the permit is returned and dropped
+        // here, which hands the slot over again.
+        let _ = tx.send(Permit {
+            state: self.state.clone(),
+            priority,
+        });
+    }
+}
+
+struct State {
+    capacity: usize,
+    // Number of sends that can start immediately. Only non-zero when nothing is pending, or when
+    // only user data sends are pending, but they used up their share of the slots.
+    available: usize,
+    // Number of user data sends that can still start before using the reserved slots.
+    user_data_available: usize,
+    // Pending sends, indexed by priority.
+    pending: [VecDeque<oneshot::Sender<Permit>>; 4],
+    // Number of pending sends each priority can still resume in the current round.
+    credits: [usize; 4],
+    // Notified when the last send finishes.
+    idle: Arc<Notify>,
+}
+
+impl State {
+    fn can_start(&self, priority: Priority) -> bool {
+        priority != Priority::UserData || self.user_data_available > 0
+    }
+
+    fn start(&mut self, priority: Priority) {
+        if priority == Priority::UserData {
+            self.user_data_available -= 1;
+        }
+    }
+
+    fn finish(&mut self, priority: Priority) {
+        if priority == Priority::UserData {
+            self.user_data_available += 1;
+        }
+    }
+
+    fn pop_next(&mut self) -> Option<(oneshot::Sender<Permit>, Priority)> {
+        if Priority::ALL.iter().all(|priority| {
+            self.pending[*priority as usize].is_empty() || !self.can_start(*priority)
+        }) {
+            return None;
+        }
+
+        loop {
+            for priority in &Priority::ALL {
+                let index = *priority as usize;
+                if self.credits[index] == 0 || !self.can_start(*priority) {
+                    continue;
+                }
+
+                if let Some(tx) = self.pending[index].pop_front() {
+                    self.credits[index] -= 1;
+                    return Some((tx, *priority));
+                }
+            }
+
+            // All the priorities with pending sends used up their credits. Start a new round.
+            self.credits = weights();
+        }
+    }
+}
+
+fn weights() -> [usize; 4] {
+    let mut weights = [0; 4];
+    for priority in &Priority::ALL {
+        weights[*priority as usize] = priority.weight();
+    }
+    weights
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<State> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::poll;
+
+    #[tokio::test]
+    async fn higher_priority_resumes_first() {
+        let queue = SendQueue::new(1);
+        let permit = queue.acquire(Priority::UserData).await;
+
+        let mut user = Box::pin(queue.acquire(Priority::UserData));
+        let mut consensus = Box::pin(queue.acquire(Priority::Consensus));
+        assert!(poll!(&mut user).is_pending());
+        assert!(poll!(&mut consensus).is_pending());
+
+        drop(permit);
+        assert!(poll!(&mut user).is_pending());
+
+        let permit = consensus.await;
+        drop(permit);
+        let _permit = user.await;
+    }
+
+    #[tokio::test]
+    async fn lower_priority_does_not_starve() {
+        let queue = SendQueue::new(1);
+        let mut permit = queue.acquire(Priority::Consensus).await;
+
+        let mut user = Box::pin(queue.acquire(Priority::UserData));
+        assert!(poll!(&mut user).is_pending());
+
+        let mut consensus: Vec<_> = (0..Priority::Consensus.weight() + 1)
+            .map(|_| Box::pin(queue.acquire(Priority::Consensus)))
+            .collect();
+        for pending in &mut consensus {
+            assert!(poll!(pending).is_pending());
+        }
+
+        // The consensus sends use up their credits for this round, then the user send resumes
+        // before the remaining consensus one.
+        for pending in consensus.drain(..Priority::Consensus.weight()) {
+            drop(permit);
+            permit = pending.await;
+        }
+
+        drop(permit);
+        assert!(poll!(&mut consensus[0]).is_pending());
+        let _permit = user.await;
+    }
+
+    #[tokio::test]
+    async fn cancelled_send_releases_slot() {
+        let queue = SendQueue::new(1);
+        let permit = queue.acquire(Priority::Membership).await;
+
+        let mut cancelled = Box::pin(queue.acquire(Priority::Consensus));
+        assert!(poll!(&mut cancelled).is_pending());
+        drop(cancelled);
+
+        drop(permit);
+        let _permit = queue.acquire(Priority::UserData).await;
+    }
+
+    #[tokio::test]
+    async fn full_queue_holds_back_user_data() {
+        let queue = SendQueue::new(4);
+        let mut permits = vec![
+            queue.acquire(Priority::Consensus).await,
+            queue.acquire(Priority::Membership).await,
+            queue.acquire(Priority::UserData).await,
+            queue.acquire(Priority::UserData).await,
+        ];
+
+        // Every slot is taken, so even user data within its share has to wait.
+        let mut user = Box::pin(queue.acquire(Priority::UserData));
+        let mut consensus = Box::pin(queue.acquire(Priority::Consensus));
+        assert!(poll!(&mut user).is_pending());
+        assert!(poll!(&mut consensus).is_pending());
+
+        // The released slot goes to the higher priority send.
+        drop(permits.pop());
+        let _permit = consensus.await;
+        assert!(poll!(&mut user).is_pending());
+    }
+
+    #[tokio::test]
+    async fn user_data_does_not_use_reserved_slots() {
+        let queue = SendQueue::new(4);
+        let _permits = vec![
+            queue.acquire(Priority::UserData).await,
+            queue.acquire(Priority::UserData).await,
+            queue.acquire(Priority::UserData).await,
+        ];
+
+        let mut user = Box::pin(queue.acquire(Priority::UserData));
+        assert!(poll!(&mut user).is_pending());
+
+        let permit = queue.acquire(Priority::Consensus).await;
+        drop(permit);
+
+        // The reserved slot was released, but it's still not available to user data.
+        assert!(poll!(&mut user).is_pending());
+        let _permit = queue.acquire(Priority::Membership).await;
+    }
+
+    #[tokio::test]
+    async fn wait_idle() {
+        let queue = SendQueue::new(2);
+        queue.wait_idle().await;
+
+        let permit = queue.acquire(Priority::UserData).await;
+        let mut idle = Box::pin(queue.wait_idle());
+        assert!(poll!(&mut idle).is_pending());
+
+        drop(permit);
+        idle.await;
+    }
+
+    #[tokio::test]
+    async fn depth() {
+        let queue = SendQueue::new(1);
+        assert_eq!(queue.depth(), (0, [0; 4]));
+
+        let permit = queue.acquire(Priority::UserData).await;
+        let mut pending = Box::pin(queue.acquire(Priority::Membership));
+        assert!(poll!(&mut pending).is_pending());
+        assert_eq!(queue.depth(), (1, [0, 1, 0, 0]));
+
+        drop(permit);
+        let _permit = pending.await;
+        assert_eq!(queue.depth(), (1, [0; 4]));
+    }
+}
@@ -6,12 +6,29 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{bootstrap, Approved, Comm, Command};
-use crate::{error::Result, event::Event, relocation::SignedRelocateDetails};
-use sn_messaging::{section_info::Error as TargetSectionError, MessageType};
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use super::{
+    bandwidth::TrafficCategory, bootstrap, event_stream::EventBuffer, liveness::PROBE_TIMEOUT,
+    rendezvous::PUNCH_TIMEOUT, storage, Approved, Comm, Command,
+};
+use crate::{
+    error::{Error, Result},
+    event::Event,
+    messages::{Message, Priority},
+    relocation::SignedRelocateDetails,
+};
+use bytes::Bytes;
+use futures::future;
+use sn_messaging::{node::NodeMessage, section_info::Error as TargetSectionError, MessageType};
+use std::{
+    fmt::Write as _,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
-    sync::{mpsc, watch, Mutex},
+    sync::{mpsc, oneshot, watch, Mutex},
     time,
 };
 use tracing::Instrument;
@@ -21,6 +38,9 @@ use tracing::Instrument;
 pub(crate) struct Stage {
     pub(super) state: Mutex<Approved>,
     pub(super) comm: Comm,
+    event_buffer: Option<EventBuffer>,
+    // Where to persist the keypair of the node, so the new one is persisted after relocation.
+    identity_path: Option<PathBuf>,
 
     cancel_timer_tx: watch::Sender<bool>,
     cancel_timer_rx: watch::Receiver<bool>,
@@ -36,11 +56,26 @@ impl Stage {
         Self {
             state: Mutex::new(state),
             comm,
+            event_buffer: None,
+            identity_path: None,
             cancel_timer_tx,
             cancel_timer_rx,
         }
     }
 
+    /// Applies the backpressure of the given event buffer to the commands handled in the
+    /// background: with `EventOverflow::Block`, they wait until the buffer is not full.
+    pub fn with_event_buffer(mut self, event_buffer: EventBuffer) -> Self {
+        self.event_buffer = Some(event_buffer);
+        self
+    }
+
+    /// Persists the new keypair of the node to the given path when it gets relocated.
+    pub fn with_identity_path(mut self, identity_path: PathBuf) -> Self {
+        self.identity_path = Some(identity_path);
+        self
+    }
+
     /// Send provided Event to the user which shall receive it through the EventStream
     pub async fn send_event(&self, event: Event) {
         self.state.lock().await.send_event(event)
@@ -59,25 +94,13 @@ impl Stage {
 
     /// Handles a single command.
     pub async fn handle_command(&self, command: Command) -> Result<Vec<Command>> {
-        // Create a tracing span containing info about the current node. This is very useful when
-        // analyzing logs produced by running multiple nodes within the same process, for example
-        // from integration tests.
-        let span = {
-            let state = self.state.lock().await;
-            trace_span!(
-                "handle_command",
-                name = %state.node().name(),
-                prefix = format_args!("({:b})", state.section().prefix()),
-                age = state.node().age,
-                elder = state.is_elder(),
-            )
-        };
+        let span = self.state.lock().await.span();
 
         async {
             trace!(?command);
 
             self.try_handle_command(command).await.map_err(|error| {
-                error!("Error encountered when handling command: {}", error);
+                error_limited!("Error encountered when handling command: {}", error);
                 error
             })
         }
@@ -92,6 +115,24 @@ impl Stage {
         self.comm.terminate()
     }
 
+    // Ask our section to let us leave, then wait until it's acknowledged (or `timeout` elapses)
+    // and until all our outgoing messages are sent.
+    pub async fn leave(self: Arc<Self>, timeout: Duration) -> Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let commands = self.state.lock().await.leave(ack_tx)?;
+        for command in commands {
+            self.clone().handle_commands(command).await?;
+        }
+
+        if time::timeout(timeout, ack_rx).await.is_err() {
+            warn!("Leave not acknowledged within {:?}", timeout);
+        }
+
+        self.comm.flush().await;
+
+        Ok(())
+    }
+
     async fn try_handle_command(&self, command: Command) -> Result<Vec<Command>> {
         match command {
             Command::HandleMessage { sender, message } => {
@@ -101,6 +142,13 @@ impl Stage {
                     .handle_message(sender, message)
                     .await
             }
+            Command::ReplayMessage { sender, message } => {
+                self.state
+                    .lock()
+                    .await
+                    .replay_message(sender, message)
+                    .await
+            }
             Command::HandleSectionInfoMsg { sender, message } => Ok(self
                 .state
                 .lock()
@@ -143,13 +191,41 @@ impl Stage {
                 recipients,
                 delivery_group_size,
                 message,
+                priority,
             } => {
-                self.send_message(&recipients, delivery_group_size, message)
+                self.send_message(&recipients, delivery_group_size, message, priority)
                     .await
             }
-            Command::SendUserMessage { src, dst, content } => {
-                self.state.lock().await.send_user_message(src, dst, content)
+            Command::SendUserMessage {
+                src,
+                dst,
+                content,
+                correlation_id,
+                traced,
+            } => {
+                self.state
+                    .lock()
+                    .await
+                    .send_user_message(src, dst, content, correlation_id, traced)
             }
+            Command::MulticastUserMessage {
+                src,
+                prefix,
+                content,
+            } => self
+                .state
+                .lock()
+                .await
+                .multicast_user_message(src, prefix, content),
+            Command::AnycastUserMessage {
+                target,
+                content,
+                correlation_id,
+            } => self
+                .state
+                .lock()
+                .await
+                .anycast_user_message(target, content, correlation_id),
             Command::ScheduleTimeout { duration, token } => Ok(self
                 .handle_schedule_timeout(duration, token)
                 .await
@@ -166,13 +242,61 @@ impl Stage {
             Command::SetJoinsAllowed(joins_allowed) => {
                 self.state.lock().await.set_joins_allowed(joins_allowed)
             }
+            Command::Punch { peer, relay } => {
+                let success = matches!(
+                    time::timeout(PUNCH_TIMEOUT, self.comm.connect(&peer)).await,
+                    Ok(Ok(()))
+                );
+                self.state
+                    .lock()
+                    .await
+                    .handle_punch_result(peer, relay, success);
+                Ok(vec![])
+            }
+            Command::Probe(peers) => {
+                let results = self.probe(peers).await?;
+                self.state.lock().await.handle_probe_results(results)
+            }
+            Command::Blacklist { entry, violation } => {
+                self.comm.blacklist().report(entry, violation);
+                Ok(vec![])
+            }
         }
     }
 
     // Note: this indirecton is needed. Trying to call `spawn(self.handle_commands(...))` directly
     // inside `handle_commands` causes compile error about type check cycle.
     fn spawn_handle_commands(self: Arc<Self>, command: Command) {
-        let _ = tokio::spawn(self.handle_commands(command));
+        let _ = tokio::spawn(async move {
+            self.wait_for_event_buffer().await;
+            self.handle_commands(command).await
+        });
+    }
+
+    /// Write the dump of the state of the node, followed by its connections, into the file at
+    /// `path`.
+    pub async fn dump_state(&self, path: &Path) -> Result<()> {
+        let mut dump = self.state.lock().await.dump_state();
+
+        let _ = writeln!(dump, "\n# Connections");
+        for (addr, bandwidth) in self.comm.bandwidth().all() {
+            let _ = writeln!(dump, "{} {:?}", addr, bandwidth);
+        }
+
+        fs::write(path, dump).map_err(Error::DumpState)?;
+        Ok(())
+    }
+
+    /// Number of events waiting for the consumer, if they are buffered with a bound.
+    pub fn pending_events(&self) -> Option<usize> {
+        self.event_buffer.as_ref().map(EventBuffer::len)
+    }
+
+    /// Waits until the consumer of the events catches up, if configured to do so.
+    pub async fn wait_for_event_buffer(&self) {
+        if let Some(event_buffer) = &self.event_buffer {
+            event_buffer.wait_for_space().await
+        }
     }
 
     pub async fn check_key_status(
@@ -187,13 +311,30 @@ impl Stage {
         recipients: &[SocketAddr],
         delivery_group_size: usize,
         message: MessageType,
+        priority: Priority,
     ) -> Result<Vec<Command>> {
         let msg_bytes = message.serialize()?;
 
         let cmds = match message {
-            MessageType::Ping | MessageType::NodeMessage(_) => self
+            MessageType::NodeMessage(NodeMessage(message)) => {
+                self.send_node_message(
+                    recipients,
+                    delivery_group_size,
+                    message,
+                    msg_bytes,
+                    priority,
+                )
+                .await?
+            }
+            MessageType::Ping => self
                 .comm
-                .send(recipients, delivery_group_size, msg_bytes)
+                .send(
+                    recipients,
+                    delivery_group_size,
+                    msg_bytes,
+                    priority,
+                    TrafficCategory::Gossip,
+                )
                 .await
                 .1
                 .into_iter()
@@ -203,7 +344,12 @@ impl Stage {
                 for recipient in recipients {
                     if self
                         .comm
-                        .send_on_existing_connection(recipient, msg_bytes.clone())
+                        .send_on_existing_connection(
+                            recipient,
+                            msg_bytes.clone(),
+                            priority,
+                            TrafficCategory::Client,
+                        )
                         .await
                         .is_err()
                     {
@@ -216,7 +362,12 @@ impl Stage {
                 for recipient in recipients {
                     let _ = self
                         .comm
-                        .send_on_existing_connection(recipient, msg_bytes.clone())
+                        .send_on_existing_connection(
+                            recipient,
+                            msg_bytes.clone(),
+                            priority,
+                            TrafficCategory::Client,
+                        )
                         .await;
                 }
                 vec![]
@@ -226,6 +377,128 @@ impl Stage {
         Ok(cmds)
     }
 
+    // Send the node message directly to the recipients, except those we failed to punch a hole
+    // to which get it through their relay.
+    async fn send_node_message(
+        &self,
+        recipients: &[SocketAddr],
+        delivery_group_size: usize,
+        message: Bytes,
+        msg_bytes: Bytes,
+        priority: Priority,
+    ) -> Result<Vec<Command>> {
+        let mut direct = Vec::with_capacity(recipients.len());
+        let mut relayed = vec![];
+        {
+            let state = self.state.lock().await;
+            for recipient in recipients {
+                if let Some(relay) = state.relay_for(recipient) {
+                    let wrapped = state.wrap_for_relay(*recipient, message.clone())?;
+                    relayed.push((*recipient, relay, wrapped));
+                } else {
+                    direct.push(*recipient);
+                }
+            }
+        }
+
+        if let Some(variant) = Message::variant_name_of(&message) {
+            self.comm.stats().record_sent(variant);
+        }
+
+        let category = TrafficCategory::of_node_message(&message);
+        let relayed_count = relayed.len().min(delivery_group_size);
+        let mut commands = vec![];
+
+        for (recipient, relay, wrapped) in relayed.into_iter().take(delivery_group_size) {
+            let wrapped = MessageType::NodeMessage(NodeMessage::new(wrapped)).serialize()?;
+            if self
+                .comm
+                .send(&[relay], 1, wrapped, priority, TrafficCategory::Relay)
+                .await
+                .0
+                .is_err()
+            {
+                commands.push(Command::HandlePeerLost(recipient));
+            }
+        }
+
+        if direct.is_empty() {
+            return Ok(commands);
+        }
+
+        commands.extend(
+            self.comm
+                .send(
+                    &direct,
+                    delivery_group_size - relayed_count,
+                    msg_bytes,
+                    priority,
+                    category,
+                )
+                .await
+                .1
+                .into_iter()
+                .map(Command::HandlePeerLost),
+        );
+
+        Ok(commands)
+    }
+
+    // Send a probe to each of the peers and measure how long it takes until its ack arrives.
+    // `None` for the peers that failed to acknowledge it within `PROBE_TIMEOUT`.
+    async fn probe(&self, peers: Vec<SocketAddr>) -> Result<Vec<(SocketAddr, Option<Duration>)>> {
+        let mut pending = Vec::with_capacity(peers.len());
+        {
+            let mut state = self.state.lock().await;
+            for peer in peers {
+                let (nonce, msg_bytes, ack_rx) = state.start_probe(peer)?;
+                let msg_bytes =
+                    MessageType::NodeMessage(NodeMessage::new(msg_bytes)).serialize()?;
+                pending.push((peer, nonce, msg_bytes, ack_rx));
+            }
+        }
+
+        let nonces: Vec<_> = pending.iter().map(|(_, nonce, ..)| *nonce).collect();
+        let probes = pending
+            .into_iter()
+            .map(|(peer, _, msg_bytes, ack_rx)| async move {
+                let start = Instant::now();
+                let round_trip = async move {
+                    let (result, _) = self
+                        .comm
+                        .send(
+                            &[peer],
+                            1,
+                            msg_bytes,
+                            Priority::Membership,
+                            TrafficCategory::Gossip,
+                        )
+                        .await;
+                    result.is_ok() && ack_rx.await.is_ok()
+                };
+                let rtt = match time::timeout(PROBE_TIMEOUT, round_trip).await {
+                    Ok(true) => Some(start.elapsed()),
+                    Ok(false) | Err(_) => None,
+                };
+                (peer, rtt)
+            });
+        let results = future::join_all(probes).await;
+
+        // The probes are sent directly, so these are the round trips of single links.
+        for rtt in results.iter().filter_map(|(_, rtt)| *rtt) {
+            self.comm
+                .stats()
+                .record_latency(Priority::Membership.name(), rtt);
+        }
+
+        let mut state = self.state.lock().await;
+        for nonce in nonces {
+            state.finish_probe(nonce);
+        }
+
+        Ok(results)
+    }
+
     async fn handle_schedule_timeout(&self, duration: Duration, token: u64) -> Option<Command> {
         let mut cancel_rx = self.cancel_timer_rx.clone();
 
@@ -246,16 +519,50 @@ impl Stage {
         details: SignedRelocateDetails,
         message_rx: mpsc::Receiver<(MessageType, SocketAddr)>,
     ) -> Result<Vec<Command>> {
-        let node = self.state.lock().await.node().clone();
+        let (node, network_id) = {
+            let state = self.state.lock().await;
+            (state.node().clone(), state.network_params().network_id)
+        };
         let previous_name = node.name();
 
-        let (node, section, backlog) =
-            bootstrap::relocate(node, &self.comm, message_rx, bootstrap_addrs, details).await?;
+        let (node, section, backlog) = bootstrap::relocate(
+            node,
+            &self.comm,
+            message_rx,
+            bootstrap_addrs,
+            details,
+            network_id,
+        )
+        .await?;
+
+        // Otherwise the node would restart with its identity from before the relocation.
+        if let Some(path) = &self.identity_path {
+            if let Err(error) = storage::store_keypair(path, &node.keypair) {
+                error!("Failed to persist the keypair after relocation: {}", error);
+            }
+        }
 
         let mut state = self.state.lock().await;
         let event_tx = state.event_tx.clone();
         let new_keypair = node.keypair.clone();
-        *state = Approved::new(node, section, None, event_tx);
+        let network_params = *state.network_params();
+        let client_rate_limits = *state.client_rate_limits();
+        let join_proof = state.take_join_proof();
+        let storage = state.take_storage();
+        let audit_log = state.take_audit_log();
+        *state = Approved::new(node, section, None, event_tx)
+            .with_network_params(network_params)
+            .with_client_rate_limits(client_rate_limits)
+            .with_join_proof(join_proof)
+            .with_stats(self.comm.stats().clone())
+            .with_audit_log(audit_log);
+
+        if let Some(storage) = storage {
+            state.store_to(storage);
+        }
+
+        // The timers of the previous state are ignored by the new one, so restart them.
+        let periodic_timers = state.schedule_periodic_timers();
 
         state.send_event(Event::Relocated {
             previous_name,
@@ -268,6 +575,7 @@ impl Stage {
                 message,
                 sender: Some(sender),
             })
+            .chain(periodic_timers)
             .collect();
         Ok(commands)
     }
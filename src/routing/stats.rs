@@ -0,0 +1,256 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use std::{
+    collections::BTreeMap,
+    mem,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+use xor_name::Prefix;
+
+/// Counts of the node messages, signature accumulations and membership changes handled by the
+/// node since it started, or since the last `Routing::take_stats`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Node messages sent, by the name of their variant. A message sent to several recipients at
+    /// once counts once.
+    pub sent: BTreeMap<&'static str, u64>,
+    /// Node messages received, by the name of their variant.
+    pub received: BTreeMap<&'static str, u64>,
+    /// Received messages relayed closer to their destination or to the rest of our section.
+    pub relayed: u64,
+    /// Signature shares that completed the signature of a vote or a message.
+    pub accumulation_successes: u64,
+    /// Signature shares that failed to accumulate, being invalid, and votes and messages that
+    /// expired before accumulating enough shares.
+    pub accumulation_failures: u64,
+    /// Membership changes observed in our section, by the prefix the section had at the time.
+    pub churn: BTreeMap<Prefix, ChurnStats>,
+    /// Round trips of our messages, by the name of their priority class, measured by our own
+    /// clock. Not one-way latencies, as those would depend on the clocks of the other nodes
+    /// agreeing with ours. Only the messages acknowledged to us are measured: the user messages
+    /// (`UserData`), from their send to their ack, unless sent again, and the probes of our peers
+    /// (`Membership`). The `Consensus` and `SectionKnowledge` classes are never acknowledged, so
+    /// they have no entry.
+    pub latency: BTreeMap<&'static str, LatencyHistogram>,
+}
+
+/// Upper bounds of the buckets of a `LatencyHistogram`, in milliseconds. The last bucket has no
+/// upper bound.
+pub const LATENCY_BUCKETS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/// Histogram of message latencies.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LatencyHistogram {
+    /// Number of latencies in each bucket, the upper bounds of which are in `LATENCY_BUCKETS`,
+    /// followed by the number of latencies exceeding the largest bound.
+    pub counts: [u64; LATENCY_BUCKETS.len() + 1],
+    /// Sum of all the latencies.
+    pub sum: Duration,
+}
+
+impl LatencyHistogram {
+    /// Number of latencies in the histogram.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Mean latency, if any.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(self.sum / count as u32),
+        }
+    }
+
+    /// Upper bound of the bucket containing the `quantile` (between 0 and 1) of the latencies,
+    /// e.g. 0.99 for the 99th percentile. `None` if there are no latencies, or if the quantile
+    /// falls in the last, unbounded bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((quantile.max(0.0).min(1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, bucket_count) in LATENCY_BUCKETS.iter().zip(&self.counts) {
+            seen += bucket_count;
+            if seen >= rank {
+                return Some(Duration::from_millis(*bound));
+            }
+        }
+
+        None
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let millis = latency.as_millis();
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| millis <= u128::from(*bound))
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counts[index] += 1;
+        self.sum += latency;
+    }
+}
+
+/// Membership changes of a section.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ChurnStats {
+    /// Nodes that joined the section, relocated ones included.
+    pub joins: ChurnCounter,
+    /// Members that left the section, not counting the relocated ones.
+    pub leaves: ChurnCounter,
+    /// Members relocated to another section.
+    pub relocations: ChurnCounter,
+    /// Splits of the section into two.
+    pub splits: ChurnCounter,
+}
+
+/// Number of membership changes of one kind, and when the last one happened.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ChurnCounter {
+    /// Number of changes.
+    pub count: u64,
+    /// Time of the last change, if any.
+    pub last: Option<SystemTime>,
+}
+
+impl ChurnCounter {
+    fn record(&mut self) {
+        self.count += 1;
+        self.last = Some(SystemTime::now());
+    }
+}
+
+/// Kind of membership change, for `StatsRecorder::record_churn`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ChurnKind {
+    Join,
+    Leave,
+    Relocation,
+    Split,
+}
+
+// Records the `Stats` of the node. Shared between the communication layer and the node state, so
+// the stats survive the relocation of the node.
+#[derive(Clone, Default)]
+pub(crate) struct StatsRecorder(Arc<Mutex<Stats>>);
+
+impl StatsRecorder {
+    pub fn record_sent(&self, variant: &'static str) {
+        *self.lock().sent.entry(variant).or_default() += 1;
+    }
+
+    pub fn record_received(&self, variant: &'static str) {
+        *self.lock().received.entry(variant).or_default() += 1;
+    }
+
+    pub fn record_relayed(&self) {
+        self.lock().relayed += 1;
+    }
+
+    pub fn record_accumulation(&self, success: bool) {
+        let mut stats = self.lock();
+        if success {
+            stats.accumulation_successes += 1;
+        } else {
+            stats.accumulation_failures += 1;
+        }
+    }
+
+    pub fn record_churn(&self, prefix: Prefix, kind: ChurnKind) {
+        let mut stats = self.lock();
+        let churn = stats.churn.entry(prefix).or_default();
+        match kind {
+            ChurnKind::Join => churn.joins.record(),
+            ChurnKind::Leave => churn.leaves.record(),
+            ChurnKind::Relocation => churn.relocations.record(),
+            ChurnKind::Split => churn.splits.record(),
+        }
+    }
+
+    pub fn record_latency(&self, priority: &'static str, latency: Duration) {
+        self.lock()
+            .latency
+            .entry(priority)
+            .or_default()
+            .record(latency)
+    }
+
+    pub fn get(&self) -> Stats {
+        self.lock().clone()
+    }
+
+    // Returns the stats recorded so far and starts recording from zero again.
+    pub fn take(&self) -> Stats {
+        mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<Stats> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_resets() {
+        let recorder = StatsRecorder::default();
+        recorder.record_sent("Vote");
+        recorder.record_sent("Vote");
+        recorder.record_received("Sync");
+        recorder.record_relayed();
+        recorder.record_accumulation(true);
+        recorder.record_accumulation(false);
+        recorder.record_churn(Prefix::default(), ChurnKind::Join);
+        recorder.record_churn(Prefix::default(), ChurnKind::Join);
+        recorder.record_churn(Prefix::default(), ChurnKind::Split);
+
+        let stats = recorder.take();
+        assert_eq!(stats.sent.get("Vote"), Some(&2));
+        assert_eq!(stats.received.get("Sync"), Some(&1));
+        assert_eq!(stats.relayed, 1);
+        assert_eq!(stats.accumulation_successes, 1);
+        assert_eq!(stats.accumulation_failures, 1);
+
+        let churn = stats.churn[&Prefix::default()];
+        assert_eq!(churn.joins.count, 2);
+        assert!(churn.joins.last.is_some());
+        assert_eq!(churn.splits.count, 1);
+        assert_eq!(churn.leaves, ChurnCounter::default());
+
+        assert_eq!(recorder.get(), Stats::default());
+    }
+
+    #[test]
+    fn latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.quantile(0.5), None);
+
+        for millis in &[1, 3, 3, 40, 10_000] {
+            histogram.record(Duration::from_millis(*millis));
+        }
+
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.counts[0], 1);
+        assert_eq!(histogram.counts[2], 2);
+        assert_eq!(histogram.counts[5], 1);
+        assert_eq!(histogram.counts[LATENCY_BUCKETS.len()], 1);
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(2_009_400)));
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.quantile(0.75), Some(Duration::from_millis(50)));
+        assert_eq!(histogram.quantile(1.0), None);
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    consensus::{ProofShare, Vote},
+    crypto,
+    error::{Error, Result},
+    network::Network,
+    section::Section,
+};
+use ed25519_dalek::Keypair;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::task;
+
+const DIGEST_LEN: usize = 32;
+
+// Persistent storage of the section and network knowledge of the node, so it can be recovered
+// after restart.
+//
+// The stored file consists of the SHA3-256 digest of the payload followed by the payload itself
+// which is the bincode-serialized `StoredState`.
+pub(crate) struct Storage {
+    path: PathBuf,
+    // Sequence number of the last state handed to `store_in_background`.
+    next_seq: AtomicU64,
+    // Sequence number of the last state written by `store_in_background`. Also serializes the
+    // background writes, as they all go through the same temporary file.
+    written_seq: Arc<Mutex<u64>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StoredState {
+    pub section: Section,
+    pub network: Network,
+    // Shares of the votes that didn't reach quorum yet.
+    pub votes: Vec<(Vote, ProofShare)>,
+}
+
+impl Storage {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            next_seq: AtomicU64::new(0),
+            written_seq: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Store the given state, replacing any previously stored one. The state is first written to
+    // a temporary file which is then atomically renamed, so a crash in the middle of storing
+    // never leaves a partially written state behind.
+    pub fn store(&self, state: &StoredState) -> Result<()> {
+        let payload = bincode::serialize(state)?;
+        write_with_digest(&self.path, &payload)
+    }
+
+    // Like `store`, but serializes and writes the state on the blocking thread pool so the caller
+    // is not held up by the disk. Writes that complete after a more recent one are skipped, so
+    // the newest state always wins.
+    pub fn store_in_background(&self, state: StoredState) {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        let written_seq = self.written_seq.clone();
+        let path = self.path.clone();
+
+        let _ = task::spawn_blocking(move || {
+            let mut written_seq = match written_seq.lock() {
+                Ok(written_seq) => written_seq,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+
+            if *written_seq > seq {
+                return;
+            }
+
+            let result = bincode::serialize(&state)
+                .map_err(Error::from)
+                .and_then(|payload| write_with_digest(&path, &payload));
+
+            match result {
+                Ok(()) => *written_seq = seq,
+                Err(error) => error!("Failed to store state to {}: {}", path.display(), error),
+            }
+        });
+    }
+
+    // Load the previously stored state. Returns `Ok(None)` if nothing was stored yet and
+    // `Error::InvalidStoredState` if the stored state is corrupted or fails verification.
+    pub fn load(&self) -> Result<Option<StoredState>> {
+        let payload = if let Some(payload) = read_with_digest(&self.path)? {
+            payload
+        } else {
+            return Ok(None);
+        };
+
+        let state: StoredState =
+            bincode::deserialize(&payload).map_err(|_| Error::InvalidStoredState)?;
+
+        if !state.section.chain().self_verify()
+            || !state
+                .section
+                .proven_elders_info()
+                .verify(state.section.chain())
+        {
+            return Err(Error::InvalidStoredState);
+        }
+
+        Ok(Some(state))
+    }
+}
+
+// Load the node keypair stored at `path`, or generate a new one and store it there if there is
+// none yet, so the node keeps its identity across restarts.
+pub(crate) fn load_or_create_keypair(path: &Path) -> Result<Keypair> {
+    if let Some(bytes) = read_with_digest(path)? {
+        return Keypair::from_bytes(&bytes).map_err(|_| Error::InvalidStoredState);
+    }
+
+    let keypair = crypto::gen_keypair();
+    store_keypair(path, &keypair)?;
+
+    Ok(keypair)
+}
+
+// Store the node keypair at `path`, replacing the one stored there, e.g. after relocation. The file
+// contains the secret key, so on unix it's created readable by its owner only.
+pub(crate) fn store_keypair(path: &Path, keypair: &Keypair) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let _ = options.mode(0o600);
+    }
+
+    write_with_digest_using(path, &keypair.to_bytes(), options)
+}
+
+// Write the SHA3-256 digest of `payload` followed by the payload itself to `path`. The bytes are
+// first written to a temporary file which is then atomically renamed, so a crash in the middle of
+// writing never leaves a partially written file behind.
+pub(crate) fn write_with_digest(path: &Path, payload: &[u8]) -> Result<()> {
+    write_with_digest_using(path, payload, fs::OpenOptions::new())
+}
+
+// Like `write_with_digest`, but creates the temporary file with the given `options`, e.g. to
+// restrict its permissions from the start.
+fn write_with_digest_using(
+    path: &Path,
+    payload: &[u8],
+    mut options: fs::OpenOptions,
+) -> Result<()> {
+    let digest = crypto::sha3_256(payload);
+
+    let mut bytes = Vec::with_capacity(DIGEST_LEN + payload.len());
+    bytes.extend_from_slice(&digest);
+    bytes.extend_from_slice(payload);
+
+    // A leftover temporary file would keep its permissions, so always create a new one.
+    let tmp_path = path.with_extension("tmp");
+    match fs::remove_file(&tmp_path) {
+        Ok(()) => (),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => return Err(Error::Storage(error)),
+    }
+
+    let mut file = options
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .map_err(Error::Storage)?;
+    file.write_all(&bytes).map_err(Error::Storage)?;
+    drop(file);
+    fs::rename(&tmp_path, path).map_err(Error::Storage)?;
+
+    Ok(())
+}
+
+// Read the payload written by `write_with_digest`. Returns `Ok(None)` if the file doesn't exist
+// and `Error::InvalidStoredState` if the digest doesn't match.
+pub(crate) fn read_with_digest(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(Error::Storage(error)),
+    };
+
+    if bytes.len() < DIGEST_LEN {
+        return Err(Error::InvalidStoredState);
+    }
+
+    let payload = bytes.split_off(DIGEST_LEN);
+    if bytes[..] != crypto::sha3_256(&payload)[..] {
+        return Err(Error::InvalidStoredState);
+    }
+
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::test_utils::proven,
+        section::{test_utils::gen_elders_info, SectionProofChain},
+    };
+    use anyhow::Result;
+    use assert_matches::assert_matches;
+    use xor_name::Prefix;
+
+    #[test]
+    fn store_and_load() -> Result<()> {
+        let storage = Storage::new(temp_path("store_and_load"));
+        assert!(storage.load()?.is_none());
+
+        let state = gen_state()?;
+        storage.store(&state)?;
+
+        let loaded = storage.load()?.expect("state not stored");
+        assert_eq!(loaded.section, state.section);
+        assert_eq!(loaded.network, state.network);
+
+        fs::remove_file(storage.path())?;
+        Ok(())
+    }
+
+    #[test]
+    fn load_corrupted() -> Result<()> {
+        let storage = Storage::new(temp_path("load_corrupted"));
+
+        storage.store(&gen_state()?)?;
+
+        let mut bytes = fs::read(storage.path())?;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(storage.path(), bytes)?;
+
+        assert_matches!(storage.load(), Err(Error::InvalidStoredState));
+
+        fs::remove_file(storage.path())?;
+        Ok(())
+    }
+
+    #[test]
+    fn keypair_persisted() -> Result<()> {
+        let path = temp_path("keypair_persisted");
+
+        let keypair = load_or_create_keypair(&path)?;
+        let loaded = load_or_create_keypair(&path)?;
+        assert_eq!(loaded.to_bytes()[..], keypair.to_bytes()[..]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+
+        // A new keypair, e.g. after relocation, replaces the stored one.
+        let new_keypair = crypto::gen_keypair();
+        store_keypair(&path, &new_keypair)?;
+        let loaded = load_or_create_keypair(&path)?;
+        assert_eq!(loaded.to_bytes()[..], new_keypair.to_bytes()[..]);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_in_background_keeps_newest() -> Result<()> {
+        let storage = Storage::new(temp_path("store_in_background_keeps_newest"));
+
+        let old = gen_state()?;
+        let new = gen_state()?;
+        storage.store_in_background(old);
+        storage.store_in_background(StoredState {
+            section: new.section.clone(),
+            network: new.network.clone(),
+            votes: vec![],
+        });
+
+        // Wait for both writes to complete, as they are serialized by this lock.
+        while *storage.written_seq.lock().unwrap() < 2 {
+            task::yield_now().await;
+        }
+
+        let loaded = storage.load()?.expect("state not stored");
+        assert_eq!(loaded.section, new.section);
+
+        fs::remove_file(storage.path())?;
+        Ok(())
+    }
+
+    fn gen_state() -> Result<StoredState> {
+        let sk = bls::SecretKey::random();
+        let chain = SectionProofChain::new(sk.public_key());
+        let (elders_info, _) = gen_elders_info(Prefix::default(), 3);
+        let elders_info = proven(&sk, elders_info)?;
+        let section = Section::new(chain, elders_info)?;
+
+        Ok(StoredState {
+            section,
+            network: Network::new(),
+            votes: vec![],
+        })
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "sn_routing_storage_{}_{}",
+            name,
+            rand::random::<u64>()
+        ))
+    }
+}
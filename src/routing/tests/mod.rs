@@ -6,41 +6,61 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+mod test_network;
+mod topology;
+
+use self::test_network::TestNetwork;
 use super::{
-    approved::{RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY},
-    Approved, Comm, Command, Stage,
+    blacklist::BlacklistEntry,
+    comm::ConnectionEvent,
+    delivery_tracker::{ACK_TIMEOUT, MAX_DELIVERY_ATTEMPTS},
+    join_challenges::ResourceProofConfig,
+    join_proof::{JoinProof, ResourceProofJoin},
+    transport::{QuicTransport, Transport},
+    Approved, Comm, Command, MemoryNetwork, NoiseTransport, Stage,
 };
 use crate::{
     consensus::{test_utils::*, Proven, Vote},
-    crypto,
+    correlation_id::CorrelationId,
+    crypto, delivery_group,
     event::Event,
     majority,
-    messages::{JoinRequest, Message, PlainMessage, ResourceProofResponse, Variant, VerifyStatus},
+    message_size_limits::MessageSizeLimits,
+    messages::{
+        JoinRequest, Message, Nonce, PlainMessage, Priority, Variant, VerifyStatus, MAX_CLOCK_SKEW,
+        MAX_FRAGMENT_SIZE, MAX_NONCE_AGE, PROTOCOL_VERSION,
+    },
     network::Network,
+    network_params::NetworkParams,
     node::Node,
     peer::Peer,
     relocation::{self, RelocateDetails, RelocatePayload, SignedRelocateDetails},
     section::{
         test_utils::*, EldersInfo, MemberInfo, PeerState, Section, SectionKeyShare,
-        SectionProofChain, MIN_AGE,
+        SectionProofChain, CHECKPOINT_INTERVAL, MIN_AGE,
     },
+    xor_name_ext::XorNameExt,
     Error, ELDER_SIZE,
 };
 use anyhow::Result;
 use assert_matches::assert_matches;
 use bls_signature_aggregator::Proof;
 use bytes::Bytes;
-use resource_proof::ResourceProof;
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use sn_data_types::PublicKey as EndUserPK;
 use sn_messaging::{
     node::NodeMessage,
     section_info::{GetSectionResponse, Message as SectionInfoMsg},
-    DstLocation, MessageType, SrcLocation,
+    DstLocation, EndUser, MessageType, SrcLocation,
 };
 use std::{
     collections::{BTreeSet, HashSet},
     iter,
     net::Ipv4Addr,
     ops::Deref,
+    sync::Arc,
+    time::Duration,
 };
 use tokio::sync::mpsc;
 use xor_name::{Prefix, XorName};
@@ -144,9 +164,11 @@ async fn receive_join_request_without_resource_proof_response() -> Result<()> {
         &new_node,
         DstLocation::Direct,
         Variant::JoinRequest(Box::new(JoinRequest {
+            protocol_version: PROTOCOL_VERSION,
+            network_id: 0,
             section_key,
             relocate_payload: None,
-            resource_proof_response: None,
+            join_proof_response: None,
         })),
         None,
         None,
@@ -165,16 +187,13 @@ async fn receive_join_request_without_resource_proof_response() -> Result<()> {
     );
     let response_message = Message::from_bytes(Bytes::from(response_message))?;
 
-    assert_matches!(
-        response_message.variant(),
-        Variant::ResourceChallenge { .. }
-    );
+    assert_matches!(response_message.variant(), Variant::JoinChallenge { .. });
 
     Ok(())
 }
 
 #[tokio::test]
-async fn receive_join_request_with_resource_proof_response() -> Result<()> {
+async fn receive_join_request_for_other_network() -> Result<()> {
     let node = create_node();
     let state = Approved::first_node(node, mpsc::unbounded_channel().0)?;
     let stage = Stage::new(state, create_comm().await?);
@@ -182,31 +201,127 @@ async fn receive_join_request_with_resource_proof_response() -> Result<()> {
     let new_node = Node::new(crypto::gen_keypair(), gen_addr());
     let section_key = *stage.state.lock().await.section().chain().last_key();
 
-    let nonce: [u8; 32] = rand::random();
-    let serialized = bincode::serialize(&(new_node.name(), nonce))?;
-    let nonce_signature = crypto::sign(&serialized, &stage.state.lock().await.node().keypair);
+    let message = Message::single_src(
+        &new_node,
+        DstLocation::Direct,
+        Variant::JoinRequest(Box::new(JoinRequest {
+            protocol_version: PROTOCOL_VERSION,
+            network_id: 1,
+            section_key,
+            relocate_payload: None,
+            join_proof_response: None,
+        })),
+        None,
+        None,
+    )?;
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            sender: Some(new_node.addr),
+            message,
+        })
+        .await?;
+
+    assert!(commands.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn receive_join_request_of_other_protocol_version() -> Result<()> {
+    let node = create_node();
+    let state = Approved::first_node(node, mpsc::unbounded_channel().0)?;
+    let stage = Stage::new(state, create_comm().await?);
 
-    let rp = ResourceProof::new(RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY);
-    let data = rp.create_proof_data(&nonce);
-    let mut prover = rp.create_prover(data.clone());
-    let solution = prover.solve();
+    let new_node = Node::new(crypto::gen_keypair(), gen_addr());
+    let section_key = *stage.state.lock().await.section().chain().last_key();
 
     let message = Message::single_src(
         &new_node,
         DstLocation::Direct,
         Variant::JoinRequest(Box::new(JoinRequest {
+            protocol_version: PROTOCOL_VERSION + 1,
+            network_id: 0,
             section_key,
             relocate_payload: None,
-            resource_proof_response: Some(ResourceProofResponse {
-                solution,
-                data,
-                nonce,
-                nonce_signature,
-            }),
+            join_proof_response: None,
         })),
         None,
         None,
     )?;
+    let mut commands = stage
+        .handle_command(Command::HandleMessage {
+            sender: Some(new_node.addr),
+            message,
+        })
+        .await?
+        .into_iter();
+
+    let (recipients, response_message) = assert_matches!(
+        commands.next(),
+        Some(Command::SendMessage {
+            recipients,
+            message: MessageType::NodeMessage(NodeMessage(message)),
+            ..
+        }) => (recipients, message)
+    );
+    let response_message = Message::from_bytes(Bytes::from(response_message))?;
+
+    assert_eq!(recipients, [new_node.addr]);
+    assert_matches!(
+        response_message.variant(),
+        Variant::IncompatibleProtocolVersion { version } => {
+            assert_eq!(*version, PROTOCOL_VERSION)
+        }
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn receive_join_request_with_resource_proof_response() -> Result<()> {
+    let node = create_node();
+    let state = Approved::first_node(node, mpsc::unbounded_channel().0)?;
+    let stage = Stage::new(state, create_comm().await?);
+
+    let new_node = Node::new(crypto::gen_keypair(), gen_addr());
+    let section_key = *stage.state.lock().await.section().chain().last_key();
+
+    let join_request = |join_proof_response| {
+        Message::single_src(
+            &new_node,
+            DstLocation::Direct,
+            Variant::JoinRequest(Box::new(JoinRequest {
+                protocol_version: PROTOCOL_VERSION,
+                network_id: 0,
+                section_key,
+                relocate_payload: None,
+                join_proof_response,
+            })),
+            None,
+            None,
+        )
+    };
+
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            sender: Some(new_node.addr),
+            message: join_request(None)?,
+        })
+        .await?;
+    let challenge_message = assert_matches!(
+        commands.into_iter().next(),
+        Some(Command::SendMessage { message: MessageType::NodeMessage(NodeMessage(message)), .. }) => message
+    );
+    let challenge_message = Message::from_bytes(Bytes::from(challenge_message))?;
+    let challenge = assert_matches!(
+        challenge_message.variant(),
+        Variant::JoinChallenge { challenge } => challenge.clone()
+    );
+
+    let response = ResourceProofJoin::new(ResourceProofConfig::default())
+        .respond(&new_node.name(), &challenge)
+        .expect("failed to respond to the challenge");
+    let message = join_request(Some(response))?;
 
     let mut commands = stage
         .handle_command(Command::HandleMessage {
@@ -286,9 +401,11 @@ async fn receive_join_request_from_relocated_node() -> Result<()> {
         &relocated_node,
         DstLocation::Direct,
         Variant::JoinRequest(Box::new(JoinRequest {
+            protocol_version: PROTOCOL_VERSION,
+            network_id: 0,
             section_key,
             relocate_payload: Some(relocate_payload),
-            resource_proof_response: None,
+            join_proof_response: None,
         })),
         None,
         None,
@@ -412,6 +529,39 @@ async fn handle_consensus_on_online() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn handle_consensus_on_online_after_startup() -> Result<()> {
+    let (elders_info, mut nodes) = gen_elders_info("0".parse().unwrap(), ELDER_SIZE);
+    let sk_set = SecretKeySet::random();
+    let (section, section_key_share) = create_section(&sk_set, &elders_info)?;
+    let node = nodes.remove(0);
+    let state = Approved::new(
+        node,
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    let new_peer = create_peer();
+
+    // A new node is approved and then immediately relocated to a destination it can't choose,
+    // outside of our section.
+    let status = handle_online_command(&new_peer, &sk_set, &stage, &elders_info).await?;
+    assert!(status.node_approval_sent);
+    assert_matches!(status.relocate_details, Some(details) => {
+        assert_ne!(details.destination, *new_peer.name());
+        assert!(!elders_info.prefix.matches(&details.destination));
+        assert_eq!(details.age, new_peer.age());
+    });
+
+    // It never becomes our member.
+    let state = stage.state.lock().await;
+    assert!(state.section().members().get(new_peer.name()).is_none());
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn handle_consensus_on_online_of_elder_candidate() -> Result<()> {
     let sk_set = SecretKeySet::random();
@@ -665,6 +815,73 @@ async fn handle_consensus_on_online_of_rejoined_node_with_low_age_after_startup(
     handle_consensus_on_online_of_rejoined_node(NetworkPhase::Regular, 8).await
 }
 
+// Rejoin of a node that left so long ago that it was pruned from the section members by a
+// checkpoint.
+async fn handle_consensus_on_online_of_rejoined_node_after_pruning(age: u8) -> Result<()> {
+    let (elders_info, mut nodes) = gen_elders_info("0".parse().unwrap(), ELDER_SIZE);
+    let mut sk = bls::SecretKey::random();
+    let mut section = Section::new(
+        SectionProofChain::new(sk.public_key()),
+        proven(&sk, elders_info.clone())?,
+    )?;
+    for peer in elders_info.elders.values() {
+        let _ = section.update_member(proven(&sk, MemberInfo::joined(*peer))?);
+    }
+
+    let peer = create_peer().with_age(age);
+    let member_info = MemberInfo {
+        peer,
+        state: PeerState::Left,
+    };
+    let _ = section.update_member(proven(&sk, member_info)?);
+
+    // Advance the section chain to the next checkpoint, ending with the current key.
+    let sk_set = SecretKeySet::random();
+    for index in 1..=CHECKPOINT_INTERVAL {
+        let new_sk = if index == CHECKPOINT_INTERVAL {
+            sk_set.secret_key().clone()
+        } else {
+            bls::SecretKey::random()
+        };
+        let key_proof = prove(&sk, &new_sk.public_key())?;
+        assert!(section.update_elders(proven(&new_sk, elders_info.clone())?, key_proof));
+        sk = new_sk;
+    }
+    assert!(section.members().get(peer.name()).is_none());
+
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let node = nodes.remove(0);
+    let section_key_share = create_section_key_share(&sk_set, 0);
+    let state = Approved::new(node, section, Some(section_key_share), event_tx);
+    let stage = Stage::new(state, create_comm().await?);
+
+    let status = handle_online_command(&peer, &sk_set, &stage, &elders_info).await?;
+    assert!(event_rx.try_recv().is_err());
+
+    if age / 2 <= MIN_AGE {
+        assert!(!status.node_approval_sent);
+        assert!(status.relocate_details.is_none());
+        return Ok(());
+    }
+
+    assert!(status.node_approval_sent);
+    assert_matches!(status.relocate_details, Some(details) => {
+        assert_eq!(details.age, age / 2);
+    });
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_consensus_on_online_of_rejoined_node_with_high_age_after_pruning() -> Result<()> {
+    handle_consensus_on_online_of_rejoined_node_after_pruning(16).await
+}
+
+#[tokio::test]
+async fn handle_consensus_on_online_of_rejoined_node_with_low_age_after_pruning() -> Result<()> {
+    handle_consensus_on_online_of_rejoined_node_after_pruning(8).await
+}
+
 #[tokio::test]
 async fn handle_consensus_on_offline_of_non_elder() -> Result<()> {
     let (elders_info, mut nodes) = create_elders_info();
@@ -702,48 +919,62 @@ async fn handle_consensus_on_offline_of_non_elder() -> Result<()> {
 }
 
 #[tokio::test]
-async fn handle_consensus_on_offline_of_elder() -> Result<()> {
+async fn handle_leave_request() -> Result<()> {
     let (elders_info, mut nodes) = create_elders_info();
     let sk_set = SecretKeySet::random();
 
     let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
 
-    let existing_peer = create_peer();
-    let member_info = MemberInfo::joined(existing_peer);
+    let leaving_node = create_node();
+    let member_info = MemberInfo::joined(leaving_node.peer());
     let member_info = proven(sk_set.secret_key(), member_info)?;
     let _ = section.update_member(member_info);
 
-    // Pick the elder to remove.
-    let remove_peer = *elders_info
-        .elders
-        .values()
-        .rev()
-        .next()
-        .expect("elders_info is empty");
-    let remove_member_info = section
-        .members()
-        .get(remove_peer.name())
-        .expect("member not found")
-        .leave()?;
-
-    // Create our node
-    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
     let node = nodes.remove(0);
-    let node_name = node.name();
-    let state = Approved::new(node, section, Some(section_key_share), event_tx);
+    let state = Approved::new(
+        node,
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
     let stage = Stage::new(state, create_comm().await?);
 
-    // Handle the consensus on the Offline vote
-    let vote = Vote::Offline(remove_member_info);
-    let proof = prove(sk_set.secret_key(), &vote.as_signable())?;
+    // The leave request makes us vote the member offline.
+    let message = Message::single_src(
+        &leaving_node,
+        DstLocation::Direct,
+        Variant::Leave,
+        None,
+        None,
+    )?;
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            sender: Some(leaving_node.addr),
+            message,
+        })
+        .await?;
+
+    let member_info = commands
+        .into_iter()
+        .find_map(|command| match command {
+            Command::HandleVote {
+                vote: Vote::Offline(member_info),
+                ..
+            } => Some(member_info),
+            _ => None,
+        })
+        .expect("Offline vote not cast");
+    assert_eq!(member_info.peer.name(), &leaving_node.name());
+    assert_eq!(member_info.state, PeerState::LeftVoluntarily);
 
+    // Once agreed on, the leaving member is acknowledged.
+    let vote = Vote::Offline(member_info);
+    let proof = prove(sk_set.secret_key(), &vote.as_signable())?;
     let commands = stage
         .handle_command(Command::HandleConsensus { vote, proof })
         .await?;
 
-    // Verify we sent a `DKGStart` message with the expected participants.
-    let mut dkg_start_sent = false;
-
+    let mut ack_sent = false;
     for command in commands {
         let (recipients, message) = match command {
             Command::SendMessage {
@@ -754,299 +985,1531 @@ async fn handle_consensus_on_offline_of_elder() -> Result<()> {
             _ => continue,
         };
 
-        let message = match message.variant() {
-            Variant::Vote {
-                content: Vote::SendMessage { message, .. },
-                ..
-            } => message,
-            _ => continue,
-        };
-
-        let actual_elders_info = match &message.variant {
-            Variant::DKGStart { elders_info, .. } => elders_info,
-            _ => continue,
-        };
-
-        let expected_new_elders: BTreeSet<_> = elders_info
-            .elders
-            .values()
-            .filter(|peer| **peer != remove_peer)
-            .copied()
-            .chain(iter::once(existing_peer))
-            .collect();
-        itertools::assert_equal(actual_elders_info.elders.values(), &expected_new_elders);
-
-        let expected_dkg_start_recipients: Vec<_> = expected_new_elders
-            .iter()
-            .filter(|peer| *peer.name() != node_name)
-            .map(Peer::addr)
-            .copied()
-            .collect();
-        assert_eq!(recipients, expected_dkg_start_recipients);
-
-        dkg_start_sent = true;
+        if let Variant::LeaveAck = message.variant() {
+            assert_eq!(recipients, [leaving_node.addr]);
+            ack_sent = true;
+        }
     }
 
-    assert!(dkg_start_sent);
-
-    assert_matches!(event_rx.try_recv(), Ok(Event::MemberLeft { name, .. }) => {
-        assert_eq!(name, *remove_peer.name());
-    });
-
-    // The removed peer is still our elder because we haven't yet processed the section update.
-    assert!(stage
-        .state
-        .lock()
-        .await
-        .section()
-        .elders_info()
-        .elders
-        .contains_key(remove_peer.name()));
+    assert!(ack_sent);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn handle_unknown_message_from_our_elder() -> Result<()> {
-    handle_unknown_message(UnknownMessageSource::OurElder).await
-}
+async fn rejoin_after_leave_keeps_age() -> Result<()> {
+    let (elders_info, mut nodes) = gen_elders_info("0".parse().unwrap(), ELDER_SIZE);
+    let sk_set = SecretKeySet::random();
 
-#[tokio::test]
-async fn handle_unknown_message_from_non_elder() -> Result<()> {
-    handle_unknown_message(UnknownMessageSource::NonElder).await
-}
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
 
-enum UnknownMessageSource {
-    OurElder,
-    NonElder,
-}
+    // Rejoining after going offline with this age would be rejected.
+    let age = MIN_AGE + 4;
+    let leaving_node = Node::new(crypto::gen_keypair(), gen_addr()).with_age(age);
+    let member_info = MemberInfo::joined(leaving_node.peer());
+    let member_info = proven(sk_set.secret_key(), member_info)?;
+    let _ = section.update_member(member_info);
 
-async fn handle_unknown_message(source: UnknownMessageSource) -> Result<()> {
-    let (elders_info, mut nodes) = create_elders_info();
+    let node = nodes.remove(0);
+    let state = Approved::new(
+        node,
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    let message = Message::single_src(
+        &leaving_node,
+        DstLocation::Direct,
+        Variant::Leave,
+        None,
+        None,
+    )?;
+    let _ = stage
+        .handle_command(Command::HandleMessage {
+            sender: Some(leaving_node.addr),
+            message,
+        })
+        .await?;
+
+    let vote = Vote::Offline(MemberInfo::joined(leaving_node.peer()).leave_voluntarily()?);
+    let proof = prove(sk_set.secret_key(), &vote.as_signable())?;
+    let _ = stage
+        .handle_command(Command::HandleConsensus { vote, proof })
+        .await?;
+
+    // The node rejoins with the age it had when it left.
+    let status = handle_online_command(&leaving_node.peer(), &sk_set, &stage, &elders_info).await?;
+    assert!(status.node_approval_sent);
+    assert_matches!(status.relocate_details, Some(details) => {
+        assert_eq!(details.destination, leaving_node.name());
+        assert_eq!(details.age, age);
+    });
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_connect_request() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+    let sk_set = SecretKeySet::random();
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
+
+    let requester = create_node();
+    let target = create_node();
+    for node in &[&requester, &target] {
+        let member_info = proven(sk_set.secret_key(), MemberInfo::joined(node.peer()))?;
+        let _ = section.update_member(member_info);
+    }
+
+    let state = Approved::new(
+        nodes.remove(0),
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    // The address we see the requester at, e.g. of its NAT.
+    let external_addr = gen_addr();
+    let message = Message::single_src(
+        &requester,
+        DstLocation::Direct,
+        Variant::ConnectRequest {
+            target: target.name(),
+        },
+        None,
+        None,
+    )?;
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            sender: Some(external_addr),
+            message,
+        })
+        .await?;
+
+    let mut introductions = vec![];
+    for command in commands {
+        let (recipients, message) = match command {
+            Command::SendMessage {
+                recipients,
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
+            _ => continue,
+        };
+
+        if let Variant::ConnectIntroduction { peer } = message.variant() {
+            introductions.push((recipients, *peer.name(), *peer.addr()));
+        }
+    }
+
+    assert_eq!(
+        introductions,
+        vec![
+            (vec![target.addr], requester.name(), external_addr),
+            (vec![external_addr], target.name(), target.addr),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_peer_lost_requests_rendezvous() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+    let sk_set = SecretKeySet::random();
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
+
+    let lost_node = create_node();
+    let member_info = proven(sk_set.secret_key(), MemberInfo::joined(lost_node.peer()))?;
+    let _ = section.update_member(member_info);
+
+    let state = Approved::new(
+        nodes.remove(0),
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    // First we ask another elder to introduce us.
+    let commands = stage
+        .handle_command(Command::HandlePeerLost(lost_node.addr))
+        .await?;
+    let (recipients, message) = assert_matches!(
+        commands.as_slice(),
+        [Command::SendMessage {
+            recipients,
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        }] => (recipients.clone(), Message::from_bytes(Bytes::from(msg_bytes.clone()))?)
+    );
+    assert_matches!(
+        message.variant(),
+        Variant::ConnectRequest { target } => assert_eq!(*target, lost_node.name())
+    );
+    let relay = recipients[0];
+    assert!(nodes.iter().any(|node| node.addr == relay));
+
+    // Punching fails, so our messages are relayed from now on.
+    stage
+        .state
+        .lock()
+        .await
+        .handle_punch_result(lost_node.addr, relay, false);
+    assert_eq!(
+        stage.state.lock().await.relay_for(&lost_node.addr),
+        Some(relay)
+    );
+
+    // Losing the peer even through the relay repeatedly makes us vote it offline.
+    for _ in 0..2 {
+        let commands = stage
+            .handle_command(Command::HandlePeerLost(lost_node.addr))
+            .await?;
+        assert!(commands.is_empty());
+    }
+
+    let commands = stage
+        .handle_command(Command::HandlePeerLost(lost_node.addr))
+        .await?;
+    assert!(commands.iter().any(|command| matches!(
+        command,
+        Command::HandleVote {
+            vote: Vote::Offline(_),
+            ..
+        }
+    )));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn probe_acknowledged_by_peer() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&[""], &[ELDER_SIZE])?;
+    let prefix = Prefix::default();
+    let (mut prober, _) = network.approved(&prefix, 0)?;
+    let (mut peer, _) = network.approved(&prefix, 1)?;
+    let prober_addr = prober.node().addr;
+    let peer_addr = peer.node().addr;
+
+    let (nonce, msg_bytes, mut ack_rx) = prober.start_probe(peer_addr)?;
+
+    // The peer replies to the prober directly.
+    let commands = peer
+        .handle_message(Some(prober_addr), Message::from_bytes(msg_bytes)?)
+        .await?;
+    let ack = assert_matches!(
+        commands.as_slice(),
+        [Command::SendMessage {
+            recipients,
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        }] => {
+            assert_eq!(recipients, &[prober_addr]);
+            Message::from_bytes(Bytes::from(msg_bytes.clone()))?
+        }
+    );
+    assert_matches!(ack.variant(), Variant::ProbeAck { .. });
+
+    // An ack from anyone else doesn't count.
+    let other = create_node();
+    let forged = Message::single_src(
+        &other,
+        DstLocation::Direct,
+        Variant::ProbeAck { nonce },
+        None,
+        None,
+    )?;
+    let _ = prober.handle_message(Some(other.addr), forged).await?;
+    assert!(ack_rx.try_recv().is_err());
+
+    let _ = prober.handle_message(Some(peer_addr), ack).await?;
+    assert!(ack_rx.try_recv().is_ok());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn replay_blacklists_only_its_signer() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&[""], &[ELDER_SIZE])?;
+    let prefix = Prefix::default();
+    let (mut state, _) = network.approved(&prefix, 0)?;
+    let signer = network.section(&prefix).unwrap().nodes[1].clone();
+    let relay = network.section(&prefix).unwrap().nodes[2].clone();
+    let our_name = state.node().name();
+
+    let message = |nonce| {
+        Message::single_src_with_nonce(
+            &signer,
+            DstLocation::Node(our_name),
+            Variant::UserMessage {
+                id: rand::random(),
+                content: Bytes::from_static(b"hello"),
+                correlation_id: None,
+            },
+            nonce,
+        )
+    };
+    let is_blacklisted = |commands: &[Command], addr| {
+        commands.iter().any(|command| {
+            matches!(
+                command,
+                Command::Blacklist {
+                    entry: BlacklistEntry::Addr(entry),
+                    ..
+                } if *entry == addr
+            )
+        })
+    };
+
+    // A stale nonce alone could be a delay or a clock difference, so it's just dropped.
+    let stale = message(Nonce::created_ago(MAX_NONCE_AGE * 2))?;
+    let commands = state.handle_message(Some(signer.addr), stale).await?;
+    assert!(!is_blacklisted(&commands, signer.addr));
+
+    let fresh = message(Nonce::new())?;
+    let commands = state
+        .handle_message(Some(signer.addr), fresh.clone())
+        .await?;
+    assert!(!is_blacklisted(&commands, signer.addr));
+
+    // A relay just passed the message on again.
+    let commands = state
+        .handle_message(Some(relay.addr), fresh.clone())
+        .await?;
+    assert!(!is_blacklisted(&commands, relay.addr));
+
+    // The signer replayed its own message.
+    let commands = state.handle_message(Some(signer.addr), fresh).await?;
+    assert!(is_blacklisted(&commands, signer.addr));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn relayed_replay_blacklists_its_signer() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&[""], &[ELDER_SIZE])?;
+    let prefix = Prefix::default();
+    let (mut state, _) = network.approved(&prefix, 0)?;
+    let signer = network.section(&prefix).unwrap().nodes[1].clone();
+    let relay = network.section(&prefix).unwrap().nodes[2].clone();
+
+    let message = Message::single_src(
+        &signer,
+        DstLocation::Direct,
+        Variant::Probe {
+            nonce: rand::random(),
+        },
+        None,
+        None,
+    )?;
+    let _ = state
+        .handle_message(Some(relay.addr), message.clone())
+        .await?;
+
+    // The relay only passed on the direct message again, its signer is responsible for it.
+    let commands = state.handle_message(Some(relay.addr), message).await?;
+    let entries: Vec<_> = commands
+        .iter()
+        .filter_map(|command| match command {
+            Command::Blacklist { entry, .. } => Some(entry),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(entries, [&BlacklistEntry::Name(signer.name())]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn relay_only_fresh_messages_of_the_sender() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&[""], &[ELDER_SIZE])?;
+    let prefix = Prefix::default();
+    let (mut state, _) = network.approved(&prefix, 0)?;
+    let sender = network.section(&prefix).unwrap().nodes[1].clone();
+    let other = network.section(&prefix).unwrap().nodes[2].clone();
+    let recipient = network.section(&prefix).unwrap().nodes[3].clone();
+
+    let inner = |node: &Node, nonce| {
+        Message::single_src_with_nonce(
+            node,
+            DstLocation::Direct,
+            Variant::Probe {
+                nonce: rand::random(),
+            },
+            nonce,
+        )
+    };
+    let relay = |inner: Message| {
+        Message::single_src(
+            &sender,
+            DstLocation::Direct,
+            Variant::Relay {
+                dst: recipient.addr,
+                message: inner.to_bytes(),
+            },
+            None,
+            None,
+        )
+    };
+
+    let forged = relay(inner(&other, Nonce::new())?)?;
+    let commands = state.handle_message(Some(sender.addr), forged).await?;
+    assert!(commands.is_empty());
+
+    let stale = relay(inner(&sender, Nonce::created_ago(MAX_NONCE_AGE * 2))?)?;
+    let commands = state.handle_message(Some(sender.addr), stale).await?;
+    assert!(commands.is_empty());
+
+    let valid = inner(&sender, Nonce::new())?;
+    let commands = state
+        .handle_message(Some(sender.addr), relay(valid.clone())?)
+        .await?;
+    assert_matches!(
+        commands.as_slice(),
+        [Command::SendMessage {
+            recipients,
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        }] => {
+            assert_eq!(recipients, &[recipient.addr]);
+            assert_eq!(&msg_bytes[..], &valid.to_bytes()[..]);
+        }
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn noise_accepts_direct_messages_relayed_by_elders() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&[""], &[ELDER_SIZE])?;
+    let prefix = Prefix::default();
+    let (state, _) = network.approved(&prefix, 0)?;
+    let relay = network.section(&prefix).unwrap().nodes[1].clone();
+    let member = network.section(&prefix).unwrap().nodes[2].clone();
+    let stranger = create_node();
+
+    let memory_network = MemoryNetwork::new();
+    let (event_tx, mut event_rx) = mpsc::channel(1);
+    let transport =
+        NoiseTransport::new(Box::new(memory_network.transport()), &state.node().keypair)?;
+    let comm = Comm::new(Box::new(transport), MessageSizeLimits::default(), event_tx);
+    let our_addr = comm.our_connection_info();
+    let stage = Arc::new(Stage::new(state, comm));
+
+    let relay_transport =
+        NoiseTransport::new(Box::new(memory_network.transport()), &relay.keypair)?;
+    relay_transport.connect(&our_addr).await?;
+    let relay_addr = relay_transport.local_addr();
+
+    // The elder relays the direct message of another member, authenticated as itself.
+    relay_probe(&stage, &relay_transport, &mut event_rx, &member).await?;
+    assert!(!stage
+        .comm
+        .blacklist()
+        .contains(&BlacklistEntry::Addr(relay_addr)));
+
+    // But not of a node that isn't our member.
+    relay_probe(&stage, &relay_transport, &mut event_rx, &stranger).await?;
+    assert!(stage
+        .comm
+        .blacklist()
+        .contains(&BlacklistEntry::Addr(relay_addr)));
+
+    Ok(())
+}
+
+// Send a direct probe signed by `signer` over `transport` and handle it at the `stage` receiving
+// it over `event_rx`.
+async fn relay_probe(
+    stage: &Arc<Stage>,
+    transport: &NoiseTransport,
+    event_rx: &mut mpsc::Receiver<ConnectionEvent>,
+    signer: &Node,
+) -> Result<()> {
+    let message = Message::single_src(
+        signer,
+        DstLocation::Direct,
+        Variant::Probe {
+            nonce: rand::random(),
+        },
+        None,
+        None,
+    )?;
+    let bytes = MessageType::NodeMessage(NodeMessage::new(message.to_bytes())).serialize()?;
+    transport
+        .send(&stage.comm.our_connection_info(), bytes)
+        .await?;
+
+    let (sender, bytes) = assert_matches!(
+        event_rx.recv().await,
+        Some(ConnectionEvent::Received(received)) => received
+    );
+    super::handle_message(stage.clone(), bytes, sender, false).await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn replay_old_trace() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&[""], &[ELDER_SIZE])?;
+    let prefix = Prefix::default();
+    let (mut state, mut event_rx) = network.approved(&prefix, 0)?;
+    let signer = network.section(&prefix).unwrap().nodes[1].clone();
+
+    // Captured longer ago than any nonce stays fresh.
+    let message = Message::single_src_with_nonce(
+        &signer,
+        DstLocation::Node(state.node().name()),
+        Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        },
+        Nonce::created_ago(MAX_NONCE_AGE + MAX_CLOCK_SKEW + Duration::from_secs(60)),
+    )?;
+
+    let commands = state.replay_message(signer.addr, message).await?;
+    assert!(!commands
+        .iter()
+        .any(|command| matches!(command, Command::Blacklist { .. })));
+    assert_matches!(
+        event_rx.try_recv(),
+        Ok(Event::MessageReceived { content, .. }) => assert_eq!(content, &b"hello"[..])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_probe_failures() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+    let sk_set = SecretKeySet::random();
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
+
+    let lost_node = create_node();
+    let member_info = proven(sk_set.secret_key(), MemberInfo::joined(lost_node.peer()))?;
+    let _ = section.update_member(member_info);
+
+    let mut state = Approved::new(
+        nodes.remove(0),
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+
+    // The first send failure asks for a rendezvous, the following ones for a probe.
+    let _ = state.handle_peer_lost(&lost_node.addr)?;
+    assert_matches!(
+        state.handle_peer_lost(&lost_node.addr)?.as_slice(),
+        [Command::Probe(peers)] => assert_eq!(peers, &[lost_node.addr])
+    );
+
+    // A successful probe resets the count of the failed ones.
+    for _ in 0..2 {
+        assert!(state
+            .handle_probe_results(vec![(lost_node.addr, None)])?
+            .is_empty());
+    }
+    let rtt = Duration::from_millis(50);
+    assert!(state
+        .handle_probe_results(vec![(lost_node.addr, Some(rtt))])?
+        .is_empty());
+    assert_eq!(state.peer_rtt(&lost_node.addr), Some(rtt));
+
+    for _ in 0..2 {
+        assert!(state
+            .handle_probe_results(vec![(lost_node.addr, None)])?
+            .is_empty());
+    }
+    let commands = state.handle_probe_results(vec![(lost_node.addr, None)])?;
+    assert!(commands.iter().any(|command| matches!(
+        command,
+        Command::HandleVote {
+            vote: Vote::Offline(info),
+            ..
+        } if info.peer.name() == &lost_node.name()
+    )));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_consensus_on_offline_of_elder() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+    let sk_set = SecretKeySet::random();
+
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
+
+    let existing_peer = create_peer();
+    let member_info = MemberInfo::joined(existing_peer);
+    let member_info = proven(sk_set.secret_key(), member_info)?;
+    let _ = section.update_member(member_info);
+
+    // Pick the elder to remove.
+    let remove_peer = *elders_info
+        .elders
+        .values()
+        .rev()
+        .next()
+        .expect("elders_info is empty");
+    let remove_member_info = section
+        .members()
+        .get(remove_peer.name())
+        .expect("member not found")
+        .leave()?;
+
+    // Create our node
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let node = nodes.remove(0);
+    let node_name = node.name();
+    let state = Approved::new(node, section, Some(section_key_share), event_tx);
+    let stage = Stage::new(state, create_comm().await?);
+
+    // Handle the consensus on the Offline vote
+    let vote = Vote::Offline(remove_member_info);
+    let proof = prove(sk_set.secret_key(), &vote.as_signable())?;
+
+    let commands = stage
+        .handle_command(Command::HandleConsensus { vote, proof })
+        .await?;
+
+    // Verify we sent a `DKGStart` message with the expected participants.
+    let mut dkg_start_sent = false;
+
+    for command in commands {
+        let (recipients, message) = match command {
+            Command::SendMessage {
+                recipients,
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
+            _ => continue,
+        };
+
+        let message = match message.variant() {
+            Variant::Vote {
+                content: Vote::SendMessage { message, .. },
+                ..
+            } => message,
+            _ => continue,
+        };
+
+        let actual_elders_info = match &message.variant {
+            Variant::DKGStart { elders_info, .. } => elders_info,
+            _ => continue,
+        };
+
+        let expected_new_elders: BTreeSet<_> = elders_info
+            .elders
+            .values()
+            .filter(|peer| **peer != remove_peer)
+            .copied()
+            .chain(iter::once(existing_peer))
+            .collect();
+        itertools::assert_equal(actual_elders_info.elders.values(), &expected_new_elders);
+
+        let expected_dkg_start_recipients: Vec<_> = expected_new_elders
+            .iter()
+            .filter(|peer| *peer.name() != node_name)
+            .map(Peer::addr)
+            .copied()
+            .collect();
+        assert_eq!(recipients, expected_dkg_start_recipients);
+
+        dkg_start_sent = true;
+    }
+
+    assert!(dkg_start_sent);
+
+    assert_matches!(event_rx.try_recv(), Ok(Event::MemberLeft { name, .. }) => {
+        assert_eq!(name, *remove_peer.name());
+    });
+
+    // The removed peer is still our elder because we haven't yet processed the section update.
+    assert!(stage
+        .state
+        .lock()
+        .await
+        .section()
+        .elders_info()
+        .elders
+        .contains_key(remove_peer.name()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_unknown_message_from_our_elder() -> Result<()> {
+    handle_unknown_message(UnknownMessageSource::OurElder).await
+}
+
+#[tokio::test]
+async fn handle_unknown_message_from_non_elder() -> Result<()> {
+    handle_unknown_message(UnknownMessageSource::NonElder).await
+}
+
+enum UnknownMessageSource {
+    OurElder,
+    NonElder,
+}
+
+async fn handle_unknown_message(source: UnknownMessageSource) -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+
+    let (sender_node, expected_recipients) = match source {
+        UnknownMessageSource::OurElder => {
+            // When the unknown message is sent from one of our elders, we should bounce it back to
+            // that elder only.
+            let node = nodes.remove(0);
+            let addr = node.addr;
+            (node, vec![addr])
+        }
+        UnknownMessageSource::NonElder => {
+            // When the unknown message is sent from a peer that is not our elder (including peers
+            // from other sections), bounce it to our elders.
+            (
+                Node::new(crypto::gen_keypair(), gen_addr()),
+                elders_info
+                    .elders
+                    .values()
+                    .map(Peer::addr)
+                    .copied()
+                    .collect(),
+            )
+        }
+    };
+
+    let sk = bls::SecretKey::random();
+    let chain = SectionProofChain::new(sk.public_key());
+
+    let proven_elders_info = proven(&sk, elders_info)?;
+    let section = Section::new(chain, proven_elders_info)?;
+
+    let node = create_node();
+    let state = Approved::new(node, section, None, mpsc::unbounded_channel().0);
+    let stage = Stage::new(state, create_comm().await?);
+
+    // non-elders can't handle messages addressed to sections.
+    let original_message = Message::single_src(
+        &sender_node,
+        DstLocation::Section(rand::random()),
+        Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        },
+        None,
+        None,
+    )?;
+    let original_message_bytes = original_message.to_bytes();
+
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            message: original_message,
+            sender: Some(sender_node.addr),
+        })
+        .await?;
+
+    let mut bounce_sent = false;
+
+    // TODO: test also that the message got relayed to the elders.
+
+    for command in commands {
+        let (recipients, message) = if let Command::SendMessage {
+            recipients,
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        } = command
+        {
+            (recipients, Message::from_bytes(Bytes::from(msg_bytes))?)
+        } else {
+            continue;
+        };
+
+        let (src_key, message) =
+            if let Variant::BouncedUnknownMessage { src_key, message } = message.variant() {
+                (src_key, message)
+            } else {
+                continue;
+            };
+
+        assert_eq!(recipients, expected_recipients);
+        assert_eq!(*src_key, sk.public_key());
+        assert_eq!(*message, original_message_bytes);
+
+        bounce_sent = true;
+    }
+
+    assert!(bounce_sent);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_untrusted_message_from_peer() -> Result<()> {
+    handle_untrusted_message(UntrustedMessageSource::Peer).await
+}
+
+#[tokio::test]
+async fn handle_untrusted_accumulated_message() -> Result<()> {
+    handle_untrusted_message(UntrustedMessageSource::Accumulation).await
+}
+
+enum UntrustedMessageSource {
+    Peer,
+    Accumulation,
+}
+
+async fn handle_untrusted_message(source: UntrustedMessageSource) -> Result<()> {
+    let sk0 = bls::SecretKey::random();
+    let pk0 = sk0.public_key();
+    let chain = SectionProofChain::new(pk0);
+
+    let (elders_info, _) = create_elders_info();
+
+    let (sender, expected_recipients) = match source {
+        UntrustedMessageSource::Peer => {
+            // When the untrusted message is sent from a single peer, we should bounce it back to
+            // that peer.
+            let sender = *elders_info
+                .elders
+                .values()
+                .next()
+                .expect("elders_info is empty")
+                .addr();
+            (Some(sender), vec![sender])
+        }
+        UntrustedMessageSource::Accumulation => {
+            // When the untrusted message is the result of message accumulation, we should bounce
+            // it to our elders.
+            (
+                None,
+                elders_info
+                    .elders
+                    .values()
+                    .map(Peer::addr)
+                    .copied()
+                    .collect(),
+            )
+        }
+    };
+
+    let proven_elders_info = proven(&sk0, elders_info)?;
+    let section = Section::new(chain.clone(), proven_elders_info)?;
+
+    let node = create_node();
+    let node_name = node.name();
+    let state = Approved::new(node, section, None, mpsc::unbounded_channel().0);
+    let stage = Stage::new(state, create_comm().await?);
+
+    let sk1 = bls::SecretKey::random();
+    let pk1 = sk1.public_key();
+
+    // Create a message signed by a key now known to the node.
+    let message = PlainMessage {
+        src: Prefix::default(),
+        dst: DstLocation::Node(node_name),
+        dst_key: pk1,
+        variant: Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        },
+    };
+    let signature = sk1.sign(&bincode::serialize(&message.as_signable())?);
+    let original_message = Message::section_src(message, signature, SectionProofChain::new(pk1))?;
+
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            message: original_message.clone(),
+            sender,
+        })
+        .await?;
+
+    let mut bounce_sent = false;
+
+    for command in commands {
+        let (recipients, message) = if let Command::SendMessage {
+            recipients,
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        } = command
+        {
+            (recipients, Message::from_bytes(Bytes::from(msg_bytes))?)
+        } else {
+            continue;
+        };
+
+        if let Variant::BouncedUntrustedMessage(bounced_message) = message.variant() {
+            assert_eq!(recipients, expected_recipients);
+            assert_eq!(**bounced_message, original_message);
+            assert_eq!(*message.dst_key(), Some(pk0));
+
+            bounce_sent = true;
+        }
+    }
+
+    assert!(bounce_sent);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_message_to_unknown_section_key() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+
+    let sk = bls::SecretKey::random();
+    let chain = SectionProofChain::new(sk.public_key());
+    let proven_elders_info = proven(&sk, elders_info)?;
+    let section = Section::new(chain, proven_elders_info)?;
+
+    let sender_node = nodes.remove(0);
+    let node = nodes.remove(0);
+    let node_name = node.name();
+    let state = Approved::new(node, section, None, mpsc::unbounded_channel().0);
+    let stage = Stage::new(state, create_comm().await?);
+
+    // The sender knows a newer key of our section than we do.
+    let new_key = bls::SecretKey::random().public_key();
+    let original_message = Message::single_src(
+        &sender_node,
+        DstLocation::Node(node_name),
+        Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        },
+        None,
+        Some(new_key),
+    )?;
+    let original_message_bytes = original_message.to_bytes();
+
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            message: original_message,
+            sender: Some(sender_node.addr),
+        })
+        .await?;
+
+    let mut bounce_sent = false;
+
+    for command in commands {
+        let (recipients, message) = match command {
+            Command::SendMessage {
+                recipients,
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
+            _ => continue,
+        };
+
+        if let Variant::BouncedUnknownMessage { src_key, message } = message.variant() {
+            assert_eq!(recipients, [sender_node.addr]);
+            assert_eq!(*src_key, sk.public_key());
+            assert_eq!(*message, original_message_bytes);
+            bounce_sent = true;
+        }
+    }
+
+    assert!(bounce_sent);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_bounced_unknown_message() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+
+    // Create section chain with two keys.
+    let sk0 = bls::SecretKey::random();
+    let pk0 = sk0.public_key();
+
+    let sk1_set = SecretKeySet::random();
+    let pk1 = sk1_set.secret_key().public_key();
+    let pk1_signature = sk0.sign(&bincode::serialize(&pk1)?);
+
+    let mut section_chain = SectionProofChain::new(pk0);
+    let _ = section_chain.push(pk1, pk1_signature);
+
+    let proven_elders_info = proven(&sk0, elders_info)?;
+    let section = Section::new(section_chain, proven_elders_info)?;
+    let section_key_share = create_section_key_share(&sk1_set, 0);
+
+    let node = nodes.remove(0);
+
+    // Create the original message whose bounce we want to test. The content of the message doesn't
+    // matter for the purpose of this test.
+    let other_node = Node::new(crypto::gen_keypair(), gen_addr());
+    let original_message_content = Bytes::from_static(b"unknown message");
+    let original_message = Message::single_src(
+        &node,
+        DstLocation::Node(other_node.name()),
+        Variant::UserMessage {
+            id: rand::random(),
+            content: original_message_content.clone(),
+            correlation_id: None,
+        },
+        None,
+        None,
+    )?;
+
+    let state = Approved::new(
+        node,
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    let bounced_message = Message::single_src(
+        &other_node,
+        DstLocation::Direct,
+        Variant::BouncedUnknownMessage {
+            src_key: pk0,
+            message: original_message.to_bytes(),
+        },
+        None,
+        None,
+    )?;
+
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            message: bounced_message,
+            sender: Some(other_node.addr),
+        })
+        .await?;
+
+    let mut sync_sent = false;
+    let mut original_message_sent = false;
+
+    for command in commands {
+        let (recipients, message) = match command {
+            Command::SendMessage {
+                recipients,
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
+            _ => continue,
+        };
+
+        match message.variant() {
+            Variant::Sync { section, .. } => {
+                assert_eq!(recipients, [other_node.addr]);
+                assert_eq!(*section.chain().last_key(), pk1);
+                sync_sent = true;
+            }
+            Variant::UserMessage { content, .. } => {
+                assert_eq!(recipients, [other_node.addr]);
+                assert_eq!(*content, original_message_content);
+                original_message_sent = true;
+            }
+            _ => continue,
+        }
+    }
+
+    assert!(sync_sent);
+    assert!(original_message_sent);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_bounced_untrusted_message() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+
+    // Create section chain with two keys.
+    let sk0 = bls::SecretKey::random();
+    let pk0 = sk0.public_key();
+
+    let sk1_set = SecretKeySet::random();
+    let pk1 = sk1_set.secret_key().public_key();
+    let pk1_signature = sk0.sign(&bincode::serialize(&pk1)?);
+
+    let mut chain = SectionProofChain::new(pk0);
+    let _ = chain.push(pk1, pk1_signature);
+
+    let proven_elders_info = proven(&sk0, elders_info)?;
+    let section = Section::new(chain.clone(), proven_elders_info)?;
+    let section_key_share = create_section_key_share(&sk1_set, 0);
+
+    let node = nodes.remove(0);
+
+    // Create the original message whose bounce we want to test. Attach a proof that starts
+    // at `pk1`.
+    let other_node = Node::new(crypto::gen_keypair(), gen_addr());
+
+    let original_message_content = Bytes::from_static(b"unknown message");
+    let original_message = PlainMessage {
+        src: Prefix::default(),
+        dst: DstLocation::Node(other_node.name()),
+        dst_key: pk1,
+        variant: Variant::UserMessage {
+            id: rand::random(),
+            content: original_message_content.clone(),
+            correlation_id: None,
+        },
+    };
+    let signature = sk1_set
+        .secret_key()
+        .sign(&bincode::serialize(&original_message.as_signable())?);
+    let proof_chain = chain.slice(1..);
+    let original_message = Message::section_src(original_message, signature, proof_chain)?;
+
+    // Create our node.
+    let state = Approved::new(
+        node,
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    // Create the bounced message, indicating the last key the peer knows is `pk0`
+    let bounced_message = Message::single_src(
+        &other_node,
+        DstLocation::Direct,
+        Variant::BouncedUntrustedMessage(Box::new(original_message)),
+        None,
+        Some(pk0),
+    )?;
+
+    let commands = stage
+        .handle_command(Command::HandleMessage {
+            message: bounced_message,
+            sender: Some(other_node.addr),
+        })
+        .await?;
+
+    let mut message_sent = false;
+
+    for command in commands {
+        let (recipients, message) = match command {
+            Command::SendMessage {
+                recipients,
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
+            _ => continue,
+        };
+
+        match message.variant() {
+            Variant::UserMessage { content, .. } => {
+                assert_eq!(recipients, [other_node.addr]);
+                assert_eq!(*content, original_message_content);
+                assert_eq!(*message.proof_chain()?, chain);
 
-    let (sender_node, expected_recipients) = match source {
-        UnknownMessageSource::OurElder => {
-            // When the unknown message is sent from one of our elders, we should bounce it back to
-            // that elder only.
-            let node = nodes.remove(0);
-            let addr = node.addr;
-            (node, vec![addr])
-        }
-        UnknownMessageSource::NonElder => {
-            // When the unknown message is sent from a peer that is not our elder (including peers
-            // from other sections), bounce it to our elders.
-            (
-                Node::new(crypto::gen_keypair(), gen_addr()),
-                elders_info
-                    .elders
-                    .values()
-                    .map(Peer::addr)
-                    .copied()
-                    .collect(),
-            )
+                message_sent = true;
+            }
+            _ => continue,
         }
-    };
+    }
 
-    let sk = bls::SecretKey::random();
-    let chain = SectionProofChain::new(sk.public_key());
+    assert!(message_sent);
 
-    let proven_elders_info = proven(&sk, elders_info)?;
-    let section = Section::new(chain, proven_elders_info)?;
+    Ok(())
+}
 
-    let node = create_node();
-    let state = Approved::new(node, section, None, mpsc::unbounded_channel().0);
+#[tokio::test]
+async fn handle_sync() -> Result<()> {
+    // Create first `Section` with a chain of length 2
+    let sk0_set = SecretKeySet::random();
+    let pk0 = sk0_set.secret_key().public_key();
+    let sk1_set = SecretKeySet::random();
+    let pk1 = sk1_set.secret_key().public_key();
+    let pk1_signature = sk0_set.secret_key().sign(bincode::serialize(&pk1)?);
+
+    let mut chain = SectionProofChain::new(pk0);
+    assert!(chain.push(pk1, pk1_signature));
+
+    let (old_elders_info, mut nodes) = create_elders_info();
+    let proven_old_elders_info = proven(sk0_set.secret_key(), old_elders_info.clone())?;
+    let old_section = Section::new(chain.clone(), proven_old_elders_info)?;
+
+    // Create our node
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let section_key_share = create_section_key_share(&sk1_set, 0);
+    let node = nodes.remove(0);
+    let state = Approved::new(node, old_section, Some(section_key_share), event_tx);
     let stage = Stage::new(state, create_comm().await?);
 
-    // non-elders can't handle messages addressed to sections.
-    let original_message = Message::single_src(
-        &sender_node,
-        DstLocation::Section(rand::random()),
-        Variant::UserMessage(Bytes::from_static(b"hello")),
+    // Create new `Section` as a successor to the previous one.
+    let sk2 = bls::SecretKey::random();
+    let pk2 = sk2.public_key();
+    let pk2_signature = sk1_set.secret_key().sign(bincode::serialize(&pk2)?);
+    assert!(chain.push(pk2, pk2_signature));
+
+    let old_node = nodes.remove(0);
+
+    // Create the new `EldersInfo` by replacing the last peer with a new one.
+    let new_peer = create_peer();
+    let new_elders_info = EldersInfo::new(
+        old_elders_info
+            .elders
+            .values()
+            .take(old_elders_info.elders.len() - 1)
+            .copied()
+            .chain(iter::once(new_peer)),
+        old_elders_info.prefix,
+    );
+    let new_elders: BTreeSet<_> = new_elders_info.elders.keys().copied().collect();
+    let proven_new_elders_info = proven(sk1_set.secret_key(), new_elders_info)?;
+    let new_section = Section::new(chain, proven_new_elders_info)?;
+
+    // Create the `Sync` message containing the new `Section`.
+    let message = Message::single_src(
+        &old_node,
+        DstLocation::Direct,
+        Variant::Sync {
+            section: new_section,
+            network: Network::new(),
+        },
         None,
         None,
     )?;
-    let original_message_bytes = original_message.to_bytes();
 
-    let commands = stage
+    // Handle the message.
+    let _ = stage
         .handle_command(Command::HandleMessage {
-            message: original_message,
-            sender: Some(sender_node.addr),
+            message,
+            sender: Some(old_node.addr),
         })
         .await?;
 
-    let mut bounce_sent = false;
+    // Verify our `Section` got updated.
+    assert_matches!(
+        event_rx.try_recv(),
+        Ok(Event::EldersChanged { key, elders, .. }) => {
+            assert_eq!(key, pk2);
+            assert_eq!(elders, new_elders);
+        }
+    );
 
-    // TODO: test also that the message got relayed to the elders.
+    Ok(())
+}
+
+// TODO: add test that untrusted `Sync` is not applied
+
+#[tokio::test]
+async fn receive_message_with_invalid_proof_chain() -> Result<()> {
+    let sk0_good_set = SecretKeySet::random();
+    let pk0_good = sk0_good_set.secret_key().public_key();
+
+    let node = create_node();
+    let peer = node.peer();
+
+    let chain = SectionProofChain::new(pk0_good);
+    let elders_info = EldersInfo::new(iter::once(peer), Prefix::default());
+    let proven_elders_info = proven(sk0_good_set.secret_key(), elders_info)?;
+    let section = Section::new(chain, proven_elders_info)?;
+    let section_key_share = create_section_key_share(&sk0_good_set, 0);
+
+    let state = Approved::new(
+        node,
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    // Create a message with a valid signature but invalid proof chain (the last key in the chain
+    // not signed with the previous key)
+    let sk0_bad = bls::SecretKey::random();
+    let sk1_bad = bls::SecretKey::random();
+    let pk1_bad = sk1_bad.public_key();
+    let pk1_bad_signature = sk0_bad.sign(&bincode::serialize(&pk1_bad)?);
+
+    let mut bad_chain = SectionProofChain::new(pk0_good);
+    bad_chain.push_without_validation(pk1_bad, pk1_bad_signature);
+
+    let message = PlainMessage {
+        src: Prefix::default(),
+        dst: DstLocation::Node(*peer.name()),
+        dst_key: pk0_good,
+        variant: Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        },
+    };
+    let signature = sk1_bad.sign(&bincode::serialize(&message.as_signable())?);
+    let message = Message::section_src(message, signature, bad_chain)?;
+
+    let result = stage
+        .handle_command(Command::HandleMessage {
+            message,
+            sender: Some(gen_addr()),
+        })
+        .await;
+
+    assert_matches!(result, Err(Error::InvalidMessage));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn relocation_of_non_elder() -> Result<()> {
+    relocation(RelocatedPeerRole::NonElder).await
+}
+
+const THRESHOLD: usize = majority(ELDER_SIZE) - 1;
+
+#[allow(dead_code)]
+enum RelocatedPeerRole {
+    NonElder,
+    Elder,
+}
+
+async fn relocation(relocated_peer_role: RelocatedPeerRole) -> Result<()> {
+    let sk_set = SecretKeySet::random();
+
+    let prefix: Prefix = "0".parse().unwrap();
+    let (elders_info, mut nodes) = gen_elders_info(prefix, ELDER_SIZE);
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
+
+    let non_elder_peer = create_peer();
+    let member_info = MemberInfo::joined(non_elder_peer);
+    let member_info = proven(sk_set.secret_key(), member_info)?;
+    assert!(section.update_member(member_info));
+
+    let node = nodes.remove(0);
+    let state = Approved::new(
+        node,
+        section,
+        Some(section_key_share),
+        mpsc::unbounded_channel().0,
+    );
+    let stage = Stage::new(state, create_comm().await?);
+
+    let relocated_peer = match relocated_peer_role {
+        RelocatedPeerRole::Elder => elders_info.peers().nth(1).expect("too few elders"),
+        RelocatedPeerRole::NonElder => &non_elder_peer,
+    };
+
+    let (vote, proof) = create_relocation_trigger(sk_set.secret_key(), relocated_peer.age())?;
+    let commands = stage
+        .handle_command(Command::HandleConsensus { vote, proof })
+        .await?;
+
+    let mut relocate_sent = false;
 
     for command in commands {
-        let (recipients, message) = if let Command::SendMessage {
-            recipients,
-            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
-            ..
-        } = command
-        {
-            (recipients, Message::from_bytes(Bytes::from(msg_bytes))?)
-        } else {
+        let (recipients, message) = match command {
+            Command::SendMessage {
+                recipients,
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
+            _ => continue,
+        };
+
+        if recipients != [*relocated_peer.addr()] {
             continue;
+        }
+
+        let message = match message.variant() {
+            Variant::Vote {
+                content: Vote::SendMessage { message, .. },
+                ..
+            } => message,
+            _ => continue,
         };
 
-        let (src_key, message) =
-            if let Variant::BouncedUnknownMessage { src_key, message } = message.variant() {
-                (src_key, message)
-            } else {
-                continue;
-            };
+        match relocated_peer_role {
+            RelocatedPeerRole::NonElder => {
+                let details = match &message.variant {
+                    Variant::Relocate(details) => details,
+                    _ => continue,
+                };
+
+                assert_eq!(details.pub_id, *relocated_peer.name());
+                assert_eq!(details.age, relocated_peer.age() + 1);
+            }
+            RelocatedPeerRole::Elder => {
+                let promise = match &message.variant {
+                    Variant::RelocatePromise(promise) => promise,
+                    _ => continue,
+                };
 
-        assert_eq!(recipients, expected_recipients);
-        assert_eq!(*src_key, sk.public_key());
-        assert_eq!(*message, original_message_bytes);
+                assert_eq!(promise.name, *relocated_peer.name());
+            }
+        }
 
-        bounce_sent = true;
+        relocate_sent = true;
     }
 
-    assert!(bounce_sent);
+    assert!(relocate_sent);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn handle_untrusted_message_from_peer() -> Result<()> {
-    handle_untrusted_message(UntrustedMessageSource::Peer).await
+async fn node_message_to_self() -> Result<()> {
+    message_to_self(MessageDst::Node).await
 }
 
 #[tokio::test]
-async fn handle_untrusted_accumulated_message() -> Result<()> {
-    handle_untrusted_message(UntrustedMessageSource::Accumulation).await
+async fn section_message_to_self() -> Result<()> {
+    message_to_self(MessageDst::Section).await
 }
 
-enum UntrustedMessageSource {
-    Peer,
-    Accumulation,
+enum MessageDst {
+    Node,
+    Section,
 }
 
-async fn handle_untrusted_message(source: UntrustedMessageSource) -> Result<()> {
-    let sk0 = bls::SecretKey::random();
-    let pk0 = sk0.public_key();
-    let chain = SectionProofChain::new(pk0);
-
-    let (elders_info, _) = create_elders_info();
+async fn message_to_self(dst: MessageDst) -> Result<()> {
+    let node = create_node();
+    let peer = node.peer();
+    let state = Approved::first_node(node, mpsc::unbounded_channel().0)?;
+    let stage = Stage::new(state, create_comm().await?);
 
-    let (sender, expected_recipients) = match source {
-        UntrustedMessageSource::Peer => {
-            // When the untrusted message is sent from a single peer, we should bounce it back to
-            // that peer.
-            let sender = *elders_info
-                .elders
-                .values()
-                .next()
-                .expect("elders_info is empty")
-                .addr();
-            (Some(sender), vec![sender])
-        }
-        UntrustedMessageSource::Accumulation => {
-            // When the untrusted message is the result of message accumulation, we should bounce
-            // it to our elders.
-            (
-                None,
-                elders_info
-                    .elders
-                    .values()
-                    .map(Peer::addr)
-                    .copied()
-                    .collect(),
-            )
-        }
+    let src = SrcLocation::Node(*peer.name());
+    let dst = match dst {
+        MessageDst::Node => DstLocation::Node(*peer.name()),
+        MessageDst::Section => DstLocation::Section(rand::random()),
     };
+    let content = Bytes::from_static(b"hello");
+    let correlation_id = CorrelationId::random();
 
-    let proven_elders_info = proven(&sk0, elders_info)?;
-    let section = Section::new(chain.clone(), proven_elders_info)?;
+    let commands = stage
+        .handle_command(Command::SendUserMessage {
+            src,
+            dst,
+            content: content.clone(),
+            correlation_id: Some(correlation_id),
+            traced: false,
+        })
+        .await?;
+
+    assert_matches!(&commands[..], [Command::HandleMessage { sender, message }] => {
+        assert_eq!(sender.as_ref(), Some(peer.addr()));
+        assert_eq!(message.src().src_location(), src);
+        assert_eq!(message.dst(), &dst);
+        assert_matches!(
+            message.variant(),
+            Variant::UserMessage {
+                id: rand::random(),
+                content: actual_content,
+                correlation_id: actual_correlation_id,
+                ..
+            } if actual_content == &content
+                && *actual_correlation_id == Some(correlation_id)
+        );
+    });
+
+    Ok(())
+}
 
+#[tokio::test]
+async fn large_message_to_self() -> Result<()> {
     let node = create_node();
-    let node_name = node.name();
-    let state = Approved::new(node, section, None, mpsc::unbounded_channel().0);
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let state = Approved::first_node(node.clone(), event_tx)?;
     let stage = Stage::new(state, create_comm().await?);
 
-    let sk1 = bls::SecretKey::random();
-    let pk1 = sk1.public_key();
-
-    // Create a message signed by a key now known to the node.
-    let message = PlainMessage {
-        src: Prefix::default(),
-        dst: DstLocation::Node(node_name),
-        dst_key: pk1,
-        variant: Variant::UserMessage(Bytes::from_static(b"hello")),
-    };
-    let signature = sk1.sign(&bincode::serialize(&message.as_signable())?);
-    let original_message = Message::section_src(message, signature, SectionProofChain::new(pk1))?;
+    let src = SrcLocation::Node(node.name());
+    let dst = DstLocation::Node(node.name());
+    let content = Bytes::from(vec![7; 2 * MAX_FRAGMENT_SIZE + 1]);
 
     let commands = stage
-        .handle_command(Command::HandleMessage {
-            message: original_message.clone(),
-            sender,
+        .handle_command(Command::SendUserMessage {
+            src,
+            dst,
+            content: content.clone(),
+            correlation_id: None,
+            traced: false,
         })
         .await?;
-
-    let mut bounce_sent = false;
+    assert_eq!(commands.len(), 3);
 
     for command in commands {
-        let (recipients, message) = if let Command::SendMessage {
-            recipients,
-            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
-            ..
-        } = command
-        {
-            (recipients, Message::from_bytes(Bytes::from(msg_bytes))?)
-        } else {
-            continue;
-        };
-
-        if let Variant::BouncedUntrustedMessage(bounced_message) = message.variant() {
-            assert_eq!(recipients, expected_recipients);
-            assert_eq!(**bounced_message, original_message);
-            assert_eq!(*message.dst_key(), Some(pk0));
-
-            bounce_sent = true;
-        }
+        assert_matches!(&command, Command::HandleMessage { message, .. } => {
+            assert_matches!(message.variant(), Variant::UserMessageFragment(_))
+        });
+        let _ = stage.handle_command(command).await?;
     }
 
-    assert!(bounce_sent);
+    assert_matches!(
+        event_rx.try_recv(),
+        Ok(Event::MessageReceived { content: actual_content, .. }) => {
+            assert_eq!(actual_content, content)
+        }
+    );
 
     Ok(())
 }
 
 #[tokio::test]
-async fn handle_bounced_unknown_message() -> Result<()> {
-    let (elders_info, mut nodes) = create_elders_info();
-
-    // Create section chain with two keys.
-    let sk0 = bls::SecretKey::random();
-    let pk0 = sk0.public_key();
+async fn retried_large_message_delivered_once() -> Result<()> {
+    let node = create_node();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let state = Approved::first_node(node.clone(), event_tx)?;
+    let stage = Stage::new(state, create_comm().await?);
 
-    let sk1_set = SecretKeySet::random();
-    let pk1 = sk1_set.secret_key().public_key();
-    let pk1_signature = sk0.sign(&bincode::serialize(&pk1)?);
+    let dst = DstLocation::Node(node.name());
+    let commands = stage
+        .handle_command(Command::SendUserMessage {
+            src: SrcLocation::Node(node.name()),
+            dst,
+            content: Bytes::from(vec![7; 2 * MAX_FRAGMENT_SIZE + 1]),
+            correlation_id: None,
+            traced: false,
+        })
+        .await?;
 
-    let mut section_chain = SectionProofChain::new(pk0);
-    let _ = section_chain.push(pk1, pk1_signature);
+    let mut variants = vec![];
+    for command in commands {
+        if let Command::HandleMessage { message, .. } = &command {
+            variants.push(message.variant().clone());
+        }
+        let _ = stage.handle_command(command).await?;
+    }
+    assert_eq!(variants.len(), 3);
+    assert_matches!(event_rx.try_recv(), Ok(Event::MessageReceived { .. }));
+
+    // All the fragments sent again as renewed copies, as on retry.
+    for variant in variants {
+        let message = Message::single_src(&node, dst, variant, None, None)?;
+        let _ = stage
+            .handle_command(Command::HandleMessage {
+                message,
+                sender: Some(node.addr),
+            })
+            .await?;
+    }
+    assert!(event_rx.try_recv().is_err());
 
-    let proven_elders_info = proven(&sk0, elders_info)?;
-    let section = Section::new(section_chain, proven_elders_info)?;
-    let section_key_share = create_section_key_share(&sk1_set, 0);
+    Ok(())
+}
 
-    let node = nodes.remove(0);
+#[tokio::test]
+async fn forward_multicast_to_adults() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+    let sk_set = SecretKeySet::random();
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
 
-    // Create the original message whose bounce we want to test. The content of the message doesn't
-    // matter for the purpose of this test.
-    let other_node = Node::new(crypto::gen_keypair(), gen_addr());
-    let original_message_content = Bytes::from_static(b"unknown message");
-    let original_message = Message::single_src(
-        &node,
-        DstLocation::Node(other_node.name()),
-        Variant::UserMessage(original_message_content.clone()),
-        None,
-        None,
-    )?;
+    // Two adults, only one of which is within the multicast prefix.
+    let prefix = Prefix::default().pushed(false);
+    let adult_inside = Peer::new(
+        prefix.substituted_in(rand::random()),
+        gen_addr(),
+        MIN_AGE + 1,
+    );
+    let adult_outside = Peer::new(
+        prefix.sibling().substituted_in(rand::random()),
+        gen_addr(),
+        MIN_AGE + 1,
+    );
+    for peer in [adult_inside, adult_outside].iter().copied() {
+        let member_info = proven(sk_set.secret_key(), MemberInfo::joined(peer))?;
+        assert!(section.update_member(member_info));
+    }
 
+    let node = nodes.remove(0);
     let state = Approved::new(
         node,
         section,
@@ -1055,97 +2518,63 @@ async fn handle_bounced_unknown_message() -> Result<()> {
     );
     let stage = Stage::new(state, create_comm().await?);
 
-    let bounced_message = Message::single_src(
-        &other_node,
-        DstLocation::Direct,
-        Variant::BouncedUnknownMessage {
-            src_key: pk0,
-            message: original_message.to_bytes(),
+    let sender_node = create_node();
+    let original_message = Message::single_src(
+        &sender_node,
+        DstLocation::Section(prefix.name()),
+        Variant::UserMessageMulticast {
+            prefix,
+            content: Bytes::from_static(b"hello"),
         },
         None,
         None,
     )?;
+    let original_message_hash = *original_message.hash();
 
     let commands = stage
         .handle_command(Command::HandleMessage {
-            message: bounced_message,
-            sender: Some(other_node.addr),
+            message: original_message,
+            sender: Some(sender_node.addr),
         })
         .await?;
 
-    let mut sync_sent = false;
-    let mut original_message_sent = false;
+    let mut forwarded = false;
 
     for command in commands {
-        let (recipients, message) = match command {
-            Command::SendMessage {
-                recipients,
-                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
-                ..
-            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
-            _ => continue,
+        let (recipients, message) = if let Command::SendMessage {
+            recipients,
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        } = command
+        {
+            (recipients, Message::from_bytes(Bytes::from(msg_bytes))?)
+        } else {
+            continue;
         };
 
-        match message.variant() {
-            Variant::Sync { section, .. } => {
-                assert_eq!(recipients, [other_node.addr]);
-                assert_eq!(*section.chain().last_key(), pk1);
-                sync_sent = true;
-            }
-            Variant::UserMessage(content) => {
-                assert_eq!(recipients, [other_node.addr]);
-                assert_eq!(*content, original_message_content);
-                original_message_sent = true;
-            }
-            _ => continue,
+        if recipients == [*adult_inside.addr()] {
+            assert_eq!(*message.hash(), original_message_hash);
+            forwarded = true;
         }
-    }
-
-    assert!(sync_sent);
-    assert!(original_message_sent);
-
-    Ok(())
-}
-
-#[tokio::test]
-async fn handle_bounced_untrusted_message() -> Result<()> {
-    let (elders_info, mut nodes) = create_elders_info();
-
-    // Create section chain with two keys.
-    let sk0 = bls::SecretKey::random();
-    let pk0 = sk0.public_key();
-
-    let sk1_set = SecretKeySet::random();
-    let pk1 = sk1_set.secret_key().public_key();
-    let pk1_signature = sk0.sign(&bincode::serialize(&pk1)?);
-
-    let mut chain = SectionProofChain::new(pk0);
-    let _ = chain.push(pk1, pk1_signature);
-
-    let proven_elders_info = proven(&sk0, elders_info)?;
-    let section = Section::new(chain.clone(), proven_elders_info)?;
-    let section_key_share = create_section_key_share(&sk1_set, 0);
+        assert!(!recipients.contains(adult_outside.addr()));
+    }
 
-    let node = nodes.remove(0);
+    assert!(forwarded);
 
-    // Create the original message whose bounce we want to test. Attach a proof that starts
-    // at `pk1`.
-    let other_node = Node::new(crypto::gen_keypair(), gen_addr());
+    Ok(())
+}
 
-    let original_message_content = Bytes::from_static(b"unknown message");
-    let original_message = PlainMessage {
-        src: Prefix::default(),
-        dst: DstLocation::Node(other_node.name()),
-        dst_key: pk1,
-        variant: Variant::UserMessage(original_message_content.clone()),
-    };
-    let signature = sk1_set
-        .secret_key()
-        .sign(&bincode::serialize(&original_message.as_signable())?);
-    let proof_chain = chain.slice(1..);
-    let original_message = Message::section_src(original_message, signature, proof_chain)?;
+#[tokio::test]
+async fn forward_anycast_to_closest_member() -> Result<()> {
+    let (elders_info, mut nodes) = create_elders_info();
+    let sk_set = SecretKeySet::random();
+    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
 
-    // Create our node.
+    let adult = Peer::new(rand::random(), gen_addr(), MIN_AGE + 1);
+    let member_info = proven(sk_set.secret_key(), MemberInfo::joined(adult))?;
+    assert!(section.update_member(member_info));
+
+    let node = nodes.remove(0);
     let state = Approved::new(
         node,
         section,
@@ -1154,325 +2583,569 @@ async fn handle_bounced_untrusted_message() -> Result<()> {
     );
     let stage = Stage::new(state, create_comm().await?);
 
-    // Create the bounced message, indicating the last key the peer knows is `pk0`
-    let bounced_message = Message::single_src(
-        &other_node,
-        DstLocation::Direct,
-        Variant::BouncedUntrustedMessage(Box::new(original_message)),
+    let sender_node = create_node();
+    let original_message = Message::single_src(
+        &sender_node,
+        DstLocation::Section(*adult.name()),
+        Variant::UserMessageAnycast {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        },
+        None,
         None,
-        Some(pk0),
     )?;
+    let original_message_hash = *original_message.hash();
 
     let commands = stage
         .handle_command(Command::HandleMessage {
-            message: bounced_message,
-            sender: Some(other_node.addr),
+            message: original_message,
+            sender: Some(sender_node.addr),
         })
         .await?;
 
-    let mut message_sent = false;
+    let mut forwarded = false;
 
     for command in commands {
-        let (recipients, message) = match command {
-            Command::SendMessage {
+        let (recipients, delivery_group_size, message) = if let Command::SendMessage {
+            recipients,
+            delivery_group_size,
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        } = command
+        {
+            (
                 recipients,
-                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
-                ..
-            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
-            _ => continue,
+                delivery_group_size,
+                Message::from_bytes(Bytes::from(msg_bytes))?,
+            )
+        } else {
+            continue;
         };
 
-        match message.variant() {
-            Variant::UserMessage(content) => {
-                assert_eq!(recipients, [other_node.addr]);
-                assert_eq!(*content, original_message_content);
-                assert_eq!(*message.proof_chain()?, chain);
-
-                message_sent = true;
-            }
-            _ => continue,
+        if recipients.contains(adult.addr()) {
+            assert_eq!(recipients, [*adult.addr()]);
+            assert_eq!(delivery_group_size, 1);
+            assert_eq!(*message.hash(), original_message_hash);
+            forwarded = true;
         }
     }
 
-    assert!(message_sent);
+    assert!(forwarded);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn handle_sync() -> Result<()> {
-    // Create first `Section` with a chain of length 2
-    let sk0_set = SecretKeySet::random();
-    let pk0 = sk0_set.secret_key().public_key();
-    let sk1_set = SecretKeySet::random();
-    let pk1 = sk1_set.secret_key().public_key();
-    let pk1_signature = sk0_set.secret_key().sign(bincode::serialize(&pk1)?);
+async fn retried_anycast_delivered_once_and_reported_again() -> Result<()> {
+    let (elders_info, _) = gen_elders_info(Default::default(), ELDER_SIZE);
+    let sk = bls::SecretKey::random();
+    let chain = SectionProofChain::new(sk.public_key());
+    let section = Section::new(chain, proven(&sk, elders_info)?)?;
 
-    let mut chain = SectionProofChain::new(pk0);
-    assert!(chain.push(pk1, pk1_signature));
+    let node = create_node();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let state = Approved::new(node.clone(), section, None, event_tx);
+    let stage = Stage::new(state, create_comm().await?);
 
-    let (old_elders_info, mut nodes) = create_elders_info();
-    let proven_old_elders_info = proven(sk0_set.secret_key(), old_elders_info.clone())?;
-    let old_section = Section::new(chain.clone(), proven_old_elders_info)?;
+    let sender_node = create_node();
+    let variant = Variant::UserMessageAnycast {
+        id: rand::random(),
+        content: Bytes::from_static(b"hello"),
+        correlation_id: None,
+    };
 
-    // Create our node
+    // As an adult, we only get anycast messages our elders picked us for.
+    for _ in 0..2 {
+        let message = Message::single_src(
+            &sender_node,
+            DstLocation::Section(rand::random()),
+            variant.clone(),
+            None,
+            None,
+        )?;
+        let hash = *message.hash();
+        let commands = stage
+            .handle_command(Command::HandleMessage {
+                message,
+                sender: Some(sender_node.addr),
+            })
+            .await?;
+
+        let reported = commands.into_iter().any(|command| match command {
+            Command::SendMessage {
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => matches!(
+                Message::from_bytes(Bytes::from(msg_bytes)).map(|msg| msg.variant().clone()),
+                Ok(Variant::AnycastDelivered { hash: actual }) if actual == hash
+            ),
+            _ => false,
+        });
+        assert!(reported);
+    }
+
+    assert_matches!(event_rx.try_recv(), Ok(Event::MessageReceived { .. }));
+    assert!(event_rx.try_recv().is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn anycast_delivery_reported_once() -> Result<()> {
+    let node = create_node();
     let (event_tx, mut event_rx) = mpsc::unbounded_channel();
-    let section_key_share = create_section_key_share(&sk1_set, 0);
-    let node = nodes.remove(0);
-    let state = Approved::new(node, old_section, Some(section_key_share), event_tx);
+    let state = Approved::first_node(node.clone(), event_tx)?;
     let stage = Stage::new(state, create_comm().await?);
 
-    // Create new `Section` as a successor to the previous one.
-    let sk2 = bls::SecretKey::random();
-    let pk2 = sk2.public_key();
-    let pk2_signature = sk1_set.secret_key().sign(bincode::serialize(&pk2)?);
-    assert!(chain.push(pk2, pk2_signature));
-
-    let old_node = nodes.remove(0);
+    let correlation_id = CorrelationId::random();
+    let commands = stage
+        .handle_command(Command::AnycastUserMessage {
+            target: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: Some(correlation_id),
+        })
+        .await?;
+    let hash = commands
+        .iter()
+        .find_map(|command| match command {
+            Command::HandleMessage { message, .. } => Some(*message.hash()),
+            _ => None,
+        })
+        .expect("message not handled");
+
+    // Another member of our section received it.
+    let recipient = create_node();
+    for _ in 0..2 {
+        let report = Message::single_src(
+            &recipient,
+            DstLocation::Node(node.name()),
+            Variant::AnycastDelivered { hash },
+            None,
+            None,
+        )?;
+        let _ = stage
+            .handle_command(Command::HandleMessage {
+                message: report,
+                sender: Some(recipient.addr),
+            })
+            .await?;
+    }
 
-    // Create the new `EldersInfo` by replacing the last peer with a new one.
-    let new_peer = create_peer();
-    let new_elders_info = EldersInfo::new(
-        old_elders_info
-            .elders
-            .values()
-            .take(old_elders_info.elders.len() - 1)
-            .copied()
-            .chain(iter::once(new_peer)),
-        old_elders_info.prefix,
+    assert_matches!(
+        event_rx.try_recv(),
+        Ok(Event::AnycastDelivered { recipient: actual_recipient, correlation_id: actual }) => {
+            assert_eq!(actual_recipient, recipient.name());
+            assert_eq!(actual, Some(correlation_id));
+        }
     );
-    let new_elders: BTreeSet<_> = new_elders_info.elders.keys().copied().collect();
-    let proven_new_elders_info = proven(sk1_set.secret_key(), new_elders_info)?;
-    let new_section = Section::new(chain, proven_new_elders_info)?;
+    assert!(event_rx.try_recv().is_err());
 
-    // Create the `Sync` message containing the new `Section`.
-    let message = Message::single_src(
-        &old_node,
-        DstLocation::Direct,
-        Variant::Sync {
-            section: new_section,
-            network: Network::new(),
-        },
-        None,
-        None,
-    )?;
+    Ok(())
+}
 
-    // Handle the message.
-    let _ = stage
-        .handle_command(Command::HandleMessage {
-            message,
-            sender: Some(old_node.addr),
+#[tokio::test]
+async fn user_message_delivery_failure() -> Result<()> {
+    let node = create_node();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let state = Approved::first_node(node.clone(), event_tx)?;
+    let stage = Stage::new(state, create_comm().await?);
+
+    let dst = DstLocation::Node(rand::random());
+    let content = Bytes::from_static(b"hello");
+    let correlation_id = CorrelationId::random();
+
+    let mut commands = stage
+        .handle_command(Command::SendUserMessage {
+            src: SrcLocation::Node(node.name()),
+            dst,
+            content: content.clone(),
+            correlation_id: Some(correlation_id),
+            traced: false,
         })
         .await?;
 
-    // Verify our `Section` got updated.
+    // The message is not acknowledged, so it's resent on every timeout until we give up.
+    for _ in 0..MAX_DELIVERY_ATTEMPTS {
+        let token = assert_matches!(
+            &commands[..],
+            [.., Command::ScheduleTimeout { duration, token }] if *duration == ACK_TIMEOUT => *token
+        );
+        commands = stage.handle_command(Command::HandleTimeout(token)).await?;
+    }
+
+    assert!(commands.is_empty());
     assert_matches!(
         event_rx.try_recv(),
-        Ok(Event::EldersChanged { key, elders, .. }) => {
-            assert_eq!(key, pk2);
-            assert_eq!(elders, new_elders);
+        Ok(Event::MessageDeliveryFailed {
+            content: actual_content,
+            dst: actual_dst,
+            correlation_id: Some(actual_correlation_id),
+        }) => {
+            assert_eq!(actual_content, content);
+            assert_eq!(actual_dst, dst);
+            assert_eq!(actual_correlation_id, correlation_id);
         }
     );
 
     Ok(())
 }
 
-// TODO: add test that untrusted `Sync` is not applied
+#[tokio::test]
+async fn user_message_retry_gets_through() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&["0", "1"], &[ELDER_SIZE, ELDER_SIZE])?;
+    let p0: Prefix = "0".parse().unwrap();
+    let p1: Prefix = "1".parse().unwrap();
+    let (mut sender, _) = network.approved(&p0, 0)?;
+    let (mut relay, _) = network.approved(&p0, 1)?;
+
+    let dst = DstLocation::Node(network.section(&p1).unwrap().nodes[0].name());
+    let commands = sender.send_user_message(
+        SrcLocation::Node(sender.node().name()),
+        dst,
+        Bytes::from_static(b"hello"),
+        Some(CorrelationId::random()),
+        false,
+    )?;
+    let (original, token) = sent_message_and_ack_timeout(commands)?;
+
+    // A relay on the original route remembers the message, so it would drop a copy of it.
+    let _ = relay.handle_message(None, original.clone()).await?;
+    assert!(relay.is_known_message(original.hash()));
+
+    // The retry is a new message, so it gets through.
+    let (retry, _) = sent_message_and_ack_timeout(sender.handle_timeout(token)?)?;
+    assert_ne!(retry.hash(), original.hash());
+    assert_eq!(retry.variant(), original.variant());
+    assert!(!relay.is_known_message(retry.hash()));
+
+    Ok(())
+}
 
 #[tokio::test]
-async fn receive_message_with_invalid_proof_chain() -> Result<()> {
-    let sk0_good_set = SecretKeySet::random();
-    let pk0_good = sk0_good_set.secret_key().public_key();
+async fn user_message_to_client_not_tracked() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&["0", "1"], &[ELDER_SIZE, ELDER_SIZE])?;
+    let p0: Prefix = "0".parse().unwrap();
+    let p1: Prefix = "1".parse().unwrap();
+    let (mut sender, _) = network.approved(&p0, 0)?;
+
+    // A client connected to the other section.
+    let end_user = loop {
+        let end_user = EndUser::AllClients(EndUserPK::Ed25519(crypto::gen_keypair().public));
+        if p1.matches(&end_user.name()) {
+            break end_user;
+        }
+    };
 
-    let node = create_node();
-    let peer = node.peer();
+    let commands = sender.send_user_message(
+        SrcLocation::Node(sender.node().name()),
+        DstLocation::EndUser(end_user),
+        Bytes::from_static(b"hello"),
+        Some(CorrelationId::random()),
+        false,
+    )?;
 
-    let chain = SectionProofChain::new(pk0_good);
-    let elders_info = EldersInfo::new(iter::once(peer), Prefix::default());
-    let proven_elders_info = proven(sk0_good_set.secret_key(), elders_info)?;
-    let section = Section::new(chain, proven_elders_info)?;
-    let section_key_share = create_section_key_share(&sk0_good_set, 0);
+    // Clients don't acknowledge messages, so there is no retry nor delivery failure.
+    assert!(commands
+        .iter()
+        .any(|command| matches!(command, Command::SendMessage { .. })));
+    assert!(!commands
+        .iter()
+        .any(|command| matches!(command, Command::ScheduleTimeout { .. })));
 
-    let state = Approved::new(
-        node,
-        section,
-        Some(section_key_share),
-        mpsc::unbounded_channel().0,
-    );
-    let stage = Stage::new(state, create_comm().await?);
+    Ok(())
+}
 
-    // Create a message with a valid signature but invalid proof chain (the last key in the chain
-    // not signed with the previous key)
-    let sk0_bad = bls::SecretKey::random();
-    let sk1_bad = bls::SecretKey::random();
-    let pk1_bad = sk1_bad.public_key();
-    let pk1_bad_signature = sk0_bad.sign(&bincode::serialize(&pk1_bad)?);
+#[tokio::test]
+async fn large_section_message_fragments_accumulate() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&["0", "1"], &[ELDER_SIZE, ELDER_SIZE])?;
+    let p0: Prefix = "0".parse().unwrap();
+    let p1: Prefix = "1".parse().unwrap();
+
+    let src = SrcLocation::Section(p0);
+    let dst = DstLocation::Node(network.section(&p1).unwrap().nodes[0].name());
+    let content = Bytes::from(vec![7; 2 * MAX_FRAGMENT_SIZE + 1]);
+    let correlation_id = Some(CorrelationId::random());
+
+    // Every elder sending the message on behalf of the section must vote for the same fragments,
+    // otherwise the votes never accumulate.
+    let mut votes_by_elder = vec![];
+    for index in 0..2 {
+        let (mut elder, _) = network.approved(&p0, index)?;
+        let commands = elder.send_user_message(src, dst, content.clone(), correlation_id, false)?;
+        let votes: Vec<_> = commands
+            .into_iter()
+            .filter_map(|command| match command {
+                Command::HandleVote { vote, .. } => Some(vote),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(votes.len(), 3);
+        votes_by_elder.push(votes);
+    }
 
-    let mut bad_chain = SectionProofChain::new(pk0_good);
-    bad_chain.push_without_validation(pk1_bad, pk1_bad_signature);
+    assert_eq!(votes_by_elder[0], votes_by_elder[1]);
 
-    let message = PlainMessage {
-        src: Prefix::default(),
-        dst: DstLocation::Node(*peer.name()),
-        dst_key: pk0_good,
-        variant: Variant::UserMessage(Bytes::from_static(b"hello")),
-    };
-    let signature = sk1_bad.sign(&bincode::serialize(&message.as_signable())?);
-    let message = Message::section_src(message, signature, bad_chain)?;
+    Ok(())
+}
 
-    let result = stage
+// The message sent by `commands` and the token of the timeout for its ack.
+fn sent_message_and_ack_timeout(commands: Vec<Command>) -> Result<(Message, u64)> {
+    let mut message = None;
+    let mut token = None;
+
+    for command in commands {
+        match command {
+            Command::SendMessage {
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => message = Some(Message::from_bytes(Bytes::from(msg_bytes))?),
+            Command::ScheduleTimeout {
+                duration,
+                token: command_token,
+            } if duration == ACK_TIMEOUT => token = Some(command_token),
+            _ => (),
+        }
+    }
+
+    match (message, token) {
+        (Some(message), Some(token)) => Ok((message, token)),
+        _ => Err(anyhow::anyhow!("message not sent for ack")),
+    }
+}
+
+#[tokio::test]
+async fn user_message_delivery_confirmed() -> Result<()> {
+    let node = create_node();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let comm = create_comm().await?;
+    let state = Approved::first_node(node.clone(), event_tx)?.with_stats(comm.stats().clone());
+    let stage = Stage::new(state, comm);
+
+    let dst_node = create_node();
+    let dst = DstLocation::Node(dst_node.name());
+    let correlation_id = CorrelationId::random();
+
+    let commands = stage
+        .handle_command(Command::SendUserMessage {
+            src: SrcLocation::Node(node.name()),
+            dst,
+            content: Bytes::from_static(b"hello"),
+            correlation_id: Some(correlation_id),
+            traced: false,
+        })
+        .await?;
+
+    let hash = commands
+        .into_iter()
+        .find_map(|command| match command {
+            Command::SendMessage {
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => Message::from_bytes(Bytes::from(msg_bytes))
+                .ok()
+                .map(|message| *message.hash()),
+            _ => None,
+        })
+        .expect("message not sent");
+
+    let ack = Message::single_src(
+        &dst_node,
+        DstLocation::Node(node.name()),
+        Variant::UserMessageAck { hash },
+        None,
+        None,
+    )?;
+    let _ = stage
         .handle_command(Command::HandleMessage {
-            message,
-            sender: Some(gen_addr()),
+            message: ack,
+            sender: Some(dst_node.addr),
         })
-        .await;
+        .await?;
 
-    assert_matches!(result, Err(Error::InvalidMessage));
+    assert_matches!(
+        event_rx.try_recv(),
+        Ok(Event::MessageDelivered {
+            dst: actual_dst,
+            correlation_id: actual_correlation_id,
+        }) => {
+            assert_eq!(actual_dst, dst);
+            assert_eq!(actual_correlation_id, correlation_id);
+        }
+    );
+
+    // The round trip to the ack is measured.
+    let latency = stage.comm.stats().get().latency;
+    assert_eq!(
+        latency.get(Priority::UserData.name()).map(|h| h.count()),
+        Some(1)
+    );
 
     Ok(())
 }
 
 #[tokio::test]
-async fn relocation_of_non_elder() -> Result<()> {
-    relocation(RelocatedPeerRole::NonElder).await
-}
-
-const THRESHOLD: usize = majority(ELDER_SIZE) - 1;
-
-#[allow(dead_code)]
-enum RelocatedPeerRole {
-    NonElder,
-    Elder,
-}
-
-async fn relocation(relocated_peer_role: RelocatedPeerRole) -> Result<()> {
-    let sk_set = SecretKeySet::random();
-
-    let prefix: Prefix = "0".parse().unwrap();
-    let (elders_info, mut nodes) = gen_elders_info(prefix, ELDER_SIZE);
-    let (mut section, section_key_share) = create_section(&sk_set, &elders_info)?;
-
-    let non_elder_peer = create_peer();
-    let member_info = MemberInfo::joined(non_elder_peer);
-    let member_info = proven(sk_set.secret_key(), member_info)?;
-    assert!(section.update_member(member_info));
+async fn ack_user_message() -> Result<()> {
+    let (elders_info, _) = gen_elders_info(Default::default(), ELDER_SIZE);
+    let sk = bls::SecretKey::random();
+    let chain = SectionProofChain::new(sk.public_key());
+    let section = Section::new(chain, proven(&sk, elders_info)?)?;
 
-    let node = nodes.remove(0);
-    let state = Approved::new(
-        node,
-        section,
-        Some(section_key_share),
-        mpsc::unbounded_channel().0,
-    );
+    let node = create_node();
+    let state = Approved::new(node.clone(), section, None, mpsc::unbounded_channel().0);
     let stage = Stage::new(state, create_comm().await?);
 
-    let relocated_peer = match relocated_peer_role {
-        RelocatedPeerRole::Elder => elders_info.peers().nth(1).expect("too few elders"),
-        RelocatedPeerRole::NonElder => &non_elder_peer,
-    };
+    let sender_node = create_node();
+    let original_message = Message::single_src(
+        &sender_node,
+        DstLocation::Node(node.name()),
+        Variant::UserMessage {
+            id: rand::random(),
+            content: Bytes::from_static(b"hello"),
+            correlation_id: None,
+        },
+        None,
+        None,
+    )?;
+    let original_message_hash = *original_message.hash();
 
-    let (vote, proof) = create_relocation_trigger(sk_set.secret_key(), relocated_peer.age())?;
     let commands = stage
-        .handle_command(Command::HandleConsensus { vote, proof })
+        .handle_command(Command::HandleMessage {
+            message: original_message,
+            sender: Some(sender_node.addr),
+        })
         .await?;
 
-    let mut relocate_sent = false;
+    let mut ack_sent = false;
 
     for command in commands {
-        let (recipients, message) = match command {
-            Command::SendMessage {
-                recipients,
-                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
-                ..
-            } => (recipients, Message::from_bytes(Bytes::from(msg_bytes))?),
-            _ => continue,
+        let message = if let Command::SendMessage {
+            message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+            ..
+        } = command
+        {
+            Message::from_bytes(Bytes::from(msg_bytes))?
+        } else {
+            continue;
         };
 
-        if recipients != [*relocated_peer.addr()] {
-            continue;
+        if let Variant::UserMessageAck { hash } = message.variant() {
+            assert_eq!(*hash, original_message_hash);
+            assert_eq!(message.dst(), &DstLocation::Node(sender_node.name()));
+            ack_sent = true;
         }
+    }
 
-        let message = match message.variant() {
-            Variant::Vote {
-                content: Vote::SendMessage { message, .. },
-                ..
-            } => message,
-            _ => continue,
-        };
+    assert!(ack_sent);
 
-        match relocated_peer_role {
-            RelocatedPeerRole::NonElder => {
-                let details = match &message.variant {
-                    Variant::Relocate(details) => details,
-                    _ => continue,
-                };
+    Ok(())
+}
 
-                assert_eq!(details.pub_id, *relocated_peer.name());
-                assert_eq!(details.age, relocated_peer.age() + 1);
-            }
-            RelocatedPeerRole::Elder => {
-                let promise = match &message.variant {
-                    Variant::RelocatePromise(promise) => promise,
-                    _ => continue,
-                };
+#[tokio::test]
+async fn resent_user_message_delivered_once() -> Result<()> {
+    let (elders_info, _) = gen_elders_info(Default::default(), ELDER_SIZE);
+    let sk = bls::SecretKey::random();
+    let chain = SectionProofChain::new(sk.public_key());
+    let section = Section::new(chain, proven(&sk, elders_info)?)?;
 
-                assert_eq!(promise.name, *relocated_peer.name());
-            }
-        }
+    let node = create_node();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let state = Approved::new(node.clone(), section, None, event_tx);
+    let stage = Stage::new(state, create_comm().await?);
 
-        relocate_sent = true;
+    let sender_node = create_node();
+    let correlation_id = CorrelationId::from_content(b"request");
+
+    // The source resends the request as a new message, but with the same correlation id.
+    for _ in 0..2 {
+        let message = Message::single_src(
+            &sender_node,
+            DstLocation::Node(node.name()),
+            Variant::UserMessage {
+                id: rand::random(),
+                content: Bytes::from_static(b"hello"),
+                correlation_id: Some(correlation_id),
+            },
+            None,
+            None,
+        )?;
+        let _ = stage
+            .handle_command(Command::HandleMessage {
+                message,
+                sender: Some(sender_node.addr),
+            })
+            .await?;
     }
 
-    assert!(relocate_sent);
+    assert_matches!(
+        event_rx.try_recv(),
+        Ok(Event::MessageReceived { correlation_id: Some(actual), .. }) => {
+            assert_eq!(actual, correlation_id)
+        }
+    );
+    assert!(event_rx.try_recv().is_err());
 
     Ok(())
 }
 
 #[tokio::test]
-async fn node_message_to_self() -> Result<()> {
-    message_to_self(MessageDst::Node).await
-}
-
-#[tokio::test]
-async fn section_message_to_self() -> Result<()> {
-    message_to_self(MessageDst::Section).await
-}
-
-enum MessageDst {
-    Node,
-    Section,
-}
+async fn retried_user_message_delivered_once() -> Result<()> {
+    let (elders_info, _) = gen_elders_info(Default::default(), ELDER_SIZE);
+    let sk = bls::SecretKey::random();
+    let chain = SectionProofChain::new(sk.public_key());
+    let section = Section::new(chain, proven(&sk, elders_info)?)?;
 
-async fn message_to_self(dst: MessageDst) -> Result<()> {
     let node = create_node();
-    let peer = node.peer();
-    let state = Approved::first_node(node, mpsc::unbounded_channel().0)?;
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let state = Approved::new(node.clone(), section, None, event_tx);
     let stage = Stage::new(state, create_comm().await?);
 
-    let src = SrcLocation::Node(*peer.name());
-    let dst = match dst {
-        MessageDst::Node => DstLocation::Node(*peer.name()),
-        MessageDst::Section => DstLocation::Section(rand::random()),
+    let sender_node = create_node();
+    let variant = Variant::UserMessage {
+        id: rand::random(),
+        content: Bytes::from_static(b"hello"),
+        correlation_id: None,
     };
-    let content = Bytes::from_static(b"hello");
 
-    let commands = stage
-        .handle_command(Command::SendUserMessage {
-            src,
-            dst,
-            content: content.clone(),
-        })
-        .await?;
+    // The source retries the message with a fresh nonce, so every copy has a different hash, but
+    // the same id. Every copy is acknowledged, as the source waits for the ack of the latest one.
+    for _ in 0..2 {
+        let message = Message::single_src(
+            &sender_node,
+            DstLocation::Node(node.name()),
+            variant.clone(),
+            None,
+            None,
+        )?;
+        let hash = *message.hash();
+        let commands = stage
+            .handle_command(Command::HandleMessage {
+                message,
+                sender: Some(sender_node.addr),
+            })
+            .await?;
 
-    assert_matches!(&commands[..], [Command::HandleMessage { sender, message }] => {
-        assert_eq!(sender.as_ref(), Some(peer.addr()));
-        assert_eq!(message.src().src_location(), src);
-        assert_eq!(message.dst(), &dst);
-        assert_matches!(
-            message.variant(),
-            Variant::UserMessage(actual_content) if actual_content == &content
-        );
-    });
+        let acked = commands.into_iter().any(|command| match command {
+            Command::SendMessage {
+                message: MessageType::NodeMessage(NodeMessage(msg_bytes)),
+                ..
+            } => matches!(
+                Message::from_bytes(Bytes::from(msg_bytes)).map(|ack| ack.variant().clone()),
+                Ok(Variant::UserMessageAck { hash: actual }) if actual == hash
+            ),
+            _ => false,
+        });
+        assert!(acked);
+    }
+
+    assert_matches!(
+        event_rx.try_recv(),
+        Ok(Event::MessageReceived {
+            correlation_id: None,
+            ..
+        })
+    );
+    assert!(event_rx.try_recv().is_err());
 
     Ok(())
 }
@@ -1727,6 +3400,292 @@ async fn handle_demote_during_split() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_network_topology() -> Result<()> {
+    let prefixes = ["0", "10", "11"];
+    let network = TestNetwork::new(0).with_sections(&prefixes, &[ELDER_SIZE, ELDER_SIZE + 2, 3])?;
+
+    for (prefix, size) in prefixes.iter().zip(&[ELDER_SIZE, ELDER_SIZE + 2, 3]) {
+        let prefix: Prefix = prefix.parse().unwrap();
+        let section = network.section(&prefix).unwrap();
+        assert_eq!(section.nodes.len(), *size);
+        assert_eq!(section.section.members().joined().count(), *size);
+        assert!(section
+            .nodes
+            .iter()
+            .all(|node| prefix.matches(&node.name())));
+        assert_eq!(section.section.chain().first_key(), &network.genesis_key());
+
+        // The elders are stable.
+        assert!(section
+            .section
+            .promote_and_demote_elders(&Default::default(), &section.nodes[0].name())
+            .is_empty());
+    }
+
+    let p10: Prefix = "10".parse().unwrap();
+    let (state, _) = network.approved(&p10, 0)?;
+    assert!(state.is_elder());
+    for other in &["0", "11"] {
+        let other: Prefix = other.parse().unwrap();
+        let their_key = network
+            .section(&other)
+            .unwrap()
+            .sk_set
+            .secret_key()
+            .public_key();
+        assert_eq!(state.network().key_by_prefix(&other), Some(&their_key));
+    }
+
+    let (state, _) = network.approved(&p10, ELDER_SIZE)?;
+    assert!(!state.is_elder());
+
+    // Same seed, same network.
+    let other = TestNetwork::new(0).with_sections(&prefixes, &[ELDER_SIZE, ELDER_SIZE + 2, 3])?;
+    let names = |network: &TestNetwork| -> Vec<_> {
+        network
+            .section(&p10)
+            .unwrap()
+            .nodes
+            .iter()
+            .map(Node::name)
+            .collect()
+    };
+    assert_eq!(names(&other), names(&network));
+
+    assert!(TestNetwork::new(0)
+        .with_sections(&["0", "01"], &[1, 1])
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_network_to_dot() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&["0", "1"], &[ELDER_SIZE, ELDER_SIZE + 1])?;
+    let dot = network.to_dot()?;
+
+    assert!(dot.starts_with("digraph topology {"));
+    assert_eq!(dot.matches("subgraph cluster_").count(), 2);
+    assert_eq!(dot.matches("doublecircle").count(), 2 * ELDER_SIZE);
+    assert_eq!(dot.matches("shape=circle];").count(), 1);
+
+    let p0: Prefix = "0".parse().unwrap();
+    let p1: Prefix = "1".parse().unwrap();
+    assert!(dot.contains(&format!(
+        "\"{:?}\" -> \"{:?}\" [label=\"#1\", style=solid];",
+        p0, p1
+    )));
+    assert!(dot.contains(&format!(
+        "\"{:?}\" -> \"{:?}\" [label=\"#1\", style=solid];",
+        p1, p0
+    )));
+
+    Ok(())
+}
+
+#[test]
+fn redundant_routes_are_disjoint() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(
+        &["00", "01", "10", "11"],
+        &[ELDER_SIZE, ELDER_SIZE, ELDER_SIZE, ELDER_SIZE],
+    )?;
+    let p00: Prefix = "00".parse().unwrap();
+    let p01: Prefix = "01".parse().unwrap();
+    let p10: Prefix = "10".parse().unwrap();
+    let p11: Prefix = "11".parse().unwrap();
+    let (state, _) = network.approved(&p00, 0)?;
+
+    let network_params = NetworkParams {
+        redundant_routes: 2,
+        ..Default::default()
+    };
+    let target = p11.substituted_in(rand::random());
+    let dst = DstLocation::Section(target);
+
+    let regular = delivery_group::delivery_targets(
+        &network_params,
+        &dst,
+        &state.node().name(),
+        state.section(),
+        state.network(),
+    )?;
+    let (targets, dg_size) = delivery_group::add_redundant_routes(
+        &network_params,
+        regular.clone(),
+        &target,
+        network_params.redundant_routes,
+        state.section(),
+        state.network(),
+    );
+    assert_eq!(dg_size, targets.len());
+
+    // We don't know the target section, as it's not our neighbour. The first route goes through
+    // the known section closest to it and the second one through the next closest, without any
+    // next hop in common.
+    let (first, second) = targets.split_at(regular.1);
+    assert!(first.iter().all(|peer| p10.matches(peer.name())));
+    assert!(!second.is_empty());
+    assert!(second.iter().all(|peer| p01.matches(peer.name())));
+
+    Ok(())
+}
+
+#[test]
+fn refresh_outdated_neighbour() -> Result<()> {
+    let network = TestNetwork::new(0).with_sections(&["0", "1"], &[ELDER_SIZE, ELDER_SIZE])?;
+    let p0: Prefix = "0".parse().unwrap();
+    let p1: Prefix = "1".parse().unwrap();
+    let ours = network.section(&p0).unwrap();
+    let theirs = network.section(&p1).unwrap();
+
+    // They know only the genesis key of ours.
+    let mut outdated = Network::new();
+    let _ = outdated.update_neighbour_info(theirs.section.proven_elders_info().clone());
+    let _ = outdated.update_their_key(proven(
+        ours.sk_set.secret_key(),
+        (p1, theirs.sk_set.secret_key().public_key()),
+    )?);
+    assert!(ours.section.chain().last_key_index() > outdated.knowledge_by_section(&p1));
+
+    let (state, _) = network.approved(&p0, 0)?;
+    let mut state = state.with_network(outdated);
+
+    let token = assert_matches!(
+        state.schedule_periodic_timers().first(),
+        Some(Command::ScheduleTimeout { token, .. }) => *token
+    );
+    let commands = state.handle_timeout(token)?;
+
+    // Our info is sent to all their elders, not just a delivery group.
+    let their_elders: BTreeSet<_> = theirs
+        .section
+        .elders_info()
+        .peers()
+        .map(Peer::addr)
+        .collect();
+    assert!(commands.iter().any(|command| matches!(
+        command,
+        Command::SendMessage { recipients, delivery_group_size, .. }
+            if *delivery_group_size == their_elders.len()
+                && recipients.iter().collect::<BTreeSet<_>>() == their_elders
+    )));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn force_split() -> Result<()> {
+    let network_params = NetworkParams::default();
+    let mut network = TestNetwork::new(0).with_sections(&["0", "1"], &[ELDER_SIZE, ELDER_SIZE])?;
+
+    let p0: Prefix = "0".parse().unwrap();
+    let commands = network.force_split(&p0, &network_params)?;
+
+    let (state, _) = network.approved(&p0, 0)?;
+    let our_name = state.node().name();
+    assert!(state
+        .section()
+        .is_split_expected(&network_params, &our_name));
+
+    let stage = Stage::new(state, create_comm().await?);
+    for command in commands {
+        let _ = stage.handle_command(command).await?;
+    }
+
+    network.complete_split(&p0)?;
+    let child = network
+        .section(&p0.pushed(our_name.bit(1)))
+        .ok_or_else(|| anyhow::anyhow!("missing child section"))?;
+
+    let state = stage.state.lock().await;
+    assert_eq!(state.section().prefix(), child.section.prefix());
+    assert_eq!(
+        state.section().chain().last_key(),
+        child.section.chain().last_key()
+    );
+    assert_eq!(state.section().elders_info(), child.section.elders_info());
+    assert!(network.section(&p0).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_network_snapshot() -> Result<()> {
+    let network_params = NetworkParams::default();
+    let build = || TestNetwork::new(0).with_sections(&["0", "1"], &[ELDER_SIZE, ELDER_SIZE + 2]);
+
+    let mut network = build()?;
+    let mut restored = TestNetwork::restore(&network.snapshot()?)?;
+
+    let p0: Prefix = "0".parse().unwrap();
+    let p1: Prefix = "1".parse().unwrap();
+    let names = |network: &TestNetwork, prefix: &Prefix| -> Vec<_> {
+        network
+            .section(prefix)
+            .unwrap()
+            .nodes
+            .iter()
+            .map(Node::name)
+            .collect()
+    };
+
+    assert_eq!(restored.genesis_key(), network.genesis_key());
+    for prefix in &[p0, p1] {
+        let ours = network.section(prefix).unwrap();
+        let theirs = restored.section(prefix).unwrap();
+        assert_eq!(theirs.section, ours.section);
+        assert_eq!(theirs.sk_set.public_keys(), ours.sk_set.public_keys());
+        assert_eq!(names(&restored, prefix), names(&network, prefix));
+    }
+
+    // The restored network continues the same as the original.
+    let _ = network.force_split(&p0, &network_params)?;
+    let _ = restored.force_split(&p0, &network_params)?;
+    network.complete_split(&p0)?;
+    restored.complete_split(&p0)?;
+    for prefix in &[p0.pushed(false), p0.pushed(true)] {
+        assert_eq!(names(&restored, prefix), names(&network, prefix));
+    }
+
+    // Built only once, then restored from the file.
+    let path =
+        std::env::temp_dir().join(format!("sn_routing_test_network_{}", rand::random::<u64>()));
+    let mut builds = 0;
+    for _ in 0..2 {
+        let network = TestNetwork::load_or_build(&path, || {
+            builds += 1;
+            build()
+        })?;
+        assert_eq!(names(&network, &p1), names(&restored, &p1));
+    }
+    assert_eq!(builds, 1);
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dump_state() -> Result<()> {
+    let node = create_node();
+    let name = node.name();
+    let state = Approved::first_node(node, mpsc::unbounded_channel().0)?;
+    let stage = Stage::new(state, create_comm().await?);
+
+    let path = std::env::temp_dir().join(format!("sn_routing-state-dump-{:x}", name));
+    stage.dump_state(&path).await?;
+    let dump = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+
+    assert!(dump.contains(&format!("name: {:x}", name)));
+    assert!(dump.contains("elder: true"));
+    assert!(dump.contains("# Section chain\n0: "));
+    assert!(dump.contains(&format!("member {:x}", name)));
+    assert!(dump.contains("# Connections"));
+
+    Ok(())
+}
+
 // TODO: add more tests here
 
 fn create_peer() -> Peer {
@@ -1734,7 +3693,8 @@ fn create_peer() -> Peer {
 }
 
 fn create_peer_in_prefix(prefix: &Prefix) -> Peer {
-    Peer::new(prefix.substituted_in(rand::random()), gen_addr(), MIN_AGE)
+    let name = XorName::random_within(prefix, &mut rand::thread_rng());
+    Peer::new(name, gen_addr(), MIN_AGE)
 }
 
 fn create_node() -> Node {
@@ -1743,14 +3703,17 @@ fn create_node() -> Node {
 
 async fn create_comm() -> Result<Comm> {
     let (tx, _rx) = mpsc::channel(1);
+    let transport = QuicTransport::new(qp2p::Config {
+        local_ip: Some(Ipv4Addr::LOCALHOST.into()),
+        ..Default::default()
+    })
+    .await?;
+
     Ok(Comm::new(
-        qp2p::Config {
-            local_ip: Some(Ipv4Addr::LOCALHOST.into()),
-            ..Default::default()
-        },
+        Box::new(transport),
+        MessageSizeLimits::default(),
         tx,
-    )
-    .await?)
+    ))
 }
 
 // Generate random EldersInfo and the corresponding Nodes.
@@ -1814,18 +3777,32 @@ fn create_relocation_trigger(sk: &bls::SecretKey, age: u8) -> Result<(Vote, Proo
 // Wrapper for `bls::SecretKeySet` that also allows to retrieve the corresponding `bls::SecretKey`.
 // Note: `bls::SecretKeySet` does have a `secret_key` method, but it's test-only and not available
 // for the consumers of the crate.
+// The keys are derived from a seed, which allows to recreate them, e.g. from a snapshot.
 pub(crate) struct SecretKeySet {
+    seed: [u8; 32],
     set: bls::SecretKeySet,
     key: bls::SecretKey,
 }
 
 impl SecretKeySet {
     pub fn random() -> Self {
-        let poly = bls::poly::Poly::random(THRESHOLD, &mut rand::thread_rng());
+        Self::random_from_rng(&mut rand::thread_rng())
+    }
+
+    pub fn random_from_rng<R: rand::Rng>(rng: &mut R) -> Self {
+        Self::from_seed(rng.gen())
+    }
+
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let poly = bls::poly::Poly::random(THRESHOLD, &mut ChaChaRng::from_seed(seed));
         let key = bls::SecretKey::from_mut(&mut poly.evaluate(0));
         let set = bls::SecretKeySet::from(poly);
 
-        Self { set, key }
+        Self { seed, set, key }
+    }
+
+    pub fn seed(&self) -> [u8; 32] {
+        self.seed
     }
 
     pub fn secret_key(&self) -> &bls::SecretKey {
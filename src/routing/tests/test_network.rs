@@ -0,0 +1,512 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{create_section_key_share, topology, SecretKeySet};
+use crate::{
+    consensus::{test_utils::proven, Vote},
+    crypto,
+    event::Event,
+    network::Network,
+    network_params::NetworkParams,
+    node::Node,
+    routing::{storage, Approved, Command},
+    section::{test_utils::gen_addr, EldersInfo, MemberInfo, Section, SectionProofChain, MIN_AGE},
+    ELDER_SIZE,
+};
+use anyhow::{anyhow, Result};
+use bls_signature_aggregator::Proof;
+use ed25519_dalek::Keypair;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, path::Path};
+use tokio::sync::mpsc;
+use xor_name::Prefix;
+
+// Network already split into sections with the given prefixes, built directly instead of adding
+// nodes one by one until the sections split. Every section knows all its neighbours, their keys
+// and that they trust its key. Everything is derived from the seed, so the same seed always gives
+// the same network.
+//
+// Building a big network takes a while, so it can be snapshotted once and restored in many tests,
+// see `load_or_build`.
+pub(crate) struct TestNetwork {
+    rng: ChaChaRng,
+    genesis_seed: [u8; 32],
+    genesis_key: bls::SecretKey,
+    sections: Vec<TestSection>,
+    // Child sections of the sections being split by `force_split`.
+    splits: HashMap<Prefix, (TestSection, TestSection)>,
+}
+
+pub(crate) struct TestSection {
+    pub sk_set: SecretKeySet,
+    pub section: Section,
+    // The elders sorted by name, followed by the adults.
+    pub nodes: Vec<Node>,
+}
+
+impl TestNetwork {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = ChaChaRng::seed_from_u64(seed);
+        let genesis_seed = rng.gen();
+
+        Self {
+            rng,
+            genesis_seed,
+            genesis_key: genesis_key_from_seed(genesis_seed),
+            sections: vec![],
+            splits: HashMap::new(),
+        }
+    }
+
+    // Add a section for each of `prefixes` with the corresponding number of nodes in `sizes`.
+    // The first `ELDER_SIZE` nodes of each section are its elders, older than the adults so they
+    // stay elders.
+    pub fn with_sections(mut self, prefixes: &[&str], sizes: &[usize]) -> Result<Self> {
+        if prefixes.len() != sizes.len() {
+            return Err(anyhow!(
+                "{} prefixes but {} sizes",
+                prefixes.len(),
+                sizes.len()
+            ));
+        }
+
+        for (prefix, size) in prefixes.iter().zip(sizes) {
+            let prefix: Prefix = prefix
+                .parse()
+                .map_err(|_| anyhow!("invalid prefix {}", prefix))?;
+            if let Some(other) = self
+                .sections
+                .iter()
+                .map(|section| section.section.prefix())
+                .find(|other| other.is_compatible(&prefix))
+            {
+                return Err(anyhow!("prefix {:?} overlaps {:?}", prefix, other));
+            }
+
+            let section = self.create_section(prefix, *size)?;
+            self.sections.push(section);
+        }
+
+        Ok(self)
+    }
+
+    // Serialize the whole network, including the secret keys, to be restored by `restore`. The
+    // restored network continues the same as this one would: the random generator is reseeded
+    // from itself and the seed included in the snapshot.
+    pub fn snapshot(&mut self) -> Result<Vec<u8>> {
+        let rng_seed = self.rng.gen();
+        self.rng = ChaChaRng::from_seed(rng_seed);
+
+        let snapshot = NetworkSnapshot {
+            rng_seed,
+            genesis_seed: self.genesis_seed,
+            sections: self.sections.iter().map(SectionSnapshot::new).collect(),
+            splits: self
+                .splits
+                .iter()
+                .map(|(prefix, (child0, child1))| {
+                    (
+                        *prefix,
+                        SectionSnapshot::new(child0),
+                        SectionSnapshot::new(child1),
+                    )
+                })
+                .collect(),
+        };
+
+        Ok(bincode::serialize(&snapshot)?)
+    }
+
+    // Recreate the network serialized by `snapshot`.
+    pub fn restore(bytes: &[u8]) -> Result<Self> {
+        let snapshot: NetworkSnapshot = bincode::deserialize(bytes)?;
+
+        Ok(Self {
+            rng: ChaChaRng::from_seed(snapshot.rng_seed),
+            genesis_seed: snapshot.genesis_seed,
+            genesis_key: genesis_key_from_seed(snapshot.genesis_seed),
+            sections: snapshot
+                .sections
+                .into_iter()
+                .map(SectionSnapshot::restore)
+                .collect::<Result<_>>()?,
+            splits: snapshot
+                .splits
+                .into_iter()
+                .map(|(prefix, child0, child1)| {
+                    Ok((prefix, (child0.restore()?, child1.restore()?)))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    // Restore the network snapshotted into the file at `path` or, if there is none or it can't be
+    // restored (e.g. because it's from an older version), build it with `build` and snapshot it
+    // there, so the next tests using the same file don't have to build it again.
+    pub fn load_or_build<F>(path: &Path, build: F) -> Result<Self>
+    where
+        F: FnOnce() -> Result<Self>,
+    {
+        if let Ok(Some(bytes)) = storage::read_with_digest(path) {
+            if let Ok(network) = Self::restore(&bytes) {
+                return Ok(network);
+            }
+        }
+
+        let mut network = build()?;
+        storage::write_with_digest(path, &network.snapshot()?)?;
+
+        Ok(network)
+    }
+
+    pub fn genesis_key(&self) -> bls::PublicKey {
+        self.genesis_key.public_key()
+    }
+
+    pub fn section(&self, prefix: &Prefix) -> Option<&TestSection> {
+        self.sections
+            .iter()
+            .find(|section| section.section.prefix() == prefix)
+    }
+
+    // Knowledge of the other sections as held by the members of the section with `prefix`.
+    pub fn network_of(&self, prefix: &Prefix) -> Result<Network> {
+        let ours = self
+            .section(prefix)
+            .ok_or_else(|| anyhow!("no section {:?}", prefix))?;
+        let our_key = ours.sk_set.secret_key();
+        let our_key_index = ours.section.chain().last_key_index();
+
+        let mut network = Network::new();
+        for theirs in self
+            .sections
+            .iter()
+            .filter(|theirs| theirs.section.prefix() != prefix)
+        {
+            let their_prefix = *theirs.section.prefix();
+            let _ = network.update_neighbour_info(theirs.section.proven_elders_info().clone());
+            let _ = network.update_their_key(proven(
+                our_key,
+                (their_prefix, theirs.sk_set.secret_key().public_key()),
+            )?);
+            network.update_knowledge(proven(our_key, (their_prefix, our_key_index))?);
+        }
+
+        network.prune_neighbours(prefix);
+        Ok(network)
+    }
+
+    // The current topology of the network as a Graphviz `.dot` graph. See `topology::to_dot`.
+    pub fn to_dot(&self) -> Result<String> {
+        let networks = self
+            .sections
+            .iter()
+            .map(|section| self.network_of(section.section.prefix()))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(topology::to_dot(
+            self.sections
+                .iter()
+                .map(|section| &section.section)
+                .zip(&networks),
+        ))
+    }
+
+    // Approved state of the node at `index` of the section with `prefix`, together with the
+    // receiver of its events.
+    pub fn approved(
+        &self,
+        prefix: &Prefix,
+        index: usize,
+    ) -> Result<(Approved, mpsc::UnboundedReceiver<Event>)> {
+        let ours = self
+            .section(prefix)
+            .ok_or_else(|| anyhow!("no section {:?}", prefix))?;
+        let node = ours
+            .nodes
+            .get(index)
+            .ok_or_else(|| anyhow!("no node {} in section {:?}", index, prefix))?
+            .clone();
+
+        let key_share = if index < ours.section.elders_info().elders.len() {
+            Some(create_section_key_share(&ours.sk_set, index))
+        } else {
+            None
+        };
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let state = Approved::new(node, ours.section.clone(), key_share, event_tx)
+            .with_network(self.network_of(prefix)?);
+
+        Ok((state, event_rx))
+    }
+
+    // Make the section with `prefix` split deterministically. Adds enough new mature members to
+    // both its halves to reach the split threshold, so the states created by `approved` from then
+    // on are due to split. Returns the consensus commands completing the split: `OurElders` and
+    // `TheirKey` for both child sections, in this order. The network keeps the parent section until
+    // `complete_split` is called.
+    pub fn force_split(
+        &mut self,
+        prefix: &Prefix,
+        network_params: &NetworkParams,
+    ) -> Result<Vec<Command>> {
+        let index = self
+            .sections
+            .iter()
+            .position(|section| section.section.prefix() == prefix)
+            .ok_or_else(|| anyhow!("no section {:?}", prefix))?;
+        let children = [prefix.pushed(false), prefix.pushed(true)];
+
+        let mut parent = self.sections.remove(index);
+        let parent_key = parent.sk_set.secret_key().clone();
+        let result = self
+            .fill_for_split(&mut parent, &children, network_params)
+            .and_then(|()| {
+                Ok((
+                    self.create_child(&parent, children[0], network_params)?,
+                    self.create_child(&parent, children[1], network_params)?,
+                ))
+            });
+        self.sections.insert(index, parent);
+        let (child0, child1) = result?;
+
+        let mut commands = vec![];
+        for child in &[&child0, &child1] {
+            let vote = Vote::OurElders(child.section.proven_elders_info().clone());
+            commands.push(consensus_command(&parent_key, vote)?);
+        }
+        for child in &[&child0, &child1] {
+            let vote = Vote::TheirKey {
+                prefix: *child.section.prefix(),
+                key: child.sk_set.secret_key().public_key(),
+            };
+            commands.push(consensus_command(&parent_key, vote)?);
+        }
+
+        let _ = self.splits.insert(*prefix, (child0, child1));
+
+        Ok(commands)
+    }
+
+    // Replace the section with `prefix` split by `force_split` with its children.
+    pub fn complete_split(&mut self, prefix: &Prefix) -> Result<()> {
+        let (child0, child1) = self
+            .splits
+            .remove(prefix)
+            .ok_or_else(|| anyhow!("section {:?} not being split", prefix))?;
+        let index = self
+            .sections
+            .iter()
+            .position(|section| section.section.prefix() == prefix)
+            .ok_or_else(|| anyhow!("no section {:?}", prefix))?;
+
+        self.sections[index] = child0;
+        self.sections.insert(index + 1, child1);
+
+        Ok(())
+    }
+
+    fn fill_for_split(
+        &mut self,
+        parent: &mut TestSection,
+        children: &[Prefix; 2],
+        network_params: &NetworkParams,
+    ) -> Result<()> {
+        for child in children {
+            let mature = parent
+                .section
+                .members()
+                .mature()
+                .filter(|peer| child.matches(peer.name()))
+                .count();
+            for _ in mature..network_params.split_threshold() {
+                let node = Node::new(self.gen_keypair(child), gen_addr()).with_age(MIN_AGE + 1);
+                let member_info =
+                    proven(parent.sk_set.secret_key(), MemberInfo::joined(node.peer()))?;
+                let _ = parent.section.update_member(member_info);
+                parent.nodes.push(node);
+            }
+        }
+
+        Ok(())
+    }
+
+    // The child section with `prefix` of `parent`, with the elders the parent's members expect.
+    fn create_child(
+        &mut self,
+        parent: &TestSection,
+        prefix: Prefix,
+        network_params: &NetworkParams,
+    ) -> Result<TestSection> {
+        let sk_set = SecretKeySet::random_from_rng(&mut self.rng);
+        let section_key = sk_set.secret_key().public_key();
+
+        let mut chain = parent.section.chain().clone();
+        let signature = parent
+            .sk_set
+            .secret_key()
+            .sign(&bincode::serialize(&section_key)?);
+        if !chain.push(section_key, signature) {
+            return Err(anyhow!(
+                "failed to extend the chain of section {:?}",
+                prefix
+            ));
+        }
+
+        let elders = parent.section.members().elder_candidates_matching_prefix(
+            &prefix,
+            network_params.elder_size,
+            parent.section.elders_info(),
+        );
+        let elders_info = EldersInfo::new(elders, prefix);
+
+        // The elders sorted by name, followed by the adults.
+        let (mut nodes, adults): (Vec<_>, Vec<_>) = parent
+            .nodes
+            .iter()
+            .filter(|node| prefix.matches(&node.name()))
+            .cloned()
+            .partition(|node| elders_info.elders.contains_key(&node.name()));
+        nodes.sort_by_key(Node::name);
+        nodes.extend(adults);
+
+        let elders_info = proven(sk_set.secret_key(), elders_info)?;
+        let mut section = Section::new(chain, elders_info)?;
+        for node in &nodes {
+            let member_info = proven(sk_set.secret_key(), MemberInfo::joined(node.peer()))?;
+            let _ = section.update_member(member_info);
+        }
+
+        Ok(TestSection {
+            sk_set,
+            section,
+            nodes,
+        })
+    }
+
+    fn create_section(&mut self, prefix: Prefix, size: usize) -> Result<TestSection> {
+        let sk_set = SecretKeySet::random_from_rng(&mut self.rng);
+        let section_key = sk_set.secret_key().public_key();
+
+        let mut chain = SectionProofChain::new(self.genesis_key.public_key());
+        let signature = self.genesis_key.sign(&bincode::serialize(&section_key)?);
+        if !chain.push(section_key, signature) {
+            return Err(anyhow!(
+                "failed to extend the chain of section {:?}",
+                prefix
+            ));
+        }
+
+        let elder_count = size.min(ELDER_SIZE);
+        let mut nodes: Vec<_> = (0..size)
+            .map(|index| {
+                let age = if index < elder_count {
+                    MIN_AGE + 2
+                } else {
+                    MIN_AGE + 1
+                };
+                Node::new(self.gen_keypair(&prefix), gen_addr()).with_age(age)
+            })
+            .collect();
+        nodes[..elder_count].sort_by_key(Node::name);
+
+        let elders_info = EldersInfo::new(nodes[..elder_count].iter().map(Node::peer), prefix);
+        let elders_info = proven(sk_set.secret_key(), elders_info)?;
+        let mut section = Section::new(chain, elders_info)?;
+
+        for node in &nodes {
+            let member_info = proven(sk_set.secret_key(), MemberInfo::joined(node.peer()))?;
+            let _ = section.update_member(member_info);
+        }
+
+        Ok(TestSection {
+            sk_set,
+            section,
+            nodes,
+        })
+    }
+
+    // Generate a keypair whose name matches `prefix`.
+    fn gen_keypair(&mut self, prefix: &Prefix) -> Keypair {
+        crypto::test_utils::gen_keypair_within_prefix(prefix, &mut self.rng)
+    }
+}
+
+fn genesis_key_from_seed(seed: [u8; 32]) -> bls::SecretKey {
+    ChaChaRng::from_seed(seed).gen()
+}
+
+#[derive(Serialize, Deserialize)]
+struct NetworkSnapshot {
+    rng_seed: [u8; 32],
+    genesis_seed: [u8; 32],
+    sections: Vec<SectionSnapshot>,
+    splits: Vec<(Prefix, SectionSnapshot, SectionSnapshot)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SectionSnapshot {
+    key_seed: [u8; 32],
+    section: Section,
+    nodes: Vec<NodeSnapshot>,
+}
+
+impl SectionSnapshot {
+    fn new(section: &TestSection) -> Self {
+        Self {
+            key_seed: section.sk_set.seed(),
+            section: section.section.clone(),
+            nodes: section
+                .nodes
+                .iter()
+                .map(|node| NodeSnapshot {
+                    keypair: node.keypair.to_bytes().to_vec(),
+                    addr: node.addr,
+                    age: node.age,
+                })
+                .collect(),
+        }
+    }
+
+    fn restore(self) -> Result<TestSection> {
+        let nodes = self
+            .nodes
+            .into_iter()
+            .map(|node| {
+                let keypair = Keypair::from_bytes(&node.keypair)?;
+                Ok(Node::new(keypair, node.addr).with_age(node.age))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(TestSection {
+            sk_set: SecretKeySet::from_seed(self.key_seed),
+            section: self.section,
+            nodes,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NodeSnapshot {
+    keypair: Vec<u8>,
+    addr: SocketAddr,
+    age: u8,
+}
+
+fn consensus_command(sk: &bls::SecretKey, vote: Vote) -> Result<Command> {
+    let signature = sk.sign(&bincode::serialize(&vote.as_signable())?);
+    let proof = Proof {
+        signature,
+        public_key: sk.public_key(),
+    };
+
+    Ok(Command::HandleConsensus { vote, proof })
+}
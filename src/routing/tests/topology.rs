@@ -0,0 +1,96 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    network::Network,
+    section::{NodeRole, Section},
+};
+use std::{collections::BTreeSet, fmt::Write as _};
+
+// Render the network topology as seen by one node of each section as a Graphviz `.dot` graph.
+// Every section is a cluster of its joined members, with the elders drawn as double circles. Every
+// neighbour known to a section is an edge to that neighbour, labelled with the index of the key of
+// ours the neighbour is known to trust, or dashed if the section doesn't know the neighbour's key.
+// Render with e.g. `dot -Tsvg topology.dot -o topology.svg`.
+pub(crate) fn to_dot<'a, I>(views: I) -> String
+where
+    I: IntoIterator<Item = (&'a Section, &'a Network)>,
+{
+    let views: Vec<_> = views.into_iter().collect();
+    let mut out = String::new();
+
+    let _ = writeln!(out, "digraph topology {{");
+    let _ = writeln!(out, "    compound=true;");
+    let _ = writeln!(out, "    node [shape=circle, fontsize=10];");
+
+    let mut known = BTreeSet::new();
+    for (index, (section, _)) in views.iter().enumerate() {
+        let prefix = section.prefix();
+        let _ = known.insert(*prefix);
+
+        let _ = writeln!(out, "    subgraph cluster_{} {{", index);
+        let _ = writeln!(
+            out,
+            "        label=\"{:?} (key #{})\";",
+            prefix,
+            section.chain().last_key_index()
+        );
+        // Anchor for the edges between sections.
+        let _ = writeln!(out, "        \"{:?}\" [shape=point, style=invis];", prefix);
+
+        for info in section.members().joined() {
+            let shape = match section.role_of(info.peer.name()) {
+                Some(NodeRole::Elder) => "doublecircle",
+                _ => "circle",
+            };
+            let _ = writeln!(
+                out,
+                "        \"{}\" [label=\"{}\\nage {}\", shape={}];",
+                info.peer.name(),
+                info.peer.name(),
+                info.peer.age(),
+                shape
+            );
+        }
+
+        let _ = writeln!(out, "    }}");
+    }
+
+    for (section, network) in &views {
+        let ours = section.prefix();
+        for elders_info in network.all() {
+            let theirs = &elders_info.prefix;
+
+            // Neighbour we don't have a view of, drawn as a plain node.
+            if known.insert(*theirs) {
+                let _ = writeln!(
+                    out,
+                    "    \"{:?}\" [label=\"{:?}\\n(unknown)\", shape=box, style=dashed];",
+                    theirs, theirs
+                );
+            }
+
+            let style = if network.key_by_prefix(theirs).is_some() {
+                "solid"
+            } else {
+                "dashed"
+            };
+            let _ = writeln!(
+                out,
+                "    \"{:?}\" -> \"{:?}\" [label=\"#{}\", style={}];",
+                ours,
+                theirs,
+                network.knowledge_by_section(theirs),
+                style
+            );
+        }
+    }
+
+    let _ = writeln!(out, "}}");
+    out
+}
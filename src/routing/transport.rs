@@ -0,0 +1,202 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    crypto::{Keypair, PublicKey},
+    error::Result,
+};
+use bytes::Bytes;
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{self, BoxStream, StreamExt},
+};
+use qp2p::{Endpoint, QuicP2p};
+use std::{
+    fmt::{self, Debug, Formatter},
+    net::SocketAddr,
+};
+use thiserror::Error;
+
+/// Networking backend a node communicates with the other nodes over. The default one is QUIC
+/// (configured by `Config::transport_config`), but any other (e.g. TCP or in-memory for testing)
+/// can be used instead by setting `Config::transport`. All the nodes of a network must use
+/// compatible transports.
+pub trait Transport: Debug + Send + Sync {
+    /// Address the other peers reach us at.
+    fn local_addr(&self) -> SocketAddr;
+
+    /// Connects to the peer at the given address, unless already connected.
+    fn connect<'a>(&'a self, addr: &'a SocketAddr) -> BoxFuture<'a, Result<(), TransportError>>;
+
+    /// Sends the message to the peer at the given address over an existing connection. Fails if
+    /// there is none.
+    fn send<'a>(
+        &'a self,
+        addr: &'a SocketAddr,
+        msg: Bytes,
+    ) -> BoxFuture<'a, Result<(), TransportError>>;
+
+    /// Closes the connection to the peer at the given address, if any.
+    fn disconnect(&self, addr: &SocketAddr);
+
+    /// Public key of the node the connection to the peer at the given address was authenticated
+    /// as, if the transport authenticates the peers (like `NoiseTransport`).
+    fn peer_key(&self, _addr: &SocketAddr) -> Option<PublicKey> {
+        None
+    }
+
+    /// Switches to the new keypair of our node, e.g. after it relocated. A transport
+    /// authenticating the peers by it must authenticate the connections again.
+    fn rekey(&self, _keypair: &Keypair) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    /// Takes the stream of the incoming connections and messages and lost connections. Called
+    /// only once, when the node starts.
+    fn events(&mut self) -> BoxStream<'static, TransportEvent>;
+
+    /// Closes all the connections and stops accepting new ones.
+    fn close(&self);
+}
+
+/// Event reported by a `Transport`.
+#[derive(Debug)]
+pub enum TransportEvent {
+    /// The peer at the given address connected to us.
+    Connected(SocketAddr),
+    /// Message received from the peer at the given address.
+    Received(SocketAddr, Bytes),
+    /// Connection to the peer at the given address was lost.
+    Disconnected(SocketAddr),
+}
+
+/// Error of a `Transport` operation.
+#[derive(Debug, Error)]
+pub enum TransportError {
+    /// The transport was closed by us.
+    #[error("Transport closed.")]
+    Closed,
+    /// Any other error, e.g. the peer being unreachable.
+    #[error("Transport error: {0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+// The default transport, over QUIC.
+pub(crate) struct QuicTransport {
+    _quic_p2p: QuicP2p,
+    endpoint: Endpoint,
+    incoming_connections: Option<qp2p::IncomingConnections>,
+    incoming_messages: Option<qp2p::IncomingMessages>,
+    disconnections: Option<qp2p::DisconnectionEvents>,
+}
+
+impl QuicTransport {
+    pub async fn new(transport_config: qp2p::Config) -> Result<Self> {
+        let quic_p2p = QuicP2p::with_config(Some(transport_config), Default::default(), true)?;
+
+        // Don't bootstrap, just create an endpoint to listen to
+        // the incoming connections and messages from other nodes.
+        // This also returns the a channel where we can listen for
+        // disconnection events.
+        let (endpoint, incoming_connections, incoming_messages, disconnections) =
+            quic_p2p.new_endpoint().await?;
+
+        Ok(Self {
+            _quic_p2p: quic_p2p,
+            endpoint,
+            incoming_connections: Some(incoming_connections),
+            incoming_messages: Some(incoming_messages),
+            disconnections: Some(disconnections),
+        })
+    }
+}
+
+impl Transport for QuicTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.endpoint.socket_addr()
+    }
+
+    fn connect<'a>(&'a self, addr: &'a SocketAddr) -> BoxFuture<'a, Result<(), TransportError>> {
+        async move { self.endpoint.connect_to(addr).await.map_err(convert_error) }.boxed()
+    }
+
+    fn send<'a>(
+        &'a self,
+        addr: &'a SocketAddr,
+        msg: Bytes,
+    ) -> BoxFuture<'a, Result<(), TransportError>> {
+        async move {
+            self.endpoint
+                .send_message(msg, addr)
+                .await
+                .map_err(convert_error)
+        }
+        .boxed()
+    }
+
+    fn disconnect(&self, addr: &SocketAddr) {
+        if let Err(error) = self.endpoint.disconnect_from(addr) {
+            trace!("Failed to disconnect from {}: {}", addr, error);
+        }
+    }
+
+    fn events(&mut self) -> BoxStream<'static, TransportEvent> {
+        let connections = self
+            .incoming_connections
+            .take()
+            .map(|incoming_connections| {
+                stream::unfold(
+                    incoming_connections,
+                    |mut incoming_connections| async move {
+                        let addr = incoming_connections.next().await?;
+                        Some((TransportEvent::Connected(addr), incoming_connections))
+                    },
+                )
+            });
+        let messages = self.incoming_messages.take().map(|incoming_messages| {
+            stream::unfold(incoming_messages, |mut incoming_messages| async move {
+                let (src, msg) = incoming_messages.next().await?;
+                Some((TransportEvent::Received(src, msg), incoming_messages))
+            })
+        });
+        let disconnections = self.disconnections.take().map(|disconnections| {
+            stream::unfold(disconnections, |mut disconnections| async move {
+                let addr = disconnections.next().await?;
+                Some((TransportEvent::Disconnected(addr), disconnections))
+            })
+        });
+
+        stream::select(
+            stream::iter(connections).flatten(),
+            stream::select(
+                stream::iter(messages).flatten(),
+                stream::iter(disconnections).flatten(),
+            ),
+        )
+        .boxed()
+    }
+
+    fn close(&self) {
+        self.endpoint.close()
+    }
+}
+
+impl Debug for QuicTransport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("QuicTransport")
+            .field("local_addr", &self.endpoint.socket_addr())
+            .finish()
+    }
+}
+
+fn convert_error(error: qp2p::Error) -> TransportError {
+    match error {
+        qp2p::Error::Connection(qp2p::ConnectionError::LocallyClosed) => TransportError::Closed,
+        error => TransportError::Other(Box::new(error)),
+    }
+}
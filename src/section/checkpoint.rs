@@ -0,0 +1,88 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{EldersInfo, SectionProofChain};
+use crate::consensus::Proven;
+use serde::{Deserialize, Serialize};
+
+/// Number of section keys between two consecutive checkpoints.
+pub const CHECKPOINT_INTERVAL: u64 = 16;
+
+/// Summary of our section state at a given point of the section chain. The summary is signed by
+/// the section key at that point, so it can be used as a proof of the section state without
+/// needing the full history that preceded it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Index of the checkpoint key in the section chain.
+    pub key_index: u64,
+    /// `EldersInfo` of the section at the checkpoint, signed with the checkpoint key.
+    pub elders_info: Proven<EldersInfo>,
+}
+
+impl Checkpoint {
+    /// Returns whether a checkpoint is due at the given section chain index.
+    pub(crate) fn is_due(key_index: u64) -> bool {
+        key_index > 0 && key_index % CHECKPOINT_INTERVAL == 0
+    }
+
+    /// Returns the section key of this checkpoint.
+    pub fn key(&self) -> &bls::PublicKey {
+        &self.elders_info.proof.public_key
+    }
+
+    /// Verify that this checkpoint is signed by a key at the expected position in `chain`.
+    pub fn verify(&self, chain: &SectionProofChain) -> bool {
+        chain.index_of(self.key()) == Some(self.key_index) && self.elders_info.self_verify()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consensus::test_utils::proven, section::test_utils::gen_elders_info};
+    use anyhow::Result;
+    use xor_name::Prefix;
+
+    #[test]
+    fn is_due() {
+        assert!(!Checkpoint::is_due(0));
+        assert!(!Checkpoint::is_due(1));
+        assert!(!Checkpoint::is_due(CHECKPOINT_INTERVAL - 1));
+        assert!(Checkpoint::is_due(CHECKPOINT_INTERVAL));
+        assert!(!Checkpoint::is_due(CHECKPOINT_INTERVAL + 1));
+        assert!(Checkpoint::is_due(2 * CHECKPOINT_INTERVAL));
+    }
+
+    #[test]
+    fn verify() -> Result<()> {
+        let sk0 = bls::SecretKey::random();
+        let sk1 = bls::SecretKey::random();
+        let pk1 = sk1.public_key();
+
+        let mut chain = SectionProofChain::new(sk0.public_key());
+        let _ = chain.push(pk1, sk0.sign(&bincode::serialize(&pk1)?));
+
+        let (elders_info, _) = gen_elders_info(Prefix::default(), 3);
+        let elders_info = proven(&sk1, elders_info)?;
+
+        let checkpoint = Checkpoint {
+            key_index: 1,
+            elders_info: elders_info.clone(),
+        };
+        assert!(checkpoint.verify(&chain));
+
+        // Wrong index.
+        let checkpoint = Checkpoint {
+            key_index: 0,
+            elders_info,
+        };
+        assert!(!checkpoint.verify(&chain));
+
+        Ok(())
+    }
+}
@@ -46,6 +46,14 @@ impl MemberInfo {
         })
     }
 
+    // Like `leave`, but for a node that asked to leave, so it keeps its age if it rejoins.
+    pub fn leave_voluntarily(self) -> Result<Self, Error> {
+        Ok(Self {
+            state: PeerState::LeftVoluntarily,
+            ..self.leave()?
+        })
+    }
+
     // Convert this info into one with the state changed to `Relocated`.
     pub fn relocate(self, destination: XorName) -> Self {
         Self {
@@ -63,4 +71,27 @@ pub enum PeerState {
     Left,
     // Node was relocated to a different section.
     Relocated(XorName),
+    // Node asked to leave, e.g. before a planned restart.
+    LeftVoluntarily,
+}
+
+impl PeerState {
+    // Is this either of the states of a node that is gone from the network?
+    pub fn is_left(&self) -> bool {
+        matches!(self, Self::Left | Self::LeftVoluntarily)
+    }
+}
+
+/// Role of a member within its section.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum NodeRole {
+    /// Member of the section's elders. Elders take part in consensus and sign on behalf of the
+    /// section.
+    Elder,
+    /// Mature member (age greater than `MIN_AGE`) that is not an elder. Adults relay messages and
+    /// store data.
+    Adult,
+    /// Member still on probation (age `MIN_AGE` or less). Infants can't become elders unless
+    /// there are not enough adults.
+    Infant,
 }
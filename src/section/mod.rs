@@ -6,6 +6,7 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+mod checkpoint;
 mod elders_info;
 mod member_info;
 mod section_keys;
@@ -16,8 +17,9 @@ mod section_proof_chain;
 pub(crate) use self::elders_info::test_utils;
 pub(crate) use self::section_peers::SectionPeers;
 pub use self::{
+    checkpoint::{Checkpoint, CHECKPOINT_INTERVAL},
     elders_info::EldersInfo,
-    member_info::{MemberInfo, PeerState, MIN_AGE},
+    member_info::{MemberInfo, NodeRole, PeerState, MIN_AGE},
     section_keys::{SectionKeyShare, SectionKeysProvider},
     section_proof_chain::{ExtendError, SectionProofChain, TrustStatus},
 };
@@ -25,20 +27,29 @@ pub use self::{
 use crate::{
     consensus::Proven,
     error::{Error, Result},
+    network_params::NetworkParams,
     peer::Peer,
-    ELDER_SIZE, RECOMMENDED_SECTION_SIZE,
 };
 use bls_signature_aggregator::Proof;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, collections::BTreeSet, convert::TryInto, iter, net::SocketAddr};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    convert::TryInto,
+    iter, mem,
+    net::SocketAddr,
+};
 use xor_name::{Prefix, XorName};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct Section {
     members: SectionPeers,
     elders_info: Proven<EldersInfo>,
+    // Previous elders infos of our section, oldest first.
+    elders_history: Vec<Proven<EldersInfo>>,
     chain: SectionProofChain,
+    checkpoint: Option<Checkpoint>,
 }
 
 impl Section {
@@ -52,8 +63,10 @@ impl Section {
 
         Ok(Self {
             elders_info,
+            elders_history: Vec::new(),
             chain,
             members: SectionPeers::default(),
+            checkpoint: None,
         })
     }
 
@@ -116,17 +129,45 @@ impl Section {
             &self.chain,
         ) {
             Ordering::Less => {
-                self.elders_info = other.elders_info;
+                self.archive_elders_info(other.elders_info);
             }
             Ordering::Greater | Ordering::Equal => (),
         }
 
+        for info in other.elders_history {
+            if info.verify(&self.chain) && !self.elders_history.contains(&info) {
+                self.elders_history.push(info);
+            }
+        }
+
+        let chain = &self.chain;
+        self.elders_history
+            .sort_by_key(|info| chain.index_of(&info.proof.public_key));
+
+        // The checkpoint index is relative to the other chain which might be only a slice of ours,
+        // so rebase it first.
+        if let Some(checkpoint) = other.checkpoint {
+            if let Some(key_index) = self.chain.index_of(checkpoint.key()) {
+                let _ = self.update_checkpoint(Checkpoint {
+                    key_index,
+                    ..checkpoint
+                });
+            }
+        }
+
+        for info in other.members.departed() {
+            if info.verify(&self.chain) {
+                self.members.add_departed(info.clone());
+            }
+        }
+
         for info in other.members {
             let _ = self.update_member(info);
         }
 
         self.members
             .prune_not_matching(&self.elders_info.value.prefix);
+        self.prune_history();
 
         Ok(())
     }
@@ -162,13 +203,129 @@ impl Section {
             return false;
         }
 
-        self.elders_info = new_elders_info;
+        self.archive_elders_info(new_elders_info);
         self.members
             .prune_not_matching(&self.elders_info.value.prefix);
 
+        if Checkpoint::is_due(self.chain.last_key_index()) {
+            let _ = self.update_checkpoint(Checkpoint {
+                key_index: self.chain.last_key_index(),
+                elders_info: self.elders_info.clone(),
+            });
+            self.prune_history();
+        }
+
         true
     }
 
+    /// Returns the elders info of our section with a prefix compatible with `prefix` that was
+    /// current at the section chain index `key_index`, if still known. Elders infos preceding the
+    /// latest checkpoint are pruned and so not available.
+    pub fn elders_info_at(&self, prefix: &Prefix, key_index: u64) -> Option<&Proven<EldersInfo>> {
+        self.elders_history
+            .iter()
+            .chain(iter::once(&self.elders_info))
+            .rev()
+            .filter(|info| info.value.prefix.is_compatible(prefix))
+            .find(|info| {
+                self.chain
+                    .index_of(&info.proof.public_key)
+                    .map(|index| index <= key_index)
+                    .unwrap_or(false)
+            })
+    }
+
+    // Replace the current elders info with `new_elders_info`, keeping the old one in the history.
+    fn archive_elders_info(&mut self, new_elders_info: Proven<EldersInfo>) {
+        let old_elders_info = mem::replace(&mut self.elders_info, new_elders_info);
+        self.elders_history.push(old_elders_info);
+    }
+
+    /// Returns the latest checkpoint of our section, if any.
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    // Replace our checkpoint with `checkpoint` if it's valid and newer than the current one.
+    // Returns whether the checkpoint changed.
+    fn update_checkpoint(&mut self, checkpoint: Checkpoint) -> bool {
+        if !checkpoint.verify(&self.chain) {
+            return false;
+        }
+
+        if let Some(current) = &self.checkpoint {
+            if current.key_index >= checkpoint.key_index {
+                return false;
+            }
+        }
+
+        self.checkpoint = Some(checkpoint);
+        true
+    }
+
+    // Remove the history older than the latest checkpoint. That is the elders infos signed by a key
+    // preceding the checkpoint key and the members that are no longer joined nor elders and whose
+    // last state change was proven by such key. Only the latest of those members are remembered,
+    // as departed. The section chain itself is kept whole: other sections and clients may only
+    // trust one of its older keys, and the proofs sent to them must extend from it.
+    fn prune_history(&mut self) {
+        let checkpoint_index = if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.key_index
+        } else {
+            return;
+        };
+
+        let chain = &self.chain;
+        let is_stale = |key: &bls::PublicKey| {
+            chain
+                .index_of(key)
+                .map(|index| index < checkpoint_index)
+                .unwrap_or(false)
+        };
+
+        self.elders_history
+            .retain(|info| !is_stale(&info.proof.public_key));
+
+        let elders = &self.elders_info.value.elders;
+        self.members.prune_history(|info| {
+            !elders.contains_key(info.value.peer.name()) && is_stale(&info.proof.public_key)
+        });
+    }
+
+    /// Panics if any of the section invariants is violated.
+    #[cfg(feature = "debug-invariants")]
+    pub fn assert_invariants(&self) {
+        assert!(self.chain.self_verify(), "section chain is not valid");
+        assert!(
+            self.elders_info.verify(&self.chain),
+            "elders info not signed by a key from our chain"
+        );
+
+        for info in self.members.all() {
+            assert!(
+                self.prefix().matches(info.peer.name()),
+                "member {} does not match our prefix ({:b})",
+                info.peer,
+                self.prefix()
+            );
+        }
+
+        // Non-elders keep only trimmed section state without members.
+        if self.members.all().next().is_some() {
+            for elder in self.elders_info.value.elders.keys() {
+                assert!(
+                    self.members.get(elder).is_some(),
+                    "elder {} is not a member",
+                    elder
+                );
+            }
+        }
+
+        if let Some(checkpoint) = &self.checkpoint {
+            assert!(checkpoint.verify(&self.chain), "checkpoint is not valid");
+        }
+    }
+
     /// Update the member. Returns whether it actually changed anything.
     pub fn update_member(&mut self, member_info: Proven<MemberInfo>) -> bool {
         if !member_info.verify(&self.chain) {
@@ -187,10 +344,21 @@ impl Section {
             .last_key_index()
             .saturating_sub(chain_len.saturating_sub(1) as u64);
 
+        let chain = self.chain.slice(first_key_index..);
+        let checkpoint = self.checkpoint.as_ref().and_then(|checkpoint| {
+            let key_index = chain.index_of(checkpoint.key())?;
+            Some(Checkpoint {
+                key_index,
+                elders_info: checkpoint.elders_info.clone(),
+            })
+        });
+
         Self {
             elders_info: self.elders_info.clone(),
-            chain: self.chain.slice(first_key_index..),
+            elders_history: Vec::new(),
+            chain,
             members: SectionPeers::default(),
+            checkpoint,
         }
     }
 
@@ -232,12 +400,16 @@ impl Section {
 
     /// Generate a new section info(s) based on the current set of members.
     /// Returns a set of EldersInfos to vote for.
-    pub fn promote_and_demote_elders(&self, our_name: &XorName) -> Vec<EldersInfo> {
-        if let Some((our_info, other_info)) = self.try_split(our_name) {
+    pub fn promote_and_demote_elders(
+        &self,
+        network_params: &NetworkParams,
+        our_name: &XorName,
+    ) -> Vec<EldersInfo> {
+        if let Some((our_info, other_info)) = self.try_split(network_params, our_name) {
             return vec![our_info, other_info];
         }
 
-        let expected_peers = self.elder_candidates(ELDER_SIZE);
+        let expected_peers = self.elder_candidates(network_params.elder_size);
         let expected_names: BTreeSet<_> = expected_peers.iter().map(Peer::name).collect();
         let current_names: BTreeSet<_> = self.elders_info().elders.keys().collect();
 
@@ -252,6 +424,11 @@ impl Section {
         }
     }
 
+    /// Returns whether our section has enough mature members to split.
+    pub fn is_split_expected(&self, network_params: &NetworkParams, our_name: &XorName) -> bool {
+        self.try_split(network_params, our_name).is_some()
+    }
+
     // Prefix of our section.
     pub fn prefix(&self) -> &Prefix {
         &self.elders_info().prefix
@@ -271,6 +448,34 @@ impl Section {
             .map(|info| &info.peer)
     }
 
+    /// Returns the role of the member with the given name, or `None` if it's not a current member
+    /// of our section.
+    pub fn role_of(&self, name: &XorName) -> Option<NodeRole> {
+        if self.is_elder(name) {
+            return Some(NodeRole::Elder);
+        }
+
+        self.members.get(name).and_then(|info| {
+            if info.state != PeerState::Joined {
+                None
+            } else if info.is_mature() {
+                Some(NodeRole::Adult)
+            } else {
+                Some(NodeRole::Infant)
+            }
+        })
+    }
+
+    /// Returns the roles of all the current members of our section.
+    pub fn roles(&self) -> BTreeMap<XorName, NodeRole> {
+        self.elders_info()
+            .elders
+            .keys()
+            .chain(self.members.joined().map(|info| info.peer.name()))
+            .filter_map(|name| Some((*name, self.role_of(name)?)))
+            .collect()
+    }
+
     /// Returns adults from our section.
     pub fn adults(&self) -> impl Iterator<Item = &Peer> {
         self.members
@@ -297,7 +502,11 @@ impl Section {
     // Tries to split our section.
     // If we have enough mature nodes for both subsections, returns the elders infos of the two
     // subsections. Otherwise returns `None`.
-    fn try_split(&self, our_name: &XorName) -> Option<(EldersInfo, EldersInfo)> {
+    fn try_split(
+        &self,
+        network_params: &NetworkParams,
+        our_name: &XorName,
+    ) -> Option<(EldersInfo, EldersInfo)> {
         let next_bit_index = if let Ok(index) = self.prefix().bit_count().try_into() {
             index
         } else {
@@ -320,7 +529,8 @@ impl Section {
             });
 
         // If none of the two new sections would contain enough entries, return `None`.
-        if our_new_size < RECOMMENDED_SECTION_SIZE || sibling_new_size < RECOMMENDED_SECTION_SIZE {
+        let threshold = network_params.split_threshold();
+        if our_new_size < threshold || sibling_new_size < threshold {
             return None;
         }
 
@@ -329,12 +539,12 @@ impl Section {
 
         let our_elders = self.members.elder_candidates_matching_prefix(
             &our_prefix,
-            ELDER_SIZE,
+            network_params.elder_size,
             self.elders_info(),
         );
         let other_elders = self.members.elder_candidates_matching_prefix(
             &other_prefix,
-            ELDER_SIZE,
+            network_params.elder_size,
             self.elders_info(),
         );
 
@@ -395,3 +605,172 @@ fn cmp_section_chain_position(
         (None, None) => Ordering::Equal,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::test_utils::{prove, proven},
+        section::test_utils::{gen_addr, gen_elders_info},
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn elders_info_at() -> Result<()> {
+        let sk0 = bls::SecretKey::random();
+        let sk1 = bls::SecretKey::random();
+
+        let (elders_info0, _) = gen_elders_info(Prefix::default(), 3);
+        let elders_info0 = proven(&sk0, elders_info0)?;
+        let mut section = Section::new(
+            SectionProofChain::new(sk0.public_key()),
+            elders_info0.clone(),
+        )?;
+
+        let (elders_info1, _) = gen_elders_info(Prefix::default(), 3);
+        let elders_info1 = proven(&sk1, elders_info1)?;
+        let key_proof = prove(&sk0, &sk1.public_key())?;
+        assert!(section.update_elders(elders_info1.clone(), key_proof));
+
+        assert_eq!(
+            section.elders_info_at(&Prefix::default(), 0),
+            Some(&elders_info0)
+        );
+        assert_eq!(
+            section.elders_info_at(&Prefix::default(), 1),
+            Some(&elders_info1)
+        );
+        assert_eq!(
+            section.elders_info_at(&Prefix::default(), 2),
+            Some(&elders_info1)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_buffer() -> Result<()> {
+        let sk = bls::SecretKey::random();
+        let (elders_info, _) = gen_elders_info(Prefix::default(), 3);
+        let mut section = Section::new(
+            SectionProofChain::new(sk.public_key()),
+            proven(&sk, elders_info)?,
+        )?;
+
+        let add_members = |section: &mut Section, count: usize| -> Result<()> {
+            for prefix in &[
+                Prefix::default().pushed(false),
+                Prefix::default().pushed(true),
+            ] {
+                for _ in 0..count {
+                    let peer = Peer::new(
+                        prefix.substituted_in(rand::random()),
+                        gen_addr(),
+                        MIN_AGE + 1,
+                    );
+                    assert!(section.update_member(proven(&sk, MemberInfo::joined(peer))?));
+                }
+            }
+            Ok(())
+        };
+
+        let params = NetworkParams {
+            elder_size: 3,
+            recommended_section_size: 2,
+            split_buffer: 1,
+            ..NetworkParams::default()
+        };
+        let unbuffered = NetworkParams {
+            split_buffer: 0,
+            ..params
+        };
+        let our_name = rand::random();
+
+        add_members(&mut section, 2)?;
+        assert!(section.is_split_expected(&unbuffered, &our_name));
+        assert!(!section.is_split_expected(&params, &our_name));
+
+        add_members(&mut section, 1)?;
+        assert!(section.is_split_expected(&params, &our_name));
+
+        Ok(())
+    }
+
+    #[test]
+    fn role_of() -> Result<()> {
+        let sk = bls::SecretKey::random();
+        let (elders_info, elders) = gen_elders_info(Prefix::default(), 3);
+        let mut section = Section::new(
+            SectionProofChain::new(sk.public_key()),
+            proven(&sk, elders_info)?,
+        )?;
+
+        let adult = Peer::new(rand::random(), gen_addr(), MIN_AGE + 1);
+        let infant = Peer::new(rand::random(), gen_addr(), MIN_AGE);
+        for peer in &[adult, infant] {
+            assert!(section.update_member(proven(&sk, MemberInfo::joined(*peer))?));
+        }
+
+        assert_eq!(section.role_of(&elders[0].name()), Some(NodeRole::Elder));
+        assert_eq!(section.role_of(adult.name()), Some(NodeRole::Adult));
+        assert_eq!(section.role_of(infant.name()), Some(NodeRole::Infant));
+        assert_eq!(section.role_of(&rand::random()), None);
+
+        let left = proven(&sk, MemberInfo::joined(adult).leave()?)?;
+        assert!(section.update_member(left));
+        assert_eq!(section.role_of(adult.name()), None);
+        assert_eq!(section.roles().len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_prunes_departed_members() -> Result<()> {
+        let mut sk = bls::SecretKey::random();
+        let (elders_info, _) = gen_elders_info(Prefix::default(), 3);
+        let mut section = Section::new(
+            SectionProofChain::new(sk.public_key()),
+            proven(&sk, elders_info)?,
+        )?;
+        let genesis = section.clone();
+
+        let peer = Peer::new(rand::random(), gen_addr(), MIN_AGE + 2);
+        let member_info = MemberInfo::joined(peer);
+        assert!(section.update_member(proven(&sk, member_info)?));
+        assert!(section.update_member(proven(&sk, member_info.leave()?)?));
+
+        for _ in 0..CHECKPOINT_INTERVAL {
+            let new_sk = bls::SecretKey::random();
+            let (elders_info, _) = gen_elders_info(Prefix::default(), 3);
+            let key_proof = prove(&sk, &new_sk.public_key())?;
+            assert!(section.update_elders(proven(&new_sk, elders_info)?, key_proof));
+            sk = new_sk;
+        }
+
+        assert!(section.checkpoint().is_some());
+        assert!(section.members().get(peer.name()).is_none());
+        assert_eq!(
+            section
+                .members()
+                .find_proven(peer.name())
+                .map(|info| info.value.state),
+            Some(PeerState::Left)
+        );
+
+        // The departed member still can't rejoin under the same name.
+        assert!(!section.update_member(proven(&sk, member_info)?));
+
+        // The departed members are carried over by merging, e.g. when recovering from storage.
+        let mut merged = genesis;
+        merged.merge(section)?;
+        assert_eq!(
+            merged
+                .members()
+                .find_proven(peer.name())
+                .map(|info| info.value.state),
+            Some(PeerState::Left)
+        );
+
+        Ok(())
+    }
+}
@@ -18,17 +18,24 @@ use std::{
     cmp::Ordering,
     collections::{
         btree_map::{self, Entry},
-        BTreeMap,
+        BTreeMap, VecDeque,
     },
     hash::{Hash, Hasher},
     mem,
 };
 use xor_name::{Prefix, XorName};
 
+/// Maximum number of departed members remembered after they are pruned from the history.
+const MAX_DEPARTED: usize = 1000;
+
 /// Container for storing information about members of our section.
 #[derive(Clone, Default, Debug, Eq, Serialize, Deserialize)]
 pub(crate) struct SectionPeers {
     members: BTreeMap<XorName, Proven<MemberInfo>>,
+    // Members that left or were relocated and were then pruned from `members` with the history
+    // older than a checkpoint, oldest first. Still needed to halve the age of rejoining nodes and
+    // to prevent relocated nodes from rejoining under their old name. At most `MAX_DEPARTED`.
+    departed: VecDeque<Proven<MemberInfo>>,
 }
 
 impl SectionPeers {
@@ -62,6 +69,19 @@ impl SectionPeers {
         self.members.get(name)
     }
 
+    /// Get proven info for the member with the given name, including the departed members already
+    /// pruned from the history.
+    pub fn find_proven(&self, name: &XorName) -> Option<&Proven<MemberInfo>> {
+        self.members.get(name).or_else(|| self.get_departed(name))
+    }
+
+    fn get_departed(&self, name: &XorName) -> Option<&Proven<MemberInfo>> {
+        self.departed
+            .iter()
+            .rev()
+            .find(|info| info.value.peer.name() == name)
+    }
+
     /// Returns the candidates for elders out of all the nodes in this section.
     pub fn elder_candidates(&self, elder_size: usize, current_elders: &EldersInfo) -> Vec<Peer> {
         elder_candidates(
@@ -102,6 +122,15 @@ impl SectionPeers {
     pub fn update(&mut self, new_info: Proven<MemberInfo>) -> bool {
         match self.members.entry(*new_info.value.peer.name()) {
             Entry::Vacant(entry) => {
+                // Pruning doesn't make any transition from the departed state allowed again.
+                if self
+                    .departed
+                    .iter()
+                    .any(|info| info.value.peer.name() == entry.key())
+                {
+                    return false;
+                }
+
                 let _ = entry.insert(new_info);
                 true
             }
@@ -109,12 +138,14 @@ impl SectionPeers {
                 // To maintain commutativity, the only allowed transitions are:
                 // - Joined -> Joined if the new age is greater than the old age
                 // - Joined -> Left
+                // - Joined -> LeftVoluntarily
                 // - Joined -> Relocated
                 // - Relocated -> Left (should not happen, but needed for consistency)
                 match (entry.get().value.state, new_info.value.state) {
                     (PeerState::Joined, PeerState::Joined)
                         if new_info.value.peer.age() > entry.get().value.peer.age() => {}
                     (PeerState::Joined, PeerState::Left)
+                    | (PeerState::Joined, PeerState::LeftVoluntarily)
                     | (PeerState::Joined, PeerState::Relocated(_))
                     | (PeerState::Relocated(_), PeerState::Left) => {}
                     _ => return false,
@@ -126,12 +157,57 @@ impl SectionPeers {
         }
     }
 
+    /// Move the members that are no longer joined and for which `is_stale` returns `true` to the
+    /// departed ones, forgetting the oldest departed ones over `MAX_DEPARTED`.
+    pub fn prune_history<F>(&mut self, is_stale: F)
+    where
+        F: Fn(&Proven<MemberInfo>) -> bool,
+    {
+        let (stale, members): (BTreeMap<_, _>, _) = mem::take(&mut self.members)
+            .into_iter()
+            .partition(|(_, info)| info.value.state != PeerState::Joined && is_stale(info));
+        self.members = members;
+
+        self.departed
+            .extend(stale.into_iter().map(|(_, info)| info));
+        while self.departed.len() > MAX_DEPARTED {
+            let _ = self.departed.pop_front();
+        }
+    }
+
+    /// Returns the departed members already pruned from the history, oldest first.
+    pub fn departed(&self) -> impl Iterator<Item = &Proven<MemberInfo>> {
+        self.departed.iter()
+    }
+
+    /// Remember a departed member learned from another copy of our section, e.g. when recovering
+    /// from storage. A current member is updated instead, subject to the same rules as `update`.
+    pub fn add_departed(&mut self, info: Proven<MemberInfo>) {
+        if info.value.state == PeerState::Joined {
+            return;
+        }
+
+        if self.members.contains_key(info.value.peer.name()) {
+            let _ = self.update(info);
+            return;
+        }
+
+        if self.get_departed(info.value.peer.name()).is_none() {
+            self.departed.push_back(info);
+            if self.departed.len() > MAX_DEPARTED {
+                let _ = self.departed.pop_front();
+            }
+        }
+    }
+
     /// Remove all members whose name does not match `prefix`.
     pub fn prune_not_matching(&mut self, prefix: &Prefix) {
         self.members = mem::take(&mut self.members)
             .into_iter()
             .filter(|(name, _)| prefix.matches(name))
             .collect();
+        self.departed
+            .retain(|info| prefix.matches(info.value.peer.name()));
     }
 }
 
@@ -206,7 +282,8 @@ fn cmp_elder_candidates(
 }
 
 // Compare candidates for the next elders according to their peer state. The one comparing `Less`
-// wins. `Joined` is preferred over `Relocated` which is preferred over `Left`.
+// wins. `Joined` is preferred over `Relocated` which is preferred over `Left` or
+// `LeftVoluntarily`.
 // NOTE: we only consider `Relocated` peers as elder candidates if we don't have enough `Joined`
 // members to reach `ELDER_SIZE`.
 fn cmp_elder_candidates_by_peer_state(lhs: &PeerState, rhs: &PeerState) -> Ordering {
@@ -214,8 +291,8 @@ fn cmp_elder_candidates_by_peer_state(lhs: &PeerState, rhs: &PeerState) -> Order
 
     match (lhs, rhs) {
         (Joined, Joined) | (Relocated(_), Relocated(_)) => Ordering::Equal,
-        (Joined, Relocated(_)) | (_, Left) => Ordering::Less,
-        (Relocated(_), Joined) | (Left, _) => Ordering::Greater,
+        (Joined, Relocated(_)) | (_, Left) | (_, LeftVoluntarily) => Ordering::Less,
+        (Relocated(_), Joined) | (Left, _) | (LeftVoluntarily, _) => Ordering::Greater,
     }
 }
 
@@ -0,0 +1,141 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Bit manipulation and distance helpers for `XorName`.
+
+use rand::Rng;
+use xor_name::{Prefix, XorName, XOR_NAME_LEN};
+
+/// Extension methods on `XorName`.
+///
+/// To compare the distances of two names to a target, use `XorName::cmp_distance`.
+pub trait XorNameExt {
+    /// Returns a copy of this name with the `i`-th bit (counting from the most significant one)
+    /// flipped.
+    fn with_flipped_bit(self, i: u8) -> XorName;
+
+    /// Returns a copy of this name with all the bits starting at the `i`-th one (counting from the
+    /// most significant one) set to `value`.
+    fn set_remaining_bits(self, i: u8, value: bool) -> XorName;
+
+    /// Returns the XOR distance between this name and `other`. The distance is a big-endian
+    /// number, so distances can be compared directly.
+    fn distance_to(&self, other: &XorName) -> XorName;
+
+    /// Returns a random name matching `prefix`, drawn from `rng` so it's reproducible from the
+    /// seed of `rng`.
+    fn random_within<R: Rng + ?Sized>(prefix: &Prefix, rng: &mut R) -> XorName;
+}
+
+impl XorNameExt for XorName {
+    fn with_flipped_bit(mut self, i: u8) -> XorName {
+        let (byte, mask) = byte_and_mask(i);
+        self.0[byte] ^= mask;
+        self
+    }
+
+    fn set_remaining_bits(mut self, i: u8, value: bool) -> XorName {
+        let (byte, mask) = byte_and_mask(i);
+        // The bit `i` and all the less significant bits of its byte.
+        let remaining_mask = mask | (mask - 1);
+
+        if value {
+            self.0[byte] |= remaining_mask;
+        } else {
+            self.0[byte] &= !remaining_mask;
+        }
+
+        for other in &mut self.0[byte + 1..] {
+            *other = if value { 0xff } else { 0 };
+        }
+
+        self
+    }
+
+    fn distance_to(&self, other: &XorName) -> XorName {
+        let mut distance = [0; XOR_NAME_LEN];
+        for (i, byte) in distance.iter_mut().enumerate() {
+            *byte = self.0[i] ^ other.0[i];
+        }
+
+        XorName(distance)
+    }
+
+    fn random_within<R: Rng + ?Sized>(prefix: &Prefix, rng: &mut R) -> XorName {
+        prefix.substituted_in(rng.gen())
+    }
+}
+
+// Index of the byte containing the `i`-th bit and the mask selecting that bit within the byte.
+fn byte_and_mask(i: u8) -> (usize, u8) {
+    (usize::from(i / 8), 0b1000_0000 >> (i % 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+
+    #[test]
+    fn with_flipped_bit() {
+        let name = XorName([0; XOR_NAME_LEN]);
+
+        let flipped = name.with_flipped_bit(0);
+        assert_eq!(flipped.0[0], 0b1000_0000);
+        assert!(flipped.bit(0));
+
+        let flipped = name.with_flipped_bit(9);
+        assert_eq!(flipped.0[1], 0b0100_0000);
+        assert_eq!(flipped.with_flipped_bit(9), name);
+    }
+
+    #[test]
+    fn set_remaining_bits() {
+        let name = XorName([0b1010_1010; XOR_NAME_LEN]);
+
+        let set = name.set_remaining_bits(4, true);
+        assert_eq!(set.0[0], 0b1010_1111);
+        assert!(set.0[1..].iter().all(|byte| *byte == 0xff));
+
+        let cleared = name.set_remaining_bits(1, false);
+        assert_eq!(cleared.0[0], 0b1000_0000);
+        assert!(cleared.0[1..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn distance_to() {
+        let target: XorName = rand::random();
+        let lhs: XorName = rand::random();
+        let rhs: XorName = rand::random();
+
+        assert_eq!(target.distance_to(&target), XorName([0; XOR_NAME_LEN]));
+        assert_eq!(lhs.distance_to(&rhs), rhs.distance_to(&lhs));
+        assert_eq!(
+            target.distance_to(&lhs).cmp(&target.distance_to(&rhs)),
+            target.cmp_distance(&lhs, &rhs)
+        );
+    }
+
+    #[test]
+    fn random_within() {
+        let prefix: Prefix = "1011".parse().unwrap();
+        let mut rng = ChaChaRng::seed_from_u64(0);
+
+        for _ in 0..16 {
+            assert!(prefix.matches(&XorName::random_within(&prefix, &mut rng)));
+        }
+
+        let mut rng0 = ChaChaRng::seed_from_u64(1);
+        let mut rng1 = ChaChaRng::seed_from_u64(1);
+        assert_eq!(
+            XorName::random_within(&prefix, &mut rng0),
+            XorName::random_within(&prefix, &mut rng1)
+        );
+    }
+}